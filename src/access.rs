@@ -0,0 +1,61 @@
+//! Document access metadata and storage placement hints.
+//!
+//! Devices often pair a small amount of fast storage with a lot of slow
+//! storage, like an SSD and an SD card. Backends that spread documents across
+//! both need to know which documents are hot. A transaction can say so up
+//! front by giving a document a [`CacheTier`] with
+//! [`Transaction::set_cache_tier`][crate::transaction::Transaction::set_cache_tier],
+//! and backends that track accesses can also move documents as their use
+//! changes.
+//!
+//! Tracking accesses turns every read into a small write, so it is optional.
+//! Whether a backend does it is reported in its
+//! [capabilities][crate::capabilities::DbCapabilities::access_tracking], and
+//! what it has recorded is available through
+//! [`Db::doc_info`][crate::Db::doc_info].
+
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+
+/// Where a document should be stored, for backends with more than one kind of
+/// storage. Backends with only one kind ignore this.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum CacheTier {
+    /// Read often; keep on the fastest storage available.
+    Hot,
+    /// No particular access pattern. Backends are free to move the document
+    /// based on how it is actually used.
+    #[default]
+    Warm,
+    /// Rarely read; keep on the cheapest storage available.
+    Cold,
+}
+
+/// How often a document has been read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessStats {
+    /// Number of times the document has been read since access tracking
+    /// started. Backends may count approximately, or decay the count over time.
+    pub count: u64,
+    /// When the document was last read.
+    pub last: Timestamp,
+}
+
+/// Metadata about a document in the database.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocInfo {
+    /// The document's schema, if it has one.
+    pub schema: Option<Hash>,
+    /// The encoded size of the document, in bytes.
+    pub bytes: u64,
+    /// The storage tier the document was placed in.
+    pub tier: CacheTier,
+    /// How often the document has been read, if the backend tracks accesses.
+    pub access: Option<AccessStats>,
+    /// If the document was stored with
+    /// [`Cursor::cache_current`][crate::cursor::Cursor::cache_current], when it
+    /// stops being kept resident as a cache entry.
+    pub cached_until: Option<Timestamp>,
+}
@@ -0,0 +1,148 @@
+//! Detection of abusive access patterns through gates.
+//!
+//! A gate open to a public group will be probed: nodes walking the entire
+//! document tree as fast as they can, scraping every entry of every document,
+//! or throwing malformed queries at hooks to see what breaks. A gate
+//! periodically summarizes each attached node's activity into an
+//! [`AccessSummary`] and hands it to the gate's [`AnomalyDetector`], set with
+//! [`Gate::anomaly_detector`][crate::gate::Gate::anomaly_detector]. The
+//! detector can flag the node, throttle it, or cut it off, and every anomaly
+//! it reports is also emitted on the gate's
+//! [event stream][crate::gate::Gate::events].
+//!
+//! Gates can also be given honeypot documents with
+//! [`Gate::set_honeypots`][crate::gate::Gate::set_honeypots]: documents
+//! reachable through the gate that no legitimate client has any reason to
+//! read. A node reading one is summarized right away, instead of waiting for
+//! the next periodic summary.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{cursor::TraceId, limits::RateLimit, NodeInfo};
+
+/// A node's activity through a gate over a window of time.
+#[derive(Clone, Debug)]
+pub struct AccessSummary {
+    /// The node being summarized.
+    pub node: NodeInfo,
+    /// How long a period this summary covers.
+    pub window: Duration,
+    /// Documents retrieved by the node.
+    pub docs: u64,
+    /// The greatest number of links followed from the gate's starting document.
+    pub max_depth: u32,
+    /// Queries made by the node.
+    pub queries: u64,
+    /// Queries rejected as malformed, either by the gate or by a query hook.
+    pub invalid_queries: u64,
+    /// Entries returned to the node.
+    pub entries: u64,
+    /// Bytes sent to the node.
+    pub bytes: u64,
+    /// Honeypot documents read by the node.
+    pub honeypots: u64,
+    /// The [trace IDs][crate::cursor::TraceId] of the cursors the node had
+    /// open through the gate during the window.
+    pub cursors: Vec<TraceId>,
+}
+
+/// The kind of abuse a detector thinks a node is engaged in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum AnomalyKind {
+    /// Following links deeper, or faster, than normal clients do.
+    DeepTraversal,
+    /// Retrieving entries exhaustively.
+    Scraping,
+    /// Making many invalid queries.
+    InvalidQueries,
+    /// Reading a honeypot document.
+    Honeypot,
+    /// Some other, detector-specific kind of abuse.
+    Other(String),
+}
+
+/// What to do about a node behaving anomalously.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AnomalyAction {
+    /// Emit an event, but otherwise leave the node alone.
+    #[default]
+    Flag,
+    /// Limit the rate of the node's requests through the gate.
+    Throttle(RateLimit),
+    /// Close the node's cursors and refuse it any further access through the
+    /// gate.
+    Disconnect,
+}
+
+/// An anomaly found by a detector.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Anomaly {
+    /// What the node is suspected of.
+    pub kind: AnomalyKind,
+    /// What the gate should do about it.
+    pub action: AnomalyAction,
+}
+
+/// An anomaly, along with the summary that triggered it.
+#[derive(Clone, Debug)]
+pub struct AnomalyReport {
+    pub anomaly: Anomaly,
+    pub summary: AccessSummary,
+}
+
+/// Inspects summaries of node activity for signs of abuse.
+pub trait AnomalyDetector: Send + Sync {
+    /// Inspect a node's activity, returning an anomaly if there is one.
+    fn inspect(&self, summary: &AccessSummary) -> Option<Anomaly>;
+}
+
+/// A simple detector that flags nodes exceeding fixed limits within a summary
+/// window. Limits left as `None` aren't checked. Honeypot reads always
+/// result in the node being disconnected.
+#[derive(Clone, Debug, Default)]
+pub struct Thresholds {
+    /// Maximum number of links followed from the gate's starting document.
+    pub max_depth: Option<u32>,
+    /// Maximum documents retrieved per second.
+    pub docs_per_sec: Option<f64>,
+    /// Maximum entries returned per second.
+    pub entries_per_sec: Option<f64>,
+    /// Maximum invalid queries per summary.
+    pub invalid_queries: Option<u64>,
+    /// What to do when a limit is exceeded.
+    pub action: AnomalyAction,
+}
+
+impl AnomalyDetector for Thresholds {
+    fn inspect(&self, summary: &AccessSummary) -> Option<Anomaly> {
+        if summary.honeypots > 0 {
+            return Some(Anomaly {
+                kind: AnomalyKind::Honeypot,
+                action: AnomalyAction::Disconnect,
+            });
+        }
+        let secs = summary.window.as_secs_f64().max(1.0);
+        let over = |count: u64, limit: Option<f64>| limit.is_some_and(|l| count as f64 / secs > l);
+        let kind = if self.max_depth.is_some_and(|d| summary.max_depth > d)
+            || over(summary.docs, self.docs_per_sec)
+        {
+            AnomalyKind::DeepTraversal
+        } else if over(summary.entries, self.entries_per_sec) {
+            AnomalyKind::Scraping
+        } else if self
+            .invalid_queries
+            .is_some_and(|q| summary.invalid_queries > q)
+        {
+            AnomalyKind::InvalidQueries
+        } else {
+            return None;
+        };
+        Some(Anomaly {
+            kind,
+            action: self.action,
+        })
+    }
+}
@@ -0,0 +1,177 @@
+//! Cheaply comparing what two nodes hold of a document tree.
+//!
+//! Syncing a document tree with a peer means working out which documents
+//! the peer has that this node doesn't. Asking for each hash in turn costs a
+//! round trip per document, most of which are wasted on documents both sides
+//! already hold. Instead, each node can sum up what it holds under a root as
+//! an [`AvailabilitySummary`]: a Bloom filter over the hashes of every document
+//! it has in the tree, which is a small fraction of the size of the hashes
+//! themselves.
+//!
+//! [`Group::summary`][crate::group::Group::summary] builds the summary for
+//! this node, and
+//! [`Group::exchange_summary`][crate::group::Group::exchange_summary] sends it
+//! to a group member, which answers with its own summary and the hashes it
+//! holds that weren't in ours. From there, each side knows what to fetch from
+//! the other, and what the other is missing.
+//!
+//! Bloom filters have false positives but no false negatives. A document
+//! reported as missing is definitely missing, but a few missing documents
+//! will be mistaken for ones already held, at about the rate the summary was
+//! built with. Those usually turn up anyway when the documents linking to
+//! them are fetched and followed.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The false positive rate summaries are built with by default.
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// A Bloom filter over document hashes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Bytes,
+    hashes: u8,
+}
+
+impl BloomFilter {
+    /// Build a filter over the given hashes, sized to give roughly the given
+    /// false positive rate.
+    pub fn new<'a>(hashes: impl ExactSizeIterator<Item = &'a Hash>, false_positive_rate: f64) -> Self {
+        let items = hashes.len().max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let size = (-(items * rate.ln()) / (ln2 * ln2)).ceil().max(8.0);
+        let count = ((size / items) * ln2).round().clamp(1.0, 32.0) as u8;
+        let mut bits = vec![0u8; (size as usize).div_ceil(8)];
+        for hash in hashes {
+            for pos in positions(hash, count, bits.len()) {
+                bits[pos / 8] |= 1 << (pos % 8);
+            }
+        }
+        Self {
+            bits: bits.into(),
+            hashes: count,
+        }
+    }
+
+    /// The size of the filter, in bytes.
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Check if the filter has no space in it at all. Filters made with
+    /// [`BloomFilter::new`] never do; an empty filter is taken to contain
+    /// everything.
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// Check if a hash might be in the filter. Returns false only if it
+    /// definitely isn't.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        positions(hash, self.hashes, self.bits.len())
+            .all(|pos| self.bits[pos / 8] & (1 << (pos % 8)) != 0)
+    }
+}
+
+/// The bit positions a hash sets in a filter of `bytes` bytes.
+fn positions(hash: &Hash, count: u8, bytes: usize) -> impl Iterator<Item = usize> {
+    // Hashes are already uniformly random, so two words of the digest are
+    // enough to derive every position by double hashing.
+    let digest = hash.digest();
+    let word = |start: usize| {
+        let mut buf = [0u8; 8];
+        let end = digest.len().min(start + 8);
+        if start < end {
+            buf[..end - start].copy_from_slice(&digest[start..end]);
+        }
+        u64::from_le_bytes(buf)
+    };
+    let (h1, h2) = (word(0), word(8) | 1);
+    let size = (bytes * 8) as u64;
+    let count = if size == 0 { 0 } else { count as u64 };
+    (0..count).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+}
+
+/// A summary of which documents a node holds in the tree under a root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AvailabilitySummary {
+    /// The root of the document tree.
+    pub root: Hash,
+    /// How many documents the node holds in the tree.
+    pub docs: u64,
+    /// Whether the node holds the whole tree, with nothing linked to left
+    /// unfetched.
+    pub complete: bool,
+    /// The hashes of every document the node holds in the tree.
+    pub filter: BloomFilter,
+}
+
+impl AvailabilitySummary {
+    /// Summarize the documents held under a root, building the filter at the
+    /// given false positive rate.
+    pub fn new<'a>(
+        root: &Hash,
+        held: impl ExactSizeIterator<Item = &'a Hash>,
+        complete: bool,
+        false_positive_rate: f64,
+    ) -> Self {
+        Self {
+            root: root.clone(),
+            docs: held.len() as u64,
+            complete,
+            filter: BloomFilter::new(held, false_positive_rate),
+        }
+    }
+
+    /// Check if the summarized node might hold a document. Returns false only
+    /// if it definitely doesn't.
+    pub fn might_have(&self, hash: &Hash) -> bool {
+        self.filter.contains(hash)
+    }
+
+    /// Pick out the hashes the summarized node definitely doesn't hold.
+    pub fn missing<'a>(&self, hashes: impl IntoIterator<Item = &'a Hash>) -> Vec<Hash> {
+        hashes
+            .into_iter()
+            .filter(|hash| !self.might_have(hash))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A group member's answer to a summary exchange.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SummaryReply {
+    /// What the member holds of the tree.
+    pub summary: AvailabilitySummary,
+    /// The hashes of documents the member holds in the tree that weren't in
+    /// the summary sent to it.
+    pub missing: Vec<Hash>,
+}
+
+/// Failure to exchange summaries with a group member.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum SummaryError {
+    /// The member couldn't be reached.
+    #[error("Group member couldn't be reached")]
+    Unreachable,
+    /// The member holds nothing of the tree.
+    #[error("Group member doesn't hold document tree {0}")]
+    NotHeld(Hash),
+    /// The member refused the exchange, giving a reason.
+    #[error("Group member refused the exchange: {0}")]
+    Refused(String),
+}
+
+/// An outgoing summary exchange, waiting on the other node's answer.
+#[async_trait]
+pub trait SummaryExchange: Send + Sync {
+    /// Wait for the other node to answer with its own summary.
+    async fn complete(self: Box<Self>) -> Result<SummaryReply, SummaryError>;
+}
@@ -0,0 +1,55 @@
+//! Write backpressure, signalled before a transaction is staged.
+//!
+//! A database that is falling behind on writes can only say so at commit time,
+//! by failing or by being slow, and by then a bulk producer has already done
+//! the work of staging the transaction. Instead, producers can ask for a
+//! [`CommitPermit`] with [`Db::commit_permit`][crate::Db::commit_permit]
+//! before staging each transaction, and wait for one to be granted. The permit
+//! is attached to the transaction with
+//! [`Transaction::with_permit`][crate::transaction::Transaction::with_permit]
+//! and held until the commit completes, so the database can bound how many
+//! transactions are in flight at once.
+//!
+//! Permits are advisory: a transaction without one can still be committed.
+//! Databases that don't apply backpressure can grant [`Unlimited`] permits
+//! immediately.
+
+use async_trait::async_trait;
+
+/// A granted slot for one transaction. Dropping the permit gives the slot
+/// back.
+pub trait CommitPermit: Send + Sync {
+    /// The most encoded bytes the database would like the transaction to
+    /// hold, if it has a preference. Larger transactions are still accepted.
+    fn max_bytes(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A pending request for a [`CommitPermit`].
+#[async_trait]
+pub trait PermitRequest: Send + Sync {
+    /// Wait until the database grants a permit.
+    async fn acquire(self: Box<Self>) -> Box<dyn CommitPermit>;
+
+    /// Get a permit only if one can be granted right away. Otherwise, returns
+    /// the request so it can be waited on later.
+    fn try_acquire(self: Box<Self>) -> Result<Box<dyn CommitPermit>, Box<dyn PermitRequest>>;
+}
+
+/// A permit, and request for one, that is always granted immediately.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Unlimited;
+
+impl CommitPermit for Unlimited {}
+
+#[async_trait]
+impl PermitRequest for Unlimited {
+    async fn acquire(self: Box<Self>) -> Box<dyn CommitPermit> {
+        self
+    }
+
+    fn try_acquire(self: Box<Self>) -> Result<Box<dyn CommitPermit>, Box<dyn PermitRequest>> {
+        Ok(self)
+    }
+}
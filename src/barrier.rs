@@ -0,0 +1,67 @@
+//! Waiting for an update to reach other group members.
+//!
+//! Committing an update only changes the local database; other members pick
+//! it up in their own time, as they sync. A publisher that needs to know the
+//! update went somewhere before moving on, like a device about to go offline,
+//! can open a barrier on the updated tree with
+//! [`Group::barrier`][crate::group::Group::barrier].
+//!
+//! The barrier takes this node's [fingerprint][crate::fingerprint] of the tree
+//! at the moment it's opened, and repeatedly asks group members for theirs. A
+//! member confirms once it holds at least everything this node held: every
+//! document and every entry. A fingerprint that
+//! [matches][TreeFingerprint::matches] shows that directly. A member whose
+//! fingerprint differs may be behind, or may have already synced past the
+//! target with changes committed elsewhere, so it's also asked for an
+//! [availability summary][crate::availability] covering the documents and
+//! entries it holds, and confirms if that summary might hold everything this
+//! node held. Summaries are Bloom filters, so a member missing a few of them
+//! can be mistaken for one that holds them all, at roughly the summary's false
+//! positive rate. Members that are behind are asked again until they catch up
+//! or the barrier times out. Changes committed locally after the barrier was
+//! opened don't move the target; open a new barrier to wait on those changes
+//! too.
+
+use async_trait::async_trait;
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{fingerprint::TreeFingerprint, NodeInfo};
+
+/// The members that confirmed holding a tree.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BarrierReport {
+    /// This node's fingerprint of the tree when the barrier was opened, which
+    /// members were checked against.
+    pub fingerprint: TreeFingerprint,
+    /// The members that confirmed, in the order they did.
+    pub confirmed: Vec<NodeInfo>,
+}
+
+/// Failure of a barrier to get enough members to confirm.
+#[derive(Clone, Debug, Error, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum BarrierError {
+    /// This node doesn't hold the tree, so there's nothing to confirm.
+    #[error("Document tree {0} isn't held by this node")]
+    NotHeld(Hash),
+    /// Too few members confirmed before the timeout.
+    #[error("Only {} of {required} group members confirmed before the timeout", confirmed.len())]
+    TimedOut {
+        /// The members that did confirm.
+        confirmed: Vec<NodeInfo>,
+        /// How many were needed.
+        required: usize,
+    },
+    /// The group can't run barriers, giving a reason.
+    #[error("Barrier refused: {0}")]
+    Refused(String),
+}
+
+/// An open barrier, waiting on group members to confirm.
+#[async_trait]
+pub trait Barrier: Send + Sync {
+    /// Wait until enough members have confirmed, or the barrier times out.
+    async fn complete(self: Box<Self>) -> Result<BarrierReport, BarrierError>;
+}
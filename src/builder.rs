@@ -0,0 +1,207 @@
+//! Incremental construction of large documents.
+//!
+//! Building a document normally means assembling a complete Rust value, then
+//! serializing it into a [`NewDocument`], then validating and encoding it for
+//! the database - with every stage resident at once. A [`DocBuilder`] instead
+//! collects a document's top-level fields one at a time, reading byte fields
+//! straight from an [`AsyncRead`] into their final staging buffer, and
+//! releases each intermediate form as soon as the next one has been made.
+//!
+//! Byte streams are checked against fog-pack's maximum document size as they
+//! are read, so an oversized stream fails early instead of after it has been
+//! fully buffered. Note that this limit is [`MAX_DOC_SIZE`], so data larger
+//! than that must still be split across multiple documents.
+
+use std::{cell::RefCell, collections::BTreeMap};
+
+use fog_pack::{
+    document::NewDocument,
+    error::Error as FogError,
+    schema::{NoSchema, Schema},
+    types::*,
+    MAX_DOC_SIZE,
+};
+use futures::io::{AsyncRead, AsyncReadExt};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::transaction::EncodedDoc;
+
+/// Size of each read when pulling a byte stream into a document.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Failure while building a document.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// The document would be larger than fog-pack allows.
+    #[error("Document too large (max {max}, at least {actual})")]
+    TooLarge { max: usize, actual: usize },
+    /// Reading a byte stream failed.
+    #[error("Failed to read byte stream: {0}")]
+    Io(String),
+    /// The document couldn't be encoded, or failed validation.
+    #[error("Document failed to encode or validate")]
+    Fog(#[from] FogError),
+}
+
+/// A single staged field.
+enum Field {
+    Value(Value),
+    Bin(Vec<u8>),
+}
+
+impl Serialize for Field {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Field::Value(v) => v.serialize(serializer),
+            Field::Bin(b) => serializer.serialize_bytes(b),
+        }
+    }
+}
+
+/// The staged fields, handed over one at a time as the document is encoded,
+/// so each is released as soon as it has been written.
+struct Draining(RefCell<BTreeMap<String, Field>>);
+
+impl Serialize for Draining {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut fields = self.0.borrow_mut();
+        let mut map = serializer.serialize_map(Some(fields.len()))?;
+        while let Some((key, field)) = fields.pop_first() {
+            map.serialize_entry(&key, &field)?;
+        }
+        map.end()
+    }
+}
+
+/// Builds a document whose top level is a map, one field at a time.
+pub struct DocBuilder {
+    schema: Option<Hash>,
+    fields: BTreeMap<String, Field>,
+    size: usize,
+    compression: Option<Option<u8>>,
+}
+
+impl DocBuilder {
+    /// Start building a document, optionally adhering to a schema.
+    pub fn new(schema: Option<&Hash>) -> Self {
+        Self {
+            schema: schema.cloned(),
+            fields: BTreeMap::new(),
+            size: 0,
+            compression: None,
+        }
+    }
+
+    /// Override the compression setting for the document, as with
+    /// [`NewDocument::compression`].
+    pub fn compression(&mut self, setting: Option<u8>) {
+        self.compression = Some(setting);
+    }
+
+    /// The number of bytes staged in byte fields so far. This is a lower bound
+    /// on the encoded document's size.
+    pub fn staged_bytes(&self) -> usize {
+        self.size
+    }
+
+    /// Set a field to a value, replacing any previous value for it.
+    pub fn field(&mut self, key: impl Into<String>, value: impl Into<Value>) {
+        self.insert(key.into(), Field::Value(value.into()));
+    }
+
+    /// Set a field to a byte buffer, replacing any previous value for it.
+    pub fn bytes(&mut self, key: impl Into<String>, data: Vec<u8>) -> Result<(), BuildError> {
+        let key = key.into();
+        self.remove(&key);
+        self.check(data.len())?;
+        self.insert(key, Field::Bin(data));
+        Ok(())
+    }
+
+    /// Set a field to the contents of a byte stream, replacing any previous
+    /// value for it. If the stream's length is known, it should be provided as
+    /// `size_hint` so the staging buffer is allocated only once. Returns the
+    /// number of bytes read.
+    pub async fn read_bytes<R: AsyncRead + Unpin>(
+        &mut self,
+        key: impl Into<String>,
+        mut reader: R,
+        size_hint: Option<usize>,
+    ) -> Result<usize, BuildError> {
+        let key = key.into();
+        self.remove(&key);
+        let mut buf = Vec::new();
+        if let Some(hint) = size_hint {
+            self.check(hint)?;
+            // Leave room for the final, empty read.
+            buf.reserve_exact(hint + READ_CHUNK);
+        }
+        loop {
+            let len = buf.len();
+            buf.resize(len + READ_CHUNK, 0);
+            let read = reader
+                .read(&mut buf[len..])
+                .await
+                .map_err(|e| BuildError::Io(e.to_string()))?;
+            buf.truncate(len + read);
+            if read == 0 {
+                break;
+            }
+            self.check(buf.len())?;
+        }
+        let read = buf.len();
+        self.insert(key, Field::Bin(buf));
+        Ok(read)
+    }
+
+    /// Finish building, producing a new document. Each staged field is
+    /// released as soon as it has been encoded into the document.
+    pub fn build(self) -> Result<NewDocument, BuildError> {
+        let fields = Draining(RefCell::new(self.fields));
+        let doc = NewDocument::new(self.schema.as_ref(), fields)?;
+        Ok(match self.compression {
+            Some(setting) => doc.compression(setting),
+            None => doc,
+        })
+    }
+
+    /// Finish building, then validate the document and encode it for the
+    /// database. `schema` must be the schema the builder was created with, or
+    /// `None` if it was created without one.
+    pub fn encode(self, schema: Option<&Schema>) -> Result<(EncodedDoc, Hash), BuildError> {
+        let doc = self.build()?;
+        let doc = match schema {
+            Some(schema) => schema.validate_new_doc(doc)?,
+            None => NoSchema::validate_new_doc(doc)?,
+        };
+        Ok(EncodedDoc::from_doc(schema, doc))
+    }
+
+    /// Check that `len` more bytes would fit in the document.
+    fn check(&self, len: usize) -> Result<(), BuildError> {
+        let actual = self.size.saturating_add(len);
+        if actual > MAX_DOC_SIZE {
+            return Err(BuildError::TooLarge {
+                max: MAX_DOC_SIZE,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, key: String, field: Field) {
+        self.remove(&key);
+        if let Field::Bin(b) = &field {
+            self.size += b.len();
+        }
+        self.fields.insert(key, field);
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(Field::Bin(b)) = self.fields.remove(key) {
+            self.size -= b.len();
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Query result caching.
+//!
+//! User interfaces tend to re-run the exact same query over and over, and each
+//! run hits both the local database and any connected remote nodes. A
+//! [`QueryCache`] sits in front of [`Cursor::query`] and shares a single live
+//! query between every consumer asking the same question of the same document.
+//! Each consumer gets its own [`CursorQuery`] that first replays every update
+//! seen so far, then continues with updates as they arrive. The live query is
+//! made directly on the database with [`Db::query`], while each consumer keeps
+//! its own cursor to back out to. Updates are kept while the query is cached,
+//! so new consumers can replay them. Once it leaves the cache, each update is
+//! dropped as soon as every remaining consumer has read it.
+//!
+//! Cached queries are dropped once they are older than the cache's
+//! time-to-live, or once a commit changes the entries under the queried key:
+//! each cached query [watches][Db::entry_watch] the key it was made on. They
+//! can also be [invalidated][QueryCache::invalidate] by hand.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use fog_pack::{document::Document, entry::Entry, types::*};
+use futures::lock::Mutex as AsyncMutex;
+
+use crate::cursor::{
+    CountEstimate, Cursor, CursorQuery, DbQuery, ForkCursor, ForkSpawner,
+    MergeStrategy, Provenance, QueryResult, QueryUpdate, Refresh, RelayHop, TraceId, UsefulReport,
+    Usefulness,
+};
+use crate::{
+    changes::EntryWatch, complexity::QueryRejected, forward::ForwardStopped, Db, NodeInfo,
+};
+
+/// Cached queries, keyed by document and then by query fingerprint.
+type SlotMap = HashMap<Hash, HashMap<Hash, Arc<Slot>>>;
+
+/// A cache of live queries, shareable across consumers. Cloning the cache is
+/// cheap, and clones share the same cached queries.
+#[derive(Clone)]
+pub struct QueryCache {
+    db: Arc<dyn Db>,
+    ttl: Duration,
+    slots: Arc<Mutex<SlotMap>>,
+}
+
+impl QueryCache {
+    /// Create a new cache of queries on a database. Cached queries are re-run
+    /// once they are older than `ttl`.
+    pub fn new(db: Arc<dyn Db>, ttl: Duration) -> Self {
+        Self {
+            db,
+            ttl,
+            slots: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Make a query on the document the cursor is over, reusing a cached query
+    /// if there is a fresh one available. The returned query can be backed out
+    /// of to get the original cursor back.
+    ///
+    /// If the query can't be fingerprinted, or the queried key can't be
+    /// watched for changes, the query is still made, but isn't cached.
+    pub async fn query(&self, cursor: Box<dyn Cursor>, query: DbQuery) -> Box<dyn CursorQuery> {
+        let doc = cursor.current().hash().to_owned();
+        let key = query.fingerprint(&doc).ok();
+        if let Some(reader) = key.as_ref().and_then(|key| self.join(&doc, key)) {
+            return Box::new(CachedQuery::new(cursor, reader));
+        }
+        let watch = match key {
+            Some(_) => self.db.entry_watch(&doc, query.query.key()).await.ok(),
+            None => None,
+        };
+        let key = key.filter(|_| watch.is_some());
+        let slot = Arc::new(Slot {
+            created: Instant::now(),
+            inner: self.db.query(&doc, query),
+            watch,
+            pull: AsyncMutex::new(()),
+            log: Mutex::new(Log::new(key.is_some())),
+        });
+        let reader = Reader::join(&slot);
+        if let Some(key) = key {
+            let old = self
+                .slots
+                .lock()
+                .unwrap()
+                .entry(doc)
+                .or_default()
+                .insert(key, slot);
+            if let Some(old) = old {
+                old.close();
+            }
+        }
+        Box::new(CachedQuery::new(cursor, reader))
+    }
+
+    /// Drop all cached queries made against a document.
+    pub fn invalidate(&self, doc: &Hash) {
+        let Some(queries) = self.slots.lock().unwrap().remove(doc) else {
+            return;
+        };
+        for slot in queries.into_values() {
+            slot.close();
+        }
+    }
+
+    /// Drop all cached queries.
+    pub fn clear(&self) {
+        let mut slots = self.slots.lock().unwrap();
+        for slot in slots.drain().flat_map(|(_, queries)| queries.into_values()) {
+            slot.close();
+        }
+    }
+
+    /// Drop all cached queries that have outlived the cache's time-to-live,
+    /// or whose entries have changed.
+    pub fn prune(&self) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|_, queries| {
+            queries.retain(|_, slot| {
+                let fresh = slot.is_fresh(self.ttl);
+                if !fresh {
+                    slot.close();
+                }
+                fresh
+            });
+            !queries.is_empty()
+        });
+    }
+
+    /// Start reading a fresh cached query, if there is one. Readers join while
+    /// the cache is locked, so the slot can't be closed and trimmed first.
+    fn join(&self, doc: &Hash, key: &Hash) -> Option<Reader> {
+        let mut slots = self.slots.lock().unwrap();
+        let queries = slots.get_mut(doc)?;
+        let slot = queries.get(key)?;
+        if slot.is_fresh(self.ttl) {
+            return Some(Reader::join(slot));
+        }
+        if let Some(slot) = queries.remove(key) {
+            slot.close();
+        }
+        None
+    }
+}
+
+/// A single live query, and the updates it has produced that a consumer may
+/// still need.
+struct Slot {
+    created: Instant,
+    inner: Box<dyn CursorQuery>,
+    watch: Option<Box<dyn EntryWatch>>,
+    /// Held while pulling from the live query, so only one consumer does at
+    /// a time.
+    pull: AsyncMutex<()>,
+    log: Mutex<Log>,
+}
+
+impl Slot {
+    /// Check if the query is younger than `ttl`, and no commit has changed
+    /// the entries under its key. A watch that fails counts as a change.
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        self.created.elapsed() < ttl
+            && self
+                .watch
+                .as_ref()
+                .is_some_and(|w| matches!(w.try_next(), Ok(None)))
+    }
+
+    /// Stop new consumers from joining, once the slot has left the cache, so
+    /// updates every current consumer has read can be dropped.
+    fn close(&self) {
+        let mut log = self.log.lock().unwrap();
+        log.open = false;
+        log.trim();
+    }
+
+    /// Get update number `idx`, waiting on the live query if it hasn't arrived
+    /// yet.
+    async fn get(&self, idx: usize) -> QueryUpdate {
+        loop {
+            if let Some(update) = self.log.lock().unwrap().get(idx) {
+                return update;
+            }
+            let _pull = self.pull.lock().await;
+            // Another consumer may have pulled the update while we waited.
+            if let Some(update) = self.log.lock().unwrap().get(idx) {
+                return update;
+            }
+            let update = self.inner.next().await;
+            self.log.lock().unwrap().push(update);
+        }
+    }
+
+    /// Get update number `idx`, only checking the live query once, and not
+    /// at all if another consumer is already waiting on it.
+    fn try_get(&self, idx: usize) -> Option<QueryUpdate> {
+        if let Some(update) = self.log.lock().unwrap().get(idx) {
+            return Some(update);
+        }
+        let _pull = self.pull.try_lock()?;
+        let mut log = self.log.lock().unwrap();
+        if log.len() <= idx {
+            let update = self.inner.try_next()?;
+            log.push(update);
+        }
+        log.get(idx)
+    }
+}
+
+/// The updates from a live query that haven't yet been read by every
+/// consumer, and how far each consumer has read.
+struct Log {
+    /// The number of the first update in `updates`.
+    start: usize,
+    updates: VecDeque<CachedUpdate>,
+    /// How many updates each consumer has read, by consumer.
+    readers: HashMap<u64, usize>,
+    next_reader: u64,
+    /// Whether new consumers can still join. They start from the first
+    /// update, so nothing is dropped until they can't.
+    open: bool,
+}
+
+impl Log {
+    fn new(open: bool) -> Self {
+        Self {
+            start: 0,
+            updates: VecDeque::new(),
+            readers: HashMap::new(),
+            next_reader: 0,
+            open,
+        }
+    }
+
+    /// The number of updates seen so far, including dropped ones.
+    fn len(&self) -> usize {
+        self.start + self.updates.len()
+    }
+
+    fn get(&self, idx: usize) -> Option<QueryUpdate> {
+        let update = self.updates.get(idx.checked_sub(self.start)?)?;
+        Some(update.to_update())
+    }
+
+    fn push(&mut self, update: QueryUpdate) {
+        self.updates.push_back(CachedUpdate::from(update));
+        self.trim();
+    }
+
+    /// Drop every update the slowest consumer has read, unless new consumers
+    /// can still join.
+    fn trim(&mut self) {
+        if self.open {
+            return;
+        }
+        let read = self.readers.values().min().copied().unwrap_or(self.len());
+        while self.start < read {
+            self.updates.pop_front();
+            self.start += 1;
+        }
+    }
+}
+
+/// A consumer's place in a slot's log, released when the consumer is
+/// dropped.
+struct Reader {
+    slot: Arc<Slot>,
+    id: u64,
+}
+
+impl Reader {
+    fn join(slot: &Arc<Slot>) -> Self {
+        let mut log = slot.log.lock().unwrap();
+        let id = log.next_reader;
+        log.next_reader += 1;
+        log.readers.insert(id, 0);
+        drop(log);
+        Self {
+            slot: slot.clone(),
+            id,
+        }
+    }
+
+    /// Record that the consumer has read `read` updates.
+    fn advance(&self, read: usize) {
+        let mut log = self.slot.log.lock().unwrap();
+        log.readers.insert(self.id, read);
+        log.trim();
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        let mut log = self.slot.log.lock().unwrap();
+        log.readers.remove(&self.id);
+        log.trim();
+    }
+}
+
+/// A clonable copy of a [`QueryUpdate`].
+enum CachedUpdate {
+    Result(Box<CachedResult>),
+    NewConnection(NodeInfo),
+    LostConnection(NodeInfo),
+    Count(CountEstimate),
+    Rejected(QueryRejected),
+    Refreshed(Refresh),
+    NotForwarded(ForwardStopped),
+}
+
+struct CachedResult {
+    entry: Entry,
+    docs: Vec<Arc<Document>>,
+    source: NodeInfo,
+    expires: Option<Timestamp>,
+    deleted: Option<Timestamp>,
+    stale: bool,
+    provenance: Option<Provenance>,
+    relays: Vec<RelayHop>,
+    useful: Arc<SharedReport>,
+    fork_spawner: SharedSpawner,
+}
+
+impl From<QueryUpdate> for CachedUpdate {
+    fn from(value: QueryUpdate) -> Self {
+        match value {
+            QueryUpdate::Result(res) => {
+                let res = *res;
+                CachedUpdate::Result(Box::new(CachedResult {
+                    entry: res.entry,
+                    docs: res.docs,
+                    source: res.source,
+                    expires: res.expires,
+                    deleted: res.deleted,
+                    stale: res.stale,
+                    provenance: res.provenance,
+                    relays: res.relays,
+                    useful: Arc::new(SharedReport(Mutex::new(Some(res.useful)))),
+                    fork_spawner: SharedSpawner(Arc::from(res.fork_spawner)),
+                }))
+            }
+            QueryUpdate::NewConnection(node) => CachedUpdate::NewConnection(node),
+            QueryUpdate::LostConnection(node) => CachedUpdate::LostConnection(node),
+            QueryUpdate::Count(count) => CachedUpdate::Count(count),
+            QueryUpdate::Rejected(rej) => CachedUpdate::Rejected(rej),
+            QueryUpdate::Refreshed(refresh) => CachedUpdate::Refreshed(refresh),
+            QueryUpdate::NotForwarded(stop) => CachedUpdate::NotForwarded(stop),
+        }
+    }
+}
+
+impl CachedUpdate {
+    fn to_update(&self) -> QueryUpdate {
+        match self {
+            CachedUpdate::Result(res) => QueryUpdate::Result(Box::new(QueryResult {
+                entry: res.entry.clone(),
+                docs: res.docs.clone(),
+                source: res.source.clone(),
+                expires: res.expires,
+                deleted: res.deleted,
+                stale: res.stale,
+                provenance: res.provenance.clone(),
+                relays: res.relays.clone(),
+                useful: Box::new(res.useful.clone()),
+                fork_spawner: Box::new(res.fork_spawner.clone()),
+            })),
+            CachedUpdate::NewConnection(node) => QueryUpdate::NewConnection(node.clone()),
+            CachedUpdate::LostConnection(node) => QueryUpdate::LostConnection(node.clone()),
+            CachedUpdate::Count(count) => QueryUpdate::Count(count.clone()),
+            CachedUpdate::Rejected(rej) => QueryUpdate::Rejected(rej.clone()),
+            CachedUpdate::Refreshed(refresh) => QueryUpdate::Refreshed(refresh.clone()),
+            CachedUpdate::NotForwarded(stop) => QueryUpdate::NotForwarded(stop.clone()),
+        }
+    }
+}
+
+/// A usefulness report shared between all consumers of a cached result. Only
+/// the first report is passed on.
+struct SharedReport(Mutex<Option<Box<dyn UsefulReport>>>);
+
+impl UsefulReport for Arc<SharedReport> {
+    fn report(self: Box<Self>, useful: Usefulness) {
+        let report = self.0.lock().unwrap().take();
+        if let Some(report) = report {
+            report.report(useful);
+        }
+    }
+}
+
+/// A fork spawner shared between all consumers of a cached result.
+#[derive(Clone)]
+struct SharedSpawner(Arc<dyn ForkSpawner>);
+
+impl ForkSpawner for SharedSpawner {
+    fn fork(&self) -> Box<dyn ForkCursor> {
+        self.0.fork()
+    }
+}
+
+/// A consumer's view of a cached query.
+struct CachedQuery {
+    cursor: Box<dyn Cursor>,
+    reader: Reader,
+    /// How many updates have been read. Held across each call to `next`, so
+    /// concurrent calls get successive updates rather than the same one.
+    idx: AsyncMutex<usize>,
+}
+
+impl CachedQuery {
+    fn new(cursor: Box<dyn Cursor>, reader: Reader) -> Self {
+        Self {
+            cursor,
+            reader,
+            idx: AsyncMutex::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl CursorQuery for CachedQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        self.cursor
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        // Only move on once the update is in hand, so a dropped call doesn't
+        // skip it.
+        let mut idx = self.idx.lock().await;
+        let update = self.reader.slot.get(*idx).await;
+        *idx += 1;
+        self.reader.advance(*idx);
+        update
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        let mut idx = self.idx.try_lock()?;
+        let update = self.reader.slot.try_get(*idx)?;
+        *idx += 1;
+        self.reader.advance(*idx);
+        Some(update)
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        self.reader.slot.inner.merge_strategy()
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.reader.slot.inner.trace_id()
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.cursor.trace_id()
+    }
+}
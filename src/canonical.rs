@@ -0,0 +1,344 @@
+//! Canonical wire encoding, with golden fixtures.
+//!
+//! The serde derives on this crate's types say what gets serialized, but not
+//! exactly which bytes come out: that depends on the serializer, its settings,
+//! and how the derives happen to be written in a given crate version. For
+//! independent implementations to store and exchange these types with each
+//! other, the encoding has to be pinned down. [`encode`] and [`decode`] give
+//! the canonical encoding for every type implementing [`WireType`]:
+//!
+//! - The value is wrapped in a 3-element array of `[version, type name,
+//!   value]`, where the version is [`WIRE_VERSION`] and the type name is
+//!   [`WireType::NAME`].
+//! - The array is encoded as an uncompressed fog-pack document with no
+//!   schema. Compression is left off so the bytes don't depend on the
+//!   compressor's version.
+//!
+//! Each wire version comes with a set of golden [`fixtures`]: the canonical
+//! encoding of a fixed sample value of every type, checked into the
+//! repository under `fixtures/wire/v<version>/`. Implementations in other
+//! languages can decode and re-encode the fixture files to check they agree
+//! with this crate, and [`check_fixtures`] does the same for this crate. A
+//! change that makes a fixture fail to check is a change to the wire format,
+//! and needs a new wire version.
+
+use bytes::Bytes;
+use fog_pack::{
+    document::NewDocument, error::Error as FogError, query::NewQuery, schema::NoSchema, types::*,
+    validator::StrValidator,
+};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Serialize,
+};
+use std::{num::NonZeroU32, num::NonZeroU8, time::Duration};
+use thiserror::Error;
+
+use crate::{
+    cert::{Cert, CertReplace, EntryPolicy, Policy, PolicyChain, PolicyLink},
+    cursor::{DbQuery, Index, MergeStrategy, Provenance, SourceFilter},
+    pinning::PinGrant,
+    NodeAddr,
+};
+
+/// The current version of the canonical wire encoding.
+pub const WIRE_VERSION: u16 = 1;
+
+/// A type with a canonical wire encoding.
+pub trait WireType: Serialize + DeserializeOwned {
+    /// The name identifying this type on the wire. Names are never reused for
+    /// a different type.
+    const NAME: &'static str;
+}
+
+impl WireType for Policy {
+    const NAME: &'static str = "Policy";
+}
+
+impl WireType for EntryPolicy {
+    const NAME: &'static str = "EntryPolicy";
+}
+
+impl WireType for Cert {
+    const NAME: &'static str = "Cert";
+}
+
+impl WireType for DbQuery {
+    const NAME: &'static str = "DbQuery";
+}
+
+impl WireType for Index {
+    const NAME: &'static str = "Index";
+}
+
+impl WireType for MergeStrategy {
+    const NAME: &'static str = "MergeStrategy";
+}
+
+impl WireType for SourceFilter {
+    const NAME: &'static str = "SourceFilter";
+}
+
+impl WireType for PinGrant {
+    const NAME: &'static str = "PinGrant";
+}
+
+impl WireType for Provenance {
+    const NAME: &'static str = "Provenance";
+}
+
+/// Failure to decode a canonically encoded value.
+#[derive(Debug, Error)]
+pub enum WireFormatError {
+    /// The data wasn't a valid encoding.
+    #[error("Invalid encoding")]
+    Fog(#[from] FogError),
+    /// The data was encoded with an unsupported wire version.
+    #[error("Unsupported wire version {0}")]
+    Version(u16),
+    /// The data held a different type than the one being decoded.
+    #[error("Expected a {expected}, but got a {actual}")]
+    Type {
+        expected: &'static str,
+        actual: String,
+    },
+}
+
+/// Encode a value in its canonical form.
+pub fn encode<T: WireType>(value: &T) -> Result<Bytes, FogError> {
+    let doc = NewDocument::new(None, (WIRE_VERSION, T::NAME, value))?.compression(None);
+    let (_, data) = NoSchema::encode_doc(NoSchema::validate_new_doc(doc)?)?;
+    Ok(Bytes::from(data))
+}
+
+/// Decode a value from its canonical form.
+pub fn decode<T: WireType>(data: &[u8]) -> Result<T, WireFormatError> {
+    let doc = NoSchema::decode_doc(data.to_vec())?;
+    // Check the header before the value, so that a value of the wrong type is
+    // reported as such rather than as a bad encoding.
+    let (version, name, _): (u16, String, IgnoredAny) = doc.deserialize()?;
+    if version != WIRE_VERSION {
+        return Err(WireFormatError::Version(version));
+    }
+    if name != T::NAME {
+        return Err(WireFormatError::Type {
+            expected: T::NAME,
+            actual: name,
+        });
+    }
+    let (_, _, value): (u16, String, T) = doc.deserialize()?;
+    Ok(value)
+}
+
+/// A golden fixture: the canonical encoding of a fixed sample value.
+pub struct Fixture {
+    /// The name of the type encoded.
+    pub name: &'static str,
+    /// The wire version the fixture was encoded with.
+    pub version: u16,
+    /// The fixture's file in the repository, relative to the crate root.
+    pub path: &'static str,
+    /// The fixture's encoded bytes.
+    pub data: &'static [u8],
+    sample: fn() -> Result<Bytes, FogError>,
+    round_trip: fn(&[u8]) -> Result<Bytes, WireFormatError>,
+}
+
+/// A fixture that didn't match this crate's encoding.
+#[derive(Debug, Error)]
+pub enum FixtureMismatch {
+    /// The fixture couldn't be decoded.
+    #[error("Fixture {path} couldn't be decoded")]
+    Decode {
+        path: &'static str,
+        #[source]
+        err: WireFormatError,
+    },
+    /// The fixture's sample value couldn't be encoded.
+    #[error("Sample value for fixture {path} couldn't be encoded")]
+    Encode {
+        path: &'static str,
+        #[source]
+        err: FogError,
+    },
+    /// The sample value encoded to different bytes than the fixture.
+    #[error("Sample value doesn't encode to fixture {path}")]
+    Sample { path: &'static str },
+    /// Decoding and re-encoding the fixture gave different bytes.
+    #[error("Fixture {path} doesn't survive a round trip")]
+    RoundTrip { path: &'static str },
+}
+
+impl Fixture {
+    /// Encode the fixture's sample value. This is what the fixture file holds,
+    /// and can be used to regenerate it when adding a new wire version.
+    pub fn encode_sample(&self) -> Result<Bytes, FogError> {
+        (self.sample)()
+    }
+
+    /// Check that the sample value encodes to the fixture, and that the
+    /// fixture decodes and re-encodes to the same bytes.
+    pub fn check(&self) -> Result<(), FixtureMismatch> {
+        let path = self.path;
+        let sample = self
+            .encode_sample()
+            .map_err(|err| FixtureMismatch::Encode { path, err })?;
+        if sample != self.data {
+            return Err(FixtureMismatch::Sample { path });
+        }
+        let round_trip =
+            (self.round_trip)(self.data).map_err(|err| FixtureMismatch::Decode { path, err })?;
+        if round_trip != self.data {
+            return Err(FixtureMismatch::RoundTrip { path });
+        }
+        Ok(())
+    }
+}
+
+fn round_trip<T: WireType>(data: &[u8]) -> Result<Bytes, WireFormatError> {
+    Ok(encode(&decode::<T>(data)?)?)
+}
+
+macro_rules! fixture {
+    ($ty:ty, $file:literal, $sample:expr) => {
+        Fixture {
+            name: <$ty as WireType>::NAME,
+            version: 1,
+            path: concat!("fixtures/wire/v1/", $file),
+            data: include_bytes!(concat!("../fixtures/wire/v1/", $file)),
+            sample: || encode::<$ty>(&$sample),
+            round_trip: round_trip::<$ty>,
+        }
+    };
+}
+
+static FIXTURES: &[Fixture] = &[
+    fixture!(Policy, "policy.fog", sample_policy()),
+    fixture!(
+        EntryPolicy,
+        "entry_policy_inline.fog",
+        EntryPolicy::Inline(sample_policy())
+    ),
+    fixture!(
+        EntryPolicy,
+        "entry_policy_template.fog",
+        EntryPolicy::Template(Hash::new("policy template"))
+    ),
+    fixture!(Cert, "cert.fog", sample_cert()),
+    fixture!(DbQuery, "db_query.fog", sample_query()),
+    fixture!(Index, "index_map.fog", Index::Map("name".into())),
+    fixture!(Index, "index_array.fog", Index::Array(3)),
+    fixture!(
+        MergeStrategy,
+        "merge_ordered.fog",
+        MergeStrategy::Ordered {
+            window: Duration::from_millis(250)
+        }
+    ),
+    fixture!(SourceFilter, "source_filter.fog", sample_filter()),
+    fixture!(
+        PinGrant,
+        "pin_grant.fog",
+        PinGrant {
+            root: Hash::new("pinned root"),
+            expires: sample_time(1),
+        }
+    ),
+    fixture!(
+        Provenance,
+        "provenance.fog",
+        Provenance {
+            sent: sample_time(2),
+            signature: Bytes::from_static(&[1, 2, 3, 4]),
+        }
+    ),
+];
+
+/// Get the golden fixtures for the current wire version.
+pub fn fixtures() -> &'static [Fixture] {
+    FIXTURES
+}
+
+/// Check every fixture for the current wire version, returning all the ones
+/// that don't match.
+pub fn check_fixtures() -> Vec<FixtureMismatch> {
+    FIXTURES.iter().filter_map(|f| f.check().err()).collect()
+}
+
+/// The Ed25519 base point, which is a valid public key that anyone can
+/// reproduce.
+const SAMPLE_KEY: [u8; 33] = [
+    1, 0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66,
+];
+
+fn sample_id() -> Identity {
+    Identity::try_from(&SAMPLE_KEY[..]).expect("sample key should be valid")
+}
+
+fn sample_time(offset: i64) -> Timestamp {
+    Timestamp::from_sec(1_700_000_000 + offset)
+}
+
+fn sample_policy() -> Policy {
+    Policy {
+        context: Hash::new("policy context"),
+        roots: vec![sample_id()],
+        chains: vec![PolicyChain {
+            chain: vec![PolicyLink::new("member", "yes", NonZeroU8::new(2).unwrap())],
+        }],
+    }
+}
+
+fn sample_cert() -> Cert {
+    Cert {
+        subject: sample_id(),
+        context: Hash::new("policy context"),
+        key: "member".into(),
+        val: "yes".into(),
+        seq: 7,
+        start: sample_time(0),
+        end: sample_time(86_400),
+        valid: true,
+        revokes: Some(CertReplace {
+            revoke: Hash::new("old cert"),
+            replace_with: Hash::new("new cert"),
+        }),
+    }
+}
+
+fn sample_filter() -> SourceFilter {
+    SourceFilter {
+        only: vec![NodeAddr {
+            perm_id: sample_id(),
+            eph_id: sample_id(),
+        }],
+        exclude: Vec::new(),
+    }
+}
+
+fn sample_query() -> DbQuery {
+    DbQuery {
+        query: NewQuery::new("posts", StrValidator::new().build()),
+        rev_order: true,
+        ordering: Some(vec![Index::Map("time".into()), Index::Array(0)]),
+        min_ttl: Some(Duration::from_secs(60)),
+        signer_policy: Some(sample_policy()),
+        sample: NonZeroU32::new(10),
+        include_history: false,
+        merge: Some(MergeStrategy::RoundRobin),
+        sources: Some(sample_filter()),
+        revalidate: true,
+        forward: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn fixtures_match() {
+        let mismatches = super::check_fixtures();
+        assert!(mismatches.is_empty(), "Fixtures don't match: {mismatches:?}");
+    }
+}
@@ -0,0 +1,47 @@
+//! Feature detection for database backends.
+//!
+//! Not every backend supports every optional part of the [`Db`][crate::Db]
+//! interface. Rather than finding out by calling a method and getting back
+//! `None`, an error, or silently degraded behavior, libraries built on these
+//! traits can check [`Db::capabilities`][crate::Db::capabilities] up front and
+//! pick a fallback.
+
+use serde::{Deserialize, Serialize};
+
+/// The optional features a database backend supports. Every feature defaults
+/// to unsupported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DbCapabilities {
+    /// Cursors opened with [`CursorOpts::snapshot`][crate::cursor::CursorOpts::snapshot]
+    /// see a consistent snapshot. If unsupported, the option is ignored and
+    /// cursors see the database as it changes.
+    pub snapshots: bool,
+    /// The database keeps a history of commits, so
+    /// [`Db::changes_since`][crate::Db::changes_since] can stream changes from
+    /// before the feed was opened. If unsupported, only changes committed after
+    /// [`Db::current_seq`][crate::Db::current_seq] can be streamed.
+    pub change_feed: bool,
+    /// Query ordering by [`DbQuery::ordering`][crate::cursor::DbQuery::ordering]
+    /// is served from an index. If unsupported, ordered queries must sort every
+    /// matching entry before returning the first one.
+    pub indexes: bool,
+    /// [`Db::journal`][crate::Db::journal] returns the database's write-ahead
+    /// journal.
+    pub journal: bool,
+    /// [`Db::mixnet`][crate::Db::mixnet] returns a mixnet provider.
+    pub mixnet: bool,
+    /// Transactions can be prepared for a two-phase commit with
+    /// [`Transaction::prepare`][crate::transaction::Transaction::prepare].
+    pub two_phase_commit: bool,
+    /// Per-schema compression policies set with
+    /// [`Db::schema_set_compression`][crate::Db::schema_set_compression] are
+    /// applied. If unsupported, the schema's compression hints are always used.
+    pub compression_policies: bool,
+    /// Document reads are tracked, and reported by
+    /// [`Db::doc_info`][crate::Db::doc_info].
+    pub access_tracking: bool,
+    /// Documents are placed according to their
+    /// [`CacheTier`][crate::access::CacheTier]. If unsupported, tier hints are
+    /// ignored.
+    pub cache_tiers: bool,
+}
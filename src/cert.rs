@@ -34,6 +34,24 @@ pub struct Policy {
     pub chains: Vec<PolicyChain>,
 }
 
+/// The policy attached to an entry: either a full [`Policy`], or a reference to
+/// a policy template published in the database's
+/// [`PolicyRegistry`][crate::policies::PolicyRegistry].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum EntryPolicy {
+    /// The policy itself.
+    Inline(Policy),
+    /// The hash of a published policy template document. The database
+    /// enforces the latest version of the template it belongs to.
+    Template(Hash),
+}
+
+impl From<Policy> for EntryPolicy {
+    fn from(value: Policy) -> Self {
+        EntryPolicy::Inline(value)
+    }
+}
+
 /// A policy chain. Each link represents a requirement that an identity must
 /// meet in order to act as a signer for the subsequent link.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -110,8 +128,31 @@ pub struct CertReplace {
     pub replace_with: Hash,
 }
 
+impl Policy {
+    /// Check if an identity is one of this policy's roots. Roots always satisfy
+    /// the policy; identities that aren't roots must be checked against the
+    /// policy chains using a certificate database.
+    pub fn is_root(&self, id: &Identity) -> bool {
+        self.roots.contains(id)
+    }
+}
+
+impl PolicyLink {
+    /// Create a link requiring at least `min_issuers` certificates asserting
+    /// the given key-value pair.
+    pub fn new(key: impl Into<String>, val: impl Into<String>, min_issuers: NonZeroU8) -> Self {
+        Self {
+            key: key.into(),
+            val: val.into(),
+            min_issuers,
+        }
+    }
+}
+
 impl Cert {
     /// Check for validity. If no time is provided, the start & end times are ignored.
+    /// See [`SkewPolicy::cert_valid`][crate::skew::SkewPolicy::cert_valid] for
+    /// a check that allows for clock drift.
     pub fn is_valid(&self, time: Option<Timestamp>) -> bool {
         if let Some(time) = time {
             if time < self.start || time > self.end {
@@ -0,0 +1,158 @@
+//! Commit sequence numbers and the database change feed.
+//!
+//! Every transaction committed to a database is assigned a [`CommitSeq`],
+//! which increases monotonically with each commit. A [`ChangeFeed`] streams
+//! a [`CommitRecord`] for each commit after a given sequence number, which is
+//! the basis for replication, incremental backup, and cache invalidation.
+//! Applications that only care about the entries under one document can
+//! instead [watch][crate::Db::entry_watch] them with an [`EntryWatch`].
+//!
+//! Root names set or removed by a transaction appear in its record, but names
+//! changed directly through the [`Db`][crate::Db] name methods don't, and
+//! neither do schema changes, which are always made outside of transactions.
+//! Documents evicted by garbage collection also don't appear.
+
+use std::fmt;
+
+use async_trait::async_trait;
+use fog_pack::{
+    entry::{Entry, EntryRef},
+    types::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::DbResult;
+
+/// The sequence number assigned to a committed transaction.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct CommitSeq(pub u64);
+
+impl CommitSeq {
+    /// The sequence number a database starts at, before any commits.
+    pub const ZERO: CommitSeq = CommitSeq(0);
+
+    /// The sequence number following this one.
+    pub fn next(self) -> CommitSeq {
+        CommitSeq(self.0 + 1)
+    }
+}
+
+impl fmt::Display for CommitSeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A change made to a document by a committed transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DocRecord {
+    /// The document was added to the database.
+    Added(Hash),
+    /// References within the document were weakened or strengthened. Each
+    /// reference is listed with `true` if it is now weak.
+    WeakRefs { doc: Hash, refs: Vec<(Hash, bool)> },
+}
+
+/// A change made to an entry by a committed transaction. Added and restored
+/// entries are carried in full, so consumers don't need to look them up;
+/// modifying an entry doesn't change its contents, and deleted entries are
+/// gone, so those only carry a reference.
+#[derive(Clone, Debug)]
+pub enum EntryRecord {
+    /// The entry was added to the database.
+    Added(Entry),
+    /// The entry's time-to-live or policy were changed.
+    Modified(EntryRef),
+    /// The entry was deleted from the database.
+    Deleted(EntryRef),
+    /// A deleted entry that was still being retained as history was brought
+    /// back.
+    Restored(Entry),
+}
+
+impl EntryRecord {
+    /// The entry that was changed.
+    pub fn entry(&self) -> &EntryRef {
+        match self {
+            EntryRecord::Added(e) | EntryRecord::Restored(e) => e.reference(),
+            EntryRecord::Modified(e) | EntryRecord::Deleted(e) => e,
+        }
+    }
+}
+
+// Entries are compared by reference, which includes their hash.
+impl PartialEq for EntryRecord {
+    fn eq(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+            && self.entry() == other.entry()
+    }
+}
+
+impl Eq for EntryRecord {}
+
+/// A change made to a root name by a committed transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameRecord {
+    /// The name that was changed.
+    pub name: String,
+    /// The document the name pointed to before, if any.
+    pub previous: Option<Hash>,
+    /// The document the name now points to, or `None` if it was removed.
+    pub target: Option<Hash>,
+}
+
+/// Every change made by a single committed transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitRecord {
+    /// The transaction's sequence number.
+    pub seq: CommitSeq,
+    /// Changes made to documents.
+    pub docs: Vec<DocRecord>,
+    /// Changes made to entries.
+    pub entries: Vec<EntryRecord>,
+    /// Changes made to root names.
+    pub names: Vec<NameRecord>,
+}
+
+/// The database no longer has the history needed to stream changes from the
+/// requested sequence number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("Change history only goes back to sequence number {oldest}")]
+pub struct SeqTooOld {
+    /// The oldest sequence number changes can be streamed from.
+    pub oldest: CommitSeq,
+}
+
+/// A stream of committed changes, in commit order.
+#[async_trait]
+pub trait ChangeFeed: Send + Sync {
+    /// Wait for the next committed transaction.
+    async fn next(&self) -> DbResult<CommitRecord>;
+
+    /// Try to get the next committed transaction, returning `None` if there
+    /// isn't one yet.
+    fn try_next(&self) -> DbResult<Option<CommitRecord>>;
+}
+
+/// A change to an entry under a watched document, from an [`EntryWatch`].
+#[derive(Clone, Debug)]
+pub struct EntryEvent {
+    /// The sequence number of the transaction that made the change.
+    pub seq: CommitSeq,
+    /// The change.
+    pub change: EntryRecord,
+}
+
+/// A stream of changes to the entries under one document and key, in commit
+/// order.
+#[async_trait]
+pub trait EntryWatch: Send + Sync {
+    /// Wait for the next change.
+    async fn next(&self) -> DbResult<EntryEvent>;
+
+    /// Try to get the next change, returning `None` if there isn't one yet.
+    fn try_next(&self) -> DbResult<Option<EntryEvent>>;
+}
@@ -0,0 +1,585 @@
+//! Interface versions, and adapters for applications written against older
+//! ones.
+//!
+//! The traits in this crate are versioned as a whole by [`INTERFACE_VERSION`],
+//! which goes up whenever a change would break an application using them: a
+//! method on [`Db`][crate::Db] being removed or changing its signature, for
+//! instance. Adding methods that backends must implement breaks backends, not
+//! applications, and doesn't change the version.
+//!
+//! When the version goes up, the previous version of each changed trait is
+//! kept here in a module named for its version, along with an adapter that
+//! implements it on top of the current traits. An application written against
+//! version N−1 can then run on a backend implementing version N by importing
+//! the old trait from here and wrapping the backend, without waiting for
+//! every backend and application to upgrade in lockstep. Only the traits'
+//! own methods are adapted, along with the types they hand out whose own
+//! methods changed, like version 1's transactions; the rest, like cursors,
+//! are always the current ones.
+//!
+//! Adapters are kept for the versions from [`OLDEST_SUPPORTED`] on.
+//!
+//! Older versions of [`Db`][crate::Db] were synchronous, so their adapters
+//! block on the current trait's async methods. They're meant for
+//! applications that don't run an async executor; calling them from inside
+//! an async task can deadlock it.
+
+/// The current version of the database traits.
+pub const INTERFACE_VERSION: u32 = 3;
+
+/// The oldest interface version that adapters are still provided for.
+pub const OLDEST_SUPPORTED: u32 = 1;
+
+/// Check if an application written against the given interface version can
+/// use a backend implementing the current one, directly or through an
+/// adapter from this module.
+pub fn is_supported(version: u32) -> bool {
+    (OLDEST_SUPPORTED..=INTERFACE_VERSION).contains(&version)
+}
+
+/// Version 1 of the interface, the first published one.
+///
+/// Version 2 changed how cursors are opened, from a single cursor on the
+/// database to one opened on a document with options, and had
+/// [`name_add`][crate::Db::name_add] report names rejected by the database's
+/// naming policy separately from database errors.
+pub mod v1 {
+    use std::sync::Arc;
+
+    use fog_pack::{
+        document::{Document, NewDocument},
+        entry::{Entry, EntryRef, NewEntry},
+        error::Error as FogError,
+        schema::Schema,
+        types::*,
+    };
+    use futures::executor::block_on;
+
+    use crate::{
+        cert::Policy,
+        cursor::{CursorOpts, CursorQuery, DbQuery, NewCursor},
+        group::{Group, GroupSpec},
+        transaction::{CommitErrors, Durability, EntryError, MissingSchema, SchemaError},
+        DbError, DbResult,
+    };
+
+    /// Version 1 of [`Db`][crate::Db].
+    pub trait Db {
+        /// Start a new transaction with this database
+        fn txn(&self) -> Transaction;
+
+        /// Open a new group through this database
+        fn group(&self, spec: GroupSpec) -> Box<dyn Group>;
+
+        /// Open a local cursor on this database
+        fn cursor(&self) -> NewCursor;
+
+        /// Get a document directly from the database
+        fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>>;
+
+        /// Make a query directly on the database
+        fn query(&self, doc: &Hash, query: DbQuery) -> Box<dyn CursorQuery>;
+
+        /// Get a schema in the database
+        fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
+
+        /// Add a schema to the database. Fails if the schema document wasn't valid.
+        fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>>;
+
+        /// Remove a schema from the database. Returns false if the schema wasn't in the database.
+        fn schema_del(&self, schema: &Hash) -> DbResult<bool>;
+
+        /// Get a list of all schemas in the database.
+        fn schema_list(&self) -> Vec<Hash>;
+
+        /// Get a hash associated with a name in the database.
+        fn name_get(&self, name: &str) -> DbResult<Option<Hash>>;
+
+        /// Add a name-to-hash mapping to the database. This pins the document
+        /// inside the database, once it's been added. This should be done before
+        /// adding the document in a transaction. Returns the previous hash, if
+        /// there was one.
+        fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Option<Hash>>;
+
+        /// Remove a name-hash mapping from the database, returning None if there
+        /// wasn't one stored.
+        fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>>;
+
+        /// Get a list of all named documents in the database.
+        fn name_list(&self) -> Vec<(String, Hash)>;
+    }
+
+    /// Version 1 of [`Transaction`][crate::transaction::Transaction], wrapping
+    /// a current one.
+    ///
+    /// Version 1 transactions were built up synchronously, so the methods that
+    /// are now async block the calling thread instead, and commits wait for
+    /// the default [`Durability`].
+    pub struct Transaction(crate::transaction::Transaction);
+
+    impl Transaction {
+        /// Wrap a current transaction.
+        pub fn new(txn: crate::transaction::Transaction) -> Self {
+            Self(txn)
+        }
+
+        /// Unwrap the current transaction.
+        pub fn into_inner(self) -> crate::transaction::Transaction {
+            self.0
+        }
+
+        /// Load a transaction from a set of errors returned by a failed commit.
+        pub fn load_from_errors(&mut self, errs: CommitErrors) {
+            self.0.load_from_errors(errs)
+        }
+
+        /// Try to add a [`NewDocument`] to the DB. On success, it returns a
+        /// copy of the document that will be committed.
+        pub fn add_new_doc(
+            &mut self,
+            doc: NewDocument,
+        ) -> DbResult<Result<Arc<Document>, SchemaError>> {
+            block_on(self.0.add_new_doc(doc))
+        }
+
+        /// Try to add a [`Document`] to the DB.
+        pub fn add_doc(&mut self, doc: Arc<Document>) -> DbResult<Result<(), MissingSchema>> {
+            block_on(self.0.add_doc(doc))
+        }
+
+        /// Try to add a [`NewEntry`] to the DB.
+        pub fn add_new_entry(&mut self, entry: NewEntry) -> DbResult<Result<(), EntryError>> {
+            block_on(self.0.add_new_entry(entry))
+        }
+
+        /// Try to add an [`Entry`] to the DB.
+        pub fn add_entry(&mut self, entry: Entry) -> DbResult<Result<(), EntryError>> {
+            block_on(self.0.add_entry(entry))
+        }
+
+        /// Set whether a document's reference to another is weak.
+        pub fn set_weak_ref(&mut self, doc: &Hash, ref_hash: &Hash, weak: bool) {
+            self.0.set_weak_ref(doc, ref_hash, weak)
+        }
+
+        /// Set an entry's time-to-live.
+        pub fn set_ttl(&mut self, entry: &EntryRef, ttl: Option<Timestamp>) {
+            self.0.set_ttl(entry, ttl)
+        }
+
+        /// Set an entry's access policy.
+        pub fn set_policy(&mut self, entry: &EntryRef, policy: Option<Policy>) {
+            self.0.set_policy(entry, policy)
+        }
+
+        /// Delete an entry from the database.
+        pub fn del_entry(&mut self, entry: &EntryRef) {
+            self.0.del_entry(entry)
+        }
+
+        /// Commit this transaction to the database, waiting for the default
+        /// [`Durability`]. This can fail due to internal database errors, but
+        /// it can also fail any of the various
+        /// [`CommitError`][crate::transaction::CommitError] reasons.
+        pub async fn commit(self) -> DbResult<Result<(), CommitErrors>> {
+            Ok(self.0.commit(Durability::default()).await?.map(|_| ()))
+        }
+    }
+
+    /// Adapts a current [`Db`][crate::Db] for use as a version 1 [`Db`].
+    ///
+    /// Version 1 had a single local cursor per database, so the adapter is
+    /// given the document that cursor starts on.
+    ///
+    /// Every method blocks the calling thread until the database answers, so
+    /// the adapter mustn't be used from inside an async task: if the database
+    /// needs that task's executor to make progress, it never will.
+    pub struct Adapter<D> {
+        db: D,
+        root: Hash,
+    }
+
+    impl<D: crate::Db> Adapter<D> {
+        /// Wrap a database, opening cursors on the given root document.
+        pub fn new(db: D, root: Hash) -> Self {
+            Self { db, root }
+        }
+
+        /// Get the wrapped database.
+        pub fn inner(&self) -> &D {
+            &self.db
+        }
+
+        /// Unwrap the database.
+        pub fn into_inner(self) -> D {
+            self.db
+        }
+    }
+
+    impl<D: crate::Db> Db for Adapter<D> {
+        fn txn(&self) -> Transaction {
+            Transaction(self.db.txn())
+        }
+
+        fn group(&self, spec: GroupSpec) -> Box<dyn Group> {
+            self.db.group(spec)
+        }
+
+        /// Open a cursor on the adapter's root document.
+        ///
+        /// Version 1 cursors couldn't fail to open, so this panics if the root
+        /// document isn't in the database or the database fails.
+        fn cursor(&self) -> NewCursor {
+            match block_on(self.db.cursor(&self.root, CursorOpts::default())) {
+                Ok(Some(cursor)) => cursor,
+                Ok(None) => panic!("root document {} isn't in the database", self.root),
+                Err(_) => panic!("database failed opening a cursor on {}", self.root),
+            }
+        }
+
+        fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+            block_on(self.db.doc_get(doc))
+        }
+
+        fn query(&self, doc: &Hash, query: DbQuery) -> Box<dyn CursorQuery> {
+            self.db.query(doc, query)
+        }
+
+        fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+            block_on(self.db.schema_get(schema))
+        }
+
+        fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+            block_on(self.db.schema_add(schema))
+        }
+
+        fn schema_del(&self, schema: &Hash) -> DbResult<bool> {
+            block_on(self.db.schema_del(schema))
+        }
+
+        /// Version 1 couldn't report errors here, so this panics if the
+        /// database fails.
+        fn schema_list(&self) -> Vec<Hash> {
+            block_on(self.db.schema_list())
+                .unwrap_or_else(|_| panic!("database failed listing schemas"))
+        }
+
+        fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+            block_on(self.db.name_get(name))
+        }
+
+        /// Names rejected by the naming policy are reported as
+        /// [`DbError::Internal`] holding the
+        /// [`NameError`][crate::names::NameError].
+        fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Option<Hash>> {
+            block_on(self.db.name_add(name, hash))?
+                .map_err(|e| Box::new(DbError::Internal(Box::new(e))))
+        }
+
+        fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>> {
+            block_on(self.db.name_del(schema))
+        }
+
+        /// Version 1 couldn't report errors here, so this panics if the
+        /// database fails.
+        fn name_list(&self) -> Vec<(String, Hash)> {
+            block_on(self.db.name_list())
+                .unwrap_or_else(|_| panic!("database failed listing names"))
+        }
+    }
+}
+
+/// Version 2 of the interface.
+///
+/// Version 3 made [`Db`][crate::Db] async and object-safe: every method that
+/// may need to touch storage became async, and
+/// [`schema_list`][crate::Db::schema_list],
+/// [`name_list`][crate::Db::name_list], and
+/// [`name_list_prefix`][crate::Db::name_list_prefix] began reporting database
+/// errors. Only the changed methods are in the version 2 trait here; the
+/// rest are reached through the adapter's [`Deref`][std::ops::Deref] to the
+/// database.
+pub mod v2 {
+    use std::{ops::Deref, sync::Arc};
+
+    use fog_pack::{
+        document::Document, error::Error as FogError, query::NewQuery, schema::Schema, types::*,
+    };
+    use futures::executor::block_on;
+
+    use crate::{
+        access::DocInfo,
+        compression::CompressionPolicy,
+        cursor::{CursorOpts, NewCursor},
+        expiry::{SweepBudget, SweepReport},
+        gc::{GcPreview, ProposedChange},
+        names::{NameError, NameInfo, NameMeta},
+        retention::RetentionPolicy,
+        stats::TreeStats,
+        weak_refs::WeakRefDefaults,
+        DbResult,
+    };
+
+    /// The methods of version 2 of [`Db`][crate::Db] that changed in version
+    /// 3.
+    pub trait Db {
+        /// Work out which documents would be garbage collected, and which open
+        /// gates would break, if a change were committed. Nothing is changed.
+        fn gc_preview(&self, change: ProposedChange) -> DbResult<GcPreview>;
+
+        /// Remove expired entries from storage, stopping once the budget runs
+        /// out.
+        fn ttl_sweep(&self, budget: SweepBudget) -> DbResult<SweepReport>;
+
+        /// Open a local cursor on this database, starting from the given
+        /// document. Returns `None` if the document isn't in the database.
+        fn cursor(&self, doc: &Hash, opts: CursorOpts) -> DbResult<Option<NewCursor>>;
+
+        /// Get a document directly from the database
+        fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>>;
+
+        /// Get metadata about a document in the database. Returns `None` if
+        /// the document isn't in the database.
+        fn doc_info(&self, doc: &Hash) -> DbResult<Option<DocInfo>>;
+
+        /// Compute statistics for every document reachable from the given root
+        /// document, or return `None` if the root isn't in the database.
+        fn tree_stats(&self, root: &Hash) -> DbResult<Option<TreeStats>>;
+
+        /// Count the entries stored under a document's key. If a query is
+        /// provided, only entries matching it are counted.
+        fn entry_count(&self, doc: &Hash, key: &str, query: Option<&NewQuery>) -> DbResult<u64>;
+
+        /// Get a schema in the database
+        fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
+
+        /// Add a schema to the database. Fails if the schema document wasn't valid.
+        fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>>;
+
+        /// Remove a schema from the database. Returns false if the schema wasn't in the database.
+        fn schema_del(&self, schema: &Hash) -> DbResult<bool>;
+
+        /// Get a list of all schemas in the database.
+        fn schema_list(&self) -> Vec<Hash>;
+
+        /// Set how documents and entries of a schema are compressed when
+        /// stored. Returns false if the schema wasn't in the database.
+        fn schema_set_compression(&self, schema: &Hash, policy: CompressionPolicy)
+            -> DbResult<bool>;
+
+        /// Get the compression policy for a schema, or `None` if the schema
+        /// isn't in the database.
+        fn schema_get_compression(&self, schema: &Hash) -> DbResult<Option<CompressionPolicy>>;
+
+        /// Set which link fields of a schema's documents are weakened by
+        /// default. Returns false if the schema wasn't in the database.
+        fn schema_set_weak_refs(&self, schema: &Hash, defaults: WeakRefDefaults) -> DbResult<bool>;
+
+        /// Get the weak link defaults for a schema, or `None` if the schema
+        /// isn't in the database.
+        fn schema_get_weak_refs(&self, schema: &Hash) -> DbResult<Option<WeakRefDefaults>>;
+
+        /// Set how many entries under a key of each of a schema's documents
+        /// are kept. Returns false if the schema wasn't in the database.
+        fn schema_set_retention(
+            &self,
+            schema: &Hash,
+            key: &str,
+            policy: RetentionPolicy,
+        ) -> DbResult<bool>;
+
+        /// Get the retention policy for a key of a schema, or `None` if the
+        /// schema isn't in the database.
+        fn schema_get_retention(&self, schema: &Hash, key: &str)
+            -> DbResult<Option<RetentionPolicy>>;
+
+        /// Get a hash associated with a name in the database.
+        fn name_get(&self, name: &str) -> DbResult<Option<Hash>>;
+
+        /// Add a name-to-hash mapping to the database. Returns the previous
+        /// hash, if there was one. Fails if the name isn't allowed by the
+        /// database's naming policy.
+        fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Result<Option<Hash>, NameError>>;
+
+        /// Add a name-to-hash mapping under one of the reserved prefixes.
+        fn name_add_reserved(
+            &self,
+            name: &str,
+            hash: &Hash,
+        ) -> DbResult<Result<Option<Hash>, NameError>>;
+
+        /// Remove a name-hash mapping from the database, returning None if
+        /// there wasn't one stored.
+        fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>>;
+
+        /// Get a list of all named documents in the database.
+        fn name_list(&self) -> Vec<(String, Hash)>;
+
+        /// Get a list of all named documents whose names start with `prefix`,
+        /// in order by name.
+        fn name_list_prefix(&self, prefix: &str) -> Vec<(String, Hash)>;
+
+        /// Get a name's target along with its metadata.
+        fn name_info(&self, name: &str) -> DbResult<Option<NameInfo>>;
+
+        /// Set a name's descriptive metadata, replacing any that was there.
+        /// Returns false if the name doesn't exist.
+        fn name_set_meta(&self, name: &str, meta: NameMeta) -> DbResult<bool>;
+    }
+
+    /// Adapts a current [`Db`][crate::Db] for use as a version 2 [`Db`].
+    /// Version 2 couldn't report errors when listing schemas or names, so a
+    /// database error there gives an empty list.
+    ///
+    /// Every adapted method blocks the calling thread until the database
+    /// answers, so the adapter mustn't be used from inside an async task: if
+    /// the database needs that task's executor to make progress, it never
+    /// will.
+    pub struct Adapter<D> {
+        db: D,
+    }
+
+    impl<D: crate::Db> Adapter<D> {
+        /// Wrap a database.
+        pub fn new(db: D) -> Self {
+            Self { db }
+        }
+
+        /// Get the wrapped database.
+        pub fn inner(&self) -> &D {
+            &self.db
+        }
+
+        /// Unwrap the database.
+        pub fn into_inner(self) -> D {
+            self.db
+        }
+    }
+
+    impl<D> Deref for Adapter<D> {
+        type Target = D;
+
+        fn deref(&self) -> &D {
+            &self.db
+        }
+    }
+
+    impl<D: crate::Db> Db for Adapter<D> {
+        fn gc_preview(&self, change: ProposedChange) -> DbResult<GcPreview> {
+            block_on(self.db.gc_preview(change))
+        }
+
+        fn ttl_sweep(&self, budget: SweepBudget) -> DbResult<SweepReport> {
+            block_on(self.db.ttl_sweep(budget))
+        }
+
+        fn cursor(&self, doc: &Hash, opts: CursorOpts) -> DbResult<Option<NewCursor>> {
+            block_on(self.db.cursor(doc, opts))
+        }
+
+        fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+            block_on(self.db.doc_get(doc))
+        }
+
+        fn doc_info(&self, doc: &Hash) -> DbResult<Option<DocInfo>> {
+            block_on(self.db.doc_info(doc))
+        }
+
+        fn tree_stats(&self, root: &Hash) -> DbResult<Option<TreeStats>> {
+            block_on(self.db.tree_stats(root))
+        }
+
+        fn entry_count(&self, doc: &Hash, key: &str, query: Option<&NewQuery>) -> DbResult<u64> {
+            block_on(self.db.entry_count(doc, key, query))
+        }
+
+        fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+            block_on(self.db.schema_get(schema))
+        }
+
+        fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+            block_on(self.db.schema_add(schema))
+        }
+
+        fn schema_del(&self, schema: &Hash) -> DbResult<bool> {
+            block_on(self.db.schema_del(schema))
+        }
+
+        fn schema_list(&self) -> Vec<Hash> {
+            block_on(self.db.schema_list()).unwrap_or_default()
+        }
+
+        fn schema_set_compression(
+            &self,
+            schema: &Hash,
+            policy: CompressionPolicy,
+        ) -> DbResult<bool> {
+            block_on(self.db.schema_set_compression(schema, policy))
+        }
+
+        fn schema_get_compression(&self, schema: &Hash) -> DbResult<Option<CompressionPolicy>> {
+            block_on(self.db.schema_get_compression(schema))
+        }
+
+        fn schema_set_weak_refs(&self, schema: &Hash, defaults: WeakRefDefaults) -> DbResult<bool> {
+            block_on(self.db.schema_set_weak_refs(schema, defaults))
+        }
+
+        fn schema_get_weak_refs(&self, schema: &Hash) -> DbResult<Option<WeakRefDefaults>> {
+            block_on(self.db.schema_get_weak_refs(schema))
+        }
+
+        fn schema_set_retention(
+            &self,
+            schema: &Hash,
+            key: &str,
+            policy: RetentionPolicy,
+        ) -> DbResult<bool> {
+            block_on(self.db.schema_set_retention(schema, key, policy))
+        }
+
+        fn schema_get_retention(
+            &self,
+            schema: &Hash,
+            key: &str,
+        ) -> DbResult<Option<RetentionPolicy>> {
+            block_on(self.db.schema_get_retention(schema, key))
+        }
+
+        fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+            block_on(self.db.name_get(name))
+        }
+
+        fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Result<Option<Hash>, NameError>> {
+            block_on(self.db.name_add(name, hash))
+        }
+
+        fn name_add_reserved(
+            &self,
+            name: &str,
+            hash: &Hash,
+        ) -> DbResult<Result<Option<Hash>, NameError>> {
+            block_on(self.db.name_add_reserved(name, hash))
+        }
+
+        fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>> {
+            block_on(self.db.name_del(schema))
+        }
+
+        fn name_list(&self) -> Vec<(String, Hash)> {
+            block_on(self.db.name_list()).unwrap_or_default()
+        }
+
+        fn name_list_prefix(&self, prefix: &str) -> Vec<(String, Hash)> {
+            block_on(self.db.name_list_prefix(prefix)).unwrap_or_default()
+        }
+
+        fn name_info(&self, name: &str) -> DbResult<Option<NameInfo>> {
+            block_on(self.db.name_info(name))
+        }
+
+        fn name_set_meta(&self, name: &str, meta: NameMeta) -> DbResult<bool> {
+            block_on(self.db.name_set_meta(name, meta))
+        }
+    }
+}
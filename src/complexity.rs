@@ -0,0 +1,191 @@
+//! Limits on how complex a query a gate will run.
+//!
+//! Queries come from other nodes, and the cost of running one grows with the
+//! size of its validator: each nested validator is another check to run on
+//! every entry, regular expressions can be slow to match, and hash validators
+//! that check the linked document mean loading another document per entry. A
+//! gate measures each incoming query's [`QueryComplexity`] and checks it
+//! against its [`ComplexityLimits`]. A query over the limits is never run, and
+//! the querier is told why with a [`QueryUpdate::Rejected`][crate::cursor::QueryUpdate::Rejected].
+
+use fog_pack::validator::Validator;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{cursor::DbQuery, NodeInfo};
+
+/// A measure of how costly a query is to run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct QueryComplexity {
+    /// The number of validators making up the query, not counting ones that
+    /// accept anything.
+    pub predicates: u32,
+    /// How deeply the validators are nested.
+    pub depth: u32,
+    /// The number of regular expressions in the query.
+    pub regexes: u32,
+    /// The number of hash validators that check the linked document.
+    pub links: u32,
+    /// Whether the query asks for its results in a particular order.
+    pub ordered: bool,
+}
+
+impl QueryComplexity {
+    /// Measure a query.
+    pub fn measure(query: &DbQuery) -> Self {
+        let mut this = Self {
+            ordered: query.ordering.is_some(),
+            ..Self::default()
+        };
+        this.walk(query.query.validator(), 1);
+        this
+    }
+
+    fn walk(&mut self, validator: &Validator, depth: u32) {
+        if matches!(validator, Validator::Any) {
+            return;
+        }
+        self.predicates = self.predicates.saturating_add(1);
+        self.depth = self.depth.max(depth);
+        let depth = depth.saturating_add(1);
+        match validator {
+            Validator::Str(v) if v.matches.is_some() => {
+                self.regexes = self.regexes.saturating_add(1);
+            }
+            Validator::Array(v) => {
+                self.walk(&v.items, depth);
+                for v in v.contains.iter().chain(v.prefix.iter()) {
+                    self.walk(v, depth);
+                }
+            }
+            Validator::Map(v) => {
+                if let Some(keys) = &v.keys {
+                    if keys.matches.is_some() {
+                        self.regexes = self.regexes.saturating_add(1);
+                    }
+                }
+                if let Some(values) = &v.values {
+                    self.walk(values, depth);
+                }
+                for v in v.req.values().chain(v.opt.values()) {
+                    self.walk(v, depth);
+                }
+            }
+            Validator::Hash(v) => {
+                if let Some(link) = &v.link {
+                    self.links = self.links.saturating_add(1);
+                    self.walk(link, depth);
+                }
+            }
+            Validator::Multi(v) => {
+                for v in v.iter() {
+                    self.walk(v, depth);
+                }
+            }
+            Validator::Enum(v) => {
+                for v in v.iter().filter_map(|(_, v)| v.as_ref()) {
+                    self.walk(v, depth);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The most complex query a gate will accept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ComplexityLimits {
+    /// Maximum number of validators.
+    pub max_predicates: u32,
+    /// Maximum nesting depth of validators.
+    pub max_depth: u32,
+    /// Maximum number of regular expressions.
+    pub max_regexes: u32,
+    /// Maximum number of hash validators that check the linked document.
+    pub max_links: u32,
+    /// Whether queries may ask for ordered results. Ordering requires
+    /// collecting every result before sending any.
+    pub allow_ordering: bool,
+}
+
+impl Default for ComplexityLimits {
+    fn default() -> Self {
+        Self {
+            max_predicates: 64,
+            max_depth: 8,
+            max_regexes: 4,
+            max_links: 2,
+            allow_ordering: true,
+        }
+    }
+}
+
+impl ComplexityLimits {
+    /// Limits that accept every query.
+    pub fn unlimited() -> Self {
+        Self {
+            max_predicates: u32::MAX,
+            max_depth: u32::MAX,
+            max_regexes: u32::MAX,
+            max_links: u32::MAX,
+            allow_ordering: true,
+        }
+    }
+
+    /// Check a measured query against these limits.
+    pub fn check(&self, complexity: &QueryComplexity) -> Result<(), ComplexityError> {
+        if complexity.predicates > self.max_predicates {
+            return Err(ComplexityError::TooManyPredicates {
+                max: self.max_predicates,
+                actual: complexity.predicates,
+            });
+        }
+        if complexity.depth > self.max_depth {
+            return Err(ComplexityError::TooDeep {
+                max: self.max_depth,
+                actual: complexity.depth,
+            });
+        }
+        if complexity.regexes > self.max_regexes {
+            return Err(ComplexityError::TooManyRegexes {
+                max: self.max_regexes,
+                actual: complexity.regexes,
+            });
+        }
+        if complexity.links > self.max_links {
+            return Err(ComplexityError::TooManyLinks {
+                max: self.max_links,
+                actual: complexity.links,
+            });
+        }
+        if complexity.ordered && !self.allow_ordering {
+            return Err(ComplexityError::OrderingNotAllowed);
+        }
+        Ok(())
+    }
+}
+
+/// Why a query was too complex to run.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ComplexityError {
+    #[error("Query has too many predicates (max {max}, actual {actual})")]
+    TooManyPredicates { max: u32, actual: u32 },
+    #[error("Query is nested too deeply (max {max}, actual {actual})")]
+    TooDeep { max: u32, actual: u32 },
+    #[error("Query has too many regular expressions (max {max}, actual {actual})")]
+    TooManyRegexes { max: u32, actual: u32 },
+    #[error("Query checks too many linked documents (max {max}, actual {actual})")]
+    TooManyLinks { max: u32, actual: u32 },
+    #[error("Query ordering is not allowed")]
+    OrderingNotAllowed,
+}
+
+/// A node refusing to run a query.
+#[derive(Clone, Debug)]
+pub struct QueryRejected {
+    /// The node that refused the query.
+    pub source: NodeInfo,
+    /// Why it was refused.
+    pub reason: ComplexityError,
+}
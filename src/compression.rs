@@ -0,0 +1,106 @@
+//! Runtime control over how documents and entries are compressed.
+//!
+//! Schemas carry compression hints for their documents and entries, chosen by
+//! the schema's author. Those hints are a reasonable default, but the right
+//! tradeoff between CPU time and storage depends on the device the database is
+//! running on. A [`CompressionPolicy`] can be set per schema with
+//! [`Db::schema_set_compression`][crate::Db::schema_set_compression] to
+//! override the hints. Policies only affect how data is stored locally; the
+//! encoded form used for hashing and for transfer between nodes is unchanged.
+
+use fog_pack::schema::Compress;
+use serde::{Deserialize, Serialize};
+
+/// The zstd compression level used when a policy disables a schema's
+/// dictionary without otherwise setting a level.
+pub const DEFAULT_LEVEL: u8 = 3;
+
+/// How to compress one kind of data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CompressionLevel {
+    /// Follow the schema's compression hint.
+    #[default]
+    Schema,
+    /// Don't compress.
+    Off,
+    /// Compress with zstd at the given level.
+    Level(u8),
+}
+
+impl CompressionLevel {
+    /// Resolve this setting against a schema's compression hint. When
+    /// `dictionary` is set and the schema provides a dictionary, the
+    /// dictionary is used at the level it was built with, unless compression
+    /// is turned off entirely.
+    pub fn resolve(&self, hint: &Compress, dictionary: bool) -> Compress {
+        match (*self, hint) {
+            (CompressionLevel::Off, _) => Compress::None,
+            (_, Compress::Dict(_)) if dictionary => hint.clone(),
+            (CompressionLevel::Level(level), _) => Compress::new_zstd_general(level),
+            (CompressionLevel::Schema, Compress::Dict(_)) => {
+                Compress::new_zstd_general(DEFAULT_LEVEL)
+            }
+            (CompressionLevel::Schema, _) => hint.clone(),
+        }
+    }
+}
+
+/// How to compress the documents and entries of a schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CompressionPolicy {
+    /// How to compress documents using the schema.
+    pub docs: CompressionLevel,
+    /// How to compress entries attached to documents using the schema.
+    pub entries: CompressionLevel,
+    /// Whether to use the schema's compression dictionaries, if it has any.
+    /// Dictionaries usually give much better compression for small
+    /// documents, but cost memory to hold.
+    pub dictionary: bool,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        Self {
+            docs: CompressionLevel::Schema,
+            entries: CompressionLevel::Schema,
+            dictionary: true,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// A policy that follows the schema's hints. This is the default.
+    pub fn schema() -> Self {
+        Self::default()
+    }
+
+    /// A policy that turns off compression, for devices where CPU time is
+    /// scarcer than storage.
+    pub fn off() -> Self {
+        Self {
+            docs: CompressionLevel::Off,
+            entries: CompressionLevel::Off,
+            dictionary: false,
+        }
+    }
+
+    /// A policy compressing everything with zstd at the given level, while
+    /// still using the schema's dictionaries.
+    pub fn level(level: u8) -> Self {
+        Self {
+            docs: CompressionLevel::Level(level),
+            entries: CompressionLevel::Level(level),
+            dictionary: true,
+        }
+    }
+
+    /// Resolve the compression to use for documents, given the schema's hint.
+    pub fn doc_compress(&self, hint: &Compress) -> Compress {
+        self.docs.resolve(hint, self.dictionary)
+    }
+
+    /// Resolve the compression to use for entries, given the schema's hint.
+    pub fn entry_compress(&self, hint: &Compress) -> Compress {
+        self.entries.resolve(hint, self.dictionary)
+    }
+}
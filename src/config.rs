@@ -0,0 +1,226 @@
+//! A portable description of how to open a database.
+//!
+//! Most of what a backend needs to be told when opening a database - storage
+//! quotas, garbage collection tuning, compression defaults, clock skew
+//! tolerance, naming rules - is defined by this crate, not by the backend. A [`DbConfig`]
+//! collects those settings in one place, so that a configuration can be
+//! written once and used with any backend. It can be stored as a fog-pack
+//! document adhering to [`DbConfig::schema`], and so shared between nodes and
+//! checked like any other document. Settings that only make sense for one
+//! backend go in [`DbConfig::backend`].
+//!
+//! Every field is optional when decoding; anything left out takes its default.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use fog_pack::{
+    document::{Document, NewDocument},
+    error::Error as FogError,
+    schema::SchemaBuilder,
+    types::*,
+    validator::{
+        ArrayValidator, BoolValidator, EnumValidator, IntValidator, MapValidator, MultiValidator,
+        StrValidator, Validator,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compression::CompressionPolicy, expiry::SweepConfig, names::NamingPolicy, quota::StorageQuota, skew::SkewPolicy,
+    validate::ValidatorConfig,
+};
+
+/// Garbage collection tuning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GcConfig {
+    /// How often to run a garbage collection pass.
+    pub interval: Duration,
+    /// How long a document must have been unreachable before it can be
+    /// collected. Gives in-flight transactions that will link to it again a
+    /// chance to commit.
+    pub grace: Duration,
+    /// The most documents to evict in a single pass, if limited. Passes with
+    /// more to evict pick up where the last one left off.
+    pub max_batch: Option<u32>,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            grace: Duration::from_secs(10),
+            max_batch: None,
+        }
+    }
+}
+
+/// Options for opening a database.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DbConfig {
+    /// Open the database without allowing any changes to it.
+    pub read_only: bool,
+    /// Limit on the storage used by the whole database.
+    pub quota: Option<StorageQuota>,
+    /// Garbage collection tuning.
+    pub gc: GcConfig,
+    /// How to compress schemas that don't have their own
+    /// [policy][crate::Db::schema_set_compression].
+    pub compression: CompressionPolicy,
+    /// Tolerance for clock skew.
+    pub skew: SkewPolicy,
+    /// Rules for which names may be added.
+    pub names: NamingPolicy,
+    /// Sizing of the database's shared [validator][crate::Db::validator].
+    pub validator: ValidatorConfig,
+    /// How expired entries are [swept][crate::expiry] in the background.
+    pub expiry: SweepConfig,
+    /// Backend-specific settings, keyed by name. Backends should ignore
+    /// settings they don't recognize.
+    pub backend: BTreeMap<String, Value>,
+}
+
+fn duration() -> Validator {
+    MapValidator::new()
+        .req_add("secs", IntValidator::new().min(0u64).build())
+        .req_add(
+            "nanos",
+            IntValidator::new().min(0u32).max(999_999_999u32).build(),
+        )
+        .build()
+}
+
+fn optional(validator: Validator) -> Validator {
+    MultiValidator::new()
+        .push(Validator::Null)
+        .push(validator)
+        .build()
+}
+
+fn compression_level() -> Validator {
+    EnumValidator::new()
+        .insert("Schema", None)
+        .insert("Off", None)
+        .insert(
+            "Level",
+            Some(IntValidator::new().min(0u8).max(u8::MAX).build()),
+        )
+        .build()
+}
+
+impl DbConfig {
+    /// Build the schema document that configuration documents adhere to.
+    pub fn schema() -> Document {
+        let u64_val = || IntValidator::new().min(0u64).build();
+        let quota = MapValidator::new()
+            .req_add("max_bytes", optional(u64_val()))
+            .req_add("max_docs", optional(u64_val()))
+            .req_add(
+                "eviction",
+                EnumValidator::new()
+                    .insert("LeastRecentlyUsed", None)
+                    .insert("OldestFirst", None)
+                    .insert("LargestFirst", None)
+                    .build(),
+            )
+            .build();
+        let gc = MapValidator::new()
+            .opt_add("interval", duration())
+            .opt_add("grace", duration())
+            .opt_add(
+                "max_batch",
+                optional(IntValidator::new().min(0u32).max(u32::MAX).build()),
+            )
+            .build();
+        let compression = MapValidator::new()
+            .req_add("docs", compression_level())
+            .req_add("entries", compression_level())
+            .req_add("dictionary", BoolValidator::new().build())
+            .build();
+        let skew = MapValidator::new()
+            .req_add("max_drift", duration())
+            .req_add(
+                "future",
+                EnumValidator::new()
+                    .insert("Reject", None)
+                    .insert("Clamp", None)
+                    .insert("Accept", None)
+                    .build(),
+            )
+            .build();
+        let prefixes = || {
+            ArrayValidator::new()
+                .items(StrValidator::new().build())
+                .build()
+        };
+        let names = MapValidator::new()
+            .req_add("max_len", u64_val())
+            .req_add("reserved", prefixes())
+            .req_add("permitted", prefixes())
+            .build();
+        let validator = MapValidator::new()
+            .opt_add(
+                "workers",
+                optional(IntValidator::new().min(0u32).max(u32::MAX).build()),
+            )
+            .opt_add(
+                "max_schemas",
+                IntValidator::new().min(0u32).max(u32::MAX).build(),
+            )
+            .build();
+        let expiry = MapValidator::new()
+            .opt_add("auto", BoolValidator::new().build())
+            .opt_add("interval", duration())
+            .opt_add(
+                "budget",
+                MapValidator::new()
+                    .opt_add("max_entries", optional(u64_val()))
+                    .opt_add("max_time", optional(duration()))
+                    .build(),
+            )
+            .opt_add(
+                "rate",
+                optional(
+                    MapValidator::new()
+                        .req_add("rate", IntValidator::new().min(0u32).max(u32::MAX).build())
+                        .req_add("period", duration())
+                        .req_add("burst", IntValidator::new().min(0u32).max(u32::MAX).build())
+                        .build(),
+                ),
+            )
+            .build();
+        let backend = MapValidator::new()
+            .keys(StrValidator::new())
+            .values(Validator::Any)
+            .build();
+        let doc = MapValidator::new()
+            .opt_add("read_only", BoolValidator::new().build())
+            .opt_add("quota", optional(quota))
+            .opt_add("gc", gc)
+            .opt_add("compression", compression)
+            .opt_add("skew", skew)
+            .opt_add("names", names)
+            .opt_add("validator", validator)
+            .opt_add("expiry", expiry)
+            .opt_add("backend", backend)
+            .build();
+        SchemaBuilder::new(doc)
+            .name("fog-db database configuration")
+            .version(1u8)
+            .build()
+            .expect("configuration schema should always be valid")
+    }
+
+    /// Make a configuration document adhering to the given configuration
+    /// schema.
+    pub fn to_doc(&self, schema: &Hash) -> Result<NewDocument, FogError> {
+        NewDocument::new(Some(schema), self)
+    }
+
+    /// Read a configuration from a document. The document should already have
+    /// been validated against the configuration schema.
+    pub fn from_doc(doc: &Document) -> Result<Self, FogError> {
+        doc.deserialize()
+    }
+}
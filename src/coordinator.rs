@@ -0,0 +1,121 @@
+//! Commits spanning several databases.
+//!
+//! A single transaction is atomic within its database, but applications that
+//! split their data between databases - say, a private one and one shared with
+//! a group - sometimes need a change to land in all of them or none of them.
+//! A [`TxnCoordinator`] gets close to this with a two-phase commit: every
+//! transaction is first [prepared][crate::DbCommit::prepare], which validates
+//! it and makes it durable without applying it, and only once all of them are
+//! prepared are they committed. If any fails to prepare, the rest are rolled
+//! back.
+//!
+//! This isn't fully atomic. The coordinator keeps no durable record of its
+//! decision to commit, and prepared transactions have no identity outside the
+//! [`PreparedCommit`] handles it holds. If the process stops, or a database
+//! fails, partway through the commit phase, some databases may have committed
+//! while others are left to roll back their prepared transactions, and there's
+//! no way to finish the job on restart. Applications that can't tolerate that
+//! should make their changes idempotent, so they can check each database and
+//! commit again whatever is missing.
+
+use async_trait::async_trait;
+
+use crate::{
+    transaction::{CommitErrors, CommitReceipt, Durability, Transaction},
+    DbResult,
+};
+
+/// A transaction that has been prepared, and is guaranteed to commit
+/// successfully barring internal database failure. Until it is committed or
+/// rolled back, the database must hold any resources the commit needs. If a
+/// prepared commit is dropped without being committed or rolled back, it is
+/// rolled back.
+#[async_trait]
+pub trait PreparedCommit: Send + Sync {
+    /// Apply the prepared transaction, returning its receipt.
+    async fn commit(self: Box<Self>) -> DbResult<CommitReceipt>;
+
+    /// Discard the prepared transaction.
+    async fn rollback(self: Box<Self>) -> DbResult<()>;
+}
+
+/// A transaction failed to prepare, so the coordinated commit was abandoned.
+pub struct PrepareErrors {
+    /// The index of the transaction that failed, in the order it was added to
+    /// the coordinator.
+    pub index: usize,
+    /// Why the transaction failed.
+    pub errors: CommitErrors,
+}
+
+/// Commits transactions across several databases, such that either all of them
+/// are committed or none of them are, as long as nothing fails once they're
+/// all prepared. See [the module documentation](self) for what happens
+/// otherwise.
+#[derive(Default)]
+pub struct TxnCoordinator {
+    txns: Vec<Transaction>,
+}
+
+impl TxnCoordinator {
+    /// Create a new, empty coordinator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a transaction to be committed. Returns the index of the
+    /// transaction, which is used to match up results.
+    pub fn add(&mut self, txn: Transaction) -> usize {
+        self.txns.push(txn);
+        self.txns.len() - 1
+    }
+
+    /// The number of transactions in the coordinator.
+    pub fn len(&self) -> usize {
+        self.txns.len()
+    }
+
+    /// Check if the coordinator has no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.txns.is_empty()
+    }
+
+    /// Commit every transaction. Each is prepared in the order it was added;
+    /// if any fails to prepare, every transaction prepared so far is rolled
+    /// back and the failure is returned. Otherwise, each transaction is
+    /// committed, and their results are returned in the order they were
+    /// added.
+    ///
+    /// If a database fails internally after every transaction has been
+    /// prepared, the remaining transactions are still committed, and the
+    /// failure is returned in place of that transaction's receipt, leaving the
+    /// databases out of step.
+    pub async fn commit(
+        self,
+        durability: Durability,
+    ) -> DbResult<Result<Vec<DbResult<CommitReceipt>>, PrepareErrors>> {
+        let mut prepared = Vec::with_capacity(self.txns.len());
+        for (index, txn) in self.txns.into_iter().enumerate() {
+            let failure = match txn.prepare(durability).await {
+                Ok(Ok(p)) => {
+                    prepared.push(p);
+                    continue;
+                }
+                Ok(Err(errors)) => Ok(Err(PrepareErrors { index, errors })),
+                Err(e) => Err(e),
+            };
+            // Roll back everything, reporting the original failure over any
+            // failure to roll back.
+            for p in prepared {
+                let _ = p.rollback().await;
+            }
+            return failure;
+        }
+
+        let mut results = Vec::with_capacity(prepared.len());
+        for p in prepared {
+            results.push(p.commit().await);
+        }
+        Ok(Ok(results))
+    }
+}
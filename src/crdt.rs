@@ -0,0 +1,432 @@
+//! Conflict-free replicated data types built from entries.
+//!
+//! Groups with multiple writers need data structures that converge no matter
+//! what order updates arrive in. This module provides a few standard ones, each
+//! stored as entries attached to a parent document under a single key:
+//!
+//! - [`GCounter`]: a grow-only counter.
+//! - [`LwwRegister`]: a last-writer-wins register holding a single value.
+//! - [`OrSet`]: an observed-remove set.
+//!
+//! Each type provides a `validator` function giving the entry validator to use
+//! for its key in the parent document's schema, an `apply` function that folds
+//! in entries returned from a query, a `merge` function that combines two
+//! replicas' views, and transaction builders that stage new entries. Replicas
+//! are identified by the Identity that signed their entries, so [`GCounter`]
+//! and [`LwwRegister`] ignore unsigned entries.
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash as StdHash,
+};
+
+use fog_crypto::identity::IdentityKey;
+use fog_pack::{
+    document::Document,
+    entry::{Entry, EntryRef, NewEntry},
+    error::Error as FogError,
+    types::*,
+    validator::{
+        ArrayValidator, HashValidator, IntValidator, MapValidator, TimeValidator, Validator,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    transaction::{EntryError, Transaction},
+    DbResult,
+};
+
+/// Create, optionally sign, and stage an entry in a transaction.
+pub(crate) async fn stage<S: Serialize>(
+    txn: &mut Transaction,
+    parent: &Document,
+    key: &str,
+    data: S,
+    signer: Option<&IdentityKey>,
+) -> DbResult<Result<EntryRef, EntryError>> {
+    let entry = match NewEntry::new(key, parent, data) {
+        Ok(entry) => entry,
+        Err(e) => return Ok(Err(e.into())),
+    };
+    let entry = match signer {
+        Some(signer) => match entry.sign(signer) {
+            Ok(entry) => entry,
+            Err(e) => return Ok(Err(e.into())),
+        },
+        None => entry,
+    };
+    let e_ref = entry.reference().to_owned();
+    Ok(txn.add_new_entry(entry).await?.map(|_| e_ref))
+}
+
+#[derive(Serialize, Deserialize)]
+struct CounterEntry {
+    count: u64,
+}
+
+/// A grow-only counter. Each replica records its own running total, and the
+/// counter's value is the sum of every replica's total.
+#[derive(Clone, Debug, Default)]
+pub struct GCounter {
+    counts: HashMap<Identity, (u64, EntryRef)>,
+}
+
+impl GCounter {
+    /// Create a new, zeroed counter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entry validator for counter entries.
+    pub fn validator() -> Validator {
+        MapValidator::new()
+            .req_add("count", IntValidator::new().min(0u64).build())
+            .build()
+    }
+
+    /// The current value of the counter.
+    pub fn value(&self) -> u64 {
+        self.counts.values().map(|(count, _)| *count).sum()
+    }
+
+    /// Fold an entry into the counter. Returns true if it changed the counter.
+    pub fn apply(&mut self, entry: &Entry) -> Result<bool, FogError> {
+        let Some(signer) = entry.signer() else {
+            return Ok(false);
+        };
+        let data: CounterEntry = entry.deserialize()?;
+        match self.counts.get(signer) {
+            Some((count, _)) if *count >= data.count => Ok(false),
+            _ => {
+                let e_ref = entry.reference().to_owned();
+                self.counts.insert(signer.to_owned(), (data.count, e_ref));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Merge another view of the counter into this one.
+    pub fn merge(&mut self, other: &GCounter) {
+        for (id, (count, e_ref)) in other.counts.iter() {
+            match self.counts.get(id) {
+                Some((ours, _)) if ours >= count => (),
+                _ => {
+                    self.counts.insert(id.clone(), (*count, e_ref.clone()));
+                }
+            }
+        }
+    }
+
+    /// Stage an increment of the counter by `by`, as the replica `signer`. The
+    /// replica's previous entry, if known, is deleted in the same transaction.
+    pub async fn increment(
+        &mut self,
+        txn: &mut Transaction,
+        parent: &Document,
+        key: &str,
+        signer: &IdentityKey,
+        by: u64,
+    ) -> DbResult<Result<(), EntryError>> {
+        let (count, old) = match self.counts.get(signer.id()) {
+            Some((count, e_ref)) => (count.saturating_add(by), Some(e_ref.clone())),
+            None => (by, None),
+        };
+        let e_ref = match stage(txn, parent, key, CounterEntry { count }, Some(signer)).await? {
+            Ok(e_ref) => e_ref,
+            Err(e) => return Ok(Err(e)),
+        };
+        if let Some(old) = old {
+            txn.del_entry(&old);
+        }
+        self.counts.insert(signer.id().to_owned(), (count, e_ref));
+        Ok(Ok(()))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegisterEntry<T> {
+    time: Timestamp,
+    value: T,
+}
+
+/// The current state of a [`LwwRegister`].
+#[derive(Clone, Debug)]
+struct RegisterState<T> {
+    time: Timestamp,
+    writer: Identity,
+    value: T,
+    entry: EntryRef,
+}
+
+impl<T> RegisterState<T> {
+    /// Check if this state wins out over a write by `writer` at `time`. Ties
+    /// on time are broken by comparing the writers' encoded identities.
+    fn beats(&self, time: Timestamp, writer: &Identity) -> bool {
+        (self.time, self.writer.as_vec()) >= (time, writer.as_vec())
+    }
+}
+
+/// A last-writer-wins register. Each write is timestamped, and the write with
+/// the latest timestamp wins. Ties are broken by the writer's Identity.
+#[derive(Clone, Debug)]
+pub struct LwwRegister<T> {
+    state: Option<RegisterState<T>>,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        Self { state: None }
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> LwwRegister<T> {
+    /// Create a new, empty register.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entry validator for register entries, given a validator for the
+    /// value held in the register.
+    pub fn validator(value: Validator) -> Validator {
+        MapValidator::new()
+            .req_add("time", TimeValidator::new().build())
+            .req_add("value", value)
+            .build()
+    }
+
+    /// The current value of the register, if it has been written to.
+    pub fn value(&self) -> Option<&T> {
+        self.state.as_ref().map(|s| &s.value)
+    }
+
+    /// When the current value was written, and by whom.
+    pub fn written(&self) -> Option<(Timestamp, &Identity)> {
+        self.state.as_ref().map(|s| (s.time, &s.writer))
+    }
+
+    /// Fold an entry into the register. Returns true if it changed the
+    /// register's value.
+    pub fn apply(&mut self, entry: &Entry) -> Result<bool, FogError> {
+        let Some(signer) = entry.signer() else {
+            return Ok(false);
+        };
+        let data: RegisterEntry<T> = entry.deserialize()?;
+        if self
+            .state
+            .as_ref()
+            .is_some_and(|s| s.beats(data.time, signer))
+        {
+            return Ok(false);
+        }
+        self.state = Some(RegisterState {
+            time: data.time,
+            writer: signer.to_owned(),
+            value: data.value,
+            entry: entry.reference().to_owned(),
+        });
+        Ok(true)
+    }
+
+    /// Merge another view of the register into this one.
+    pub fn merge(&mut self, other: &LwwRegister<T>) {
+        let Some(theirs) = &other.state else { return };
+        if self
+            .state
+            .as_ref()
+            .is_some_and(|s| s.beats(theirs.time, &theirs.writer))
+        {
+            return;
+        }
+        self.state = Some(theirs.clone());
+    }
+
+    /// Stage a write of `value` to the register at time `time`. The entry
+    /// holding the previous value, if known, is deleted in the same
+    /// transaction. If the register already holds a write that beats this
+    /// one, nothing is staged and this returns false.
+    pub async fn set(
+        &mut self,
+        txn: &mut Transaction,
+        parent: &Document,
+        key: &str,
+        signer: &IdentityKey,
+        time: Timestamp,
+        value: T,
+    ) -> DbResult<Result<bool, EntryError>> {
+        if self
+            .state
+            .as_ref()
+            .is_some_and(|s| s.beats(time, signer.id()))
+        {
+            return Ok(Ok(false));
+        }
+        let data = RegisterEntry {
+            time,
+            value: value.clone(),
+        };
+        let e_ref = match stage(txn, parent, key, data, Some(signer)).await? {
+            Ok(e_ref) => e_ref,
+            Err(e) => return Ok(Err(e)),
+        };
+        if let Some(old) = self.state.take() {
+            txn.del_entry(&old.entry);
+        }
+        self.state = Some(RegisterState {
+            time,
+            writer: signer.id().to_owned(),
+            value,
+            entry: e_ref,
+        });
+        Ok(Ok(true))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SetEntry<T> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    add: Option<T>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    remove: Vec<Hash>,
+}
+
+/// An observed-remove set. Each added element is tagged with the hash of the
+/// entry that added it, and removing an element removes only the tags that
+/// have been observed. An add that happens concurrently with a remove thus
+/// survives the remove.
+#[derive(Clone, Debug)]
+pub struct OrSet<T> {
+    adds: HashMap<Hash, T>,
+    removed: HashSet<Hash>,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            adds: HashMap::new(),
+            removed: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Clone + Eq + StdHash + Serialize + DeserializeOwned> OrSet<T> {
+    /// Create a new, empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entry validator for set entries, given a validator for the set's
+    /// elements.
+    pub fn validator(elem: Validator) -> Validator {
+        MapValidator::new()
+            .opt_add("add", elem)
+            .opt_add(
+                "remove",
+                ArrayValidator::new()
+                    .items(HashValidator::new().build())
+                    .build(),
+            )
+            .build()
+    }
+
+    /// Check if an element is in the set.
+    pub fn contains(&self, elem: &T) -> bool {
+        self.live_tags(elem).next().is_some()
+    }
+
+    /// Iterate over the elements in the set.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut seen = HashSet::new();
+        self.adds
+            .iter()
+            .filter(|(tag, _)| !self.removed.contains(*tag))
+            .map(|(_, elem)| elem)
+            .filter(move |elem| seen.insert(*elem))
+    }
+
+    fn live_tags<'a>(&'a self, elem: &'a T) -> impl Iterator<Item = &'a Hash> {
+        self.adds
+            .iter()
+            .filter(move |(tag, e)| *e == elem && !self.removed.contains(*tag))
+            .map(|(tag, _)| tag)
+    }
+
+    /// Fold an entry into the set. Returns true if it changed which elements
+    /// are in the set; removing one of several additions of an element, or
+    /// adding an element already in the set, doesn't count.
+    pub fn apply(&mut self, entry: &Entry) -> Result<bool, FogError> {
+        let data: SetEntry<T> = entry.deserialize()?;
+        let touched: Vec<(T, bool)> = data
+            .remove
+            .iter()
+            .filter_map(|tag| self.adds.get(tag))
+            .chain(data.add.as_ref())
+            .map(|elem| (elem.clone(), self.contains(elem)))
+            .collect();
+        self.removed.extend(data.remove);
+        if let Some(elem) = data.add {
+            let tag = entry.hash().to_owned();
+            if !self.removed.contains(&tag) {
+                self.adds.entry(tag).or_insert(elem);
+            }
+        }
+        Ok(touched
+            .iter()
+            .any(|(elem, was)| self.contains(elem) != *was))
+    }
+
+    /// Merge another view of the set into this one.
+    pub fn merge(&mut self, other: &OrSet<T>) {
+        self.removed.extend(other.removed.iter().cloned());
+        for (tag, elem) in other.adds.iter() {
+            self.adds.entry(tag.clone()).or_insert_with(|| elem.clone());
+        }
+    }
+
+    /// Stage the addition of an element to the set.
+    pub async fn insert(
+        &mut self,
+        txn: &mut Transaction,
+        parent: &Document,
+        key: &str,
+        signer: Option<&IdentityKey>,
+        elem: T,
+    ) -> DbResult<Result<(), EntryError>> {
+        let data = SetEntry {
+            add: Some(elem.clone()),
+            remove: Vec::new(),
+        };
+        let e_ref = match stage(txn, parent, key, data, signer).await? {
+            Ok(e_ref) => e_ref,
+            Err(e) => return Ok(Err(e)),
+        };
+        self.adds.insert(e_ref.hash, elem);
+        Ok(Ok(()))
+    }
+
+    /// Stage the removal of an element from the set. Only additions of the
+    /// element that have already been observed are removed. Does nothing if
+    /// the element isn't in the set.
+    pub async fn remove(
+        &mut self,
+        txn: &mut Transaction,
+        parent: &Document,
+        key: &str,
+        signer: Option<&IdentityKey>,
+        elem: &T,
+    ) -> DbResult<Result<(), EntryError>> {
+        let tags: Vec<Hash> = self.live_tags(elem).cloned().collect();
+        if tags.is_empty() {
+            return Ok(Ok(()));
+        }
+        let data = SetEntry::<T> {
+            add: None,
+            remove: tags.clone(),
+        };
+        if let Err(e) = stage(txn, parent, key, data, signer).await? {
+            return Ok(Err(e));
+        }
+        self.removed.extend(tags);
+        Ok(Ok(()))
+    }
+}
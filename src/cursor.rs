@@ -7,16 +7,31 @@
 //! when a cursor is used to make queries: any connected node within the group
 //! may respond to the query, and it is up to the various networking
 //! implementations to deduplicate query results as best as they are able.
-use std::sync::Arc;
+use std::{cmp::Ordering, num::NonZeroU32, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use fog_pack::{document::Document, entry::Entry, query::NewQuery, types::*};
+use bytes::Bytes;
+use fog_crypto::identity::{IdentityKey, UnverifiedSignature};
+use fog_pack::{
+    document::{Document, NewDocument},
+    entry::{Entry, EntryRef},
+    query::NewQuery,
+    types::*,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::NodeInfo;
+use crate::{
+    cert::Policy,
+    complexity::QueryRejected,
+    fetch::Priority,
+    forward::{ForwardHeader, ForwardStopped},
+    limits::{Budget, RateLimit},
+    schema_fetch::SchemaInstall,
+    DbResult, NodeAddr, NodeInfo,
+};
 
-#[derive(Clone, Debug, Error)]
+#[derive(Clone, Debug, Error, Serialize, Deserialize)]
 #[non_exhaustive]
 pub enum CursorError {
     /// The navigated-to document matched the hash, but was invalid somehow - it
@@ -28,19 +43,81 @@ pub enum CursorError {
     /// cursor.
     #[error("Hash is not in current document ({0})")]
     NotInDoc(Hash),
+    /// Fulfilling the request would go over one of the limits set in the
+    /// cursor's [`CursorOpts`].
+    #[error("Cursor budget exhausted ({0})")]
+    BudgetExhausted(CursorLimit),
+    /// A chunked fetch was asked to resume from past the end of the encoded
+    /// document.
+    #[error("Offset {offset} is past the end of the document ({len} bytes)")]
+    BadOffset { offset: u64, len: u64 },
+    /// The document's schema isn't in the local database, and couldn't be
+    /// installed under the cursor's [`SchemaInstall`] policy.
+    #[error("Missing schema {0}")]
+    MissingSchema(Hash),
+    /// No reachable node could provide the document.
+    #[error("Document is unavailable ({0})")]
+    Unavailable(Hash),
+}
+
+/// One of the traversal limits that can be set in [`CursorOpts`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Error, Serialize, Deserialize)]
+pub enum CursorLimit {
+    #[error("remote requests")]
+    Requests,
+    #[error("bytes fetched")]
+    Bytes,
+    #[error("distinct peers")]
+    Peers,
 }
 
 #[derive(Clone, Copy, Debug, Error)]
 #[error("Cursor couldn't go back a step because it was already at the root")]
 pub struct CursorBackError;
 
+/// An identifier for a cursor or query, for correlating what it does across
+/// logs, events, and nodes.
+///
+/// A cursor keeps the same ID for as long as it's open, including while it's
+/// being used for a query and after going [back][CursorQuery::back] from one.
+/// Forked cursors and queries get IDs of their own. IDs are picked by the
+/// implementation, should be unique on the node that opened the cursor, and
+/// are sent along with the cursor's requests, so a gate serving a remote
+/// cursor sees the same ID as the node that opened it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TraceId(pub u64);
+
+impl std::fmt::Display for TraceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// An error tagged with the cursor or query it came from.
+#[derive(Clone, Debug, Error, Serialize, Deserialize)]
+#[error("{err} (cursor {id})")]
+pub struct Traced<E: std::error::Error + 'static> {
+    /// The ID of the cursor or query.
+    pub id: TraceId,
+    /// The error itself.
+    #[source]
+    pub err: E,
+}
+
+impl CursorError {
+    /// Tag this error with the cursor or query it came from.
+    pub fn traced(self, id: TraceId) -> Traced<CursorError> {
+        Traced { id, err: self }
+    }
+}
+
 /// A cursor for navigating through a database.
 ///
 /// A cursor is opened through a specific [`Gate`][crate::gate::Gate] or on the
 /// local database, and permits navigation through the database by following
 /// Document Hashes or making queries against Documents.
 #[async_trait]
-pub trait Cursor {
+pub trait Cursor: Send + Sync {
     /// Move the cursor forward by navigating to one of the documents linked to
     /// by the current document. Fails if the requested document hash isn't in
     /// the current document, or if the returned data hashes correctly but isn't
@@ -59,20 +136,138 @@ pub trait Cursor {
 
     /// Fork the cursor. Works like `forward` but produces a new cursor in the
     /// process - one that starts from the document it navigated to.
-    fn fork(&self) -> Box<dyn ForkCursor>;
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor>;
 
     /// Fork the cursor. Works like `forward_local` but produces a new cursor in
     /// the process - one that starts from the document it navigated to.
-    fn fork_local(&self) -> Result<Option<NewCursor>, CursorError> {
-        let fork = self.fork();
+    fn fork_local(&self, hash: &Hash) -> Result<Option<NewCursor>, CursorError> {
+        let fork = self.fork(hash);
         fork.complete_local()
     }
 
     /// Return the document the cursor is currently on.
     fn current(&self) -> Arc<Document>;
 
+    /// Return all outgoing links of the current document, along with whether
+    /// each one is weak or strong.
+    fn links(&self) -> Vec<(Hash, LinkStrength)>;
+
     /// Make a query on the current document.
     fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery>;
+
+    /// Fetch the encoded form of a document linked to by the current document,
+    /// as a stream of chunks starting from `offset` bytes in. If a fetch is
+    /// interrupted, it can be resumed by starting a new one at the offset just
+    /// past the last chunk received. Implementations should also hold on to
+    /// partially fetched documents themselves, so that a later fetch or
+    /// [`forward`][Cursor::forward] to the same document can pick up where
+    /// this one left off.
+    ///
+    /// The reassembled bytes are in the same encoding as
+    /// [`EncodedDoc::data`][crate::transaction::EncodedDoc::data], and must
+    /// be decoded and checked against the document hash before use.
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream>;
+
+    /// Store the current document in the local database as a cache entry,
+    /// keeping it resident for at least `ttl` without it being reachable from
+    /// a root. Caching an already-cached document extends its lifetime if the
+    /// new one is longer. Once the time is up, it is evicted like any other
+    /// unreachable document. Documents cached through a group's cursors count
+    /// against the group's [storage quota][crate::quota].
+    ///
+    /// Documents that link to the cached one aren't cached along with it, and
+    /// cached documents don't keep their own links resident.
+    fn cache_current(&self, ttl: Duration) -> DbResult<()>;
+
+    /// Get the cursor's trace ID.
+    fn trace_id(&self) -> TraceId;
+}
+
+#[async_trait]
+impl<C: Cursor + ?Sized> Cursor for Box<C> {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        (**self).forward(hash).await
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        (**self).forward_local(hash)
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        (**self).back()
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        (**self).fork(hash)
+    }
+
+    fn fork_local(&self, hash: &Hash) -> Result<Option<NewCursor>, CursorError> {
+        (**self).fork_local(hash)
+    }
+
+    fn current(&self) -> Arc<Document> {
+        (**self).current()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        (**self).links()
+    }
+
+    fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery> {
+        (*self).query(query)
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream> {
+        (**self).fetch_chunked(hash, offset)
+    }
+
+    fn cache_current(&self, ttl: Duration) -> DbResult<()> {
+        (**self).cache_current(ttl)
+    }
+
+    fn trace_id(&self) -> TraceId {
+        (**self).trace_id()
+    }
+}
+
+/// Options for opening a cursor. These apply to the cursor and every cursor
+/// forked from it, so limits are shared across an entire traversal.
+#[derive(Clone, Debug, Default)]
+pub struct CursorOpts {
+    /// Limit on how quickly remote requests may be made.
+    pub rate: Option<RateLimit>,
+    /// Priority given to remote document requests made by the cursor.
+    pub priority: Priority,
+    /// Maximum number of remote requests (`ops`) and bytes fetched from remote
+    /// nodes (`bytes`). Once exhausted, any operation needing a remote node
+    /// fails with [`CursorError::BudgetExhausted`]. Documents already in the
+    /// local database don't count against the budget.
+    pub budget: Budget,
+    /// Maximum number of distinct remote nodes that may be contacted.
+    pub max_peers: Option<u32>,
+    /// Open the cursor on a consistent snapshot of the local database. Every
+    /// document reachable at the time the cursor was opened stays readable,
+    /// and queries see the entries as they were at that time, until the
+    /// cursor and all cursors forked from it are dropped. This pins those
+    /// documents against garbage collection, so snapshot cursors shouldn't be
+    /// held longer than needed.
+    pub snapshot: bool,
+    /// Which missing schemas may be fetched from group members and installed
+    /// in the local database, so that documents using them can be read.
+    pub install_schemas: SchemaInstall,
+}
+
+/// Whether a document link keeps its target resident in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LinkStrength {
+    /// The link keeps the linked document from being garbage-collected.
+    Strong,
+    /// The link has been weakened, and doesn't keep the linked document
+    /// resident.
+    Weak,
+    /// The link strength isn't known, usually because the document came from a
+    /// remote node and isn't in the local database.
+    Unknown,
 }
 
 /// Successful result of forking a cursor.
@@ -80,7 +275,7 @@ pub type NewCursor = (Box<dyn Cursor>, Arc<Document>);
 
 /// An active query on a document.
 #[async_trait]
-pub trait CursorQuery {
+pub trait CursorQuery: Send + Sync {
     /// Give up on the query and return to the document the query was made against.
     fn back(self: Box<Self>) -> Box<dyn Cursor>;
 
@@ -90,18 +285,152 @@ pub trait CursorQuery {
     /// Try to get the next query update, returning `None` if no update is yet
     /// available.
     fn try_next(&self) -> Option<QueryUpdate>;
+
+    /// The strategy actually being used to merge results from multiple
+    /// sources. This is the one requested in [`DbQuery::merge`], if the
+    /// implementation supports it.
+    fn merge_strategy(&self) -> MergeStrategy;
+
+    /// Get the query's trace ID. This is separate from the ID of the cursor
+    /// the query was made with.
+    fn trace_id(&self) -> TraceId;
+
+    /// Get the trace ID of the cursor the query was made with, which is also
+    /// the ID of the cursor [`back`][Self::back] returns.
+    fn cursor_trace_id(&self) -> TraceId;
+}
+
+/// How results arriving from multiple sources are combined into a single
+/// stream for a query run over a group.
+///
+/// Queries against only the local database have a single source, and are
+/// always returned in order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MergeStrategy {
+    /// Pass results on as soon as they arrive, from whichever source. Lowest
+    /// latency, but results aren't ordered.
+    #[default]
+    Arrival,
+    /// Hold results for up to `window` to put them in [canonical
+    /// order][ResultOrd] before passing them on. A result arriving after
+    /// results that follow it have already been passed on is still returned,
+    /// out of order. Ordering is only guaranteed across all sources if every
+    /// source responds within the window.
+    Ordered {
+        /// How long to buffer results for.
+        window: Duration,
+    },
+    /// Take one result from each source in turn, so that no single fast or
+    /// prolific source crowds out the others. Each source's results stay in
+    /// the order that source returned them.
+    RoundRobin,
 }
 
 /// A full query made against a database and zero or more remote nodes.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbQuery {
     /// The fog-pack query being run against the entries attached to a document.
+    #[serde(with = "new_query")]
     pub query: NewQuery,
     /// Set to reverse the result ordering. Normally starts with the
     /// lowest-numbered.
     pub rev_order: bool,
-    /// Location of the field to order results by
+    /// Location of the field to order results by. Results are always returned
+    /// in the canonical order given by [`ResultOrd`].
     pub ordering: Option<Vec<Index>>,
+    /// Exclude entries that will expire within this duration. Entries without
+    /// a time-to-live are never excluded.
+    pub min_ttl: Option<Duration>,
+    /// Only return entries signed by an identity satisfying this policy.
+    /// Unsigned entries are never returned when this is set. The local
+    /// database enforces this directly; remote nodes are asked to apply it,
+    /// but their results must still be re-verified locally before being
+    /// passed on.
+    pub signer_policy: Option<Policy>,
+    /// Return a uniformly random sample of at most this many matching entries,
+    /// instead of all of them. Result ordering is ignored when sampling. Each
+    /// responding node samples independently, so a query run over a group may
+    /// see more than this many results in total.
+    pub sample: Option<NonZeroU32>,
+    /// Also return entries that have been deleted but are still being retained
+    /// as history. These are marked by [`QueryResult::deleted`], and can be
+    /// brought back with
+    /// [`Transaction::restore_entry`][crate::transaction::Transaction::restore_entry].
+    pub include_history: bool,
+    /// How to merge results from multiple sources. If not set, the
+    /// implementation picks one; [`CursorQuery::merge_strategy`] reports which.
+    pub merge: Option<MergeStrategy>,
+    /// Restrict which remote nodes the query is run on. Leave unset to run it
+    /// on every node the cursor can reach.
+    pub sources: Option<SourceFilter>,
+    /// Return matching entries already held locally straight away, marked as
+    /// [stale][QueryResult::stale], before the query has reached any remote
+    /// node. Results from remote nodes follow as usual, and once every node
+    /// has answered a [`QueryUpdate::Refreshed`] says which of the stale
+    /// results weren't confirmed.
+    pub revalidate: bool,
+    /// Let nodes that receive the query through a gate [forward][crate::forward]
+    /// it on to their own groups. Leave unset to only run the query on nodes
+    /// the cursor can reach directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward: Option<ForwardHeader>,
+}
+
+/// A restriction on which remote nodes a query is run on.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFilter {
+    /// If not empty, only run the query on these nodes.
+    pub only: Vec<NodeAddr>,
+    /// Never run the query on these nodes.
+    pub exclude: Vec<NodeAddr>,
+}
+
+impl SourceFilter {
+    /// Only run the query on the given nodes.
+    pub fn only(nodes: impl IntoIterator<Item = NodeAddr>) -> Self {
+        Self {
+            only: nodes.into_iter().collect(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Run the query on any node except the given ones.
+    pub fn exclude(nodes: impl IntoIterator<Item = NodeAddr>) -> Self {
+        Self {
+            only: Vec::new(),
+            exclude: nodes.into_iter().collect(),
+        }
+    }
+
+    /// Check if the query may be run on a node.
+    pub fn allows(&self, node: &NodeAddr) -> bool {
+        (self.only.is_empty() || self.only.contains(node)) && !self.exclude.contains(node)
+    }
+}
+
+impl DbQuery {
+    /// Compute a hash uniquely identifying this query when run against the
+    /// given document. Identical queries on the same document always have the
+    /// same fingerprint. Fails if the query is too large to encode as a
+    /// document.
+    pub fn fingerprint(&self, doc: &Hash) -> Result<Hash, fog_pack::error::Error> {
+        Ok(NewDocument::new(None, (doc, self))?.hash().to_owned())
+    }
+}
+
+/// Serialize a [`NewQuery`] as its key and validator.
+mod new_query {
+    use fog_pack::{query::NewQuery, validator::Validator};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(query: &NewQuery, ser: S) -> Result<S::Ok, S::Error> {
+        (query.key(), query.validator()).serialize(ser)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<NewQuery, D::Error> {
+        let (key, validator) = <(String, Validator)>::deserialize(de)?;
+        Ok(NewQuery::new(&key, validator))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -111,8 +440,46 @@ pub enum Index {
     Array(u32),
 }
 
+/// A piece of an encoded document.
+#[derive(Clone, Debug)]
+pub struct DocChunk {
+    /// Where in the encoded document this chunk starts.
+    pub offset: u64,
+    /// The total length of the encoded document.
+    pub len: u64,
+    /// The chunk's bytes.
+    pub data: Bytes,
+}
+
+/// An update from a chunked document fetch.
+#[derive(Clone, Debug)]
+pub enum ChunkUpdate {
+    /// The next chunk of the document. Chunks are always delivered in order
+    /// and without gaps.
+    Chunk(DocChunk),
+    /// Every chunk has been delivered. This is returned indefinitely once the
+    /// fetch is done.
+    Done,
+    /// The fetch failed. It may be resumed by starting a new fetch from the
+    /// offset just past the last chunk received. This is returned indefinitely
+    /// once the fetch has failed.
+    Failed(CursorError),
+}
+
+/// An ongoing chunked fetch of a document, started with
+/// [`Cursor::fetch_chunked`].
 #[async_trait]
-pub trait ForkCursor {
+pub trait ChunkStream: Send + Sync {
+    /// Get the next update from the fetch.
+    async fn next(&self) -> ChunkUpdate;
+
+    /// Get the next update from the fetch, returning `None` if there isn't
+    /// one ready yet.
+    fn try_next(&self) -> Option<ChunkUpdate>;
+}
+
+#[async_trait]
+pub trait ForkCursor: Send + Sync {
     /// Complete the opening of a new cursor, returning the document it was
     /// commanded to start from.
     async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError>;
@@ -124,6 +491,7 @@ pub trait ForkCursor {
 
 /// An indication of how useful a query was. This is advisory information for
 /// the network subsystem that returned the query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Usefulness {
     /// The received entry is correct and useful to the query maker.
     Useful,
@@ -149,6 +517,23 @@ pub struct QueryResult {
     pub docs: Vec<Arc<Document>>,
     /// The source node this result came from
     pub source: NodeInfo,
+    /// When the entry is set to expire, if it has a time-to-live.
+    pub expires: Option<Timestamp>,
+    /// If the entry has been deleted and is only being returned as history,
+    /// when it was deleted.
+    pub deleted: Option<Timestamp>,
+    /// Whether this is a locally held copy returned early by a
+    /// [revalidating][DbQuery::revalidate] query, which may since have been
+    /// changed or removed on the nodes it came from.
+    pub stale: bool,
+    /// The source node's signature over this result, if it came through a gate
+    /// that [signs its responses][crate::gate::GateSettings::sign_responses].
+    /// Always `None` for results from the local database.
+    pub provenance: Option<Provenance>,
+    /// If the result was relayed to `source` by other nodes, such as mixnet
+    /// hops or relays, the nodes it passed through, starting with the one it
+    /// originated from. Empty if `source` is where it originated.
+    pub relays: Vec<RelayHop>,
     /// Optional return to indicate how useful this result was to the query maker. Completing this
     /// can help the network eliminate poorly behaved or unhelpful nodes.
     pub useful: Box<dyn UsefulReport>,
@@ -158,14 +543,286 @@ pub struct QueryResult {
     pub fork_spawner: Box<dyn ForkSpawner>,
 }
 
+impl QueryResult {
+    /// The node the result originated from: the first relay hop if it was
+    /// relayed, or the source otherwise. Usefulness reports are about this
+    /// node, while latency and delivery failures are more likely the fault of
+    /// the relayers.
+    pub fn origin(&self) -> &NodeInfo {
+        self.relays.first().map_or(&self.source, |hop| &hop.node)
+    }
+
+    /// The nodes that passed the result on without originating it, in the
+    /// order it passed through them, ending with the source.
+    pub fn relayers(&self) -> impl Iterator<Item = &NodeInfo> {
+        let relayed = !self.relays.is_empty();
+        self.relays
+            .iter()
+            .skip(1)
+            .map(|hop| &hop.node)
+            .chain(relayed.then_some(&self.source))
+    }
+
+    /// How long until the entry expires, as of the time `now`. Returns `None`
+    /// if the entry has no time-to-live, and a zero duration if it has already
+    /// expired.
+    pub fn time_remaining(&self, now: Timestamp) -> Option<Duration> {
+        self.expires.map(|expires| time_between(now, expires))
+    }
+
+    /// Verify this result's provenance signature, given the
+    /// [fingerprint][DbQuery::fingerprint] of the query it was returned for.
+    /// Returns the signing identity if the signature is valid and was made by
+    /// one of the source node's identities.
+    pub fn verify_provenance(&self, query: &Hash) -> Option<Identity> {
+        let signer = self.provenance.as_ref()?.verify(query, self.entry.hash())?;
+        let source = [&self.source.perm_id, &self.source.eph_id];
+        source
+            .into_iter()
+            .any(|id| id.as_ref() == Some(&signer))
+            .then_some(signer)
+    }
+}
+
+/// A node that a relayed [`QueryResult`] passed through.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RelayHop {
+    /// The node.
+    pub node: NodeInfo,
+    /// The node's signature over the result, if it signs what it sends on.
+    pub provenance: Option<Provenance>,
+}
+
+/// A node's signed statement that it sent a particular entry in response to a
+/// particular query. Results carrying one can be kept as proof of what a node
+/// sent, for reputation scoring or settling disputes, and checked later by
+/// anyone.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// When the node sent the result, by its own clock.
+    pub sent: Timestamp,
+    /// The encoded signature.
+    pub signature: Bytes,
+}
+
+impl Provenance {
+    /// The hash signed over by a provenance signature.
+    pub fn signed_hash(query: &Hash, entry: &Hash, sent: Timestamp) -> Hash {
+        NewDocument::new(None, (query, entry, sent))
+            .expect("Provenance should always be serializable")
+            .hash()
+            .to_owned()
+    }
+
+    /// Sign a result being sent in response to the query with the given
+    /// fingerprint. Used by gates that sign their responses.
+    pub fn sign(key: &IdentityKey, query: &Hash, entry: &Hash, sent: Timestamp) -> Self {
+        let mut signature = Vec::new();
+        key.sign(&Self::signed_hash(query, entry, sent))
+            .encode_vec(&mut signature);
+        Self {
+            sent,
+            signature: signature.into(),
+        }
+    }
+
+    /// Verify the signature for the given query fingerprint and entry hash,
+    /// returning the signing identity if it's valid.
+    pub fn verify(&self, query: &Hash, entry: &Hash) -> Option<Identity> {
+        let sig = UnverifiedSignature::try_from(&self.signature[..]).ok()?;
+        let sig = sig
+            .verify(&Self::signed_hash(query, entry, self.sent))
+            .ok()?;
+        Some(sig.signer().to_owned())
+    }
+}
+
+/// Get the duration from `start` to `end`, saturating at zero if `end` comes
+/// first.
+pub(crate) fn time_between(start: Timestamp, end: Timestamp) -> Duration {
+    let nanos = |t: Timestamp| {
+        t.timestamp_utc() as i128 * 1_000_000_000 + t.timestamp_subsec_nanos() as i128
+    };
+    let diff = (nanos(end) - nanos(start)).max(0);
+    Duration::from_nanos(u64::try_from(diff).unwrap_or(u64::MAX))
+}
+
+/// The canonical ordering of query results.
+///
+/// Every database and every node must return ordered query results in this
+/// order, so that result streams from different sources can be merged, and
+/// compared, deterministically. Results are ordered by the value at the
+/// query's ordering field, with ties (including entries missing the field
+/// entirely) broken by the entry hash, making the ordering total for distinct
+/// entries. Reversing the query reverses the entire ordering, tie-breaks
+/// included.
+///
+/// Values are compared as follows:
+///
+/// - An entry without a value at the ordering field comes before every entry
+///   with one.
+/// - Values of different types are ordered by type: null, booleans, integers,
+///   floating-point numbers, strings, binary, arrays, maps, hashes,
+///   identities, stream IDs, lock IDs, timestamps, then lockboxes.
+/// - Integers and timestamps are ordered numerically. 32-bit and 64-bit
+///   floats are compared as 64-bit floats using IEEE 754 total ordering, with
+///   32-bit floats coming first if they're equal.
+/// - Strings, binary, and lockboxes are ordered bytewise. Identities, stream
+///   IDs, and lock IDs are ordered by their encoded bytes.
+/// - Arrays are ordered lexicographically by element, and maps
+///   lexicographically by key-value pair.
+#[derive(Clone, Debug, Default)]
+pub struct ResultOrd {
+    ordering: Option<Vec<Index>>,
+    rev_order: bool,
+}
+
+impl ResultOrd {
+    /// Create a comparator for results ordered by the given field.
+    pub fn new(ordering: Option<Vec<Index>>, rev_order: bool) -> Self {
+        Self {
+            ordering,
+            rev_order,
+        }
+    }
+
+    /// Create the comparator for a query's results.
+    pub fn for_query(query: &DbQuery) -> Self {
+        Self::new(query.ordering.clone(), query.rev_order)
+    }
+
+    /// Compare two entries in canonical order.
+    pub fn cmp_entries(&self, a: &Entry, b: &Entry) -> Ordering {
+        let ord = match &self.ordering {
+            Some(path) => {
+                let a_val = a.deserialize::<ValueRef>().ok();
+                let b_val = b.deserialize::<ValueRef>().ok();
+                let a_field = a_val.as_ref().and_then(|v| lookup(v, path));
+                let b_field = b_val.as_ref().and_then(|v| lookup(v, path));
+                match (a_field, b_field) {
+                    (None, None) => Ordering::Equal,
+                    (None, Some(_)) => Ordering::Less,
+                    (Some(_), None) => Ordering::Greater,
+                    (Some(a), Some(b)) => cmp_values(a, b),
+                }
+            }
+            None => Ordering::Equal,
+        }
+        .then_with(|| a.hash().cmp(b.hash()));
+        if self.rev_order {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    /// Compare two query results in canonical order.
+    pub fn cmp(&self, a: &QueryResult, b: &QueryResult) -> Ordering {
+        self.cmp_entries(&a.entry, &b.entry)
+    }
+
+    /// Sort query results into canonical order.
+    pub fn sort(&self, results: &mut [QueryResult]) {
+        results.sort_by(|a, b| self.cmp(a, b))
+    }
+}
+
+pub(crate) fn lookup<'a, 'b>(
+    mut val: &'b ValueRef<'a>,
+    path: &[Index],
+) -> Option<&'b ValueRef<'a>> {
+    for index in path {
+        val = match index {
+            Index::Map(key) => val.as_map()?.get(key.as_str())?,
+            Index::Array(i) => val.as_array()?.get(*i as usize)?,
+        };
+    }
+    Some(val)
+}
+
+fn type_rank(val: &ValueRef) -> u8 {
+    match val {
+        ValueRef::Null => 0,
+        ValueRef::Bool(_) => 1,
+        ValueRef::Int(_) => 2,
+        ValueRef::F32(_) | ValueRef::F64(_) => 3,
+        ValueRef::Str(_) => 4,
+        ValueRef::Bin(_) => 5,
+        ValueRef::Array(_) => 6,
+        ValueRef::Map(_) => 7,
+        ValueRef::Hash(_) => 8,
+        ValueRef::Identity(_) => 9,
+        ValueRef::StreamId(_) => 10,
+        ValueRef::LockId(_) => 11,
+        ValueRef::Timestamp(_) => 12,
+        ValueRef::DataLockbox(_)
+        | ValueRef::IdentityLockbox(_)
+        | ValueRef::StreamLockbox(_)
+        | ValueRef::LockLockbox(_) => 13,
+    }
+}
+
+fn lockbox_bytes<'a>(val: &ValueRef<'a>) -> Option<&'a [u8]> {
+    match *val {
+        ValueRef::DataLockbox(l) => Some(l.as_bytes()),
+        ValueRef::IdentityLockbox(l) => Some(l.as_bytes()),
+        ValueRef::StreamLockbox(l) => Some(l.as_bytes()),
+        ValueRef::LockLockbox(l) => Some(l.as_bytes()),
+        _ => None,
+    }
+}
+
+/// Compare two values in the canonical order described on [`ResultOrd`].
+pub fn cmp_values(a: &ValueRef, b: &ValueRef) -> Ordering {
+    use ValueRef::*;
+    match (a, b) {
+        (Null, Null) => Ordering::Equal,
+        (Bool(a), Bool(b)) => a.cmp(b),
+        (Int(a), Int(b)) => a.cmp(b),
+        (F32(a), F32(b)) => a.total_cmp(b),
+        (F64(a), F64(b)) => a.total_cmp(b),
+        (F32(a), F64(b)) => (*a as f64).total_cmp(b).then(Ordering::Less),
+        (F64(a), F32(b)) => a.total_cmp(&(*b as f64)).then(Ordering::Greater),
+        (Str(a), Str(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (Bin(a), Bin(b)) => a.cmp(b),
+        (Array(a), Array(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| cmp_values(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Map(a), Map(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|((ak, av), (bk, bv))| {
+                ak.as_bytes()
+                    .cmp(bk.as_bytes())
+                    .then_with(|| cmp_values(av, bv))
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Hash(a), Hash(b)) => a.cmp(b),
+        (Identity(a), Identity(b)) => a.as_vec().cmp(&b.as_vec()),
+        (StreamId(a), StreamId(b)) => a.as_vec().cmp(&b.as_vec()),
+        (LockId(a), LockId(b)) => a.as_vec().cmp(&b.as_vec()),
+        (Timestamp(a), Timestamp(b)) => a.cmp(b),
+        _ => type_rank(a).cmp(&type_rank(b)).then_with(|| {
+            match (lockbox_bytes(a), lockbox_bytes(b)) {
+                (Some(a), Some(b)) => a.cmp(b),
+                _ => Ordering::Equal,
+            }
+        }),
+    }
+}
+
 /// Used to fork a querying cursor into one of the documents linked to by a
 /// returned Entry.
-pub trait ForkSpawner {
+pub trait ForkSpawner: Send + Sync {
     fn fork(&self) -> Box<dyn ForkCursor>;
 }
 
 /// Used to report how useful a query result was.
-pub trait UsefulReport {
+pub trait UsefulReport: Send + Sync {
     fn report(self: Box<Self>, useful: Usefulness);
 }
 
@@ -179,4 +836,144 @@ pub enum QueryUpdate {
     NewConnection(NodeInfo),
     /// A node the query was being run on became disconnected
     LostConnection(NodeInfo),
+    /// A node has reported how many entries match the query
+    Count(CountEstimate),
+    /// A node refused to run the query, and won't return any results for it
+    Rejected(QueryRejected),
+    /// A [revalidating][DbQuery::revalidate] query has heard back from every
+    /// node it was run on.
+    Refreshed(Refresh),
+    /// A node declined to [forward][DbQuery::forward] the query any further.
+    NotForwarded(ForwardStopped),
+}
+
+/// The outcome of revalidating a query's stale results.
+#[derive(Clone, Debug, Default)]
+pub struct Refresh {
+    /// Stale results that no node returned again. These have likely been
+    /// changed or removed, and should be dropped.
+    pub unconfirmed: Vec<EntryRef>,
+}
+
+/// A count of how many entries match a query, as reported by one node.
+#[derive(Clone, Debug)]
+pub struct CountEstimate {
+    /// The number of matching entries.
+    pub count: u64,
+    /// Whether the count is exact, or only an approximation.
+    pub exact: bool,
+    /// The node that made the count.
+    pub source: NodeInfo,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fog_pack::{
+        entry::NewEntry,
+        schema::{Schema, SchemaBuilder},
+        validator::Validator,
+    };
+
+    use super::*;
+
+    /// Make an entry for each map of fields, all under the same parent.
+    fn entries(maps: &[&[(&str, Value)]]) -> Vec<Entry> {
+        let schema_doc = SchemaBuilder::new(Validator::new_any())
+            .entry_add("item", Validator::new_any(), None)
+            .build()
+            .unwrap();
+        let schema = Schema::from_doc(&schema_doc).unwrap();
+        let parent = schema
+            .validate_new_doc(NewDocument::new(Some(schema_doc.hash()), "parent").unwrap())
+            .unwrap();
+        maps.iter()
+            .map(|fields| {
+                let map: BTreeMap<&str, Value> = fields.iter().cloned().collect();
+                let entry = NewEntry::new("item", &parent, map).unwrap();
+                schema
+                    .validate_new_entry(entry)
+                    .unwrap()
+                    .complete()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn by_v(rev_order: bool) -> ResultOrd {
+        ResultOrd::new(Some(vec![Index::Map("v".into())]), rev_order)
+    }
+
+    #[test]
+    fn ties_broken_by_hash() {
+        let e = entries(&[
+            &[("v", 1.into()), ("n", 1.into())],
+            &[("v", 1.into()), ("n", 2.into())],
+        ]);
+        let by_hash = e[0].hash().cmp(e[1].hash());
+        assert!(by_hash.is_ne());
+        assert_eq!(by_v(false).cmp_entries(&e[0], &e[1]), by_hash);
+        assert_eq!(ResultOrd::default().cmp_entries(&e[0], &e[1]), by_hash);
+        assert_eq!(by_v(true).cmp_entries(&e[0], &e[1]), by_hash.reverse());
+    }
+
+    #[test]
+    fn missing_field_first() {
+        let e = entries(&[&[("n", 1.into())], &[("v", ().into())]]);
+        assert_eq!(by_v(false).cmp_entries(&e[0], &e[1]), Ordering::Less);
+        assert_eq!(by_v(true).cmp_entries(&e[0], &e[1]), Ordering::Greater);
+    }
+
+    #[test]
+    fn ordered_by_value() {
+        let e = entries(&[&[("v", 2.into())], &[("v", 10.into())]]);
+        assert_eq!(by_v(false).cmp_entries(&e[0], &e[1]), Ordering::Less);
+        assert_eq!(by_v(true).cmp_entries(&e[0], &e[1]), Ordering::Greater);
+    }
+
+    #[test]
+    fn types_ranked() {
+        let hash = Hash::new(b"hash");
+        let ranked = [
+            ValueRef::Null,
+            ValueRef::Bool(true),
+            ValueRef::Int(Integer::from(100)),
+            ValueRef::F64(-1.0),
+            ValueRef::Str(""),
+            ValueRef::Bin(&[]),
+            ValueRef::Array(Vec::new()),
+            ValueRef::Map(BTreeMap::new()),
+            ValueRef::Hash(hash),
+            ValueRef::Timestamp(Timestamp::zero()),
+        ];
+        for (i, a) in ranked.iter().enumerate() {
+            for (j, b) in ranked.iter().enumerate() {
+                assert_eq!(cmp_values(a, b), i.cmp(&j), "{a:?} vs {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn floats() {
+        use ValueRef::{F32, F64};
+        assert_eq!(cmp_values(&F32(1.0), &F64(1.0)), Ordering::Less);
+        assert_eq!(cmp_values(&F64(1.0), &F32(1.0)), Ordering::Greater);
+        assert_eq!(cmp_values(&F32(1.0), &F64(1.5)), Ordering::Less);
+        assert_eq!(cmp_values(&F32(2.0), &F64(1.5)), Ordering::Greater);
+        assert_eq!(cmp_values(&F64(-0.0), &F64(0.0)), Ordering::Less);
+        assert_eq!(cmp_values(&F64(f64::NAN), &F64(f64::NAN)), Ordering::Equal);
+        assert_eq!(
+            cmp_values(&F64(f64::NAN), &F64(f64::INFINITY)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp_values(&F32(f32::NAN), &F64(f64::INFINITY)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            cmp_values(&F64(-f64::NAN), &F64(f64::NEG_INFINITY)),
+            Ordering::Less
+        );
+    }
 }
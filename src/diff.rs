@@ -0,0 +1,492 @@
+//! Structural diffs between two document trees.
+//!
+//! Comparing two versions of a tree, like the content of two versions of a
+//! [versioned][crate::versioned] root, comes down to finding the documents
+//! that only one of them holds. [`tree_diff`] reports every document reachable
+//! from the old root but not the new one as [removed][DiffEntry::Removed], and
+//! every document reachable from the new root but not the old one as
+//! [added][DiffEntry::Added]. Where a document sits in each tree doesn't
+//! matter, so a document that only moved to a different parent isn't reported.
+//!
+//! Because documents are content-addressed, a document found in both trees
+//! has everything below it in both trees as well, and the diff doesn't look
+//! for differences below it. Deciding that a document is in only one tree can
+//! still mean walking parts the trees share, since the document might also be
+//! reachable through them; that walk stops once no document is left in
+//! question. Diffing two heads of a versioned root walks the old head's whole
+//! history this way, and finds nothing removed, since the new head links to
+//! the old one. Diff the two versions' content roots to compare the versions
+//! themselves.
+//!
+//! Removed and added documents are paired up as [changed][DiffEntry::Changed]
+//! by a single rule. The two roots are paired if neither is in the other
+//! tree. Below a changed pair, a removed document linked from the old one and
+//! an added document linked from the new one are paired if they're the only
+//! such documents with their schema under that pair, and are then paired up
+//! below in turn. Every other document is reported as removed or added.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use fog_pack::{document::Document, types::*};
+use futures::future::join_all;
+use thiserror::Error;
+
+use crate::cursor::{Cursor, CursorError};
+
+/// A difference between two document trees.
+#[derive(Clone, Debug)]
+pub enum DiffEntry {
+    /// A document only in the new tree.
+    Added(Arc<Document>),
+    /// A document only in the old tree.
+    Removed(Arc<Document>),
+    /// A document in the old tree that was replaced by one in the new tree.
+    Changed {
+        /// The document in the old tree.
+        old: Arc<Document>,
+        /// The document that replaced it in the new tree.
+        new: Arc<Document>,
+    },
+}
+
+/// A document that couldn't be reached while diffing. Nothing below it is
+/// compared.
+#[derive(Clone, Debug, Error)]
+#[error("Couldn't reach document {doc} while diffing: {error}")]
+pub struct DiffError {
+    /// The document that couldn't be reached.
+    pub doc: Hash,
+    /// Why it couldn't be reached.
+    pub error: CursorError,
+}
+
+/// Which tree a document is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Old,
+    New,
+}
+
+/// The documents found in one of the trees.
+#[derive(Default)]
+struct Tree {
+    docs: HashMap<Hash, Arc<Document>>,
+    /// The documents in the order they were found.
+    order: Vec<Hash>,
+    /// Cursors on found documents whose links haven't been followed yet.
+    queue: VecDeque<Box<dyn Cursor>>,
+}
+
+/// A running diff between two document trees, from [`tree_diff`].
+pub struct DiffStream {
+    roots: Option<(Hash, Hash)>,
+    old: Tree,
+    new: Tree,
+    /// Documents in both trees.
+    common: HashSet<Hash>,
+    /// Cursors on documents in both trees whose links haven't been followed.
+    common_queue: VecDeque<Box<dyn Cursor>>,
+    /// The links of every document followed so far.
+    links: HashMap<Hash, Vec<Hash>>,
+    /// How many found documents aren't known to be in both trees.
+    pending: usize,
+    ready: VecDeque<Result<DiffEntry, DiffError>>,
+}
+
+/// Diff the trees under the documents two cursors are on. `old` is the tree
+/// being compared against, and `new` the tree it's compared to.
+pub fn tree_diff(old: Box<dyn Cursor>, new: Box<dyn Cursor>) -> DiffStream {
+    let mut stream = DiffStream {
+        roots: None,
+        old: Tree::default(),
+        new: Tree::default(),
+        common: HashSet::new(),
+        common_queue: VecDeque::new(),
+        links: HashMap::new(),
+        pending: 0,
+        ready: VecDeque::new(),
+    };
+    let (old_doc, new_doc) = (old.current(), new.current());
+    if old_doc.hash() != new_doc.hash() {
+        stream.roots = Some((old_doc.hash().clone(), new_doc.hash().clone()));
+        stream.found(Side::Old, old, old_doc);
+        stream.found(Side::New, new, new_doc);
+    }
+    stream
+}
+
+impl DiffStream {
+    /// Get the next difference, or `None` once the diff is done. Differences
+    /// are only known once both trees have been walked, so the first call
+    /// does most of the work.
+    pub async fn next(&mut self) -> Option<Result<DiffEntry, DiffError>> {
+        loop {
+            if let Some(item) = self.ready.pop_front() {
+                return Some(item);
+            }
+            self.roots.as_ref()?;
+            if !self.step().await {
+                self.finish();
+            }
+        }
+    }
+
+    /// Collect every remaining difference.
+    pub async fn collect(mut self) -> Vec<Result<DiffEntry, DiffError>> {
+        let mut out = Vec::new();
+        while let Some(item) = self.next().await {
+            out.push(item);
+        }
+        out
+    }
+
+    fn tree(&mut self, side: Side) -> &mut Tree {
+        match side {
+            Side::Old => &mut self.old,
+            Side::New => &mut self.new,
+        }
+    }
+
+    fn other(&self, side: Side) -> &Tree {
+        match side {
+            Side::Old => &self.new,
+            Side::New => &self.old,
+        }
+    }
+
+    /// Follow one queued document's links on each side, and one of the
+    /// shared documents' if anything is still in question. Returns false
+    /// once there's nothing left worth following.
+    async fn step(&mut self) -> bool {
+        let mut progressed = false;
+        for side in [Side::Old, Side::New] {
+            if let Some(cursor) = self.tree(side).queue.pop_front() {
+                self.expand(side, cursor).await;
+                progressed = true;
+            }
+        }
+        if self.pending > 0 {
+            if let Some(cursor) = self.common_queue.pop_front() {
+                self.expand_common(cursor).await;
+                progressed = true;
+            }
+        }
+        progressed
+    }
+
+    /// Record a document found on one side, queueing it to be followed.
+    fn found(&mut self, side: Side, cursor: Box<dyn Cursor>, doc: Arc<Document>) {
+        let hash = doc.hash().clone();
+        let tree = self.tree(side);
+        tree.docs.insert(hash.clone(), doc);
+        tree.order.push(hash);
+        tree.queue.push_back(cursor);
+        self.pending += 1;
+    }
+
+    /// Mark a document as being in both trees, along with everything below it
+    /// that's been found so far.
+    fn mark_common(&mut self, hash: Hash) {
+        let mut stack = vec![hash];
+        while let Some(hash) = stack.pop() {
+            if self.common.contains(&hash) {
+                continue;
+            }
+            if self.old.docs.contains_key(&hash) {
+                self.pending -= 1;
+            }
+            if self.new.docs.contains_key(&hash) {
+                self.pending -= 1;
+            }
+            if let Some(links) = self.links.get(&hash) {
+                stack.extend(links.iter().cloned());
+            }
+            self.common.insert(hash);
+        }
+    }
+
+    /// Record a document's links, returning the ones that still need to be
+    /// opened: those not yet found in either tree or known to be in both.
+    fn record_links(&mut self, cursor: &dyn Cursor) -> Vec<Hash> {
+        let mut links: Vec<Hash> = cursor.links().into_iter().map(|(h, _)| h).collect();
+        links.sort();
+        links.dedup();
+        self.links
+            .insert(cursor.current().hash().clone(), links.clone());
+        links
+    }
+
+    /// Open cursors on documents linked from `cursor`, reporting any that
+    /// can't be reached.
+    async fn open(
+        &mut self,
+        cursor: &dyn Cursor,
+        links: Vec<Hash>,
+    ) -> Vec<(Box<dyn Cursor>, Arc<Document>)> {
+        let forks = links.iter().map(|hash| cursor.fork(hash).complete());
+        let mut opened = Vec::with_capacity(links.len());
+        for (hash, res) in links.iter().zip(join_all(forks).await) {
+            match res {
+                Ok(new) => opened.push(new),
+                Err(error) => self.ready.push_back(Err(DiffError {
+                    doc: hash.clone(),
+                    error,
+                })),
+            }
+        }
+        opened
+    }
+
+    /// Follow the links of a document found on one side.
+    async fn expand(&mut self, side: Side, cursor: Box<dyn Cursor>) {
+        let hash = cursor.current().hash().clone();
+        if self.links.contains_key(&hash) {
+            return;
+        }
+        if self.common.contains(&hash) {
+            self.common_queue.push_back(cursor);
+            return;
+        }
+        let mut unknown = Vec::new();
+        for link in self.record_links(cursor.as_ref()) {
+            if self.common.contains(&link) || self.tree(side).docs.contains_key(&link) {
+                continue;
+            }
+            if self.other(side).docs.contains_key(&link) {
+                self.mark_common(link);
+            } else {
+                unknown.push(link);
+            }
+        }
+        for (cursor, doc) in self.open(cursor.as_ref(), unknown).await {
+            self.found(side, cursor, doc);
+        }
+    }
+
+    /// Follow the links of a document in both trees, marking everything below
+    /// it as being in both.
+    async fn expand_common(&mut self, cursor: Box<dyn Cursor>) {
+        if self.links.contains_key(cursor.current().hash()) {
+            return;
+        }
+        let mut unknown = Vec::new();
+        for link in self.record_links(cursor.as_ref()) {
+            if self.common.contains(&link) {
+                continue;
+            }
+            if self.old.docs.contains_key(&link) || self.new.docs.contains_key(&link) {
+                self.mark_common(link);
+            } else {
+                unknown.push(link);
+            }
+        }
+        for (cursor, doc) in self.open(cursor.as_ref(), unknown).await {
+            self.mark_common(doc.hash().clone());
+            self.common_queue.push_back(cursor);
+        }
+    }
+
+    /// Report everything found in only one tree, pairing up changed documents.
+    fn finish(&mut self) {
+        let Some((old_root, new_root)) = self.roots.take() else {
+            return;
+        };
+        let removed =
+            |s: &Self, hash: &Hash| s.old.docs.contains_key(hash) && !s.common.contains(hash);
+        let added =
+            |s: &Self, hash: &Hash| s.new.docs.contains_key(hash) && !s.common.contains(hash);
+
+        let mut paired = HashSet::new();
+        let mut pairs = VecDeque::new();
+        if removed(self, &old_root) && added(self, &new_root) {
+            pairs.push_back((old_root.clone(), new_root.clone()));
+            paired.insert(old_root);
+            paired.insert(new_root);
+        }
+        while let Some((old, new)) = pairs.pop_front() {
+            self.ready.push_back(Ok(DiffEntry::Changed {
+                old: self.old.docs[&old].clone(),
+                new: self.new.docs[&new].clone(),
+            }));
+            // Pair up documents by schema where there's exactly one on each
+            // side.
+            let mut by_schema: HashMap<Option<&Hash>, (Vec<&Hash>, Vec<&Hash>)> = HashMap::new();
+            let links = |hash: &Hash| self.links.get(hash).into_iter().flatten();
+            for link in links(&old) {
+                if removed(self, link) && !paired.contains(link) {
+                    let schema = self.old.docs[link].schema_hash();
+                    by_schema.entry(schema).or_default().0.push(link);
+                }
+            }
+            for link in links(&new) {
+                if added(self, link) && !paired.contains(link) {
+                    let schema = self.new.docs[link].schema_hash();
+                    by_schema.entry(schema).or_default().1.push(link);
+                }
+            }
+            let mut found = Vec::new();
+            for (olds, news) in by_schema.into_values() {
+                if let ([o], [n]) = (&olds[..], &news[..]) {
+                    found.push(((*o).clone(), (*n).clone()));
+                }
+            }
+            for (o, n) in found {
+                paired.insert(o.clone());
+                paired.insert(n.clone());
+                pairs.push_back((o, n));
+            }
+        }
+
+        for hash in &self.old.order {
+            if removed(self, hash) && !paired.contains(hash) {
+                self.ready
+                    .push_back(Ok(DiffEntry::Removed(self.old.docs[hash].clone())));
+            }
+        }
+        for hash in &self.new.order {
+            if added(self, hash) && !paired.contains(hash) {
+                self.ready
+                    .push_back(Ok(DiffEntry::Added(self.new.docs[hash].clone())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fog_pack::document::NewDocument;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        cursor::CursorOpts,
+        memory::MemDb,
+        transaction::{Durability, Transaction},
+        versioned::{HistoryLinks, VersionedRoot},
+        Db,
+    };
+
+    fn add(txn: &mut Transaction, content: impl serde::Serialize) -> Hash {
+        let doc = NewDocument::new(None, content).unwrap();
+        block_on(txn.add_new_doc(doc))
+            .ok()
+            .unwrap()
+            .unwrap()
+            .hash()
+            .clone()
+    }
+
+    fn diff(db: &MemDb, old: &Hash, new: &Hash) -> Vec<DiffEntry> {
+        block_on(async {
+            let (old, _) = db
+                .cursor(old, CursorOpts::default())
+                .await
+                .ok()
+                .unwrap()
+                .unwrap();
+            let (new, _) = db
+                .cursor(new, CursorOpts::default())
+                .await
+                .ok()
+                .unwrap()
+                .unwrap();
+            tree_diff(old, new)
+                .collect()
+                .await
+                .into_iter()
+                .map(|res| res.unwrap())
+                .collect()
+        })
+    }
+
+    type Hashes = (HashSet<Hash>, HashSet<Hash>, HashSet<(Hash, Hash)>);
+
+    /// Split diff entries into the added, removed, and changed hashes.
+    fn hashes(entries: &[DiffEntry]) -> Hashes {
+        let mut out = Hashes::default();
+        for entry in entries {
+            match entry {
+                DiffEntry::Added(doc) => {
+                    out.0.insert(doc.hash().clone());
+                }
+                DiffEntry::Removed(doc) => {
+                    out.1.insert(doc.hash().clone());
+                }
+                DiffEntry::Changed { old, new } => {
+                    out.2.insert((old.hash().clone(), new.hash().clone()));
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn versioned_heads() {
+        let db = MemDb::default();
+        let root = VersionedRoot::new("app/root", HistoryLinks::Strong);
+        let publish = |content: &Hash, txn: Transaction| {
+            block_on(root.update(&db, txn, content, Durability::default()))
+                .ok()
+                .unwrap()
+                .ok()
+                .unwrap()
+        };
+
+        let mut txn = db.txn();
+        let shared = add(&mut txn, "shared");
+        let leaf_0 = add(&mut txn, "leaf 0");
+        let content_0 = add(&mut txn, vec![shared.clone(), leaf_0.clone()]);
+        let head_0 = publish(&content_0, txn);
+
+        let mut txn = db.txn();
+        let leaf_1 = add(&mut txn, "leaf 1");
+        let content_1 = add(&mut txn, vec![shared.clone(), leaf_1.clone()]);
+        let head_1 = publish(&content_1, txn);
+
+        let mut txn = db.txn();
+        let leaf_2 = add(&mut txn, "leaf 2");
+        // The old leaf moves under a new parent, which isn't a change.
+        let moved = add(&mut txn, vec![leaf_1.clone()]);
+        let content_2 = add(
+            &mut txn,
+            vec![shared.clone(), leaf_2.clone(), moved.clone()],
+        );
+        let head_2 = publish(&content_2, txn);
+
+        // The new head links to the old one, so nothing is removed, and
+        // neither older heads nor anything shared is reported.
+        let (added, removed, changed) = hashes(&diff(&db, &head_1, &head_2));
+        let expected: HashSet<Hash> = [&head_2, &content_2, &leaf_2, &moved]
+            .into_iter()
+            .cloned()
+            .collect();
+        assert_eq!(added, expected);
+        assert!(removed.is_empty());
+        assert!(changed.is_empty());
+        assert!(!added.contains(&head_0));
+
+        // Comparing content pairs the roots, and then the lone documents
+        // with the same schema below them.
+        let (added, removed, changed) = hashes(&diff(&db, &content_0, &content_1));
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+        let expected: HashSet<(Hash, Hash)> = [
+            (content_0.clone(), content_1.clone()),
+            (leaf_0.clone(), leaf_1.clone()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(changed, expected);
+
+        // A document that only moved isn't reported.
+        let (added, removed, changed) = hashes(&diff(&db, &content_1, &content_2));
+        assert!(removed.is_empty());
+        assert_eq!(changed.len(), 1);
+        assert!(!added.contains(&leaf_1));
+        let expected: HashSet<Hash> = [&leaf_2, &moved].into_iter().cloned().collect();
+        assert_eq!(added, expected);
+    }
+}
@@ -0,0 +1,118 @@
+//! Peer discovery interface.
+//!
+//! Finding other nodes is separate from connecting to them. A [`Discovery`]
+//! provider (mDNS, a DHT, a rendezvous server, ...) announces this node's
+//! presence under a group key and streams back candidate peers, each tagged
+//! with the [transport][crate::transport] that can dial it. This lets
+//! discovery mechanisms and transports be mixed and matched per
+//! [`GroupSpec`].
+//!
+//! A group key is any hash the members of a group agree upon ahead of time,
+//! such as the context hash of the group's [`Policy`][crate::cert::Policy].
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use fog_pack::types::*;
+use thiserror::Error;
+
+use crate::{
+    group::GroupSpec,
+    transport::{PeerCandidate, PeerStream},
+    NetInfo, NetType,
+};
+
+/// Failure within a discovery provider.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum DiscoveryError {
+    /// The provider doesn't operate on the requested network.
+    #[error("Discovery provider doesn't operate on network {0:?}")]
+    UnsupportedNet(NetType),
+    /// Some other provider-specific failure occurred.
+    #[error("Discovery failure: {0}")]
+    Other(String),
+}
+
+/// An active announcement of this node's presence. Dropping it withdraws the
+/// announcement.
+pub trait Announcement: Send + Sync {
+    /// Explicitly withdraw the announcement - should be equivalent to calling
+    /// `drop(announcement)`.
+    fn withdraw(self: Box<Self>);
+}
+
+/// A peer discovery provider.
+#[async_trait]
+pub trait Discovery: Send + Sync {
+    /// A unique name for this provider.
+    fn name(&self) -> &str;
+
+    /// The network types this provider can operate on.
+    fn net_types(&self) -> Vec<NetType>;
+
+    /// Announce this node's presence for a group key on a network, advertising
+    /// the given addresses as ways to reach it.
+    async fn announce(
+        &self,
+        key: &Hash,
+        net: &NetType,
+        addrs: Vec<PeerCandidate>,
+    ) -> Result<Box<dyn Announcement>, DiscoveryError>;
+
+    /// Start streaming candidate peers that have announced themselves for a
+    /// group key, on any of the networks permitted by `net`.
+    fn discover(&self, key: &Hash, net: &NetInfo) -> Result<Box<dyn PeerStream>, DiscoveryError>;
+}
+
+/// A collection of discovery providers, keyed by name.
+#[derive(Clone, Default)]
+pub struct DiscoveryRegistry {
+    providers: BTreeMap<String, Arc<dyn Discovery>>,
+}
+
+impl DiscoveryRegistry {
+    /// Create a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a provider to the registry, returning the previous provider with
+    /// the same name, if there was one.
+    pub fn register(&mut self, provider: Arc<dyn Discovery>) -> Option<Arc<dyn Discovery>> {
+        self.providers.insert(provider.name().to_owned(), provider)
+    }
+
+    /// Remove a provider from the registry.
+    pub fn unregister(&mut self, name: &str) -> Option<Arc<dyn Discovery>> {
+        self.providers.remove(name)
+    }
+
+    /// Get a provider by name.
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Discovery>> {
+        self.providers.get(name)
+    }
+
+    /// Iterate over all registered providers.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Discovery>> {
+        self.providers.values()
+    }
+
+    /// Iterate over the providers a group with the given specification should
+    /// use: those listed in the spec (or all of them, if none are listed) that
+    /// operate on at least one permitted network.
+    pub fn for_spec<'a>(
+        &'a self,
+        spec: &'a GroupSpec,
+    ) -> impl Iterator<Item = &'a Arc<dyn Discovery>> {
+        self.providers
+            .iter()
+            .filter(|(name, _)| {
+                spec.discovery
+                    .as_ref()
+                    .is_none_or(|list| list.iter().any(|n| n == *name))
+            })
+            .map(|(_, p)| p)
+            .filter(|p| p.net_types().iter().any(|n| spec.net.allows(n)))
+    }
+}
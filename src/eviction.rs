@@ -0,0 +1,115 @@
+//! Hooks for delaying or vetoing garbage collection of documents.
+//!
+//! Documents are normally evicted as soon as they can't be reached from a root
+//! document, a quota-backed [pin][crate::pinning], an open snapshot, or an
+//! unexpired [cache entry][crate::cursor::Cursor::cache_current]. That's
+//! too blunt when the database is also acting as a cache: a document read a
+//! moment ago is likely to be read again, and some documents are worth
+//! keeping for reasons the database can't see. Before evicting a document, the
+//! garbage collector puts it to every [`EvictionPolicy`] in the database's
+//! [`EvictionRegistry`], and any one of them can hold it back.
+//!
+//! Policies are consulted on the garbage collector's hot path, so they should
+//! answer quickly and without blocking.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use fog_pack::types::*;
+
+/// A document the garbage collector is about to evict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvictionCandidate {
+    /// The document's hash.
+    pub doc: Hash,
+    /// The document's schema, if it has one.
+    pub schema: Option<Hash>,
+    /// The encoded size of the document, in bytes.
+    pub bytes: u64,
+    /// When the document was stored in the database.
+    pub stored: Timestamp,
+    /// When the document was last read, if the database tracks this.
+    pub last_access: Option<Timestamp>,
+}
+
+/// A policy's decision on an eviction candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EvictionVerdict {
+    /// The policy doesn't object to evicting the document.
+    Evict,
+    /// Don't evict the document for at least this long. It will be considered
+    /// again afterwards if it's still unreachable.
+    Delay(Duration),
+    /// Don't evict the document. It will be considered again on the next
+    /// garbage collection pass.
+    Keep,
+}
+
+impl EvictionVerdict {
+    /// Combine two verdicts, keeping the more conservative one.
+    pub fn and(self, other: Self) -> Self {
+        use EvictionVerdict::*;
+        match (self, other) {
+            (Keep, _) | (_, Keep) => Keep,
+            (Delay(a), Delay(b)) => Delay(a.max(b)),
+            (Delay(d), Evict) | (Evict, Delay(d)) => Delay(d),
+            (Evict, Evict) => Evict,
+        }
+    }
+}
+
+/// A hook consulted before a document is evicted.
+pub trait EvictionPolicy: Send + Sync {
+    /// A unique name for this policy.
+    fn name(&self) -> &str;
+
+    /// Decide whether a document may be evicted.
+    fn consider(&self, candidate: &EvictionCandidate) -> EvictionVerdict;
+}
+
+/// A collection of eviction policies, keyed by name.
+#[derive(Clone, Default)]
+pub struct EvictionRegistry {
+    policies: BTreeMap<String, Arc<dyn EvictionPolicy>>,
+}
+
+impl EvictionRegistry {
+    /// Create a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a policy to the registry, returning the previous policy with the
+    /// same name, if there was one.
+    pub fn register(&mut self, policy: Arc<dyn EvictionPolicy>) -> Option<Arc<dyn EvictionPolicy>> {
+        self.policies.insert(policy.name().to_owned(), policy)
+    }
+
+    /// Remove a policy from the registry.
+    pub fn unregister(&mut self, name: &str) -> Option<Arc<dyn EvictionPolicy>> {
+        self.policies.remove(name)
+    }
+
+    /// Get a policy by name.
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn EvictionPolicy>> {
+        self.policies.get(name)
+    }
+
+    /// Iterate over all registered policies.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn EvictionPolicy>> {
+        self.policies.values()
+    }
+
+    /// Consult every registered policy on a candidate, returning the most
+    /// conservative verdict. Stops early once a policy says to keep the
+    /// document.
+    pub fn consider(&self, candidate: &EvictionCandidate) -> EvictionVerdict {
+        let mut verdict = EvictionVerdict::Evict;
+        for policy in self.policies.values() {
+            verdict = verdict.and(policy.consider(candidate));
+            if verdict == EvictionVerdict::Keep {
+                break;
+            }
+        }
+        verdict
+    }
+}
@@ -0,0 +1,89 @@
+//! When entries with a time-to-live expire, and how they're swept away.
+//!
+//! An entry added with a time-to-live expires once that time has passed by
+//! the local clock, allowing for the database's
+//! [clock skew tolerance][crate::skew::SkewPolicy::is_expired]. Expiry is
+//! logical and immediate: from the moment an entry expires, it's treated as
+//! missing everywhere, whether or not it has been removed from storage yet.
+//! Specifically, an expired entry:
+//!
+//! - Is never returned by a query, counted by
+//!   [`Db::entry_count`][crate::Db::entry_count], or reported by an
+//!   [entry watch][crate::changes::EntryWatch].
+//! - Can't be modified, deleted, or restored by a transaction, which fails
+//!   with [`CommitError::MissingEntry`][crate::transaction::CommitError::MissingEntry].
+//! - Isn't kept as deleted history, so it can't be queried with
+//!   [`include_history`][crate::cursor::DbQuery::include_history].
+//! - Can be added again, which gives it a fresh time-to-live.
+//!
+//! Removing expired entries from storage is called sweeping. Sweeps aren't
+//! transactions: they don't appear in the [change feed][crate::changes], and
+//! don't leave [tombstones][crate::tombstone], since every node expires
+//! entries on its own clock. Each sweep removes the entries that expired
+//! earliest first.
+//!
+//! Databases sweep in the background, as set by the [`SweepConfig`] they were
+//! opened with. A dataset where many entries share an expiry time would
+//! otherwise see them all swept at once, stalling everything else; the
+//! background sweep's rate limit spreads that work out instead. Sweeps can
+//! also be run directly, within a [`SweepBudget`], with
+//! [`Db::ttl_sweep`][crate::Db::ttl_sweep], for instance to sweep only when the
+//! device is idle.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::limits::RateLimit;
+
+/// How much work a single sweep may do. Unset limits aren't enforced, so the
+/// default sweeps everything that has expired.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SweepBudget {
+    /// The most entries to remove.
+    pub max_entries: Option<u64>,
+    /// The longest to spend sweeping.
+    pub max_time: Option<Duration>,
+}
+
+/// What a sweep did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SweepReport {
+    /// How many expired entries were removed.
+    pub swept: u64,
+    /// Whether expired entries were left behind because the budget ran out.
+    pub remaining: bool,
+    /// How long the sweep took.
+    pub elapsed: Duration,
+}
+
+/// How a database sweeps expired entries in the background.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SweepConfig {
+    /// Sweep in the background. If off, expired entries are only removed by
+    /// calling [`Db::ttl_sweep`][crate::Db::ttl_sweep].
+    pub auto: bool,
+    /// How often to run a background sweep.
+    pub interval: Duration,
+    /// The budget for each background sweep.
+    pub budget: SweepBudget,
+    /// Limit on how quickly background sweeps remove entries, counted across
+    /// all sweeps. Expired entries past the limit wait for a later sweep.
+    pub rate: Option<RateLimit>,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            interval: Duration::from_secs(60),
+            budget: SweepBudget {
+                max_entries: None,
+                max_time: Some(Duration::from_millis(100)),
+            },
+            rate: None,
+        }
+    }
+}
@@ -0,0 +1,894 @@
+//! Fault injection, for testing how applications cope with an unreliable
+//! database.
+//!
+//! [`FaultDb`], [`FaultCommit`], and [`FaultCursor`] wrap any [`Db`],
+//! [`DbCommit`], or [`Cursor`] and pass calls through to it, except that a
+//! shared [`FaultInjector`] may fail them first. Depending on the injector's
+//! [`FaultConfig`], calls can:
+//!
+//! - Fail with a transient [`DbError::Internal`] holding an [`InjectedFault`],
+//!   or a [`CursorError::Unavailable`] for cursor operations.
+//! - Be slowed down before being passed through.
+//! - Have commits rejected with [`CommitError::Rejected`], handing the changes
+//!   back as they would be for any other failed commit. When several change sets
+//!   are committed together, each is rejected independently, so some may
+//!   succeed while others fail.
+//!
+//! Random faults are drawn from a generator seeded by [`FaultConfig::seed`].
+//! For exact control, [`FaultInjector::fail_next`] and
+//! [`FaultInjector::reject_next`] force the next few calls to fail regardless
+//! of the configured rates.
+//!
+//! Slowdowns use real time. This module is only available with the `sim`
+//! feature.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use fog_pack::{
+    document::Document, entry::EntryRef, error::Error as FogError, query::NewQuery, schema::Schema,
+    types::*,
+};
+use futures_timer::Delay;
+use thiserror::Error;
+
+use crate::{
+    access, backpressure, capabilities, changes, compression,
+    coordinator::PreparedCommit,
+    cursor::{
+        ChunkStream, ChunkUpdate, Cursor, CursorBackError, CursorError, CursorOpts, CursorQuery,
+        DbQuery, ForkCursor, LinkStrength, MergeStrategy, NewCursor, QueryUpdate, TraceId,
+    },
+    discovery, eviction, expiry, fetch, fingerprint, gc, group, health, import, journal, mixnet, names,
+    retention, runtime,
+    sim::SimRng,
+    skew, stats,
+    transaction::{
+        ChangeSet, CommitError, CommitErrors, CommitReceipt, DocChange, Durability, EntryChange,
+        NameChange, Transaction,
+    },
+    transport, validate, weak_refs, Db, DbCommit, DbError, DbResult, GroupSpec,
+};
+
+/// The error held by [`DbError::Internal`] for injected failures.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("Injected fault in {0}")]
+pub struct InjectedFault(pub &'static str);
+
+/// How often a [`FaultInjector`] injects each kind of fault.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaultConfig {
+    /// Seed for the random choice of which calls fail.
+    pub seed: u64,
+    /// Probability that a call fails with a transient error, between 0 and 1.
+    pub error_rate: f64,
+    /// Probability that a commit, or each change set in a multi-commit, is
+    /// rejected, between 0 and 1.
+    pub reject_rate: f64,
+    /// Probability that an async call is slowed down, between 0 and 1.
+    pub slow_rate: f64,
+    /// How long slowed-down calls are delayed for.
+    pub slow_by: Duration,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            error_rate: 0.0,
+            reject_rate: 0.0,
+            slow_rate: 0.0,
+            slow_by: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Counts of the faults injected so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FaultStats {
+    /// Calls failed with a transient error.
+    pub errors: u64,
+    /// Commits or change sets rejected.
+    pub rejections: u64,
+    /// Calls slowed down.
+    pub slowdowns: u64,
+}
+
+struct FaultState {
+    config: FaultConfig,
+    rng: SimRng,
+    fail_next: u32,
+    reject_next: u32,
+    stats: FaultStats,
+}
+
+/// Decides which calls fail. Cloning gives another handle to the same
+/// injector, so one injector can be shared by a database and everything
+/// opened through it.
+#[derive(Clone)]
+pub struct FaultInjector {
+    inner: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjector {
+    /// Create a new injector.
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(FaultState {
+                config,
+                rng: SimRng::new(config.seed),
+                fail_next: 0,
+                reject_next: 0,
+                stats: FaultStats::default(),
+            })),
+        }
+    }
+
+    /// Get the injector's settings.
+    pub fn config(&self) -> FaultConfig {
+        self.inner.lock().unwrap().config
+    }
+
+    /// Change the injector's settings. The random number generator is not
+    /// reseeded.
+    pub fn set_config(&self, config: FaultConfig) {
+        self.inner.lock().unwrap().config = config;
+    }
+
+    /// Fail the next `count` calls with a transient error.
+    pub fn fail_next(&self, count: u32) {
+        self.inner.lock().unwrap().fail_next = count;
+    }
+
+    /// Reject the next `count` commits or change sets.
+    pub fn reject_next(&self, count: u32) {
+        self.inner.lock().unwrap().reject_next = count;
+    }
+
+    /// Get counts of the faults injected so far.
+    pub fn stats(&self) -> FaultStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    fn roll_error(&self) -> bool {
+        let mut state = self.inner.lock().unwrap();
+        let fail = if state.fail_next > 0 {
+            state.fail_next -= 1;
+            true
+        } else {
+            let rate = state.config.error_rate;
+            state.rng.chance(rate)
+        };
+        if fail {
+            state.stats.errors += 1;
+        }
+        fail
+    }
+
+    fn roll_reject(&self) -> bool {
+        let mut state = self.inner.lock().unwrap();
+        let reject = if state.reject_next > 0 {
+            state.reject_next -= 1;
+            true
+        } else {
+            let rate = state.config.reject_rate;
+            state.rng.chance(rate)
+        };
+        if reject {
+            state.stats.rejections += 1;
+        }
+        reject
+    }
+
+    /// Fail with an injected error, or pass.
+    fn check(&self, op: &'static str) -> DbResult<()> {
+        if self.roll_error() {
+            Err(injected(op))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get a future that may delay for a while.
+    fn slow(&self) -> impl Future<Output = ()> + Send {
+        let delay = {
+            let mut state = self.inner.lock().unwrap();
+            let config = state.config;
+            let slow = state.rng.chance(config.slow_rate);
+            if slow {
+                state.stats.slowdowns += 1;
+            }
+            slow.then_some(config.slow_by)
+        };
+        async move {
+            if let Some(delay) = delay {
+                Delay::new(delay).await;
+            }
+        }
+    }
+}
+
+fn injected(op: &'static str) -> Box<DbError> {
+    Box::new(DbError::Internal(Box::new(InjectedFault(op))))
+}
+
+fn rejected(changes: ChangeSet) -> CommitErrors {
+    let (docs, entries, names) = changes;
+    CommitErrors {
+        docs,
+        entries,
+        names,
+        errors: vec![CommitError::Rejected("Injected fault".into())],
+    }
+}
+
+/// A [`Db`] that injects faults into calls made on it, and on the
+/// transactions and cursors opened through it.
+pub struct FaultDb<D> {
+    inner: D,
+    faults: FaultInjector,
+}
+
+impl<D: Db> FaultDb<D> {
+    /// Wrap a database.
+    pub fn new(inner: D, faults: FaultInjector) -> Self {
+        Self { inner, faults }
+    }
+
+    /// Get the injector deciding which calls fail.
+    pub fn faults(&self) -> &FaultInjector {
+        &self.faults
+    }
+
+    /// Get the wrapped database.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Unwrap the database.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<D: Db> Db for FaultDb<D> {
+    fn txn(&self) -> Transaction {
+        let faults = self.faults.clone();
+        self.inner
+            .txn()
+            .wrap_commit(|db| Box::new(FaultCommit::new(db, faults)))
+    }
+
+    fn commit_permit(&self) -> Box<dyn backpressure::PermitRequest> {
+        self.inner.commit_permit()
+    }
+
+    fn bulk_import(&self) -> Box<dyn import::BulkImport> {
+        self.inner.bulk_import()
+    }
+
+    async fn current_seq(&self) -> DbResult<changes::CommitSeq> {
+        self.inner.current_seq().await
+    }
+
+    async fn changes_since(
+        &self,
+        seq: changes::CommitSeq,
+    ) -> DbResult<Result<Box<dyn changes::ChangeFeed>, changes::SeqTooOld>> {
+        self.faults.check("changes_since")?;
+        self.inner.changes_since(seq).await
+    }
+
+    async fn entry_watch(&self, doc: &Hash, key: &str) -> DbResult<Box<dyn changes::EntryWatch>> {
+        self.faults.check("entry_watch")?;
+        self.inner.entry_watch(doc, key).await
+    }
+
+    fn group(&self, spec: GroupSpec) -> Box<dyn group::Group> {
+        self.inner.group(spec)
+    }
+
+    fn transports(&self) -> &transport::TransportRegistry {
+        self.inner.transports()
+    }
+
+    fn discovery(&self) -> &discovery::DiscoveryRegistry {
+        self.inner.discovery()
+    }
+
+    fn runtime(&self) -> &dyn runtime::NodeRuntime {
+        self.inner.runtime()
+    }
+
+    fn mixnet(&self) -> Option<&dyn mixnet::Mixnet> {
+        self.inner.mixnet()
+    }
+
+    fn journal(&self) -> Option<&dyn journal::Journal> {
+        self.inner.journal()
+    }
+
+    fn eviction_policies(&self) -> &eviction::EvictionRegistry {
+        self.inner.eviction_policies()
+    }
+
+    async fn gc_preview(&self, change: gc::ProposedChange) -> DbResult<gc::GcPreview> {
+        self.faults.check("gc_preview")?;
+        self.inner.gc_preview(change).await
+    }
+
+    async fn capabilities(&self) -> DbResult<capabilities::DbCapabilities> {
+        self.inner.capabilities().await
+    }
+
+    async fn skew_policy(&self) -> DbResult<skew::SkewPolicy> {
+        self.inner.skew_policy().await
+    }
+
+    async fn set_skew_policy(&self, policy: skew::SkewPolicy) -> DbResult<()> {
+        self.inner.set_skew_policy(policy).await
+    }
+
+    async fn health(&self) -> DbResult<health::Health> {
+        self.inner.health().await
+    }
+
+    fn health_events(&self) -> Box<dyn health::HealthEvents> {
+        self.inner.health_events()
+    }
+
+    async fn ttl_sweep(&self, budget: expiry::SweepBudget) -> DbResult<expiry::SweepReport> {
+        self.faults.check("ttl_sweep")?;
+        self.inner.ttl_sweep(budget).await
+    }
+
+    fn retention_events(&self) -> Box<dyn retention::RetentionEvents> {
+        self.inner.retention_events()
+    }
+
+    fn fetch_scheduler(&self) -> Arc<dyn fetch::FetchScheduler> {
+        self.inner.fetch_scheduler()
+    }
+
+    fn validator(&self) -> Arc<dyn validate::Validator> {
+        self.inner.validator()
+    }
+
+    async fn cursor(&self, doc: &Hash, opts: CursorOpts) -> DbResult<Option<NewCursor>> {
+        self.faults.check("cursor")?;
+        let cursor = self.inner.cursor(doc, opts).await?;
+        Ok(cursor.map(|(cursor, doc)| {
+            let cursor: Box<dyn Cursor> = Box::new(FaultCursor::new(cursor, self.faults.clone()));
+            (cursor, doc)
+        }))
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        self.faults.check("doc_get")?;
+        self.inner.doc_get(doc).await
+    }
+
+    async fn doc_info(&self, doc: &Hash) -> DbResult<Option<access::DocInfo>> {
+        self.faults.check("doc_info")?;
+        self.inner.doc_info(doc).await
+    }
+
+    async fn tree_stats(&self, root: &Hash) -> DbResult<Option<stats::TreeStats>> {
+        self.faults.check("tree_stats")?;
+        self.inner.tree_stats(root).await
+    }
+
+    async fn tree_fingerprint(
+        &self,
+        root: &Hash,
+    ) -> DbResult<Option<fingerprint::TreeFingerprint>> {
+        self.faults.check("tree_fingerprint")?;
+        self.inner.tree_fingerprint(root).await
+    }
+
+    fn query(&self, doc: &Hash, query: DbQuery) -> Box<dyn CursorQuery> {
+        Box::new(FaultQuery {
+            inner: self.inner.query(doc, query),
+            faults: self.faults.clone(),
+        })
+    }
+
+    async fn entry_count(&self, doc: &Hash, key: &str, query: Option<&NewQuery>) -> DbResult<u64> {
+        self.faults.check("entry_count")?;
+        self.inner.entry_count(doc, key, query).await
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        self.faults.check("schema_get")?;
+        self.inner.schema_get(schema).await
+    }
+
+    async fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+        self.faults.check("schema_add")?;
+        self.inner.schema_add(schema).await
+    }
+
+    async fn schema_del(&self, schema: &Hash) -> DbResult<bool> {
+        self.faults.check("schema_del")?;
+        self.inner.schema_del(schema).await
+    }
+
+    async fn schema_list(&self) -> DbResult<Vec<Hash>> {
+        self.faults.check("schema_list")?;
+        self.inner.schema_list().await
+    }
+
+    async fn schema_set_compression(
+        &self,
+        schema: &Hash,
+        policy: compression::CompressionPolicy,
+    ) -> DbResult<bool> {
+        self.faults.check("schema_set_compression")?;
+        self.inner.schema_set_compression(schema, policy).await
+    }
+
+    async fn schema_get_compression(
+        &self,
+        schema: &Hash,
+    ) -> DbResult<Option<compression::CompressionPolicy>> {
+        self.faults.check("schema_get_compression")?;
+        self.inner.schema_get_compression(schema).await
+    }
+
+    async fn schema_set_weak_refs(
+        &self,
+        schema: &Hash,
+        defaults: weak_refs::WeakRefDefaults,
+    ) -> DbResult<bool> {
+        self.faults.check("schema_set_weak_refs")?;
+        self.inner.schema_set_weak_refs(schema, defaults).await
+    }
+
+    async fn schema_get_weak_refs(&self, schema: &Hash) -> DbResult<Option<weak_refs::WeakRefDefaults>> {
+        self.faults.check("schema_get_weak_refs")?;
+        self.inner.schema_get_weak_refs(schema).await
+    }
+
+    async fn schema_set_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+        policy: retention::RetentionPolicy,
+    ) -> DbResult<bool> {
+        self.faults.check("schema_set_retention")?;
+        self.inner.schema_set_retention(schema, key, policy).await
+    }
+
+    async fn schema_get_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+    ) -> DbResult<Option<retention::RetentionPolicy>> {
+        self.faults.check("schema_get_retention")?;
+        self.inner.schema_get_retention(schema, key).await
+    }
+
+    async fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+        self.faults.check("name_get")?;
+        self.inner.name_get(name).await
+    }
+
+    async fn name_add(
+        &self,
+        name: &str,
+        hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, names::NameError>> {
+        self.faults.check("name_add")?;
+        self.inner.name_add(name, hash).await
+    }
+
+    async fn name_add_reserved(
+        &self,
+        name: &str,
+        hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, names::NameError>> {
+        self.faults.check("name_add_reserved")?;
+        self.inner.name_add_reserved(name, hash).await
+    }
+
+    async fn naming_policy(&self) -> DbResult<names::NamingPolicy> {
+        self.inner.naming_policy().await
+    }
+
+    async fn set_naming_policy(&self, policy: names::NamingPolicy) -> DbResult<()> {
+        self.inner.set_naming_policy(policy).await
+    }
+
+    async fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>> {
+        self.faults.check("name_del")?;
+        self.inner.name_del(schema).await
+    }
+
+    async fn name_list(&self) -> DbResult<Vec<(String, Hash)>> {
+        self.faults.check("name_list")?;
+        self.inner.name_list().await
+    }
+
+    async fn name_list_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Hash)>> {
+        self.faults.check("name_list_prefix")?;
+        self.inner.name_list_prefix(prefix).await
+    }
+
+    async fn name_info(&self, name: &str) -> DbResult<Option<names::NameInfo>> {
+        self.faults.check("name_info")?;
+        self.inner.name_info(name).await
+    }
+
+    async fn name_set_meta(&self, name: &str, meta: names::NameMeta) -> DbResult<bool> {
+        self.faults.check("name_set_meta")?;
+        self.inner.name_set_meta(name, meta).await
+    }
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A [`DbCommit`] that injects faults into commits. [`FaultDb`] uses this for
+/// its transactions; other transactions can be wrapped with
+/// [`Transaction::wrap_commit`].
+pub struct FaultCommit {
+    inner: Box<dyn DbCommit>,
+    faults: FaultInjector,
+}
+
+impl FaultCommit {
+    /// Wrap a commit connection.
+    pub fn new(inner: Box<dyn DbCommit>, faults: FaultInjector) -> Self {
+        Self { inner, faults }
+    }
+}
+
+// The wrapped connection isn't `Send`, so the commit futures are built
+// without holding on to it: faults are decided first, the inner commit is
+// started, and only the resulting future is awaited.
+#[async_trait]
+impl DbCommit for FaultCommit {
+    fn commit<'async_trait>(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        durability: Durability,
+    ) -> BoxFuture<'async_trait, DbResult<Result<CommitReceipt, CommitErrors>>>
+    where
+        Self: 'async_trait,
+    {
+        let slow = self.faults.slow();
+        if self.faults.roll_error() {
+            return Box::pin(async { Err(injected("commit")) });
+        }
+        if self.faults.roll_reject() {
+            let errs = rejected((docs, entries, names));
+            return Box::pin(async move {
+                slow.await;
+                Ok(Err(errs))
+            });
+        }
+        let commit = self.inner.commit(docs, entries, names, durability);
+        Box::pin(async move {
+            slow.await;
+            commit.await
+        })
+    }
+
+    fn commit_many<'async_trait>(
+        self: Box<Self>,
+        changes: Vec<ChangeSet>,
+        durability: Durability,
+    ) -> BoxFuture<'async_trait, DbResult<Vec<Result<CommitReceipt, CommitErrors>>>>
+    where
+        Self: 'async_trait,
+    {
+        let slow = self.faults.slow();
+        if self.faults.roll_error() {
+            return Box::pin(async { Err(injected("commit_many")) });
+        }
+        // Decide which change sets are rejected, and only pass the rest on.
+        let mut results: Vec<Option<Result<CommitReceipt, CommitErrors>>> = Vec::new();
+        let mut kept = Vec::new();
+        for set in changes {
+            if self.faults.roll_reject() {
+                results.push(Some(Err(rejected(set))));
+            } else {
+                results.push(None);
+                kept.push(set);
+            }
+        }
+        let commit = self.inner.commit_many(kept, durability);
+        Box::pin(async move {
+            slow.await;
+            let mut committed = commit.await?.into_iter();
+            let mut merged = Vec::with_capacity(results.len());
+            for result in results {
+                match result {
+                    Some(r) => merged.push(r),
+                    None => merged.extend(committed.next()),
+                }
+            }
+            Ok(merged)
+        })
+    }
+
+    fn prepare<'async_trait>(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        durability: Durability,
+    ) -> BoxFuture<'async_trait, DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>>>
+    where
+        Self: 'async_trait,
+    {
+        let slow = self.faults.slow();
+        if self.faults.roll_error() {
+            return Box::pin(async { Err(injected("prepare")) });
+        }
+        if self.faults.roll_reject() {
+            let errs = rejected((docs, entries, names));
+            return Box::pin(async move {
+                slow.await;
+                Ok(Err(errs))
+            });
+        }
+        let prepare = self.inner.prepare(docs, entries, names, durability);
+        Box::pin(async move {
+            slow.await;
+            prepare.await
+        })
+    }
+
+    fn schema_get<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        schema: &'life1 Hash,
+    ) -> BoxFuture<'async_trait, DbResult<Option<Arc<Schema>>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        if self.faults.roll_error() {
+            return Box::pin(async { Err(injected("schema_get")) });
+        }
+        self.inner.schema_get(schema)
+    }
+
+    fn doc_get<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        doc: &'life1 Hash,
+    ) -> BoxFuture<'async_trait, DbResult<Option<Arc<Document>>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        if self.faults.roll_error() {
+            return Box::pin(async { Err(injected("doc_get")) });
+        }
+        self.inner.doc_get(doc)
+    }
+
+    fn schema_weak_refs<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        schema: &'life1 Hash,
+    ) -> BoxFuture<'async_trait, DbResult<Option<weak_refs::WeakRefDefaults>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        if self.faults.roll_error() {
+            return Box::pin(async { Err(injected("schema_weak_refs")) });
+        }
+        self.inner.schema_weak_refs(schema)
+    }
+
+    fn validator(&self) -> Option<Arc<dyn validate::Validator>> {
+        self.inner.validator()
+    }
+
+    fn db_id(&self) -> usize {
+        self.inner.db_id()
+    }
+}
+
+/// A [`Cursor`] that injects faults into navigation, queries, and fetches.
+/// Cursors forked from it, and queries made with it, inject faults too.
+pub struct FaultCursor {
+    inner: Box<dyn Cursor>,
+    faults: FaultInjector,
+}
+
+impl FaultCursor {
+    /// Wrap a cursor.
+    pub fn new(inner: Box<dyn Cursor>, faults: FaultInjector) -> Self {
+        Self { inner, faults }
+    }
+
+    fn wrap(&self, cursor: NewCursor) -> NewCursor {
+        let (cursor, doc) = cursor;
+        (Box::new(Self::new(cursor, self.faults.clone())), doc)
+    }
+}
+
+#[async_trait]
+impl Cursor for FaultCursor {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        self.faults.slow().await;
+        if self.faults.roll_error() {
+            return Err(CursorError::Unavailable(hash.clone()));
+        }
+        self.inner.forward(hash).await
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        if self.faults.roll_error() {
+            return Err(CursorError::Unavailable(hash.clone()));
+        }
+        self.inner.forward_local(hash)
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        self.inner.back()
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        Box::new(FaultFork {
+            inner: self.inner.fork(hash),
+            hash: hash.clone(),
+            faults: self.faults.clone(),
+        })
+    }
+
+    fn fork_local(&self, hash: &Hash) -> Result<Option<NewCursor>, CursorError> {
+        if self.faults.roll_error() {
+            return Err(CursorError::Unavailable(hash.clone()));
+        }
+        Ok(self.inner.fork_local(hash)?.map(|c| self.wrap(c)))
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.inner.current()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        self.inner.links()
+    }
+
+    fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery> {
+        Box::new(FaultQuery {
+            inner: self.inner.query(query),
+            faults: self.faults,
+        })
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream> {
+        Box::new(FaultChunks {
+            inner: self.inner.fetch_chunked(hash, offset),
+            hash: hash.clone(),
+            faults: self.faults.clone(),
+            failed: Mutex::new(None),
+        })
+    }
+
+    fn cache_current(&self, ttl: Duration) -> DbResult<()> {
+        self.faults.check("cache_current")?;
+        self.inner.cache_current(ttl)
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.inner.trace_id()
+    }
+}
+
+struct FaultFork {
+    inner: Box<dyn ForkCursor>,
+    hash: Hash,
+    faults: FaultInjector,
+}
+
+#[async_trait]
+impl ForkCursor for FaultFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        self.faults.slow().await;
+        if self.faults.roll_error() {
+            return Err(CursorError::Unavailable(self.hash.clone()));
+        }
+        let (cursor, doc) = self.inner.complete().await?;
+        Ok((Box::new(FaultCursor::new(cursor, self.faults)), doc))
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        if self.faults.roll_error() {
+            return Err(CursorError::Unavailable(self.hash.clone()));
+        }
+        let faults = self.faults;
+        Ok(self.inner.complete_local()?.map(|(cursor, doc)| {
+            let cursor: Box<dyn Cursor> = Box::new(FaultCursor::new(cursor, faults));
+            (cursor, doc)
+        }))
+    }
+}
+
+/// Slows down query updates. Queries have no way to report errors, so only
+/// delays are injected.
+struct FaultQuery {
+    inner: Box<dyn CursorQuery>,
+    faults: FaultInjector,
+}
+
+#[async_trait]
+impl CursorQuery for FaultQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        Box::new(FaultCursor::new(self.inner.back(), self.faults))
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        self.faults.slow().await;
+        self.inner.next().await
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        self.inner.try_next()
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        self.inner.merge_strategy()
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.inner.trace_id()
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.inner.cursor_trace_id()
+    }
+}
+
+/// Interrupts chunked fetches partway through.
+struct FaultChunks {
+    inner: Box<dyn ChunkStream>,
+    hash: Hash,
+    faults: FaultInjector,
+    failed: Mutex<Option<ChunkUpdate>>,
+}
+
+impl FaultChunks {
+    fn fail(&self) -> Option<ChunkUpdate> {
+        let mut failed = self.failed.lock().unwrap();
+        if failed.is_none() && self.faults.roll_error() {
+            *failed = Some(ChunkUpdate::Failed(CursorError::Unavailable(
+                self.hash.clone(),
+            )));
+        }
+        failed.clone()
+    }
+}
+
+#[async_trait]
+impl ChunkStream for FaultChunks {
+    async fn next(&self) -> ChunkUpdate {
+        self.faults.slow().await;
+        if let Some(failed) = self.fail() {
+            return failed;
+        }
+        self.inner.next().await
+    }
+
+    fn try_next(&self) -> Option<ChunkUpdate> {
+        if let Some(failed) = self.failed.lock().unwrap().clone() {
+            return Some(failed);
+        }
+        self.inner.try_next()
+    }
+}
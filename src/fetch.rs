@@ -0,0 +1,339 @@
+//! Scheduling of remote document fetches.
+//!
+//! When many cursors are forked at once, they can all go after the same small
+//! set of remote nodes at the same time. To avoid this, Group and cursor
+//! implementations route every remote document request through a
+//! [`FetchScheduler`], which decides when each request may go out. Requests
+//! carry a [`Priority`], and the scheduler enforces per-peer and node-wide
+//! concurrency caps along with an overall [`Budget`].
+//!
+//! [`PriorityScheduler`] is provided as a default implementation.
+
+use std::{
+    collections::{BinaryHeap, HashMap},
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use fog_pack::types::*;
+use futures::channel::oneshot;
+
+use crate::{
+    limits::{Budget, BudgetExhausted},
+    NodeAddr,
+};
+
+/// How urgently a fetch is needed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Prefetching and other speculative work.
+    Background,
+    /// Ordinary traversal.
+    #[default]
+    Normal,
+    /// Something a user is actively waiting on.
+    Interactive,
+}
+
+/// A remote document request awaiting scheduling.
+#[derive(Clone, Debug)]
+pub struct FetchRequest {
+    /// The document being requested.
+    pub doc: Hash,
+    /// The node the request will be sent to.
+    pub peer: NodeAddr,
+    /// How urgently the document is needed.
+    pub priority: Priority,
+}
+
+/// Permission to send a single fetch request. The permit should be held until
+/// the request completes; dropping it frees up the slot for another request.
+pub struct FetchPermit {
+    bytes: u64,
+    release: Option<Box<dyn FnOnce(u64) + Send + Sync>>,
+}
+
+impl FetchPermit {
+    /// Create a new permit. When the permit is dropped, `release` is called with
+    /// the total number of bytes [recorded][FetchPermit::record] against it.
+    pub fn new(release: impl FnOnce(u64) + Send + Sync + 'static) -> Self {
+        Self {
+            bytes: 0,
+            release: Some(Box::new(release)),
+        }
+    }
+
+    /// Record bytes received while fulfilling the request.
+    pub fn record(&mut self, bytes: u64) {
+        self.bytes = self.bytes.saturating_add(bytes);
+    }
+}
+
+impl Drop for FetchPermit {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release(self.bytes)
+        }
+    }
+}
+
+/// Decides when remote document requests may be sent.
+#[async_trait]
+pub trait FetchScheduler: Send + Sync {
+    /// Wait until the request may be sent. Fails if the scheduler's budget has
+    /// been exhausted.
+    async fn acquire(&self, req: &FetchRequest) -> Result<FetchPermit, BudgetExhausted>;
+
+    /// Get permission to send the request, returning `Ok(None)` if it can't be
+    /// sent yet.
+    fn try_acquire(&self, req: &FetchRequest) -> Result<Option<FetchPermit>, BudgetExhausted>;
+}
+
+/// Settings for a [`PriorityScheduler`].
+#[derive(Clone, Debug)]
+pub struct SchedulerConfig {
+    /// Maximum number of requests in flight to a single peer.
+    pub per_peer: NonZeroU32,
+    /// Maximum number of requests in flight overall.
+    pub total: NonZeroU32,
+    /// Total bytes and requests that may be used by all fetches.
+    pub budget: Budget,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            per_peer: NonZeroU32::new(4).unwrap(),
+            total: NonZeroU32::new(32).unwrap(),
+            budget: Budget::default(),
+        }
+    }
+}
+
+/// A [`FetchScheduler`] that grants requests in priority order, and in arrival
+/// order within a priority, subject to concurrency caps and a budget.
+#[derive(Clone)]
+pub struct PriorityScheduler {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: SchedulerConfig,
+    bytes_left: Option<AtomicU64>,
+    ops_left: Option<AtomicU64>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    active: u32,
+    per_peer: HashMap<NodeAddr, u32>,
+    waiting: BinaryHeap<Waiter>,
+    seq: u64,
+}
+
+struct Waiter {
+    priority: Priority,
+    seq: u64,
+    peer: NodeAddr,
+    tx: oneshot::Sender<FetchPermit>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // Higher priorities first, then earlier arrivals first.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PriorityScheduler {
+    /// Create a new scheduler with the given settings.
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                bytes_left: config.budget.bytes.map(AtomicU64::new),
+                ops_left: config.budget.ops.map(AtomicU64::new),
+                config,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// What remains of the scheduler's budget.
+    pub fn remaining(&self) -> Budget {
+        Budget {
+            bytes: self
+                .inner
+                .bytes_left
+                .as_ref()
+                .map(|b| b.load(Ordering::Acquire)),
+            ops: self
+                .inner
+                .ops_left
+                .as_ref()
+                .map(|o| o.load(Ordering::Acquire)),
+        }
+    }
+
+    /// How many requests are currently in flight.
+    pub fn active(&self) -> u32 {
+        self.inner.state.lock().unwrap().active
+    }
+
+    /// Charge a request against the budget. The request is refunded when the
+    /// returned charge is dropped, unless it's [kept][OpCharge::keep] once the
+    /// request has been granted.
+    fn charge(&self) -> Result<OpCharge<'_>, BudgetExhausted> {
+        let inner = &self.inner;
+        if inner
+            .bytes_left
+            .as_ref()
+            .is_some_and(|b| b.load(Ordering::Acquire) == 0)
+        {
+            return Err(BudgetExhausted::Bytes);
+        }
+        if let Some(ops) = &inner.ops_left {
+            ops.fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| v.checked_sub(1))
+                .map_err(|_| BudgetExhausted::Ops)?;
+        }
+        Ok(OpCharge {
+            ops_left: inner.ops_left.as_ref(),
+        })
+    }
+}
+
+/// A request charged against the budget but not yet granted. Refunds the
+/// request if dropped, which covers an `acquire` future being dropped while
+/// it's still waiting.
+struct OpCharge<'a> {
+    ops_left: Option<&'a AtomicU64>,
+}
+
+impl OpCharge<'_> {
+    /// Keep the charge, as the request was granted.
+    fn keep(mut self) {
+        self.ops_left = None;
+    }
+}
+
+impl Drop for OpCharge<'_> {
+    fn drop(&mut self) {
+        if let Some(ops) = self.ops_left {
+            ops.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Inner {
+    fn has_room(&self, state: &State, peer: &NodeAddr) -> bool {
+        state.active < self.config.total.get()
+            && state.per_peer.get(peer).copied().unwrap_or(0) < self.config.per_peer.get()
+    }
+
+    /// Mark a request to `peer` as in flight and create its permit. Must be
+    /// called with the state lock held.
+    fn grant(self: &Arc<Self>, state: &mut State, peer: NodeAddr) -> FetchPermit {
+        state.active += 1;
+        *state.per_peer.entry(peer.clone()).or_default() += 1;
+        let inner = self.clone();
+        FetchPermit::new(move |bytes| inner.release(peer, bytes))
+    }
+
+    fn release(self: &Arc<Self>, peer: NodeAddr, bytes: u64) {
+        if let Some(left) = &self.bytes_left {
+            let _ = left.fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| {
+                Some(v.saturating_sub(bytes))
+            });
+        }
+        let mut granted = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.active -= 1;
+            if let Some(count) = state.per_peer.get_mut(&peer) {
+                *count -= 1;
+                if *count == 0 {
+                    state.per_peer.remove(&peer);
+                }
+            }
+            let mut blocked = Vec::new();
+            while let Some(waiter) = state.waiting.pop() {
+                if waiter.tx.is_canceled() {
+                    continue;
+                }
+                if self.has_room(&state, &waiter.peer) {
+                    let permit = self.grant(&mut state, waiter.peer.clone());
+                    granted.push((waiter.tx, permit));
+                } else {
+                    blocked.push(waiter);
+                }
+            }
+            state.waiting.extend(blocked);
+        }
+        // Send permits outside the lock: a permit that fails to send is
+        // dropped, which re-enters `release`.
+        for (tx, permit) in granted {
+            let _ = tx.send(permit);
+        }
+    }
+}
+
+#[async_trait]
+impl FetchScheduler for PriorityScheduler {
+    async fn acquire(&self, req: &FetchRequest) -> Result<FetchPermit, BudgetExhausted> {
+        let charge = self.charge()?;
+        let rx = {
+            let mut state = self.inner.state.lock().unwrap();
+            if self.inner.has_room(&state, &req.peer) {
+                charge.keep();
+                return Ok(self.inner.grant(&mut state, req.peer.clone()));
+            }
+            let (tx, rx) = oneshot::channel();
+            state.seq += 1;
+            let seq = state.seq;
+            state.waiting.push(Waiter {
+                priority: req.priority,
+                seq,
+                peer: req.peer.clone(),
+                tx,
+            });
+            rx
+        };
+        // Waiters are only dropped without being sent a permit once their
+        // receiver is gone, or along with the scheduler, which `self` keeps
+        // alive.
+        let permit = rx.await.expect("scheduler dropped a waiting request");
+        charge.keep();
+        Ok(permit)
+    }
+
+    fn try_acquire(&self, req: &FetchRequest) -> Result<Option<FetchPermit>, BudgetExhausted> {
+        let charge = self.charge()?;
+        let mut state = self.inner.state.lock().unwrap();
+        if self.inner.has_room(&state, &req.peer) {
+            charge.keep();
+            Ok(Some(self.inner.grant(&mut state, req.peer.clone())))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Deterministic fingerprints of the document trees held in a database.
+//!
+//! Two nodes holding the same root document may still hold different views
+//! of it: one may be missing documents further down the tree, or hold
+//! entries the other hasn't seen yet. A [`TreeFingerprint`] condenses
+//! everything a node holds under a root into a single hash, so two nodes can
+//! check whether their views are identical by trading fingerprints, without
+//! either walking the tree. Fingerprints are computed the same way by every
+//! implementation, so they can be compared between different backends.
+//!
+//! A tree's fingerprint is built up Merkle-style from the fingerprint of
+//! each document in it. A document's fingerprint, from
+//! [`doc_fingerprint`], covers:
+//!
+//! - The document's own hash.
+//! - The hash of every entry held under it, by key.
+//! - The fingerprint of every document it links to, or a marker for linked
+//!   documents that aren't held.
+//!
+//! Links are followed regardless of whether they are weak or strong, and
+//! each linked document is counted once no matter how many times it's linked
+//! to. Expired entries aren't held, and so aren't counted.
+//!
+//! Since a document's fingerprint only changes when something at or below it
+//! does, implementations can cache them in a [`FingerprintCache`] and, on
+//! each commit, invalidate only the documents whose entries or links changed,
+//! along with everything above them. Asking for a tree's fingerprint then
+//! only recomputes the path from the changes back up to the root.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use fog_crypto::hash::HashState;
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+
+/// The fingerprint of everything a database holds in the tree under a root,
+/// as computed by [`Db::tree_fingerprint`][crate::Db::tree_fingerprint].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TreeFingerprint {
+    /// The root of the document tree.
+    pub root: Hash,
+    /// The root document's fingerprint, covering the whole tree.
+    pub fingerprint: Hash,
+    /// Whether the database holds the whole tree, with nothing linked to left
+    /// unfetched.
+    pub complete: bool,
+}
+
+impl TreeFingerprint {
+    /// Check if another fingerprint is of an identical view of the same tree.
+    pub fn matches(&self, other: &TreeFingerprint) -> bool {
+        self.root == other.root && self.fingerprint == other.fingerprint
+    }
+}
+
+/// Compute the fingerprint of a single document from its entries and the
+/// documents it links to. `links` maps each linked document to its
+/// fingerprint, or to `None` if it isn't held.
+pub fn doc_fingerprint(
+    doc: &Hash,
+    entries: &BTreeMap<String, BTreeSet<Hash>>,
+    links: &BTreeMap<Hash, Option<Hash>>,
+) -> Hash {
+    let mut state = HashState::new();
+    state.update(doc);
+    state.update((entries.len() as u64).to_le_bytes());
+    for (key, hashes) in entries {
+        state.update((key.len() as u64).to_le_bytes());
+        state.update(key);
+        state.update((hashes.len() as u64).to_le_bytes());
+        for hash in hashes {
+            state.update(hash);
+        }
+    }
+    state.update((links.len() as u64).to_le_bytes());
+    for (link, fingerprint) in links {
+        state.update(link);
+        match fingerprint {
+            Some(fingerprint) => {
+                state.update([1]);
+                state.update(fingerprint);
+            }
+            None => state.update([0]),
+        }
+    }
+    state.finalize()
+}
+
+/// A cache of document fingerprints, along with which documents link to
+/// which, so that changes can be invalidated up the tree.
+#[derive(Clone, Debug, Default)]
+pub struct FingerprintCache {
+    fingerprints: HashMap<Hash, (Hash, bool)>,
+    parents: HashMap<Hash, HashSet<Hash>>,
+}
+
+impl FingerprintCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the cached fingerprint of a document, and whether everything below
+    /// it is held.
+    pub fn get(&self, doc: &Hash) -> Option<(&Hash, bool)> {
+        self.fingerprints.get(doc).map(|(f, complete)| (f, *complete))
+    }
+
+    /// Cache the fingerprint of a document, along with the documents it links
+    /// to and whether everything below it is held.
+    pub fn insert<'a>(
+        &mut self,
+        doc: &Hash,
+        links: impl IntoIterator<Item = &'a Hash>,
+        fingerprint: Hash,
+        complete: bool,
+    ) {
+        for link in links {
+            self.parents.entry(link.clone()).or_default().insert(doc.clone());
+        }
+        self.fingerprints.insert(doc.clone(), (fingerprint, complete));
+    }
+
+    /// Invalidate the fingerprints of a document and every document above it.
+    /// Call this for each document added, removed, or whose entries changed
+    /// in a commit. Returns how many fingerprints were dropped.
+    pub fn invalidate(&mut self, doc: &Hash) -> usize {
+        let mut dropped = 0;
+        let mut pending = vec![doc.clone()];
+        let mut visited = HashSet::new();
+        while let Some(doc) = pending.pop() {
+            if !visited.insert(doc.clone()) {
+                continue;
+            }
+            if self.fingerprints.remove(&doc).is_some() {
+                dropped += 1;
+            }
+            if let Some(parents) = self.parents.get(&doc) {
+                pending.extend(parents.iter().cloned());
+            }
+        }
+        dropped
+    }
+
+    /// Forget a document, once it has been evicted. Its parents' fingerprints
+    /// are invalidated.
+    pub fn remove(&mut self, doc: &Hash) {
+        self.invalidate(doc);
+        for parents in self.parents.values_mut() {
+            parents.remove(doc);
+        }
+        self.parents.retain(|_, parents| !parents.is_empty());
+    }
+
+    /// Drop every cached fingerprint.
+    pub fn clear(&mut self) {
+        self.fingerprints.clear();
+        self.parents.clear();
+    }
+}
@@ -0,0 +1,195 @@
+//! Forwarding queries on to nodes the querier can't reach itself.
+//!
+//! In a small swarm with patchy connectivity, the node holding the entries a
+//! query wants may not be reachable from the querier at all, only from one of
+//! the nodes it can reach. Forwarding lets a node that receives a query
+//! through a gate pass it on to its own groups, and relay the results back
+//! along with a [relay chain][crate::cursor::QueryResult::relays].
+//!
+//! Both ends have to opt in. The querier sets
+//! [`DbQuery::forward`][crate::cursor::DbQuery::forward] to a
+//! [`ForwardHeader`], saying how many times the query may be forwarded. A
+//! gate only forwards queries if it was opened with
+//! [`GateSettings::forward`][crate::gate::GateSettings::forward] set, and
+//! only into the groups given to
+//! [`Gate::forward_groups`][crate::gate::Gate::forward_groups]. Each forward
+//! counts as a hop, and the gate caps the hop count at its own limit.
+//!
+//! Every forwarded query keeps the ID it was given by the querier, so a node
+//! that sees the same ID twice knows the query has looped back around, or
+//! reached it along two paths, and doesn't run it again. A [`ForwardTracker`]
+//! does this bookkeeping. When a node won't forward a query, the querier is
+//! told why with a
+//! [`QueryUpdate::NotForwarded`][crate::cursor::QueryUpdate::NotForwarded].
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use fog_pack::{document::NewDocument, types::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::NodeInfo;
+
+/// How a gate forwards queries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ForwardSettings {
+    /// The most hops this gate will let a query reach, no matter what the
+    /// querier asked for.
+    pub max_hops: u8,
+    /// How long to remember a forwarded query's ID, to catch it coming back
+    /// around.
+    pub remember: Duration,
+    /// The most queries this gate will be forwarding at once.
+    pub max_active: u32,
+}
+
+impl Default for ForwardSettings {
+    fn default() -> Self {
+        Self {
+            max_hops: 2,
+            remember: Duration::from_secs(60),
+            max_active: 64,
+        }
+    }
+}
+
+/// Forwarding information carried along with a query.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ForwardHeader {
+    /// The ID of the query, shared by every forwarded copy of it.
+    pub id: Hash,
+    /// How many times the query has been forwarded so far.
+    pub hops: u8,
+    /// The most times the query may be forwarded.
+    pub max_hops: u8,
+}
+
+impl ForwardHeader {
+    /// Start forwarding a query with the given
+    /// [fingerprint][crate::cursor::DbQuery::fingerprint]. The nonce should
+    /// be random, so the same query made twice gets different IDs.
+    pub fn new(fingerprint: &Hash, nonce: u64, max_hops: u8) -> Self {
+        let id = NewDocument::new(None, (fingerprint, nonce))
+            .expect("ForwardHeader should always be serializable")
+            .hash()
+            .to_owned();
+        Self {
+            id,
+            hops: 0,
+            max_hops,
+        }
+    }
+
+    /// The header to forward the query on with, given the forwarding node's
+    /// own hop limit. Fails if the query has already made as many hops as it
+    /// may.
+    pub fn next(&self, max_hops: u8) -> Result<ForwardHeader, ForwardStop> {
+        let max = self.max_hops.min(max_hops);
+        if self.hops >= max {
+            return Err(ForwardStop::HopLimit { max });
+        }
+        Ok(ForwardHeader {
+            id: self.id.clone(),
+            hops: self.hops + 1,
+            max_hops: max,
+        })
+    }
+}
+
+/// Why a node didn't forward a query.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum ForwardStop {
+    /// The gate the query came through doesn't forward queries.
+    #[error("Gate doesn't forward queries")]
+    Disabled,
+    /// The query has made as many hops as it may.
+    #[error("Query reached the hop limit of {max}")]
+    HopLimit { max: u8 },
+    /// The node has already seen this query, so it either looped back or
+    /// arrived along more than one path.
+    #[error("Query was already seen")]
+    Loop,
+    /// The node is already forwarding as many queries as it will.
+    #[error("Too many queries are being forwarded")]
+    Overloaded,
+}
+
+/// A node declining to forward a query. The node may still have run the query
+/// itself.
+#[derive(Clone, Debug)]
+pub struct ForwardStopped {
+    /// The node that didn't forward the query.
+    pub source: NodeInfo,
+    /// Why it didn't.
+    pub reason: ForwardStop,
+}
+
+/// Tracks the queries a gate has forwarded recently, to catch loops and
+/// enforce its [`ForwardSettings`].
+#[derive(Clone, Debug)]
+pub struct ForwardTracker {
+    settings: ForwardSettings,
+    seen: HashMap<Hash, Instant>,
+    active: HashSet<Hash>,
+}
+
+impl ForwardTracker {
+    /// Create a tracker that has seen nothing yet.
+    pub fn new(settings: ForwardSettings) -> Self {
+        Self {
+            settings,
+            seen: HashMap::new(),
+            active: HashSet::new(),
+        }
+    }
+
+    /// Get the settings being enforced.
+    pub fn settings(&self) -> &ForwardSettings {
+        &self.settings
+    }
+
+    /// Decide whether to forward a query with the given header, as of the
+    /// time `now`. On success, the query's ID is remembered and the header to
+    /// forward it with is returned.
+    pub fn check_at(
+        &mut self,
+        header: &ForwardHeader,
+        now: Instant,
+    ) -> Result<ForwardHeader, ForwardStop> {
+        let remember = self.settings.remember;
+        self.seen
+            .retain(|_, seen| now.saturating_duration_since(*seen) < remember);
+        if self.seen.contains_key(&header.id) {
+            return Err(ForwardStop::Loop);
+        }
+        let next = header.next(self.settings.max_hops)?;
+        if self.active.len() >= self.settings.max_active as usize {
+            return Err(ForwardStop::Overloaded);
+        }
+        self.seen.insert(header.id.clone(), now);
+        self.active.insert(header.id.clone());
+        Ok(next)
+    }
+
+    /// Decide whether to forward a query with the given header, as of now.
+    pub fn check(&mut self, header: &ForwardHeader) -> Result<ForwardHeader, ForwardStop> {
+        self.check_at(header, Instant::now())
+    }
+
+    /// Mark a query as done being forwarded, so it no longer counts against
+    /// the active limit. Its ID is still treated as seen, to catch late
+    /// copies of it, until the remember window passes. Returns false if the
+    /// query wasn't being forwarded.
+    pub fn finish(&mut self, id: &Hash) -> bool {
+        self.active.remove(id)
+    }
+
+    /// How many queries are being forwarded.
+    pub fn active(&self) -> usize {
+        self.active.len()
+    }
+}
@@ -5,23 +5,77 @@
 //! local database for document retrieval and querying, via the
 //! [`cursor`][crate::cursor] API.
 
-use std::{fmt::Display, sync::Arc};
+use std::{collections::HashSet, fmt::Display, sync::Arc};
 
-use crate::{cert::Policy, NodeInfo};
+use crate::{anomaly::{AnomalyDetector, AnomalyReport}, cert::Policy, complexity::ComplexityLimits, cursor::TraceId, forward::{ForwardHeader, ForwardSettings}, group::Group, limits::{Budget, RateLimit}, NodeInfo};
 use crate::NodeAddr;
 use async_trait::async_trait;
-use fog_pack::{document::Document, entry::Entry, query::Query, types::Hash};
+use fog_pack::{document::Document, entry::{Entry, EntryRef}, error::Error as FogError, query::Query, schema::Schema, types::{Hash, Timestamp}};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GateSettings {
-    /// An advisory policy for which nodes to give preferential treatment to.
-    pub prefer: Policy,
+    /// Policies for which nodes to give preferential treatment to, highest
+    /// priority first. Each attached node lands in the [`Tier`] of the first
+    /// policy it satisfies, or in [`Tier::Default`] if it satisfies none.
+    pub prefer: Vec<Policy>,
     /// Open the gate for only this node
     pub node: Option<NodeAddr>,
     /// How many cursors a node is permitted to have open through this gate.
     pub cursors: u32,
     /// How many total cursors may be opened within this gate
     pub total_cursors: u32,
+    /// Limit on how quickly each node may make requests through this gate.
+    pub rate: Option<RateLimit>,
+    /// Total amount of data and requests each node may consume through this
+    /// gate before it is cut off.
+    pub budget: Budget,
+    /// Sign every query result served through this gate with the node's
+    /// identity, so the receiver can [prove where it came
+    /// from][crate::cursor::Provenance]. Costs a signature per result.
+    pub sign_responses: bool,
+    /// The most complex query this gate will run. Queries over the limits are
+    /// [rejected][crate::cursor::QueryUpdate::Rejected] without being run.
+    pub complexity: ComplexityLimits,
+    /// Forward queries that ask for it on to the groups set with
+    /// [`Gate::forward_groups`]. If not set, queries are never forwarded. See
+    /// [the forward module][crate::forward] for details.
+    #[serde(default)]
+    pub forward: Option<ForwardSettings>,
+}
+
+/// The priority tier a node attached to a gate landed in, set by the gate's
+/// [`prefer`][GateSettings::prefer] policies.
+///
+/// Tiers are ordered from highest priority to lowest: `Preferred(0)` comes
+/// first, then `Preferred(1)`, and so on, with `Default` last. A gate treats
+/// them as follows:
+///
+/// - When the gate is at its [total cursor limit][GateSettings::total_cursors]
+///   and a node asks to open another cursor, the gate closes a cursor held by
+///   a node in the lowest occupied tier to make room, as long as that tier
+///   ranks below the asking node's. If every cursor is held by nodes in the
+///   same tier or higher, the request is refused. Each node shed this way
+///   produces a [`GateEvent::Shed`].
+/// - When the gate can't keep up with requests, pending requests from a
+///   higher tier are served before any from a lower tier.
+///
+/// Rate limits, budgets, and per-node cursor limits apply the same way to
+/// every tier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Tier {
+    /// The node satisfied the `prefer` policy at this index.
+    Preferred(usize),
+    /// The node satisfied none of the `prefer` policies.
+    Default,
+}
+
+impl Tier {
+    /// Check if this tier has strictly higher priority than another.
+    pub fn outranks(&self, other: &Tier) -> bool {
+        self < other
+    }
 }
 
 /// An open Gate. Allows other nodes in a network to read the database with a
@@ -38,6 +92,10 @@ pub trait Gate {
     /// How many cursors are currently open on this gate.
     fn total_cursors(&self) -> u32;
 
+    /// Get which tier an attached node landed in. Returns `None` if the node
+    /// isn't attached to this gate.
+    fn tier(&self, node: &NodeInfo) -> Option<Tier>;
+
     /// Add a hook for handling all incoming queries on a specific document,
     /// scoped to just nodes that came in through this Gate. When a hook is
     /// established, *all* queries go through it - none will ever hit the
@@ -45,10 +103,105 @@ pub trait Gate {
     /// it choose to do so.
     fn query_hook(&self, doc: &Hash, hook: Box<dyn QueryHook>);
 
+    /// Watch for events on this gate.
+    fn events(&self) -> Box<dyn GateEvents>;
+
+    /// Set the detector that node activity through this gate is inspected by,
+    /// replacing any previous detector.
+    fn anomaly_detector(&self, detector: Box<dyn AnomalyDetector>);
+
+    /// Set the honeypot documents of this gate, replacing any previous set.
+    /// See [the anomaly module][crate::anomaly] for details.
+    fn set_honeypots(&self, docs: Vec<Hash>);
+
+    /// Turn delivery receipts on or off for entries under `key` on a document.
+    /// While on, every such entry sent to a node through this gate produces a
+    /// [`GateEvent::Delivered`] event. Receipts are off by default.
+    fn receipts(&self, doc: &Hash, key: &str, enabled: bool);
+
+    /// Set the groups that queries arriving through this gate may be
+    /// [forwarded][crate::forward] into, replacing any previous set. Has no
+    /// effect unless the gate was opened with
+    /// [`GateSettings::forward`] set.
+    fn forward_groups(&self, groups: Vec<Arc<dyn Group>>);
+
     /// Explicitly close the gate - should be equivalent to calling `drop(gate)`.
     fn close(self);
 }
 
+/// Something that happened on a gate.
+#[derive(Clone, Debug)]
+pub enum GateEvent {
+    /// A node opened its first cursor through the gate.
+    Attached(NodeInfo),
+    /// A node closed its last cursor through the gate.
+    Detached(NodeInfo),
+    /// A node opened a cursor through the gate.
+    CursorOpened {
+        /// The node that opened the cursor.
+        node: NodeInfo,
+        /// The cursor's trace ID, as sent by the node.
+        cursor: TraceId,
+    },
+    /// A cursor open through the gate was closed.
+    CursorClosed {
+        /// The node that had the cursor open.
+        node: NodeInfo,
+        /// The cursor's trace ID, as sent by the node.
+        cursor: TraceId,
+    },
+    /// The gate's anomaly detector reported a node.
+    Anomaly(Box<AnomalyReport>),
+    /// An entry with receipts turned on was sent to a node.
+    Delivered(Box<Delivery>),
+    /// Cursors held by a node were closed to make room for a node in a higher
+    /// [`Tier`].
+    Shed {
+        /// The node whose cursors were closed.
+        node: NodeInfo,
+        /// The tier the node was in.
+        tier: Tier,
+        /// How many of its cursors were closed.
+        cursors: u32,
+    },
+    /// A query from a node was forwarded on to this gate's forwarding
+    /// groups.
+    Forwarded {
+        /// The node that made the query.
+        node: NodeInfo,
+        /// The query's trace ID, as sent by the node.
+        query: TraceId,
+        /// The forwarding information the query was sent on with.
+        header: ForwardHeader,
+    },
+}
+
+/// A record of an entry being sent to a node through a gate. This only means
+/// the entry was sent, not that the node's application has seen it.
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    /// The node the entry was sent to.
+    pub node: NodeInfo,
+    /// The entry that was sent.
+    pub entry: EntryRef,
+    /// When the entry was sent.
+    pub at: Timestamp,
+    /// The trace ID of the remote query the entry was sent in response to.
+    pub query: TraceId,
+    /// The trace ID of the remote cursor the query was made with.
+    pub cursor: TraceId,
+}
+
+/// A stream of events from a gate.
+#[async_trait]
+pub trait GateEvents: Send + Sync {
+    /// Wait for the next event.
+    async fn next(&self) -> GateEvent;
+
+    /// Try to get the next event, returning `None` if there isn't one yet.
+    fn try_next(&self) -> Option<GateEvent>;
+}
+
 #[async_trait]
 pub trait ResponseStream {
     /// Send a response to the query. Should fail if the query is closed.
@@ -91,6 +244,81 @@ pub struct Response {
     pub docs: Vec<Arc<Document>>,
 }
 
+/// A reason a hook response would be dropped by the node receiving it.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum InvalidResponse {
+    /// The entry isn't attached to the queried document.
+    #[error("Entry is attached to {actual}, not the queried document {expected}")]
+    WrongParent { expected: Hash, actual: Hash },
+    /// The entry is under a different key than the query.
+    #[error("Entry is under key {actual:?}, but the query is for {expected:?}")]
+    WrongKey { expected: String, actual: String },
+    /// The entry doesn't pass the parent document's schema.
+    #[error("Entry failed schema validation")]
+    Entry(#[source] FogError),
+    /// The entry doesn't match the query.
+    #[error("Entry doesn't match the query")]
+    Query(#[source] FogError),
+    /// A document needed to validate the entry wasn't included.
+    #[error("Missing required document {0}")]
+    MissingDoc(Hash),
+    /// An included document failed the checks required by the entry or query.
+    #[error("Document {doc} failed validation")]
+    InvalidDoc {
+        doc: Hash,
+        #[source]
+        err: FogError,
+    },
+    /// A document was included that wasn't needed to validate the entry.
+    #[error("Document {0} isn't required by the entry")]
+    ExtraDoc(Hash),
+}
+
+impl Response {
+    /// Check this response the way the receiving node will, so a hook can
+    /// find out about a malformed response before sending it. `parent` is the
+    /// queried document, `schema` is its schema, and `query` is the query
+    /// being responded to.
+    pub fn verify(&self, parent: &Document, schema: &Schema, query: &Query) -> Result<(), InvalidResponse> {
+        if self.entry.parent() != parent.hash() {
+            return Err(InvalidResponse::WrongParent {
+                expected: parent.hash().clone(),
+                actual: self.entry.parent().clone(),
+            });
+        }
+        if self.entry.key() != query.key() {
+            return Err(InvalidResponse::WrongKey {
+                expected: query.key().to_owned(),
+                actual: self.entry.key().to_owned(),
+            });
+        }
+
+        // Re-run validation from the encoded entry to find which documents
+        // the schema requires.
+        let (_, data, _) = schema
+            .encode_entry(self.entry.clone())
+            .map_err(InvalidResponse::Entry)?;
+        let mut entry_list = schema
+            .decode_entry(data, self.entry.key(), parent)
+            .map_err(InvalidResponse::Entry)?;
+        let mut query_list = query.query(&self.entry).map_err(InvalidResponse::Query)?;
+
+        let find = |hash: &Hash| self.docs.iter().find(|d| d.hash() == hash);
+        let mut required = HashSet::new();
+        for (hash, item) in entry_list.iter().chain(query_list.iter()) {
+            let doc = find(&hash).ok_or_else(|| InvalidResponse::MissingDoc(hash.clone()))?;
+            item.check(doc)
+                .map_err(|err| InvalidResponse::InvalidDoc { doc: hash.clone(), err })?;
+            required.insert(hash);
+        }
+        if let Some(extra) = self.docs.iter().find(|d| !required.contains(d.hash())) {
+            return Err(InvalidResponse::ExtraDoc(extra.hash().clone()));
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 pub trait QueryHook {
     /// Handle an incoming query.
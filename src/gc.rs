@@ -0,0 +1,45 @@
+//! Previewing what a change would do to garbage collection.
+//!
+//! Removing a root name or weakening a link can leave a large part of the
+//! document tree unreachable, and there's no getting it back once the garbage
+//! collector has run. [`Db::gc_preview`][crate::Db::gc_preview] answers what a
+//! [`ProposedChange`] would free, and what it would break, without committing
+//! anything.
+
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+
+/// A change whose effect on garbage collection should be previewed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProposedChange {
+    /// Point a name at a different document, or remove it if `target` is
+    /// `None`.
+    SetName { name: String, target: Option<Hash> },
+    /// Make a document's link to `target` weak or strong.
+    SetWeakRef { doc: Hash, target: Hash, weak: bool },
+}
+
+/// The effect a [`ProposedChange`] would have on garbage collection.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcPreview {
+    /// Documents that would become unreachable and be evicted. Eviction
+    /// policies aren't consulted, so some of these may be kept for a while
+    /// longer.
+    pub evicted: Vec<Hash>,
+    /// The total encoded size of the evicted documents, in bytes.
+    pub evicted_bytes: u64,
+    /// Documents that would no longer be reachable from any name, but are
+    /// still held by a pin, an open snapshot, or a cache entry, and so would
+    /// only be evicted once that lapses.
+    pub held: Vec<Hash>,
+    /// Open gates whose starting document would be evicted. Nodes using these
+    /// gates would lose access.
+    pub broken_gates: Vec<Hash>,
+}
+
+impl GcPreview {
+    /// Check if the change would have no effect on garbage collection.
+    pub fn is_empty(&self) -> bool {
+        self.evicted.is_empty() && self.held.is_empty() && self.broken_gates.is_empty()
+    }
+}
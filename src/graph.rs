@@ -0,0 +1,192 @@
+//! Graph-style traversal over the cursor API.
+//!
+//! A FogDB can be viewed as a graph database, where documents are nodes and the
+//! hash links between them are edges. This module provides a small declarative
+//! traversal engine on top of [`Cursor`]: describe which documents should be
+//! followed and which should be collected with a [`TraversalSpec`], then run it
+//! with [`traverse`]. The engine forks cursors to each newly discovered link,
+//! keeping no more than the spec's concurrency limit in flight at once.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    num::NonZeroUsize,
+    sync::Arc,
+};
+
+use fog_pack::{document::Document, types::*};
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use crate::cursor::{Cursor, CursorError, ForkCursor, LinkStrength};
+
+/// A predicate run against each document reached during a traversal.
+pub type DocPredicate = Box<dyn Fn(&Document) -> bool + Send + Sync>;
+
+/// A declarative description of a traversal through a document graph.
+pub struct TraversalSpec {
+    /// How many links away from the starting document the traversal may go.
+    /// A depth of 0 only examines the starting document.
+    pub max_depth: u32,
+    /// Only follow links into documents using one of these schemas. If `None`,
+    /// documents of any schema (or no schema at all) are followed. Documents
+    /// that don't match are neither collected nor expanded further.
+    pub schemas: Option<Vec<Hash>>,
+    /// Only collect documents that pass this predicate. If `None`, every
+    /// followed document is collected.
+    pub collect: Option<DocPredicate>,
+    /// Stop the traversal once this many documents have been collected.
+    pub limit: Option<usize>,
+    /// Maximum number of forked cursors that may be completing at once.
+    pub concurrency: NonZeroUsize,
+    /// Which links to follow, based on their strength.
+    pub links: LinkFilter,
+}
+
+/// Which document links a traversal follows, based on their
+/// [strength][LinkStrength].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LinkFilter {
+    /// Follow every link.
+    #[default]
+    All,
+    /// Only follow links known to be strong, i.e. the documents that are kept
+    /// resident by the starting document.
+    Strong,
+    /// Follow every link that isn't known to be weak.
+    NotWeak,
+}
+
+impl LinkFilter {
+    /// Check if a link with the given strength passes the filter.
+    pub fn allows(&self, strength: LinkStrength) -> bool {
+        match self {
+            LinkFilter::All => true,
+            LinkFilter::Strong => strength == LinkStrength::Strong,
+            LinkFilter::NotWeak => strength != LinkStrength::Weak,
+        }
+    }
+}
+
+impl TraversalSpec {
+    /// Create a new specification that follows and collects every document up
+    /// to `max_depth` links away, with a concurrency limit of 8.
+    pub fn new(max_depth: u32) -> Self {
+        Self {
+            max_depth,
+            schemas: None,
+            collect: None,
+            limit: None,
+            concurrency: NonZeroUsize::new(8).unwrap(),
+            links: LinkFilter::All,
+        }
+    }
+
+    /// Check if a reached document should be followed.
+    pub fn follows(&self, doc: &Document) -> bool {
+        match (&self.schemas, doc.schema_hash()) {
+            (None, _) => true,
+            (Some(list), Some(schema)) => list.contains(schema),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Check if a followed document should be collected.
+    pub fn collects(&self, doc: &Document) -> bool {
+        self.collect.as_ref().is_none_or(|pred| pred(doc))
+    }
+}
+
+/// A document collected during a traversal.
+#[derive(Clone, Debug)]
+pub struct TraversalHit {
+    /// The collected document.
+    pub doc: Arc<Document>,
+    /// How many links away from the starting document it was found.
+    pub depth: u32,
+}
+
+/// The outcome of running a [`TraversalSpec`].
+#[derive(Debug, Default)]
+pub struct Traversal {
+    /// Every collected document, in the order they were reached.
+    pub hits: Vec<TraversalHit>,
+    /// Links that couldn't be followed, and why.
+    pub errors: Vec<(Hash, CursorError)>,
+}
+
+/// Run a traversal starting from the document a cursor is currently over.
+///
+/// Each distinct document hash is visited at most once, so cycles and
+/// diamond-shaped links are handled. The starting document is always
+/// expanded, but is only collected if it also passes the spec's schema and
+/// predicate checks.
+pub async fn traverse(root: Box<dyn Cursor>, spec: &TraversalSpec) -> Traversal {
+    let mut result = Traversal::default();
+    let mut visited = HashSet::new();
+    let mut pending: VecDeque<(Hash, u32, Box<dyn ForkCursor>)> = VecDeque::new();
+    let mut in_flight = FuturesUnordered::new();
+
+    let doc = root.current();
+    visited.insert(doc.hash().to_owned());
+    if spec.follows(&doc) && spec.collects(&doc) {
+        result.hits.push(TraversalHit {
+            doc: doc.clone(),
+            depth: 0,
+        });
+    }
+    expand(root.as_ref(), 0, spec, &mut visited, &mut pending);
+    drop(root);
+
+    loop {
+        if spec.limit.is_some_and(|limit| result.hits.len() >= limit) {
+            break;
+        }
+        while in_flight.len() < spec.concurrency.get() {
+            let Some((hash, depth, fork)) = pending.pop_front() else {
+                break;
+            };
+            in_flight.push(async move { (hash, depth, fork.complete().await) });
+        }
+        let Some((hash, depth, res)) = in_flight.next().await else {
+            break;
+        };
+        match res {
+            Ok((cursor, doc)) => {
+                if !spec.follows(&doc) {
+                    continue;
+                }
+                if spec.collects(&doc) {
+                    result.hits.push(TraversalHit {
+                        doc: doc.clone(),
+                        depth,
+                    });
+                }
+                expand(cursor.as_ref(), depth, spec, &mut visited, &mut pending);
+            }
+            Err(e) => result.errors.push((hash, e)),
+        }
+    }
+    result
+}
+
+/// Queue up forks for every not-yet-visited link in a cursor's current
+/// document.
+fn expand(
+    cursor: &dyn Cursor,
+    depth: u32,
+    spec: &TraversalSpec,
+    visited: &mut HashSet<Hash>,
+    pending: &mut VecDeque<(Hash, u32, Box<dyn ForkCursor>)>,
+) {
+    if depth >= spec.max_depth {
+        return;
+    }
+    for (hash, strength) in cursor.links() {
+        if !spec.links.allows(strength) {
+            continue;
+        }
+        if visited.insert(hash.clone()) {
+            let fork = cursor.fork(&hash);
+            pending.push_back((hash, depth + 1, fork));
+        }
+    }
+}
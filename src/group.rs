@@ -5,10 +5,13 @@
 //! nodes can be aggregated over multiple network types, and can be specified by
 //! a [`Policy`].
 
+use std::{sync::Arc, time::Duration};
+
 use fog_crypto::identity::IdentityKey;
-use fog_pack::types::*;
+use bytes::Bytes;
+use fog_pack::{document::Document, types::*};
 
-use crate::{gate::{GateSettings, Gate}, cursor::ForkCursor, cert::Policy, NetInfo};
+use crate::{barrier::Barrier, availability::{AvailabilitySummary, SummaryExchange}, gate::{GateSettings, Gate}, cursor::{CursorOpts, ForkCursor}, cert::Policy, invite::{Invite, InviteError}, limits::{Backoff, RateLimit}, mixnet::{Mixnet, MixnetError}, peers::PeerStore, pinning::{HostedPin, PinPolicy, PinRequest}, quota::{QuotaUsage, StorageQuota}, resources::{ResourceFilter, Resources}, schema_fetch::SchemaRequest, service::{ServiceDescriptor, ServiceFilter}, skew::SkewPolicy, transport::PeerCandidate, warm::Warmup, NodeAddr, NodeInfo, NetInfo};
 
 pub trait Group {
     /// Open up a gate, which lets members of this group open a cursor in your
@@ -21,15 +24,93 @@ pub trait Group {
     /// For a given hash:
     ///
     /// 1. If a gate is open without a specific node listed, no other gates may
-    ///     be opened.
+    ///    be opened.
     /// 2. If at least one gate is open with a specific node listed, other gates
-    ///     with specific nodes may be opened, but all nodes across open gates
-    ///     must be unique.
+    ///    with specific nodes may be opened, but all nodes across open gates
+    ///    must be unique.
     ///
     fn gate(&self, gate: &Hash, settings: Option<GateSettings>) -> Option<Box<dyn Gate>>;
 
     /// Prepare a new cursor for use, starting from the given hash.
-    fn cursor(&self, gate: &Hash) -> Box<dyn ForkCursor>;
+    fn cursor(&self, gate: &Hash, opts: CursorOpts) -> Box<dyn ForkCursor>;
+
+    /// Limit the storage used by content held on behalf of this group, or
+    /// remove the limit by passing `None`. If the group is already over the
+    /// new quota, content is evicted until it fits.
+    fn set_storage_quota(&self, quota: Option<StorageQuota>);
+
+    /// Get the current storage quota for this group, if any.
+    fn storage_quota(&self) -> Option<StorageQuota>;
+
+    /// Get the storage currently used by content held on behalf of this group.
+    fn storage_usage(&self) -> QuotaUsage;
+
+    /// Ask a group member to keep the document tree starting at `root`
+    /// resident for `ttl`. Asking again for the same tree renews the pin.
+    fn pin_request(&self, node: &NodeAddr, root: &Hash, ttl: Duration) -> Box<dyn PinRequest>;
+
+    /// Set the policy deciding which pin requests from group members are
+    /// granted, replacing any previous policy.
+    fn set_pin_policy(&self, policy: Box<dyn PinPolicy>);
+
+    /// Get every pin this node currently holds on behalf of group members.
+    fn hosted_pins(&self) -> Vec<HostedPin>;
+
+    /// Override the database's clock skew policy for entries and certificates
+    /// received through this group, or go back to the database's policy by
+    /// passing `None`.
+    fn set_skew_policy(&self, policy: Option<SkewPolicy>);
+
+    /// Get the clock skew policy in effect for this group.
+    fn skew_policy(&self) -> SkewPolicy;
+
+    /// Ask group members for a schema document, so it can be installed
+    /// locally.
+    fn find_schema(&self, schema: &Hash) -> Box<dyn SchemaRequest>;
+
+    /// Advertise this node's resources to group members, signed with the
+    /// identity from the group's [`GroupSpec::policy_settings`], or stop
+    /// advertising by passing `None`. Groups joined without an identity can't
+    /// advertise, and ignore this.
+    fn advertise_resources(&self, resources: Option<Resources>);
+
+    /// Get the currently connected group members whose verified resource
+    /// advertisements pass the filter, along with what they advertised.
+    /// Members that haven't advertised are left out.
+    fn members_by_resource(&self, filter: &ResourceFilter) -> Vec<(NodeInfo, Resources)>;
+
+    /// Advertise [descriptors][crate::service::ServiceDescriptor] of the gates
+    /// this node serves to group members, replacing any previous set, or stop
+    /// advertising by passing an empty list. Descriptors not signed by the
+    /// identity from the group's [`GroupSpec::policy_settings`] are dropped,
+    /// so groups joined without an identity can't advertise, and ignore this.
+    fn advertise_services(&self, services: Vec<Arc<Document>>);
+
+    /// Get the service descriptors advertised by currently connected group
+    /// members that pass the filter, along with the member that advertised
+    /// each. Descriptors that have expired, or that weren't signed by the
+    /// advertising member, are left out.
+    fn services(&self, filter: ServiceFilter) -> Vec<(NodeAddr, ServiceDescriptor)>;
+
+    /// Summarize which documents this node holds in the tree under `root`,
+    /// at the [default false positive rate][crate::availability::DEFAULT_FALSE_POSITIVE_RATE].
+    fn summary(&self, root: &Hash) -> AvailabilitySummary;
+
+    /// Send a summary of what this node holds to a group member, which
+    /// answers with its own summary and the documents it holds that ours
+    /// doesn't.
+    fn exchange_summary(&self, node: &NodeAddr, have: AvailabilitySummary) -> Box<dyn SummaryExchange>;
+
+    /// Wait for at least `min_peers` group members to hold at least as much of
+    /// the tree under `root` as this node holds now, giving up after
+    /// `timeout`.
+    /// See the [barrier][crate::barrier] module for details.
+    fn barrier(&self, root: &Hash, min_peers: usize, timeout: Duration) -> Box<dyn Barrier>;
+
+    /// Open connections to the given group members, or refresh them if
+    /// they're already open, ahead of traffic to them. See the
+    /// [warm][crate::warm] module for details.
+    fn warm(&self, nodes: &[NodeAddr]) -> Box<dyn Warmup>;
 }
 
 /// Specification for a group. This limits what networks will be used for the
@@ -42,8 +123,70 @@ pub struct GroupSpec {
     pub policy_settings: Option<(IdentityKey, Option<Policy>)>,
     /// What networks should be used when navigating this group.
     pub net: NetInfo,
+    /// Which discovery providers, by name, should be used to find group
+    /// members. If `None`, every provider operating on a permitted network is
+    /// used.
+    pub discovery: Option<Vec<String>>,
     /// Whether or not a mixnet must be used when finding group members
     pub mixnet_locator: bool,
     /// Whether or not a mixnet must be used when communicating with group members.
     pub mixnet_comms: bool,
+    /// Limit on the bytes transferred across all connections in the group.
+    pub bandwidth: Option<RateLimit>,
+    /// How to back off when reconnecting to a group member.
+    pub reconnect: Backoff,
+    /// An address book of previously seen peers, used as a bootstrap source
+    /// alongside discovery. The group records the peers it finds and connects
+    /// to back into it.
+    pub peers: Option<Arc<dyn PeerStore>>,
+    /// Peers to dial straight away, before discovery has found any.
+    pub bootstrap: Vec<PeerCandidate>,
+    /// A capability token to present to group members when connecting, as
+    /// handed out in an [`Invite`].
+    pub token: Option<Bytes>,
+}
+
+impl GroupSpec {
+    /// Build a specification for joining the group described by an invite,
+    /// as of the time `now`. Groups limited by a policy need an identity to
+    /// join with; for other groups, the identity is optional. Everything the
+    /// invite doesn't cover is left at its default.
+    pub fn from_invite(invite: &Invite, key: Option<IdentityKey>, now: Timestamp) -> Result<Self, InviteError> {
+        if let Some(expires) = invite.expires.filter(|_| invite.is_expired(now)) {
+            return Err(InviteError::Expired(expires));
+        }
+        let policy_settings = match (key, &invite.policy) {
+            (Some(key), policy) => Some((key, policy.clone())),
+            (None, Some(_)) => return Err(InviteError::NeedsIdentity),
+            (None, None) => None,
+        };
+        Ok(Self {
+            policy_settings,
+            net: invite.net.clone(),
+            discovery: invite.discovery.clone(),
+            mixnet_locator: invite.mixnet_locator,
+            mixnet_comms: invite.mixnet_comms,
+            bandwidth: None,
+            reconnect: Backoff::default(),
+            peers: None,
+            bootstrap: invite.candidates(),
+            token: invite.token.clone(),
+        })
+    }
+
+    /// Whether this group requires a mixnet for either locating or
+    /// communicating with group members.
+    pub fn requires_mixnet(&self) -> bool {
+        self.mixnet_locator || self.mixnet_comms
+    }
+
+    /// Verify that this group's mixnet requirements can be met by the given
+    /// mixnet provider, if any.
+    pub fn check_mixnet(&self, mixnet: Option<&dyn Mixnet>) -> Result<(), MixnetError> {
+        match mixnet {
+            Some(mixnet) => mixnet.check(self),
+            None if self.requires_mixnet() => Err(MixnetError::Missing),
+            None => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,95 @@
+//! Database health reporting.
+//!
+//! Storage trouble usually shows up well before it causes data loss: the disk
+//! fills, writes start failing intermittently, the journal falls behind. A
+//! database reports its current condition with [`Db::health`][crate::Db::health],
+//! and a long-running service can watch for changes with
+//! [`Db::health_events`][crate::Db::health_events] to alert an operator or
+//! stop accepting writes before things get worse.
+
+use async_trait::async_trait;
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+
+/// The overall condition of a database.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Health {
+    /// Everything is working normally.
+    #[default]
+    Ok,
+    /// Reads work, but transactions, name changes, and schema changes will be
+    /// rejected.
+    ReadOnly(Vec<HealthIssue>),
+    /// The database is fully working, but has problems that may get worse.
+    Degraded(Vec<HealthIssue>),
+    /// The database can no longer be relied upon to read or write data.
+    Failing(Vec<HealthIssue>),
+}
+
+impl Health {
+    /// Check if the database is working normally.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Health::Ok)
+    }
+
+    /// Check if the database is currently accepting writes.
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Health::Ok | Health::Degraded(_))
+    }
+
+    /// The issues behind the database's current condition.
+    pub fn issues(&self) -> &[HealthIssue] {
+        match self {
+            Health::Ok => &[],
+            Health::ReadOnly(i) | Health::Degraded(i) | Health::Failing(i) => i,
+        }
+    }
+}
+
+/// A specific problem affecting a database's health.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum HealthIssue {
+    /// Storage space is running low.
+    LowSpace {
+        /// Bytes still available for the database to use.
+        available: u64,
+        /// Total bytes of storage the database can use.
+        total: u64,
+    },
+    /// Reads or writes to storage have been failing.
+    StorageErrors {
+        /// Number of failed operations since the database was opened.
+        count: u64,
+        /// The most recent failure.
+        last: String,
+    },
+    /// Commits are being journaled faster than they can be applied.
+    JournalBacklog {
+        /// Number of journaled commits waiting to be applied.
+        pending: u64,
+    },
+    /// Stored data failed an integrity check.
+    Corruption {
+        /// The affected document, if known.
+        doc: Option<Hash>,
+        /// What was found.
+        detail: String,
+    },
+    /// The database was opened read-only.
+    OpenedReadOnly,
+    /// A backend-specific problem.
+    Other(String),
+}
+
+/// A stream of changes to a database's health. Each item is the database's new
+/// condition; items are only produced when it changes.
+#[async_trait]
+pub trait HealthEvents: Send + Sync {
+    /// Wait for the database's health to change.
+    async fn next(&self) -> Health;
+
+    /// Try to get the next change to the database's health, returning `None`
+    /// if it hasn't changed.
+    fn try_next(&self) -> Option<Health>;
+}
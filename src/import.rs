@@ -0,0 +1,66 @@
+//! Bulk import of large document sets.
+//!
+//! Ordinary transactions validate every document and entry as it's staged,
+//! keep the whole change set in memory, and update indexes and reachability as
+//! part of each commit. For a one-off import of an archive holding millions of
+//! documents, that's far more work than needed. A [`BulkImport`] from
+//! [`Db::bulk_import`][crate::Db::bulk_import] instead lets the database spool
+//! staged data to storage, validate it in batches, and put off index and
+//! garbage collection work until the end.
+//!
+//! An import is all-or-nothing: nothing staged in it is visible until
+//! [`finish`][BulkImport::finish] completes, and an import that is dropped, or
+//! interrupted by a crash, leaves the database as it was. Any garbage left
+//! behind by an interrupted import is cleaned up the next time the database is
+//! opened.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use fog_pack::{document::Document, entry::Entry, types::*};
+
+use crate::{
+    changes::CommitSeq,
+    transaction::{CommitError, Durability},
+    DbResult,
+};
+
+/// How much has been staged in a [`BulkImport`] so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ImportProgress {
+    /// Documents staged.
+    pub docs: u64,
+    /// Entries staged.
+    pub entries: u64,
+    /// Encoded bytes staged.
+    pub bytes: u64,
+}
+
+/// An in-progress bulk import. Dropping it without calling
+/// [`finish`][Self::finish] abandons the import.
+#[async_trait]
+pub trait BulkImport: Send {
+    /// Stage a document. Documents can be staged in any order, including
+    /// before the documents they link to, and their schemas only need to be
+    /// in the database by the time the import is finished.
+    async fn add_doc(&mut self, doc: Arc<Document>) -> DbResult<()>;
+
+    /// Stage an entry. Its parent document, and any documents it needs for
+    /// validation, may be staged before or after it.
+    async fn add_entry(&mut self, entry: Entry) -> DbResult<()>;
+
+    /// Point a name at a document once the import is finished. The document
+    /// may be staged in the import or already in the database.
+    fn set_name(&mut self, name: &str, target: &Hash);
+
+    /// Get how much has been staged so far.
+    fn progress(&self) -> ImportProgress;
+
+    /// Validate everything staged and make it visible all at once, at the
+    /// requested durability. If anything fails validation, nothing is
+    /// imported and every failure found is returned.
+    async fn finish(
+        self: Box<Self>,
+        durability: Durability,
+    ) -> DbResult<Result<CommitSeq, Vec<CommitError>>>;
+}
@@ -0,0 +1,191 @@
+//! Invitation documents for joining a group.
+//!
+//! Getting a new node into a group takes more than a hash: it needs to know
+//! which networks and discovery providers the group uses, who's allowed in,
+//! where to start reading, and ideally a few peers to dial straight away. An
+//! [`Invite`] bundles all of that into a single document, small enough to
+//! hand over as a file, a link, or a QR code. The receiving node turns it into
+//! a [`GroupSpec`][crate::group::GroupSpec] with
+//! [`GroupSpec::from_invite`][crate::group::GroupSpec::from_invite], opens the
+//! group, and opens a cursor on the invite's [`gate`][Invite::gate].
+//!
+//! Invites adhere to the schema from [`Invite::schema`], so they can be
+//! checked before being trusted and stored like any other document.
+
+use bytes::Bytes;
+use fog_pack::{
+    document::{Document, NewDocument},
+    error::Error as FogError,
+    schema::SchemaBuilder,
+    types::*,
+    validator::{
+        ArrayValidator, BinValidator, BoolValidator, HashValidator, MapValidator, StrValidator,
+        TimeValidator, Validator,
+    },
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{cert::Policy, transport::PeerCandidate, NetInfo, NetType, NodeAddr};
+
+/// A peer to try dialing when joining through an invite.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootstrapHint {
+    /// Name of the transport that can dial the peer.
+    pub transport: String,
+    /// The network the peer is on.
+    pub net: NetType,
+    /// Transport-specific address.
+    pub addr: Bytes,
+    /// The peer's node address, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<NodeAddr>,
+}
+
+impl From<&PeerCandidate> for BootstrapHint {
+    fn from(value: &PeerCandidate) -> Self {
+        Self {
+            transport: value.transport.clone(),
+            net: value.net.clone(),
+            addr: Bytes::copy_from_slice(&value.addr),
+            node: value.node.clone(),
+        }
+    }
+}
+
+impl From<BootstrapHint> for PeerCandidate {
+    fn from(value: BootstrapHint) -> Self {
+        Self {
+            transport: value.transport,
+            net: value.net,
+            addr: value.addr.to_vec(),
+            node: value.node,
+        }
+    }
+}
+
+/// An invitation to join a group.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Invite {
+    /// The gate to open a cursor on once in the group.
+    pub gate: Hash,
+    /// The policy limiting who may be in the group, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+    /// The networks the group uses.
+    pub net: NetInfo,
+    /// Which discovery providers, by name, the group uses. If `None`, any
+    /// provider on the group's networks may be used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery: Option<Vec<String>>,
+    /// Whether a mixnet must be used when finding group members.
+    #[serde(default)]
+    pub mixnet_locator: bool,
+    /// Whether a mixnet must be used when communicating with group members.
+    #[serde(default)]
+    pub mixnet_comms: bool,
+    /// Peers to dial before discovery has found any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bootstrap: Vec<BootstrapHint>,
+    /// A capability token to present to group members when connecting. What
+    /// it holds, and how it's checked, is up to the node that issued it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Bytes>,
+    /// When the invite stops being valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<Timestamp>,
+}
+
+/// Failure to use an invite.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InviteError {
+    /// The invite has expired.
+    #[error("Invite expired at {0}")]
+    Expired(Timestamp),
+    /// The group is limited by a policy, so joining it needs an identity.
+    #[error("Invite is for a group that requires an identity")]
+    NeedsIdentity,
+}
+
+impl Invite {
+    /// Create an invite to a gate, for a group on the given networks with no
+    /// policy.
+    pub fn new(gate: Hash, net: NetInfo) -> Self {
+        Self {
+            gate,
+            policy: None,
+            net,
+            discovery: None,
+            mixnet_locator: false,
+            mixnet_comms: false,
+            bootstrap: Vec::new(),
+            token: None,
+            expires: None,
+        }
+    }
+
+    /// Check if the invite has expired as of the time `now`.
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires.is_some_and(|expires| now > expires)
+    }
+
+    /// Get the bootstrap hints as candidates that can be dialed.
+    pub fn candidates(&self) -> Vec<PeerCandidate> {
+        self.bootstrap
+            .iter()
+            .cloned()
+            .map(PeerCandidate::from)
+            .collect()
+    }
+
+    /// Build the schema document that invites adhere to.
+    pub fn schema() -> Document {
+        let str_list = || {
+            ArrayValidator::new()
+                .items(StrValidator::new().build())
+                .build()
+        };
+        let net = MapValidator::new()
+            .req_add("db", BoolValidator::new().build())
+            .req_add("machine", BoolValidator::new().build())
+            .req_add("direct", BoolValidator::new().build())
+            .req_add("local", BoolValidator::new().build())
+            .req_add("regional", BoolValidator::new().build())
+            .req_add("global", BoolValidator::new().build())
+            .req_add("other", Validator::Any)
+            .build();
+        let hint = MapValidator::new()
+            .req_add("transport", StrValidator::new().build())
+            .req_add("net", Validator::Any)
+            .req_add("addr", BinValidator::new().build())
+            .opt_add("node", Validator::Any)
+            .build();
+        let doc = MapValidator::new()
+            .req_add("gate", HashValidator::new().build())
+            .opt_add("policy", Validator::Any)
+            .req_add("net", net)
+            .opt_add("discovery", str_list())
+            .req_add("mixnet_locator", BoolValidator::new().build())
+            .req_add("mixnet_comms", BoolValidator::new().build())
+            .opt_add("bootstrap", ArrayValidator::new().items(hint).build())
+            .opt_add("token", BinValidator::new().build())
+            .opt_add("expires", TimeValidator::new().build())
+            .build();
+        SchemaBuilder::new(doc)
+            .name("fog-db group invite")
+            .version(1u8)
+            .build()
+            .expect("invite schema should always be valid")
+    }
+
+    /// Make an invite document adhering to the given invite schema.
+    pub fn to_doc(&self, schema: &Hash) -> Result<NewDocument, FogError> {
+        NewDocument::new(Some(schema), self)
+    }
+
+    /// Read an invite from a document. The document should already have been
+    /// validated against the invite schema.
+    pub fn from_doc(doc: &Document) -> Result<Self, FogError> {
+        doc.deserialize()
+    }
+}
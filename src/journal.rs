@@ -0,0 +1,68 @@
+//! Inspection of a database's write-ahead journal.
+//!
+//! Many backends write each commit to a journal before applying it to their
+//! main storage, so that a crash partway through a commit can be recovered
+//! from. Backends that do can expose their journal through the [`Journal`]
+//! trait, which gives a window onto commits that have been journaled but not
+//! yet applied, and onto recently applied commits that haven't yet been
+//! trimmed from the journal.
+//!
+//! Journal payloads are in a backend-specific encoding, identified by
+//! [`Journal::format`]. Tooling that understands the format can use them for
+//! crash recovery or to bootstrap a replica; tooling that doesn't should use
+//! the [change feed][crate::changes::ChangeFeed] instead.
+
+use bytes::Bytes;
+
+use crate::{
+    changes::{CommitSeq, SeqTooOld},
+    transaction::Durability,
+    DbResult,
+};
+
+/// Where a journaled commit is in its lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JournalState {
+    /// The commit has been written to the journal, but not yet applied to the
+    /// database.
+    Pending,
+    /// The commit has been applied to the database.
+    Applied,
+}
+
+/// A single commit, as recorded in the journal.
+#[derive(Clone, Debug)]
+pub struct JournalRecord {
+    /// The sequence number assigned to the commit.
+    pub seq: CommitSeq,
+    /// Whether the commit has been applied yet.
+    pub state: JournalState,
+    /// The durability the commit was made with.
+    pub durability: Durability,
+    /// The encoded commit, in the journal's [format][Journal::format].
+    pub payload: Bytes,
+}
+
+/// A window onto a database's write-ahead journal.
+pub trait Journal: Send + Sync {
+    /// A name identifying the encoding used for record payloads. This should
+    /// change whenever the encoding does.
+    fn format(&self) -> &str;
+
+    /// The oldest sequence number still held in the journal. Records older
+    /// than this have been trimmed.
+    fn oldest(&self) -> CommitSeq;
+
+    /// Get every commit that has been journaled but not yet applied, in
+    /// sequence order.
+    fn pending(&self) -> DbResult<Vec<JournalRecord>>;
+
+    /// Get a single commit from the journal, or `None` if it is not in the
+    /// journal.
+    fn get(&self, seq: CommitSeq) -> DbResult<Option<JournalRecord>>;
+
+    /// Get every journaled commit after the given sequence number, in sequence
+    /// order, including pending ones. Fails if commits after `seq` have
+    /// already been trimmed from the journal.
+    fn since(&self, seq: CommitSeq) -> DbResult<Result<Vec<JournalRecord>, SeqTooOld>>;
+}
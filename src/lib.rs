@@ -1,5 +1,7 @@
 /*!
 This crate defines the interface to a generic implementation of a fog-pack database (a FogDB).
+A complete implementation held entirely in memory, [`MemDb`][memory::MemDb], is
+included for development and testing.
 
 The Database
 ------------
@@ -34,11 +36,12 @@ FogDB multi-modal:
 
 - From a document-oriented view, they're a collection of documents all matching the same schema.
 - From a relational database view, the parent document & entry key is a table
-    reference, and the entries are records (or *entries*, get it?) in the table.
+  reference, and the entries are records (or *entries*, get it?) in the table.
 - From a graph database view, the documents are nodes, and the entries are edges.
 
 Rather than provide the expected access APIs for all of these, FogDB provides a
-base over which such APIs can be built.
+base over which such APIs can be built. The [graph] module is one such layer,
+running declarative traversals over the cursor API.
 
 Transactions: Modifying the Database
 -----
@@ -59,17 +62,32 @@ Transactions can do the following:
 - Add a document to the database
 - Weaken/strengthen document hash links
 - Add an entry to the database, optionally setting a time-to-live or an access
-    policy
+  policy
 - Modify an entry's time-to-live or its access policy
 - Delete an entry from the database
+- Restore a deleted entry that is still being retained as history
 
 Documents cannot be deleted directly; instead, when they are no longer reachable
 from the named root documents, they are automatically garbage-collected.
 
+Deleting an entry only removes it from the local database. To let other nodes
+know about the deletion, a [tombstone][tombstone::Tombstone] can be added in
+the same transaction.
+
+Each committed transaction is assigned a [sequence number][changes::CommitSeq],
+and the changes made by every transaction after a given sequence number can be
+streamed from the database's [change feed][changes::ChangeFeed]. The
+[receipt][transaction::CommitReceipt] returned on commit carries the sequence
+number, along with which of the added documents were already in the database.
+
 Note that all transactions will only execute on the local FogDB instance; this
 follows the rule of the system can only modify itself, and it is up to other
 database nodes to modify themselves to match as they desire.
 
+When several nodes write to the same document's entries, the [crdt] module
+provides counters, sets, and registers that converge regardless of the order
+in which each node sees the others' entries.
+
 Cursors: Reading the Database
 ------
 
@@ -111,6 +129,10 @@ Each FogDB instance exists as a single Node, which may use any number of
 network protocols to communicate with other Nodes. This lets the [cursor]
 interface use many remote databases at once to retrieve documents and get query
 results, and lets portions of the database be exposed to other nodes in turn.
+Each protocol is supplied to the database as a [Transport][transport::Transport]
+plugin, so new network types can be added without modifying the database
+backend. Finding nodes is handled separately, by
+[Discovery][discovery::Discovery] providers.
 
 Connecting to other nodes is done by [opening a group][Db::group] using a [group
 specification][group::GroupSpec]. This specification limits the network types
@@ -121,14 +143,14 @@ Certificates](#policies-and-certificates)).
 Node discovery can be limited to these approximate network classes:
 
 - Machine: communication between other running FogDB instances on the same
-    computer.
+  computer.
 - Direct: Direct machine-to-machine networking, with no switches or routers
-    present. Primary example is WiFi Direct.
+  present. Primary example is WiFi Direct.
 - Local: local networks. LANs, ad-hoc networks, and other physically close
-    networking systems fall under this category.
+  networking systems fall under this category.
 - Regional: A collection of local networks that isn't the internet. Campus
-    networks and Metropolitan area networks fall under this category. The IPv6
-    "organization-level" multicast scope also fits.
+  networks and Metropolitan area networks fall under this category. The IPv6
+  "organization-level" multicast scope also fits.
 - Global: the global internet.
 
 Once a group is opened, the various underlying network protocols will attempt to
@@ -153,7 +175,8 @@ entry; otherwise it is skipped over.
 When a query is made on a particular document reached through a gate, you can
 optionally [hook into the query][gate::Gate::query_hook] and manually provide
 query results. This allows for dynamic generation of query responses, and can be
-used to build RPC-like mechanisms from the query system.
+used to build RPC-like mechanisms from the query system. The [rpc] module
+provides one such mechanism.
 
 Policies and Certificates
 -------------------------
@@ -195,8 +218,9 @@ use std::{collections::{HashMap, BTreeMap}, error::Error, sync::Arc};
 
 use async_trait::async_trait;
 use cursor::{DbQuery, CursorQuery};
-use fog_pack::{entry::EntryRef, error::Error as FogError, schema::Schema, types::*, document::Document};
+use fog_pack::{entry::EntryRef, error::Error as FogError, query::NewQuery, schema::Schema, types::*, document::Document};
 use group::GroupSpec;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 pub mod gate;
@@ -204,8 +228,76 @@ pub mod cert;
 pub mod group;
 pub mod transaction;
 pub mod cursor;
+pub mod graph;
+pub mod transport;
+pub mod mixnet;
+pub mod discovery;
+pub mod limits;
+pub mod cache;
+pub mod fetch;
+pub mod stats;
+pub mod changes;
+pub mod crdt;
+pub mod tombstone;
+pub mod journal;
+pub mod coordinator;
+pub mod compression;
+pub mod builder;
+pub mod wire;
+pub mod remote;
+pub mod capabilities;
+pub mod health;
+pub mod quota;
+pub mod pinning;
+pub mod eviction;
+pub mod access;
+pub mod rpc;
+pub mod anomaly;
+pub mod presence;
+pub mod skew;
+pub mod middleware;
+pub mod config;
+pub mod names;
+pub mod versioned;
+pub mod registry;
+pub mod backpressure;
+pub mod complexity;
+pub mod schema_fetch;
+pub mod policies;
+pub mod reputation;
+pub mod weak_refs;
+pub mod gc;
+pub mod import;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "sim")]
+pub mod fault;
+#[cfg(feature = "testsuite")]
+pub mod testsuite;
+pub mod record;
+pub mod canonical;
+pub mod compat;
+pub mod runtime;
+pub mod peers;
+pub mod invite;
+pub mod saved_gates;
+pub mod views;
+pub mod validate;
+pub mod shard;
+pub mod resources;
+pub mod forward;
+pub mod availability;
+pub mod retention;
+pub mod expiry;
+pub mod diff;
+pub mod fingerprint;
+pub mod memory;
+pub mod barrier;
+pub mod service;
+pub mod warm;
 
 /// Network connection information
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct NetInfo {
     /// Local database connection
     pub db: bool,
@@ -223,9 +315,25 @@ pub struct NetInfo {
     pub other: BTreeMap<String, BTreeMap<String, String>>,
 }
 
+impl NetInfo {
+    /// Check if the given network type is permitted by this network info.
+    pub fn allows(&self, net: &NetType) -> bool {
+        match net {
+            NetType::Db => self.db,
+            NetType::Machine => self.machine,
+            NetType::Direct => self.direct,
+            NetType::Local => self.local,
+            NetType::Regional => self.regional,
+            NetType::Global => self.global,
+            NetType::Other(name) => self.other.contains_key(name),
+        }
+    }
+}
+
 /// Information about a connecting node. Includes the source network type from
 /// which the connection was made, and optionally the Identities used by the
 /// node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NodeInfo {
     /// The network info for this node
     pub net: NetType,
@@ -233,13 +341,46 @@ pub struct NodeInfo {
     pub perm_id: Option<Identity>,
     /// Ephemeral Identity, notionally tied to the node itself
     pub eph_id: Option<Identity>,
+    /// The node's attestation of its clock when it connected, if it gave one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<Box<skew::TimeAttestation>>,
+    /// The node's advertisement of what it can offer the group, if it made
+    /// one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Box<resources::ResourceAd>>,
+}
+
+impl NodeInfo {
+    /// Get the node's verified clock attestation for a context. Returns
+    /// `None` if the node didn't give one, if it was for a different context,
+    /// or if it wasn't signed by one of the node's identities.
+    pub fn attested_time(&self, context: &Hash) -> Option<&skew::TimeAttestation> {
+        let time = self.time.as_ref().filter(|t| &t.context == context)?;
+        let signer = time.verify()?;
+        [&self.perm_id, &self.eph_id]
+            .into_iter()
+            .any(|id| id.as_ref() == Some(&signer))
+            .then_some(time)
+    }
+
+    /// Get the resources the node advertised. Returns `None` if it didn't
+    /// advertise any, or if the advertisement wasn't signed by one of the
+    /// node's identities.
+    pub fn advertised_resources(&self) -> Option<&resources::Resources> {
+        let ad = self.resources.as_ref()?;
+        let signer = ad.verify()?;
+        [&self.perm_id, &self.eph_id]
+            .into_iter()
+            .any(|id| id.as_ref() == Some(&signer))
+            .then_some(&ad.resources)
+    }
 }
 
 /// An origin address for a database node on the network.
 ///
 /// This address is generally unique, and at the very least the node's intent is
 /// to act as though it is unique.
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NodeAddr {
     /// Long-term Identity, notionally tied to the user of the node
     pub perm_id: Identity,
@@ -270,6 +411,7 @@ impl TryFrom<NodeInfo> for NodeAddr {
 }
 
 /// A network type
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NetType {
     Db,
     Machine,
@@ -282,6 +424,9 @@ pub enum NetType {
 
 /// A fundamental database error has occurred. Usually means the database must
 /// be closed and access halted.
+///
+/// Can be serialized for sending to another process; see the [wire] module for
+/// details.
 #[non_exhaustive]
 pub enum DbError {
     /// Internal Database error
@@ -311,67 +456,316 @@ type DbResult<T> = Result<T, Box<DbError>>;
 /// - Groups may be opened through the database by calling [`Db::group`].
 /// - Schemas may be added, retrieved, and removed from the database.
 /// - Name-to-Document mappings may be added, retrieved, and removed from the
-///     database. These mappings function as the roots of the database's
-///     Document tree, pinning documents to the database.
-
-pub trait Db {
-
+///   database. These mappings function as the roots of the database's
+///   Document tree, pinning documents to the database.
+///
+/// Methods that may need to touch storage, or to reach a database living in
+/// another process, are async and fallible, so backends never have to block
+/// inside them or make up answers. The trait is object-safe, so a database can
+/// be passed around as a `Box<dyn Db>` or `Arc<dyn Db>`.
+#[async_trait]
+pub trait Db: Send + Sync {
     /// Start a new transaction with this database
     fn txn(&self) -> transaction::Transaction;
 
+    /// Ask for permission to stage and commit a transaction. Bulk producers
+    /// should wait for the permit before staging each transaction, and attach
+    /// it with [`Transaction::with_permit`][transaction::Transaction::with_permit],
+    /// so that a database falling behind on writes can hold them back.
+    fn commit_permit(&self) -> Box<dyn backpressure::PermitRequest>;
+
+    /// Start a bulk import, for loading far more documents and entries than
+    /// an ordinary transaction should hold. The import becomes visible all at
+    /// once when finished, and leaves no trace if abandoned.
+    fn bulk_import(&self) -> Box<dyn import::BulkImport>;
+
+    /// Get the sequence number of the most recently committed transaction.
+    async fn current_seq(&self) -> DbResult<changes::CommitSeq>;
+
+    /// Stream every change committed after the given sequence number, followed
+    /// by new changes as they are committed. Fails if the database no longer
+    /// keeps history going back that far.
+    async fn changes_since(
+        &self,
+        seq: changes::CommitSeq,
+    ) -> DbResult<Result<Box<dyn changes::ChangeFeed>, changes::SeqTooOld>>;
+
+    /// Watch for entries being added, modified, or deleted under a document's
+    /// key, from the time the watch is opened. Every commit is reported,
+    /// including those made by layers syncing entries in from other nodes,
+    /// like [remote databases][remote] and replication. Entries can be
+    /// watched before their parent document is in the database.
+    async fn entry_watch(
+        &self,
+        doc: &Hash,
+        key: &str,
+    ) -> DbResult<Box<dyn changes::EntryWatch>>;
+
     /// Open a new group through this database
     fn group(&self, spec: GroupSpec) -> Box<dyn group::Group>;
 
-    /// Open a local cursor on this database
-    fn cursor(&self) -> cursor::NewCursor;
+    /// Get the network transports this database uses when opening groups.
+    fn transports(&self) -> &transport::TransportRegistry;
+
+    /// Get the peer discovery providers this database uses when opening
+    /// groups.
+    fn discovery(&self) -> &discovery::DiscoveryRegistry;
+
+    /// Get the runtime managing connections for every group opened through
+    /// this database.
+    fn runtime(&self) -> &dyn runtime::NodeRuntime;
+
+    /// Get the mixnet provider this database uses, if it has one. Opening a
+    /// group that requires a mixnet should fail if this can't satisfy it.
+    fn mixnet(&self) -> Option<&dyn mixnet::Mixnet>;
+
+    /// Get the database's write-ahead journal, if it keeps one and exposes it.
+    fn journal(&self) -> Option<&dyn journal::Journal>;
+
+    /// Get the policies consulted before garbage collection evicts a document.
+    fn eviction_policies(&self) -> &eviction::EvictionRegistry;
+
+    /// Work out which documents would be garbage collected, and which open
+    /// gates would break, if a change were committed. Nothing is changed.
+    async fn gc_preview(&self, change: gc::ProposedChange) -> DbResult<gc::GcPreview>;
+
+    /// Get the optional features this database supports.
+    async fn capabilities(&self) -> DbResult<capabilities::DbCapabilities>;
+
+    /// Get the clock skew policy applied to entry time-to-lives, certificate
+    /// validity, and query time filters.
+    async fn skew_policy(&self) -> DbResult<skew::SkewPolicy>;
+
+    /// Set the clock skew policy. Groups use this policy unless they have
+    /// their own.
+    async fn set_skew_policy(&self, policy: skew::SkewPolicy) -> DbResult<()>;
+
+    /// Get the current health of the database.
+    async fn health(&self) -> DbResult<health::Health>;
+
+    /// Watch for changes to the health of the database.
+    fn health_events(&self) -> Box<dyn health::HealthEvents>;
+
+    /// Remove expired entries from storage, stopping once the budget runs
+    /// out. Expired entries are already treated as missing before they're
+    /// swept; see [the expiry module][expiry] for details.
+    async fn ttl_sweep(&self, budget: expiry::SweepBudget) -> DbResult<expiry::SweepReport>;
+
+    /// Watch for entries evicted by the database's
+    /// [retention policies][Db::schema_set_retention].
+    fn retention_events(&self) -> Box<dyn retention::RetentionEvents>;
+
+    /// Get the scheduler that all remote document requests made through this
+    /// database's groups and cursors are routed through.
+    fn fetch_scheduler(&self) -> Arc<dyn fetch::FetchScheduler>;
+
+    /// Get the service that documents and entries are validated through,
+    /// which keeps compiled schemas cached and validates on a pool of workers.
+    fn validator(&self) -> Arc<dyn validate::Validator>;
+
+    /// Open a local cursor on this database, starting from the given document.
+    /// Returns `None` if the document isn't in the database.
+    async fn cursor(&self, doc: &Hash, opts: cursor::CursorOpts) -> DbResult<Option<cursor::NewCursor>>;
 
     /// Get a document directly from the database
-    fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>>;
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>>;
+
+    /// Get metadata about a document in the database, including how often it
+    /// has been read. Returns `None` if the document isn't in the database.
+    async fn doc_info(&self, doc: &Hash) -> DbResult<Option<access::DocInfo>>;
+
+    /// Compute statistics for every document reachable from the given root
+    /// document, or return `None` if the root isn't in the database.
+    /// Implementations may cache these statistics and update them
+    /// incrementally as documents are added or evicted.
+    async fn tree_stats(&self, root: &Hash) -> DbResult<Option<stats::TreeStats>>;
+
+    /// Get the fingerprint of everything held in the tree under the given
+    /// root, or `None` if the root isn't in the database. Two databases with
+    /// matching fingerprints hold identical views of the tree. Implementations
+    /// should cache document fingerprints and invalidate them as commits
+    /// change the tree; see [the fingerprint module][fingerprint] for details.
+    async fn tree_fingerprint(&self, root: &Hash)
+        -> DbResult<Option<fingerprint::TreeFingerprint>>;
 
     /// Make a query directly on the database
     fn query(&self, doc: &Hash, query: DbQuery) -> Box<dyn CursorQuery>;
 
+    /// Count the entries stored under a document's key. If a query is
+    /// provided, only entries matching it are counted.
+    async fn entry_count(&self, doc: &Hash, key: &str, query: Option<&NewQuery>) -> DbResult<u64>;
+
     /// Get a schema in the database
-    fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
 
     /// Add a schema to the database. Fails if the schema document wasn't valid.
-    fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>>;
+    async fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>>;
 
     /// Remove a schema from the database. Returns false if the schema wasn't in the database.
-    fn schema_del(&self, schema: &Hash) -> DbResult<bool>;
+    async fn schema_del(&self, schema: &Hash) -> DbResult<bool>;
 
     /// Get a list of all schemas in the database.
-    fn schema_list(&self) -> Vec<Hash>;
+    async fn schema_list(&self) -> DbResult<Vec<Hash>>;
+
+    /// Set how documents and entries of a schema are compressed when stored.
+    /// Only affects data stored after the policy is set. Returns false if the
+    /// schema wasn't in the database.
+    async fn schema_set_compression(
+        &self,
+        schema: &Hash,
+        policy: compression::CompressionPolicy,
+    ) -> DbResult<bool>;
+
+    /// Get the compression policy for a schema, or `None` if the schema isn't
+    /// in the database.
+    async fn schema_get_compression(
+        &self,
+        schema: &Hash,
+    ) -> DbResult<Option<compression::CompressionPolicy>>;
+
+    /// Set which link fields of a schema's documents are weakened by default
+    /// when added in a transaction. Only affects documents added after the
+    /// defaults are set. Returns false if the schema wasn't in the database.
+    async fn schema_set_weak_refs(
+        &self,
+        schema: &Hash,
+        defaults: weak_refs::WeakRefDefaults,
+    ) -> DbResult<bool>;
+
+    /// Get the weak link defaults for a schema, or `None` if the schema isn't
+    /// in the database.
+    async fn schema_get_weak_refs(&self, schema: &Hash)
+        -> DbResult<Option<weak_refs::WeakRefDefaults>>;
+
+    /// Set how many entries under a key of each of a schema's documents are
+    /// kept. The database evicts entries to enforce the policy from then on,
+    /// reporting each on its [retention events][Db::retention_events].
+    /// Setting an [unlimited][retention::RetentionPolicy::is_unlimited] policy
+    /// removes it. Returns false if the schema wasn't in the database.
+    async fn schema_set_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+        policy: retention::RetentionPolicy,
+    ) -> DbResult<bool>;
+
+    /// Get the retention policy for a key of a schema, or `None` if the schema
+    /// isn't in the database. Keys without a policy get an unlimited one.
+    async fn schema_get_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+    ) -> DbResult<Option<retention::RetentionPolicy>>;
 
     /// Get a hash associated with a name in the database.
-    fn name_get(&self, name: &str) -> DbResult<Option<Hash>>;
+    async fn name_get(&self, name: &str) -> DbResult<Option<Hash>>;
 
     /// Add a name-to-hash mapping to the database. This pins the document
     /// inside the database, once it's been added. This should be done before
     /// adding the document in a transaction. Returns the previous hash, if
-    /// there was one.
-    fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Option<Hash>>;
+    /// there was one. Fails if the name isn't allowed by the database's
+    /// [naming policy][names::NamingPolicy::check].
+    async fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Result<Option<Hash>, names::NameError>>;
+
+    /// Add a name-to-hash mapping under one of the
+    /// [reserved prefixes][names::NamingPolicy::reserved]. Only for use by the
+    /// components that own those prefixes; applications should use
+    /// [`name_add`][Db::name_add].
+    async fn name_add_reserved(
+        &self,
+        name: &str,
+        hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, names::NameError>>;
+
+    /// Get the policy new names are checked against.
+    async fn naming_policy(&self) -> DbResult<names::NamingPolicy>;
+
+    /// Set the policy new names are checked against. Existing names aren't
+    /// affected.
+    async fn set_naming_policy(&self, policy: names::NamingPolicy) -> DbResult<()>;
 
     /// Remove a name-hash mapping from the database, returning None if there
     /// wasn't one stored.
-    fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>>;
+    async fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>>;
 
     /// Get a list of all named documents in the database.
-    fn name_list(&self) -> Vec<(String, Hash)>;
+    async fn name_list(&self) -> DbResult<Vec<(String, Hash)>>;
+
+    /// Get a list of all named documents whose names start with `prefix`, in
+    /// order by name.
+    async fn name_list_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Hash)>>;
+
+    /// Get a name's target along with its metadata.
+    async fn name_info(&self, name: &str) -> DbResult<Option<names::NameInfo>>;
+
+    /// Set a name's descriptive metadata, replacing any that was there.
+    /// Returns false if the name doesn't exist. Metadata is removed along with
+    /// the name.
+    async fn name_set_meta(&self, name: &str, meta: names::NameMeta) -> DbResult<bool>;
 }
 
+// Databases are meant to be usable as trait objects.
+const _: Option<&dyn Db> = None;
+
 /// A connection to the database through which a transaction can be committed.
 #[async_trait]
 pub trait DbCommit {
+    /// Commit the changes, returning a receipt with the sequence number
+    /// assigned to them once they have reached the requested durability.
+    /// Documents that were already in the database are listed in the receipt
+    /// as [existing][transaction::CommitReceipt::existing] rather than
+    /// [stored][transaction::CommitReceipt::stored].
     async fn commit(
         self: Box<Self>,
         docs: HashMap<Hash, transaction::DocChange>,
         entries: HashMap<EntryRef, transaction::EntryChange>,
-    ) -> DbResult<Result<(), transaction::CommitErrors>>;
+        names: HashMap<String, transaction::NameChange>,
+        durability: transaction::Durability,
+    ) -> DbResult<Result<transaction::CommitReceipt, transaction::CommitErrors>>;
+
+    /// Commit several independent sets of changes together, making them all
+    /// durable at once. Each set of changes succeeds or fails on its own, and
+    /// successful ones are assigned sequence numbers in the order given. The
+    /// returned results are in the same order as the provided change sets.
+    async fn commit_many(
+        self: Box<Self>,
+        changes: Vec<transaction::ChangeSet>,
+        durability: transaction::Durability,
+    ) -> DbResult<Vec<Result<transaction::CommitReceipt, transaction::CommitErrors>>>;
+
+    /// Prepare the changes for a two-phase commit: validate them and make them
+    /// durable, but don't apply them yet. Once prepared, the commit must
+    /// succeed unless the database fails internally.
+    async fn prepare(
+        self: Box<Self>,
+        docs: HashMap<Hash, transaction::DocChange>,
+        entries: HashMap<EntryRef, transaction::EntryChange>,
+        names: HashMap<String, transaction::NameChange>,
+        durability: transaction::Durability,
+    ) -> DbResult<Result<Box<dyn coordinator::PreparedCommit>, transaction::CommitErrors>>;
 
     /// Get a schema in the database
-    fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
 
     /// Get a document directly from the database
-    fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>>;
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>>;
+
+    /// Get the weak link defaults for a schema, if any have been set
+    async fn schema_weak_refs(
+        &self,
+        schema: &Hash,
+    ) -> DbResult<Option<weak_refs::WeakRefDefaults>>;
+
+    /// Get the database's shared validator, if this connection can use it.
+    /// Transactions validate batches on the calling task without one.
+    fn validator(&self) -> Option<Arc<dyn validate::Validator>> {
+        None
+    }
+
+    /// Identify the database this connection commits to, so transactions
+    /// can be checked for coming from the same database. Connections to the
+    /// same database must give the same value while any of them are alive,
+    /// and connections to different databases must give different ones. The
+    /// address of the database's shared state works well.
+    fn db_id(&self) -> usize;
 }
\ No newline at end of file
@@ -0,0 +1,258 @@
+//! Shared rate limit, budget, and backoff types.
+//!
+//! Gates, groups, and cursors all need to bound how much work they do on behalf
+//! of someone. Rather than each growing its own ad-hoc fields, they share the
+//! types in this module:
+//!
+//! - [`RateLimit`]: a token-bucket limit on how quickly something may happen.
+//! - [`Budget`]: an absolute cap on bytes and operations.
+//! - [`Backoff`]: an exponential delay between retries.
+//!
+//! Limits compose by tightening: combining two limits gives one that is at
+//! least as strict as both. Implementations consume limits through the
+//! [`Limiter`] and [`BudgetMeter`] traits, and this module provides
+//! [`TokenBucket`] and [`BudgetCounter`] as ready-made implementations.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A token-bucket rate limit: `rate` units are replenished every `period`, and
+/// at most `burst` units may be consumed at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Units replenished per period.
+    pub rate: u32,
+    /// The replenishment period.
+    pub period: Duration,
+    /// Maximum number of units that can be accumulated and used at once.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A rate limit of `rate` units per second, with a burst size equal to the
+    /// rate.
+    pub fn per_sec(rate: u32) -> Self {
+        Self {
+            rate,
+            period: Duration::from_secs(1),
+            burst: rate,
+        }
+    }
+
+    /// The sustained rate, in units per second.
+    pub fn units_per_sec(&self) -> f64 {
+        if self.period.is_zero() {
+            return f64::INFINITY;
+        }
+        self.rate as f64 / self.period.as_secs_f64()
+    }
+
+    /// Combine with another rate limit, keeping the slower sustained rate and
+    /// the smaller burst.
+    pub fn tighten(self, other: RateLimit) -> RateLimit {
+        let mut slower = if other.units_per_sec() < self.units_per_sec() {
+            other
+        } else {
+            self
+        };
+        slower.burst = self.burst.min(other.burst);
+        slower
+    }
+}
+
+/// An absolute cap on resource use. Unset fields are unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Budget {
+    /// Maximum number of bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Maximum number of operations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ops: Option<u64>,
+}
+
+impl Budget {
+    /// A budget with no limits.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Combine with another budget, keeping the smaller of each limit.
+    pub fn tighten(self, other: Budget) -> Budget {
+        fn min(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        Budget {
+            bytes: min(self.bytes, other.bytes),
+            ops: min(self.ops, other.ops),
+        }
+    }
+}
+
+/// An exponential backoff between retries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Backoff {
+    /// Delay before the first retry.
+    pub initial: Duration,
+    /// Upper bound on the delay between retries.
+    pub max: Duration,
+    /// Factor the delay is multiplied by after each attempt.
+    pub multiplier: u32,
+    /// Give up after this many retries. Retries forever if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(60),
+            multiplier: 2,
+            max_retries: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Get the delay to wait before retry number `attempt` (starting from 0),
+    /// or `None` if no more retries should be made.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_retries.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+        let factor = self.multiplier.checked_pow(attempt).unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
+
+/// A budget ran out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum BudgetExhausted {
+    #[error("Byte budget exhausted")]
+    Bytes,
+    #[error("Operation budget exhausted")]
+    Ops,
+}
+
+/// Something that enforces a rate limit.
+pub trait Limiter: Send + Sync {
+    /// Try to consume `units` from the limiter. On failure, returns how long
+    /// to wait before the units would be available.
+    fn try_acquire(&self, units: u32) -> Result<(), Duration>;
+}
+
+/// Something that enforces a budget.
+pub trait BudgetMeter: Send + Sync {
+    /// Try to spend from the budget. Nothing is spent if this fails.
+    fn try_spend(&self, bytes: u64, ops: u64) -> Result<(), BudgetExhausted>;
+
+    /// What remains of the budget.
+    fn remaining(&self) -> Budget;
+}
+
+/// A [`Limiter`] implementing a [`RateLimit`] as a token bucket.
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Create a new, full token bucket.
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new((limit.burst as f64, Instant::now())),
+        }
+    }
+
+    /// The rate limit this bucket enforces.
+    pub fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+
+    /// Try to consume `units` from the bucket, as of the time `now`.
+    pub fn try_acquire_at(&self, units: u32, now: Instant) -> Result<(), Duration> {
+        let rate = self.limit.units_per_sec();
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = &mut *state;
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * rate).min(self.limit.burst as f64);
+        *last = now.max(*last);
+        let units = units as f64;
+        if units <= *tokens {
+            *tokens -= units;
+            Ok(())
+        } else if units > self.limit.burst as f64 || rate == 0.0 {
+            Err(Duration::MAX)
+        } else {
+            Err(Duration::try_from_secs_f64((units - *tokens) / rate).unwrap_or(Duration::MAX))
+        }
+    }
+}
+
+impl Limiter for TokenBucket {
+    fn try_acquire(&self, units: u32) -> Result<(), Duration> {
+        self.try_acquire_at(units, Instant::now())
+    }
+}
+
+/// A [`BudgetMeter`] that counts down from a [`Budget`].
+#[derive(Debug)]
+pub struct BudgetCounter {
+    bytes: Option<AtomicU64>,
+    ops: Option<AtomicU64>,
+}
+
+impl BudgetCounter {
+    /// Start counting down from the given budget.
+    pub fn new(budget: Budget) -> Self {
+        Self {
+            bytes: budget.bytes.map(AtomicU64::new),
+            ops: budget.ops.map(AtomicU64::new),
+        }
+    }
+}
+
+impl BudgetMeter for BudgetCounter {
+    fn try_spend(&self, bytes: u64, ops: u64) -> Result<(), BudgetExhausted> {
+        fn spend(counter: &Option<AtomicU64>, amount: u64) -> bool {
+            let Some(counter) = counter else { return true };
+            counter
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| {
+                    v.checked_sub(amount)
+                })
+                .is_ok()
+        }
+        if !spend(&self.bytes, bytes) {
+            return Err(BudgetExhausted::Bytes);
+        }
+        if !spend(&self.ops, ops) {
+            // Refund the bytes so a failed spend consumes nothing.
+            if let Some(counter) = &self.bytes {
+                counter.fetch_add(bytes, Ordering::AcqRel);
+            }
+            return Err(BudgetExhausted::Ops);
+        }
+        Ok(())
+    }
+
+    fn remaining(&self) -> Budget {
+        Budget {
+            bytes: self.bytes.as_ref().map(|c| c.load(Ordering::Acquire)),
+            ops: self.ops.as_ref().map(|c| c.load(Ordering::Acquire)),
+        }
+    }
+}
@@ -0,0 +1,2825 @@
+//! A complete database held entirely in memory.
+//!
+//! [`MemDb`] implements [`Db`] without any storage behind it, so applications
+//! have something to develop and test against before a real backend exists,
+//! and backend authors have a reference for how each part of the interface is
+//! meant to behave. Nothing is persisted: dropping the last handle to a
+//! database drops everything in it.
+//!
+//! Transactions are validated and applied exactly as described on
+//! [`DbCommit`], and garbage collection follows the rules in
+//! [the crate documentation](crate). A document stays in the
+//! database while it can be reached from a name through strong document links
+//! or through the links in its ancestors' entries, while it's an unexpired
+//! [cache entry][Cursor::cache_current] or a prepared transaction depends on
+//! it, or while it can be reached from one of those. Anything else is put to
+//! the database's [eviction policies][crate::eviction] once it has been
+//! unreachable for the configured
+//! [grace period][crate::config::GcConfig::grace], and evicted
+//! along with its entries if none of them object. Names and entries a prepared
+//! transaction changes can't be changed any other way until it's committed or
+//! rolled back: [`Db::name_add`] fails with [`NameError::Held`], and
+//! [`Db::name_del`] fails with that error as [`DbError::Internal`].
+//!
+//! There's no background work. Garbage is collected at the end of every
+//! commit and name change, and whenever [`MemDb::collect_garbage`] is called;
+//! tests that want unreachable documents gone straight away should open the
+//! database with a zero grace period. Expired entries are treated as missing
+//! from the moment they expire, and are removed from memory by
+//! [`Db::ttl_sweep`].
+//!
+//! Some parts of the interface only make sense with storage or a network
+//! behind them, and are simplified:
+//!
+//! - Every commit is kept in the change feed's history, and durability
+//!   settings are ignored.
+//! - Compression policies and cache tiers are recorded but have no effect.
+//! - Query signer policies only accept entries signed by one of the policy's
+//!   roots, since there's no certificate store to check chains against.
+//! - Groups have no other members. Their cursors only reach documents in this
+//!   database, pins are refused, summary exchanges and warmups find no one
+//!   to talk to, and advertisements go unseen. Barriers that need any
+//!   members to confirm fail straight away. Gates can be opened, and are
+//!   reported by [`Db::gc_preview`] when they would break, but nothing ever
+//!   attaches to them.
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fog_pack::{
+    document::{Document, NewDocument},
+    entry::{Entry, EntryRef, NewEntry},
+    error::Error as FogError,
+    query::{NewQuery, Query},
+    schema::{NoSchema, Schema},
+    types::*,
+};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    lock::Mutex as AsyncMutex,
+    StreamExt,
+};
+use thiserror::Error;
+
+use crate::{
+    access::{AccessStats, CacheTier, DocInfo},
+    anomaly::AnomalyDetector,
+    availability::{
+        AvailabilitySummary, SummaryError, SummaryExchange, SummaryReply,
+        DEFAULT_FALSE_POSITIVE_RATE,
+    },
+    backpressure::{PermitRequest, Unlimited},
+    barrier::{Barrier, BarrierError, BarrierReport},
+    capabilities::DbCapabilities,
+    cert::EntryPolicy,
+    changes::{
+        ChangeFeed, CommitRecord, CommitSeq, DocRecord, EntryEvent, EntryRecord, EntryWatch,
+        NameRecord, SeqTooOld,
+    },
+    compression::CompressionPolicy,
+    config::DbConfig,
+    coordinator::PreparedCommit,
+    cursor::{
+        time_between, ChunkStream, ChunkUpdate, Cursor, CursorBackError, CursorError, CursorOpts,
+        CursorQuery, DbQuery, DocChunk, ForkCursor, ForkSpawner, LinkStrength, MergeStrategy,
+        NewCursor, QueryResult, QueryUpdate, ResultOrd, TraceId, UsefulReport, Usefulness,
+    },
+    discovery::DiscoveryRegistry,
+    eviction::{EvictionCandidate, EvictionRegistry, EvictionVerdict},
+    expiry::{SweepBudget, SweepReport},
+    fetch::{FetchScheduler, PriorityScheduler, SchedulerConfig},
+    fingerprint::{doc_fingerprint, FingerprintCache, TreeFingerprint},
+    gate::{Gate, GateEvent, GateEvents, GateSettings, QueryHook, Tier},
+    gc::{GcPreview, ProposedChange},
+    group::{Group, GroupSpec},
+    health::{Health, HealthEvents, HealthIssue},
+    import::{BulkImport, ImportProgress},
+    journal::Journal,
+    mixnet::Mixnet,
+    names::{NameError, NameInfo, NameMeta, NamingPolicy},
+    pinning::{HostedPin, PinError, PinGrant, PinPolicy, PinRequest},
+    quota::{QuotaUsage, StorageQuota},
+    resources::{ResourceFilter, Resources},
+    retention::{RetainedEntry, RetentionEvents, RetentionEviction, RetentionPolicy},
+    runtime::{Connection, GroupSummary, NodeEvent, NodeEvents, NodeLimits, NodeRuntime},
+    schema_fetch::{SchemaFetchError, SchemaRequest},
+    service::{ServiceDescriptor, ServiceFilter},
+    skew::SkewPolicy,
+    stats::TreeStats,
+    transaction::{
+        ChangeSet, CommitError, CommitErrors, CommitReceipt, DocChange, Durability, EncodedDoc,
+        EncodedEntry, EntryChange, EntryError, NameChange, SchemaError, Transaction,
+    },
+    transport::TransportRegistry,
+    validate::{Validator, ValidatorStats},
+    warm::{WarmError, Warmed, Warmup},
+    weak_refs::WeakRefDefaults,
+    Db, DbCommit, DbError, DbResult, NetType, NodeAddr, NodeInfo,
+};
+
+/// Size of the chunks delivered by chunked fetches, in bytes.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The error held by [`DbError::Internal`] when a change is made to a
+/// database opened [read-only][DbConfig::read_only]. Transactions are
+/// rejected with [`CommitError::Rejected`] instead.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("The database is read-only")]
+pub struct ReadOnly;
+
+fn read_only() -> Box<DbError> {
+    Box::new(DbError::Internal(Box::new(ReadOnly)))
+}
+
+/// Get the time `after` past `time`, to the second.
+fn later(time: Timestamp, after: Duration) -> Timestamp {
+    time + after.as_secs().min(i64::MAX as u64) as i64
+}
+
+/// The local database, as the source of query results.
+fn local_info() -> NodeInfo {
+    NodeInfo {
+        net: NetType::Db,
+        perm_id: None,
+        eph_id: None,
+        time: None,
+        resources: None,
+    }
+}
+
+/// The current time, and the skew policy entries are expired by.
+#[derive(Clone, Copy)]
+struct Now {
+    time: Timestamp,
+    skew: SkewPolicy,
+}
+
+impl Now {
+    fn expired(&self, entry: &StoredEntry) -> bool {
+        entry
+            .ttl
+            .is_some_and(|ttl| self.skew.is_expired(ttl, self.time))
+    }
+
+    fn is_live(&self, entry: &StoredEntry) -> bool {
+        entry.deleted.is_none() && !self.expired(entry)
+    }
+
+    fn is_retained(&self, entry: &StoredEntry) -> bool {
+        entry.deleted.is_some_and(|d| d.until >= self.time) && !self.expired(entry)
+    }
+}
+
+/// What a transaction is checked against.
+struct Rules {
+    read_only: bool,
+    quota: Option<StorageQuota>,
+    naming: NamingPolicy,
+}
+
+struct StoredDoc {
+    doc: Arc<Document>,
+    data: Bytes,
+    weak: HashSet<Hash>,
+    tier: CacheTier,
+    stored: Timestamp,
+    access: Option<AccessStats>,
+    cached_until: Option<Timestamp>,
+    /// When the document was first found unreachable, if it still is.
+    unreachable_since: Option<Timestamp>,
+    /// Set when an eviction policy asked for the document to be kept a while
+    /// longer.
+    delayed_until: Option<Timestamp>,
+}
+
+impl StoredDoc {
+    fn new(doc: Arc<Document>, data: Bytes, weak: HashSet<Hash>, stored: Timestamp) -> Self {
+        Self {
+            doc,
+            data,
+            weak,
+            tier: CacheTier::default(),
+            stored,
+            access: None,
+            cached_until: None,
+            unreachable_since: None,
+            delayed_until: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Deleted {
+    at: Timestamp,
+    until: Timestamp,
+}
+
+struct StoredEntry {
+    entry: Entry,
+    bytes: u64,
+    ttl: Option<Timestamp>,
+    policy: Option<EntryPolicy>,
+    stored: Timestamp,
+    /// Order of storage, to break ties between entries stored in the same
+    /// second.
+    order: u64,
+    deleted: Option<Deleted>,
+}
+
+struct StoredSchema {
+    schema: Arc<Schema>,
+    compression: Option<CompressionPolicy>,
+    weak_refs: Option<WeakRefDefaults>,
+    retention: BTreeMap<String, RetentionPolicy>,
+}
+
+/// A transaction that has been validated and is ready to apply.
+#[derive(Default)]
+struct Plan {
+    docs: Vec<PlannedDoc>,
+    doc_mods: Vec<PlannedMod>,
+    entries: Vec<(EntryRef, PlannedEntry)>,
+    names: Vec<(String, Option<Hash>)>,
+}
+
+struct PlannedDoc {
+    doc: Arc<Document>,
+    data: Bytes,
+    weak: HashSet<Hash>,
+    tier: Option<CacheTier>,
+}
+
+struct PlannedMod {
+    doc: Hash,
+    refs: Vec<(Hash, bool)>,
+    tier: Option<CacheTier>,
+}
+
+enum PlannedEntry {
+    Add {
+        entry: Box<Entry>,
+        bytes: u64,
+        ttl: Option<Timestamp>,
+        policy: Option<EntryPolicy>,
+    },
+    Modify {
+        ttl: Option<Option<Timestamp>>,
+        policy: Option<Option<EntryPolicy>>,
+    },
+    Delete {
+        retain: Option<Duration>,
+    },
+    Restore {
+        ttl: Option<Option<Timestamp>>,
+        policy: Option<Option<EntryPolicy>>,
+    },
+}
+
+impl Plan {
+    /// Everything a prepared transaction needs left alone until it's applied.
+    fn reservation(&self) -> Reservation {
+        let mut held = Reservation::default();
+        for (e_ref, change) in self.entries.iter() {
+            held.entries.insert(e_ref.clone());
+            held.docs.insert(e_ref.parent.clone());
+            if let PlannedEntry::Add { entry, .. } = change {
+                held.docs.extend(entry.find_hashes());
+            }
+        }
+        for (name, target) in self.names.iter() {
+            held.names.insert(name.clone());
+            held.docs.extend(target.iter().cloned());
+        }
+        held.docs
+            .extend(self.doc_mods.iter().map(|m| m.doc.clone()));
+        // New documents aren't stored until the transaction is applied, so
+        // the documents they strongly link to are held directly.
+        for planned in self.docs.iter() {
+            held.docs.extend(
+                planned
+                    .doc
+                    .find_hashes()
+                    .into_iter()
+                    .filter(|link| !planned.weak.contains(link)),
+            );
+        }
+        held
+    }
+}
+
+#[derive(Default)]
+struct Reservation {
+    entries: HashSet<EntryRef>,
+    names: HashSet<String>,
+    docs: HashSet<Hash>,
+}
+
+#[derive(Default)]
+struct MemState {
+    seq: CommitSeq,
+    docs: HashMap<Hash, StoredDoc>,
+    entries: HashMap<Hash, HashMap<EntryRef, StoredEntry>>,
+    next_entry: u64,
+    schemas: HashMap<Hash, StoredSchema>,
+    names: BTreeMap<String, NameInfo>,
+    gates: HashMap<Hash, u32>,
+    history: Vec<CommitRecord>,
+    feeds: Vec<UnboundedSender<CommitRecord>>,
+    watches: Vec<(Hash, String, UnboundedSender<EntryEvent>)>,
+    retention: Vec<UnboundedSender<RetentionEviction>>,
+    prepared: HashMap<u64, Reservation>,
+    next_prepared: u64,
+    fingerprints: FingerprintCache,
+    /// The earliest expiry among the entries counted in each cached
+    /// fingerprint, so stale fingerprints can be found without a sweep.
+    fingerprint_ttls: BTreeSet<(Timestamp, Hash)>,
+}
+
+impl MemState {
+    fn schema_of(&self, doc: &Document) -> Option<&Arc<Schema>> {
+        self.schemas.get(doc.schema_hash()?).map(|s| &s.schema)
+    }
+
+    fn entry(&self, e_ref: &EntryRef) -> Option<&StoredEntry> {
+        self.entries.get(&e_ref.parent)?.get(e_ref)
+    }
+
+    fn usage(&self) -> QuotaUsage {
+        QuotaUsage {
+            docs: self.docs.len() as u64,
+            bytes: self.docs.values().map(|d| d.data.len() as u64).sum(),
+        }
+    }
+
+    fn touch(&mut self, hash: &Hash, now: Timestamp) -> Option<Arc<Document>> {
+        let stored = self.docs.get_mut(hash)?;
+        let count = stored.access.as_ref().map_or(0, |a| a.count);
+        stored.access = Some(AccessStats {
+            count: count + 1,
+            last: now,
+        });
+        Some(stored.doc.clone())
+    }
+
+    /// Encode a document for storage, or return `None` if its schema isn't in
+    /// the database.
+    fn encode_doc(&self, doc: &Document) -> Option<EncodedDoc> {
+        let schema = match doc.schema_hash() {
+            Some(hash) => Some(self.schemas.get(hash)?.schema.as_ref()),
+            None => None,
+        };
+        Some(EncodedDoc::from_doc(schema, doc.clone()).0)
+    }
+
+    /// Compile a query against the schema of the document it's made on.
+    fn compile(&self, parent: &Hash, query: &NewQuery) -> Option<Query> {
+        let schema = self.schema_of(&self.docs.get(parent)?.doc)?;
+        let encoded = schema.encode_query(query.clone()).ok()?;
+        schema.decode_query(encoded).ok()
+    }
+
+    fn matches(&self, entry: &Entry, query: &Query) -> bool {
+        if entry.key() != query.key() {
+            return false;
+        }
+        let Ok(mut list) = query.query(entry) else {
+            return false;
+        };
+        let linked = list.iter().all(|(hash, item)| {
+            self.docs
+                .get(&hash)
+                .is_some_and(|doc| item.check(&doc.doc).is_ok())
+        });
+        linked && list.complete().is_ok()
+    }
+
+    /// Check a query result against everything a query asks for besides the
+    /// fog-pack query itself.
+    fn accept(&self, stored: &StoredEntry, query: &Query, opts: &DbQuery, now: Now) -> bool {
+        if !(now.is_live(stored) || (opts.include_history && now.is_retained(stored))) {
+            return false;
+        }
+        if let (Some(min_ttl), Some(ttl)) = (opts.min_ttl, stored.ttl) {
+            if ttl < later(now.time, min_ttl) {
+                return false;
+            }
+        }
+        if let Some(policy) = &opts.signer_policy {
+            if !stored.entry.signer().is_some_and(|id| policy.is_root(id)) {
+                return false;
+            }
+        }
+        self.matches(&stored.entry, query)
+    }
+
+    /// Validate a set of changes, turning them into a plan that can't fail to
+    /// apply.
+    fn validate(
+        &self,
+        docs: &HashMap<Hash, DocChange>,
+        entries: &HashMap<EntryRef, EntryChange>,
+        names: &HashMap<String, NameChange>,
+        rules: &Rules,
+        now: Now,
+    ) -> DbResult<Result<Plan, Vec<CommitError>>> {
+        if rules.read_only {
+            return Ok(Err(vec![CommitError::Rejected(ReadOnly.to_string())]));
+        }
+        let mut errors = Vec::new();
+        let mut plan = Plan::default();
+
+        let mut added: HashMap<&Hash, &Arc<Document>> = HashMap::new();
+        let mut added_usage = QuotaUsage::default();
+        for (hash, change) in docs {
+            match change {
+                DocChange::Add {
+                    encoded,
+                    doc,
+                    weak_ref,
+                    tier,
+                } => {
+                    if let Some(schema) = doc.schema_hash() {
+                        if !self.schemas.contains_key(schema) {
+                            errors.push(CommitError::MissingSchema {
+                                doc: hash.clone(),
+                                schema: schema.clone(),
+                            });
+                            continue;
+                        }
+                    }
+                    let links = doc.find_hashes();
+                    for target in weak_ref.iter().filter(|t| !links.contains(t)) {
+                        errors.push(CommitError::MissingDocRef {
+                            doc: hash.clone(),
+                            target: target.clone(),
+                        });
+                    }
+                    if !self.docs.contains_key(hash) {
+                        added_usage.docs += 1;
+                        added_usage.bytes += encoded.data().len() as u64;
+                    }
+                    added.insert(hash, doc);
+                    plan.docs.push(PlannedDoc {
+                        doc: doc.clone(),
+                        data: encoded.data().clone(),
+                        weak: weak_ref.clone(),
+                        tier: *tier,
+                    });
+                }
+                DocChange::Modify { weak_ref, tier } => {
+                    let Some(stored) = self.docs.get(hash) else {
+                        errors.push(CommitError::MissingDoc(hash.clone()));
+                        continue;
+                    };
+                    let links = stored.doc.find_hashes();
+                    for target in weak_ref.keys().filter(|t| !links.contains(t)) {
+                        errors.push(CommitError::MissingDocRef {
+                            doc: hash.clone(),
+                            target: target.clone(),
+                        });
+                    }
+                    let mut refs: Vec<(Hash, bool)> =
+                        weak_ref.iter().map(|(t, w)| (t.clone(), *w)).collect();
+                    refs.sort();
+                    plan.doc_mods.push(PlannedMod {
+                        doc: hash.clone(),
+                        refs,
+                        tier: *tier,
+                    });
+                }
+            }
+        }
+        if let Some(quota) = rules.quota {
+            let usage = self.usage();
+            let usage = QuotaUsage {
+                docs: usage.docs + added_usage.docs,
+                bytes: usage.bytes + added_usage.bytes,
+            };
+            if added_usage.docs > 0 && !quota.allows(&usage) {
+                errors.push(CommitError::Rejected(
+                    "The database's storage quota would be exceeded".into(),
+                ));
+            }
+        }
+
+        let find_doc = |hash: &Hash| -> Option<Arc<Document>> {
+            match added.get(hash) {
+                Some(doc) => Some((*doc).clone()),
+                None => self.docs.get(hash).map(|d| d.doc.clone()),
+            }
+        };
+        let missing_template = |e_ref: &EntryRef, policy: Option<&EntryPolicy>| match policy {
+            Some(EntryPolicy::Template(template)) if find_doc(template).is_none() => {
+                Some(CommitError::MissingPolicyTemplate {
+                    entry: e_ref.clone(),
+                    template: template.clone(),
+                })
+            }
+            _ => None,
+        };
+        let is_prepared =
+            |e_ref: &EntryRef| self.prepared.values().any(|r| r.entries.contains(e_ref));
+
+        for (e_ref, change) in entries {
+            if is_prepared(e_ref) {
+                errors.push(CommitError::Rejected(format!(
+                    "Entry {e_ref} is held by a prepared transaction"
+                )));
+                continue;
+            }
+            let current = self.entry(e_ref);
+            match change {
+                EntryChange::Add { entry, ttl, policy } => {
+                    let parent = find_doc(&e_ref.parent);
+                    let schema = parent.as_ref().and_then(|p| self.schema_of(p));
+                    let (Some(parent), Some(schema)) = (parent.as_ref(), schema) else {
+                        errors.push(CommitError::MissingParent(e_ref.clone()));
+                        continue;
+                    };
+                    let entry_err = |err| {
+                        Box::new(DbError::FogEntry {
+                            context: "decoding entry in transaction".into(),
+                            entry: e_ref.clone(),
+                            err,
+                        })
+                    };
+                    let mut checklist = schema
+                        .decode_entry(entry.data().to_vec(), &e_ref.key, parent)
+                        .map_err(entry_err)?;
+                    let mut missing = false;
+                    for (link, item) in checklist.iter() {
+                        match find_doc(&link) {
+                            Some(doc) => item.check(&doc).map_err(entry_err)?,
+                            None => {
+                                errors.push(CommitError::MissingDoc(link));
+                                missing = true;
+                            }
+                        }
+                    }
+                    if missing {
+                        continue;
+                    }
+                    let decoded = checklist.complete().map_err(entry_err)?;
+                    if let Some(err) = missing_template(e_ref, policy.as_ref()) {
+                        errors.push(err);
+                        continue;
+                    }
+                    plan.entries.push((
+                        e_ref.clone(),
+                        PlannedEntry::Add {
+                            entry: Box::new(decoded),
+                            bytes: entry.data().len() as u64,
+                            ttl: *ttl,
+                            policy: policy.clone(),
+                        },
+                    ));
+                }
+                EntryChange::Modify { ttl, policy } => {
+                    if !current.is_some_and(|e| now.is_live(e)) {
+                        errors.push(CommitError::MissingEntry(e_ref.clone()));
+                        continue;
+                    }
+                    if let Some(err) = missing_template(e_ref, policy.clone().flatten().as_ref()) {
+                        errors.push(err);
+                        continue;
+                    }
+                    plan.entries.push((
+                        e_ref.clone(),
+                        PlannedEntry::Modify {
+                            ttl: *ttl,
+                            policy: policy.clone(),
+                        },
+                    ));
+                }
+                EntryChange::Delete { retain } => {
+                    if !current.is_some_and(|e| now.is_live(e)) {
+                        errors.push(CommitError::MissingEntry(e_ref.clone()));
+                        continue;
+                    }
+                    plan.entries
+                        .push((e_ref.clone(), PlannedEntry::Delete { retain: *retain }));
+                }
+                EntryChange::Restore { ttl, policy } => {
+                    if !current.is_some_and(|e| now.is_retained(e)) {
+                        errors.push(CommitError::NotRetained(e_ref.clone()));
+                        continue;
+                    }
+                    if let Some(err) = missing_template(e_ref, policy.clone().flatten().as_ref()) {
+                        errors.push(err);
+                        continue;
+                    }
+                    plan.entries.push((
+                        e_ref.clone(),
+                        PlannedEntry::Restore {
+                            ttl: *ttl,
+                            policy: policy.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+
+        for (name, change) in names {
+            if self.prepared.values().any(|r| r.names.contains(name)) {
+                errors.push(CommitError::Rejected(format!(
+                    "Name {name:?} is held by a prepared transaction"
+                )));
+                continue;
+            }
+            let checked = match (&change.target, change.reserved) {
+                (_, true) => rules.naming.check_reserved(name),
+                (Some(_), false) => rules.naming.check(name),
+                // Removing a name only needs to respect the reserved prefixes.
+                (None, false) => match rules
+                    .naming
+                    .reserved
+                    .iter()
+                    .find(|p| name.starts_with(p.as_str()))
+                {
+                    Some(prefix) => Err(NameError::Reserved(prefix.clone())),
+                    None => Ok(()),
+                },
+            };
+            if let Err(err) = checked {
+                errors.push(CommitError::InvalidName {
+                    name: name.clone(),
+                    err,
+                });
+                continue;
+            }
+            if let Some(target) = &change.target {
+                if find_doc(target).is_none() {
+                    errors.push(CommitError::MissingNameTarget {
+                        name: name.clone(),
+                        target: target.clone(),
+                    });
+                    continue;
+                }
+            }
+            let current = self.names.get(name).map(|n| n.hash.clone());
+            if let Some(expect) = &change.expect {
+                if *expect != current {
+                    errors.push(CommitError::NameChanged {
+                        name: name.clone(),
+                        current,
+                    });
+                    continue;
+                }
+            }
+            plan.names.push((name.clone(), change.target.clone()));
+        }
+
+        if errors.is_empty() {
+            Ok(Ok(plan))
+        } else {
+            Ok(Err(errors))
+        }
+    }
+
+    /// Apply a validated plan as the next commit.
+    fn apply(&mut self, plan: Plan, now: Now) -> CommitReceipt {
+        self.seq = self.seq.next();
+        let seq = self.seq;
+        let mut receipt = CommitReceipt::new(seq);
+        let mut record = CommitRecord {
+            seq,
+            docs: Vec::new(),
+            entries: Vec::new(),
+            names: Vec::new(),
+        };
+
+        for PlannedDoc {
+            doc,
+            data,
+            weak,
+            tier,
+        } in plan.docs
+        {
+            let hash = doc.hash().clone();
+            if let Some(stored) = self.docs.get_mut(&hash) {
+                if let Some(tier) = tier {
+                    stored.tier = tier;
+                }
+                receipt.existing.push(hash);
+                continue;
+            }
+            let mut stored = StoredDoc::new(doc, data, weak, now.time);
+            stored.tier = tier.unwrap_or_default();
+            self.docs.insert(hash.clone(), stored);
+            self.fingerprints.invalidate(&hash);
+            record.docs.push(DocRecord::Added(hash.clone()));
+            receipt.stored.push(hash);
+        }
+        for PlannedMod {
+            doc: hash,
+            refs,
+            tier,
+        } in plan.doc_mods
+        {
+            let Some(stored) = self.docs.get_mut(&hash) else {
+                continue;
+            };
+            for (target, weak) in refs.iter() {
+                if *weak {
+                    stored.weak.insert(target.clone());
+                } else {
+                    stored.weak.remove(target);
+                }
+            }
+            if let Some(tier) = tier {
+                stored.tier = tier;
+            }
+            if !refs.is_empty() {
+                record.docs.push(DocRecord::WeakRefs { doc: hash, refs });
+            }
+        }
+
+        let mut events = Vec::new();
+        for (e_ref, change) in plan.entries {
+            let entries = self.entries.entry(e_ref.parent.clone()).or_default();
+            let current = entries.get_mut(&e_ref);
+            let event = match change {
+                PlannedEntry::Add {
+                    entry,
+                    bytes,
+                    ttl,
+                    policy,
+                } => match current.filter(|e| now.is_live(e)) {
+                    // Adding an entry that's already held only updates it.
+                    Some(stored) => {
+                        stored.ttl = ttl;
+                        stored.policy = policy;
+                        EntryRecord::Modified(e_ref.clone())
+                    }
+                    None => {
+                        self.next_entry += 1;
+                        let stored = StoredEntry {
+                            entry: (*entry).clone(),
+                            bytes,
+                            ttl,
+                            policy,
+                            stored: now.time,
+                            order: self.next_entry,
+                            deleted: None,
+                        };
+                        entries.insert(e_ref.clone(), stored);
+                        EntryRecord::Added(*entry)
+                    }
+                },
+                PlannedEntry::Modify { ttl, policy } => {
+                    if let Some(stored) = current {
+                        if let Some(ttl) = ttl {
+                            stored.ttl = ttl;
+                        }
+                        if let Some(policy) = policy {
+                            stored.policy = policy;
+                        }
+                    }
+                    EntryRecord::Modified(e_ref.clone())
+                }
+                PlannedEntry::Delete { retain } => {
+                    match (retain, current) {
+                        (Some(retain), Some(stored)) => {
+                            stored.deleted = Some(Deleted {
+                                at: now.time,
+                                until: later(now.time, retain),
+                            });
+                        }
+                        _ => {
+                            entries.remove(&e_ref);
+                        }
+                    }
+                    EntryRecord::Deleted(e_ref.clone())
+                }
+                PlannedEntry::Restore { ttl, policy } => {
+                    // Validation checked the entry is held.
+                    let Some(stored) = current else {
+                        continue;
+                    };
+                    stored.deleted = None;
+                    if let Some(ttl) = ttl {
+                        stored.ttl = ttl;
+                    }
+                    if let Some(policy) = policy {
+                        stored.policy = policy;
+                    }
+                    EntryRecord::Restored(stored.entry.clone())
+                }
+            };
+            self.fingerprints.invalidate(&e_ref.parent);
+            record.entries.push(event.clone());
+            events.push(event);
+        }
+
+        for (name, target) in plan.names {
+            let previous = match &target {
+                Some(target) => self.set_name(&name, target.clone(), now.time),
+                None => self.names.remove(&name).map(|info| info.hash),
+            };
+            record.names.push(NameRecord {
+                name,
+                previous,
+                target,
+            });
+        }
+
+        for change in events {
+            let event = EntryEvent { seq, change };
+            let e_ref = event.change.entry();
+            self.watches.retain(|(doc, key, tx)| {
+                if *doc != e_ref.parent || *key != e_ref.key {
+                    return true;
+                }
+                tx.unbounded_send(event.clone()).is_ok()
+            });
+        }
+        self.feeds
+            .retain(|tx| tx.unbounded_send(record.clone()).is_ok());
+        self.history.push(record);
+        receipt
+    }
+
+    /// Point a name at a document, returning where it pointed before.
+    fn set_name(&mut self, name: &str, target: Hash, now: Timestamp) -> Option<Hash> {
+        match self.names.get_mut(name) {
+            Some(info) => {
+                info.updated = now;
+                Some(std::mem::replace(&mut info.hash, target))
+            }
+            None => {
+                let info = NameInfo {
+                    hash: target,
+                    created: now,
+                    updated: now,
+                    meta: NameMeta::default(),
+                };
+                self.names.insert(name.to_owned(), info);
+                None
+            }
+        }
+    }
+
+    /// Evict entries to enforce every schema's retention policies.
+    fn enforce_retention(&mut self, now: Now) {
+        let policies: Vec<(Hash, String, RetentionPolicy)> = self
+            .schemas
+            .iter()
+            .flat_map(|(hash, s)| {
+                s.retention
+                    .iter()
+                    .map(move |(key, policy)| (hash.clone(), key.clone(), *policy))
+            })
+            .collect();
+        let mut evicted = Vec::new();
+        for (schema, key, policy) in policies {
+            let parents: Vec<Hash> = self
+                .docs
+                .iter()
+                .filter(|(_, d)| d.doc.schema_hash() == Some(&schema))
+                .map(|(hash, _)| hash.clone())
+                .collect();
+            for parent in parents {
+                let Some(entries) = self.entries.get_mut(&parent) else {
+                    continue;
+                };
+                let mut held: Vec<&StoredEntry> = entries
+                    .values()
+                    .filter(|e| e.entry.key() == key && now.is_live(e))
+                    .collect();
+                held.sort_by_key(|e| (e.stored, e.order));
+                let held: Vec<RetainedEntry> = held
+                    .into_iter()
+                    .map(|e| RetainedEntry {
+                        entry: e.entry.reference().clone(),
+                        stored: e.stored,
+                        bytes: e.bytes,
+                    })
+                    .collect();
+                let selected = policy.select(&held, now.time);
+                if selected.is_empty() {
+                    continue;
+                }
+                for eviction in selected.iter() {
+                    entries.remove(&eviction.entry);
+                }
+                self.fingerprints.invalidate(&parent);
+                evicted.extend(selected);
+            }
+        }
+        for eviction in evicted {
+            self.retention
+                .retain(|tx| tx.unbounded_send(eviction.clone()).is_ok());
+        }
+    }
+
+    /// Find every document reachable from a name, as it would be if `change`
+    /// were made.
+    fn reachable(&self, change: Option<&ProposedChange>, now: Now) -> HashSet<Hash> {
+        let mut queue: Vec<Hash> = self
+            .names
+            .iter()
+            .filter(|(name, _)| {
+                !matches!(change, Some(ProposedChange::SetName { name: n, .. }) if n == *name)
+            })
+            .map(|(_, info)| info.hash.clone())
+            .collect();
+        if let Some(ProposedChange::SetName {
+            target: Some(target),
+            ..
+        }) = change
+        {
+            queue.push(target.clone());
+        }
+        self.reach_from(queue, change, now, &HashSet::new())
+    }
+
+    /// Find every document reachable from the queued ones through strong
+    /// document links and the links in their entries, as it would be if
+    /// `change` were made. Documents in `skip` aren't walked into.
+    fn reach_from(
+        &self,
+        mut queue: Vec<Hash>,
+        change: Option<&ProposedChange>,
+        now: Now,
+        skip: &HashSet<Hash>,
+    ) -> HashSet<Hash> {
+        let mut live = HashSet::new();
+        while let Some(hash) = queue.pop() {
+            if live.contains(&hash) || skip.contains(&hash) {
+                continue;
+            }
+            let Some(stored) = self.docs.get(&hash) else {
+                continue;
+            };
+            for link in stored.doc.find_hashes() {
+                let weak = match change {
+                    Some(ProposedChange::SetWeakRef { doc, target, weak })
+                        if *doc == hash && *target == link =>
+                    {
+                        *weak
+                    }
+                    _ => stored.weak.contains(&link),
+                };
+                if !weak {
+                    queue.push(link);
+                }
+            }
+            for entry in self.entries.get(&hash).into_iter().flat_map(|e| e.values()) {
+                if now.is_live(entry) || now.is_retained(entry) {
+                    queue.extend(entry.entry.find_hashes());
+                }
+            }
+            live.insert(hash);
+        }
+        live
+    }
+
+    /// Documents outside of `live` that are kept anyway: unexpired cache
+    /// entries, documents a prepared transaction depends on, and everything
+    /// reachable from those, as it would be if `change` were made.
+    fn held(
+        &self,
+        live: &HashSet<Hash>,
+        change: Option<&ProposedChange>,
+        now: Now,
+    ) -> HashSet<Hash> {
+        let roots = self
+            .docs
+            .iter()
+            .filter(|(hash, stored)| {
+                !live.contains(*hash) && stored.cached_until.is_some_and(|t| t > now.time)
+            })
+            .map(|(hash, _)| hash.clone())
+            .chain(self.prepared.values().flat_map(|r| r.docs.iter().cloned()))
+            .collect();
+        self.reach_from(roots, change, now, live)
+    }
+
+    /// Run a garbage collection pass, returning the evicted documents.
+    fn collect(&mut self, now: Now, eviction: &EvictionRegistry, grace: Duration) -> Vec<Hash> {
+        for entries in self.entries.values_mut() {
+            entries.retain(|_, e| e.deleted.is_none_or(|d| d.until >= now.time));
+        }
+        let live = self.reachable(None, now);
+        let held = self.held(&live, None, now);
+        let mut evicted = Vec::new();
+        for (hash, stored) in self.docs.iter_mut() {
+            if live.contains(hash) {
+                stored.unreachable_since = None;
+                continue;
+            }
+            let since = *stored.unreachable_since.get_or_insert(now.time);
+            if held.contains(hash)
+                || stored.delayed_until.is_some_and(|t| t > now.time)
+                || time_between(since, now.time) < grace
+            {
+                continue;
+            }
+            let candidate = EvictionCandidate {
+                doc: hash.clone(),
+                schema: stored.doc.schema_hash().cloned(),
+                bytes: stored.data.len() as u64,
+                stored: stored.stored,
+                last_access: stored.access.as_ref().map(|a| a.last),
+            };
+            match eviction.consider(&candidate) {
+                EvictionVerdict::Evict => evicted.push(hash.clone()),
+                EvictionVerdict::Delay(delay) => {
+                    stored.delayed_until = Some(later(now.time, delay))
+                }
+                EvictionVerdict::Keep => (),
+            }
+        }
+        for hash in evicted.iter() {
+            self.docs.remove(hash);
+            self.entries.remove(hash);
+            self.fingerprints.remove(hash);
+        }
+        evicted.sort();
+        evicted
+    }
+
+    /// Remove expired entries, earliest to expire first.
+    fn sweep(&mut self, budget: SweepBudget, now: Now) -> SweepReport {
+        let start = Instant::now();
+        let mut expired: Vec<(Timestamp, EntryRef)> = self
+            .entries
+            .values()
+            .flat_map(|e| e.iter())
+            .filter(|(_, e)| now.expired(e))
+            .filter_map(|(e_ref, e)| Some((e.ttl?, e_ref.clone())))
+            .collect();
+        expired.sort();
+        let mut report = SweepReport::default();
+        for (_, e_ref) in expired {
+            let out_of_budget = budget.max_entries.is_some_and(|max| report.swept >= max)
+                || budget.max_time.is_some_and(|max| start.elapsed() >= max);
+            if out_of_budget {
+                report.remaining = true;
+                break;
+            }
+            if let Some(entries) = self.entries.get_mut(&e_ref.parent) {
+                entries.remove(&e_ref);
+            }
+            self.fingerprints.invalidate(&e_ref.parent);
+            report.swept += 1;
+        }
+        report.elapsed = start.elapsed();
+        report
+    }
+
+    /// Get the documents held in the tree under `root`, following links from
+    /// documents and their entries, and whether all of them are held.
+    fn tree(&self, root: &Hash, now: Now) -> (HashSet<Hash>, bool) {
+        let mut held = HashSet::new();
+        let mut complete = true;
+        let mut queue = vec![root.clone()];
+        while let Some(hash) = queue.pop() {
+            if held.contains(&hash) {
+                continue;
+            }
+            let Some(stored) = self.docs.get(&hash) else {
+                complete = false;
+                continue;
+            };
+            queue.extend(stored.doc.find_hashes());
+            for entry in self.entries.get(&hash).into_iter().flat_map(|e| e.values()) {
+                if now.is_live(entry) {
+                    queue.extend(entry.entry.find_hashes());
+                }
+            }
+            held.insert(hash);
+        }
+        (held, complete)
+    }
+
+    fn tree_stats(&self, root: &Hash, now: Now) -> Option<TreeStats> {
+        self.docs.get(root)?;
+        let mut stats = TreeStats::default();
+        let mut seen = HashSet::from([root.clone()]);
+        let mut queue = VecDeque::from([(root.clone(), 0)]);
+        while let Some((hash, depth)) = queue.pop_front() {
+            let Some(stored) = self.docs.get(&hash) else {
+                stats.missing += 1;
+                continue;
+            };
+            stats.add_doc(
+                stored.doc.schema_hash().cloned(),
+                stored.data.len() as u64,
+                depth,
+            );
+            let entries = self.entries.get(&hash).into_iter().flat_map(|e| e.values());
+            let links = stored.doc.find_hashes().into_iter().chain(
+                entries
+                    .filter(|e| now.is_live(e))
+                    .flat_map(|e| e.entry.find_hashes()),
+            );
+            for link in links {
+                if seen.insert(link.clone()) {
+                    queue.push_back((link, depth + 1));
+                }
+            }
+        }
+        Some(stats)
+    }
+
+    /// Get a document's fingerprint, and whether everything below it is held,
+    /// computing and caching it if needed. Cached fingerprints that counted
+    /// entries which have since expired are recomputed.
+    fn fingerprint(&mut self, root: &Hash, now: Now) -> Option<(Hash, bool)> {
+        while let Some((ttl, doc)) = self.fingerprint_ttls.first().cloned() {
+            if !now.skew.is_expired(ttl, now.time) {
+                break;
+            }
+            self.fingerprint_ttls.pop_first();
+            self.fingerprints.invalidate(&doc);
+        }
+        // Each document is visited twice: once to queue the documents it
+        // links to, and again once their fingerprints are all known. Links
+        // can't form cycles, so every link is done by the second visit.
+        let mut stack = vec![(root.clone(), false)];
+        while let Some((doc, ready)) = stack.pop() {
+            if self.fingerprints.get(&doc).is_some() {
+                continue;
+            }
+            let Some(stored) = self.docs.get(&doc) else {
+                continue;
+            };
+            let links: BTreeSet<Hash> = stored.doc.find_hashes().into_iter().collect();
+            if !ready {
+                stack.push((doc, true));
+                stack.extend(links.into_iter().map(|link| (link, false)));
+                continue;
+            }
+            let mut entries: BTreeMap<String, BTreeSet<Hash>> = BTreeMap::new();
+            let mut first_ttl: Option<Timestamp> = None;
+            for entry in self.entries.get(&doc).into_iter().flat_map(|e| e.values()) {
+                if now.is_live(entry) {
+                    entries
+                        .entry(entry.entry.key().to_owned())
+                        .or_default()
+                        .insert(entry.entry.hash().clone());
+                    if let Some(ttl) = entry.ttl {
+                        first_ttl = Some(first_ttl.map_or(ttl, |t| t.min(ttl)));
+                    }
+                }
+            }
+            let mut linked = BTreeMap::new();
+            let mut complete = true;
+            for link in links {
+                let found = self.fingerprints.get(&link);
+                complete &= found.is_some_and(|(_, c)| c);
+                linked.insert(link, found.map(|(f, _)| f.clone()));
+            }
+            let fingerprint = doc_fingerprint(&doc, &entries, &linked);
+            if let Some(ttl) = first_ttl {
+                self.fingerprint_ttls.insert((ttl, doc.clone()));
+            }
+            self.fingerprints
+                .insert(&doc, linked.keys(), fingerprint, complete);
+        }
+        self.fingerprints
+            .get(root)
+            .map(|(fingerprint, complete)| (fingerprint.clone(), complete))
+    }
+}
+
+struct Shared {
+    state: Mutex<MemState>,
+    config: DbConfig,
+    eviction: EvictionRegistry,
+    skew: Mutex<SkewPolicy>,
+    naming: Mutex<NamingPolicy>,
+    transports: TransportRegistry,
+    discovery: DiscoveryRegistry,
+    fetch: Arc<dyn FetchScheduler>,
+    next_trace: AtomicU64,
+}
+
+impl Shared {
+    fn now(&self) -> Now {
+        Now {
+            time: Timestamp::now().unwrap_or_else(Timestamp::zero),
+            skew: *self.skew.lock().unwrap(),
+        }
+    }
+
+    fn rules(&self) -> Rules {
+        Rules {
+            read_only: self.config.read_only,
+            quota: self.config.quota,
+            naming: self.naming.lock().unwrap().clone(),
+        }
+    }
+
+    fn writable(&self) -> DbResult<()> {
+        if self.config.read_only {
+            Err(read_only())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn trace_id(&self) -> TraceId {
+        TraceId(self.next_trace.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Enforce retention policies and collect garbage after a change.
+    fn maintain(&self, state: &mut MemState, now: Now) -> Vec<Hash> {
+        state.enforce_retention(now);
+        state.collect(now, &self.eviction, self.config.gc.grace)
+    }
+
+    fn commit(
+        &self,
+        changes: Vec<ChangeSet>,
+    ) -> DbResult<Vec<Result<CommitReceipt, CommitErrors>>> {
+        let now = self.now();
+        let rules = self.rules();
+        let mut state = self.state.lock().unwrap();
+        let mut results = Vec::with_capacity(changes.len());
+        for (docs, entries, names) in changes {
+            match state.validate(&docs, &entries, &names, &rules, now)? {
+                Ok(plan) => results.push(Ok(state.apply(plan, now))),
+                Err(errors) => results.push(Err(CommitErrors {
+                    docs,
+                    entries,
+                    names,
+                    errors,
+                })),
+            }
+        }
+        if results.iter().any(|r| r.is_ok()) {
+            self.maintain(&mut state, now);
+        }
+        Ok(results)
+    }
+
+    fn prepare(
+        self: &Arc<Self>,
+        (docs, entries, names): ChangeSet,
+    ) -> DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>> {
+        let now = self.now();
+        let rules = self.rules();
+        let mut state = self.state.lock().unwrap();
+        match state.validate(&docs, &entries, &names, &rules, now)? {
+            Ok(plan) => {
+                let id = state.next_prepared;
+                state.next_prepared += 1;
+                state.prepared.insert(id, plan.reservation());
+                Ok(Ok(Box::new(MemPrepared {
+                    shared: self.clone(),
+                    id,
+                    plan: Some(plan),
+                })))
+            }
+            Err(errors) => Ok(Err(CommitErrors {
+                docs,
+                entries,
+                names,
+                errors,
+            })),
+        }
+    }
+
+    fn set_name(
+        &self,
+        name: &str,
+        hash: &Hash,
+        reserved: bool,
+    ) -> DbResult<Result<Option<Hash>, NameError>> {
+        self.writable()?;
+        let naming = self.naming.lock().unwrap().clone();
+        let checked = if reserved {
+            naming.check_reserved(name)
+        } else {
+            naming.check(name)
+        };
+        if let Err(err) = checked {
+            return Ok(Err(err));
+        }
+        let now = self.now();
+        let mut state = self.state.lock().unwrap();
+        if state.prepared.values().any(|r| r.names.contains(name)) {
+            return Ok(Err(NameError::Held));
+        }
+        let prev = state.set_name(name, hash.clone(), now.time);
+        self.maintain(&mut state, now);
+        Ok(Ok(prev))
+    }
+
+    fn read_doc(&self, hash: &Hash) -> Option<Arc<Document>> {
+        let now = self.now();
+        self.state.lock().unwrap().touch(hash, now.time)
+    }
+
+    fn cursor(self: &Arc<Self>, doc: Arc<Document>) -> NewCursor {
+        let cursor = MemCursor {
+            id: self.trace_id(),
+            shared: self.clone(),
+            stack: vec![doc.clone()],
+        };
+        (Box::new(cursor), doc)
+    }
+
+    fn fork(self: &Arc<Self>, hash: &Hash, error: Option<CursorError>) -> Box<dyn ForkCursor> {
+        Box::new(MemFork {
+            shared: self.clone(),
+            hash: hash.clone(),
+            error,
+        })
+    }
+
+    fn tree_fingerprint(&self, root: &Hash) -> Option<TreeFingerprint> {
+        let now = self.now();
+        self.state
+            .lock()
+            .unwrap()
+            .fingerprint(root, now)
+            .map(|(fingerprint, complete)| TreeFingerprint {
+                root: root.clone(),
+                fingerprint,
+                complete,
+            })
+    }
+
+    fn summary(&self, root: &Hash) -> AvailabilitySummary {
+        let now = self.now();
+        let (held, complete) = self.state.lock().unwrap().tree(root, now);
+        AvailabilitySummary::new(root, held.iter(), complete, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+}
+
+/// A database held entirely in memory. Cloning gives another handle to the
+/// same database.
+#[derive(Clone)]
+pub struct MemDb {
+    shared: Arc<Shared>,
+}
+
+impl Default for MemDb {
+    fn default() -> Self {
+        Self::new(DbConfig::default())
+    }
+}
+
+impl MemDb {
+    /// Create an empty database.
+    pub fn new(config: DbConfig) -> Self {
+        Self::with_eviction_policies(config, EvictionRegistry::new())
+    }
+
+    /// Create an empty database that consults the given policies before
+    /// evicting anything.
+    pub fn with_eviction_policies(config: DbConfig, eviction: EvictionRegistry) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(MemState::default()),
+                eviction,
+                skew: Mutex::new(config.skew),
+                naming: Mutex::new(config.names.clone()),
+                transports: TransportRegistry::new(),
+                discovery: DiscoveryRegistry::new(),
+                fetch: Arc::new(PriorityScheduler::new(SchedulerConfig::default())),
+                next_trace: AtomicU64::new(0),
+                config,
+            }),
+        }
+    }
+
+    /// Get the configuration the database was opened with.
+    pub fn config(&self) -> &DbConfig {
+        &self.shared.config
+    }
+
+    /// Run a garbage collection pass now, returning the documents it evicted.
+    /// Documents that became unreachable less than the grace period ago are
+    /// left for a later pass.
+    pub fn collect_garbage(&self) -> Vec<Hash> {
+        let now = self.shared.now();
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.maintain(&mut state, now)
+    }
+}
+
+#[async_trait]
+impl Db for MemDb {
+    fn txn(&self) -> Transaction {
+        Transaction::new(Box::new(MemCommit {
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn commit_permit(&self) -> Box<dyn PermitRequest> {
+        Box::new(Unlimited)
+    }
+
+    fn bulk_import(&self) -> Box<dyn BulkImport> {
+        Box::new(MemImport {
+            shared: self.shared.clone(),
+            docs: Vec::new(),
+            entries: Vec::new(),
+            names: Vec::new(),
+            progress: ImportProgress::default(),
+        })
+    }
+
+    async fn current_seq(&self) -> DbResult<CommitSeq> {
+        Ok(self.shared.state.lock().unwrap().seq)
+    }
+
+    async fn changes_since(&self, seq: CommitSeq) -> DbResult<Result<Box<dyn ChangeFeed>, SeqTooOld>> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut state = self.shared.state.lock().unwrap();
+        for record in state.history.iter().filter(|r| r.seq > seq) {
+            let _ = tx.unbounded_send(record.clone());
+        }
+        state.feeds.push(tx);
+        Ok(Ok(Box::new(MemFeed {
+            rx: AsyncMutex::new(rx),
+        })))
+    }
+
+    async fn entry_watch(&self, doc: &Hash, key: &str) -> DbResult<Box<dyn EntryWatch>> {
+        let (tx, rx) = mpsc::unbounded();
+        let mut state = self.shared.state.lock().unwrap();
+        state.watches.push((doc.clone(), key.to_owned(), tx));
+        Ok(Box::new(MemWatch {
+            rx: AsyncMutex::new(rx),
+        }))
+    }
+
+    fn group(&self, _spec: GroupSpec) -> Box<dyn Group> {
+        Box::new(MemGroup {
+            shared: self.shared.clone(),
+            quota: Mutex::new(None),
+            skew: Mutex::new(None),
+        })
+    }
+
+    fn transports(&self) -> &TransportRegistry {
+        &self.shared.transports
+    }
+
+    fn discovery(&self) -> &DiscoveryRegistry {
+        &self.shared.discovery
+    }
+
+    fn runtime(&self) -> &dyn NodeRuntime {
+        &MemRuntime
+    }
+
+    fn mixnet(&self) -> Option<&dyn Mixnet> {
+        None
+    }
+
+    fn journal(&self) -> Option<&dyn Journal> {
+        None
+    }
+
+    fn eviction_policies(&self) -> &EvictionRegistry {
+        &self.shared.eviction
+    }
+
+    async fn gc_preview(&self, change: ProposedChange) -> DbResult<GcPreview> {
+        let now = self.shared.now();
+        let state = self.shared.state.lock().unwrap();
+        let before = state.reachable(None, now);
+        let after = state.reachable(Some(&change), now);
+        let held = state.held(&after, Some(&change), now);
+        let mut preview = GcPreview::default();
+        for hash in before.difference(&after) {
+            let stored = &state.docs[hash];
+            if held.contains(hash) {
+                preview.held.push(hash.clone());
+            } else {
+                preview.evicted.push(hash.clone());
+                preview.evicted_bytes += stored.data.len() as u64;
+            }
+        }
+        preview.evicted.sort();
+        preview.held.sort();
+        preview.broken_gates = state
+            .gates
+            .keys()
+            .filter(|gate| preview.evicted.binary_search(gate).is_ok())
+            .cloned()
+            .collect();
+        preview.broken_gates.sort();
+        Ok(preview)
+    }
+
+    async fn capabilities(&self) -> DbResult<DbCapabilities> {
+        Ok(DbCapabilities {
+            change_feed: true,
+            two_phase_commit: true,
+            access_tracking: true,
+            ..DbCapabilities::default()
+        })
+    }
+
+    async fn skew_policy(&self) -> DbResult<SkewPolicy> {
+        Ok(*self.shared.skew.lock().unwrap())
+    }
+
+    async fn set_skew_policy(&self, policy: SkewPolicy) -> DbResult<()> {
+        *self.shared.skew.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    async fn health(&self) -> DbResult<Health> {
+        if self.shared.config.read_only {
+            Ok(Health::ReadOnly(vec![HealthIssue::OpenedReadOnly]))
+        } else {
+            Ok(Health::Ok)
+        }
+    }
+
+    fn health_events(&self) -> Box<dyn HealthEvents> {
+        Box::new(MemHealth)
+    }
+
+    async fn ttl_sweep(&self, budget: SweepBudget) -> DbResult<SweepReport> {
+        let now = self.shared.now();
+        Ok(self.shared.state.lock().unwrap().sweep(budget, now))
+    }
+
+    fn retention_events(&self) -> Box<dyn RetentionEvents> {
+        let (tx, rx) = mpsc::unbounded();
+        self.shared.state.lock().unwrap().retention.push(tx);
+        Box::new(MemRetention {
+            rx: AsyncMutex::new(rx),
+        })
+    }
+
+    fn fetch_scheduler(&self) -> Arc<dyn FetchScheduler> {
+        self.shared.fetch.clone()
+    }
+
+    fn validator(&self) -> Arc<dyn Validator> {
+        Arc::new(MemValidator {
+            shared: self.shared.clone(),
+        })
+    }
+
+    async fn cursor(&self, doc: &Hash, _opts: CursorOpts) -> DbResult<Option<NewCursor>> {
+        Ok(self.shared.read_doc(doc).map(|doc| self.shared.cursor(doc)))
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        Ok(self.shared.read_doc(doc))
+    }
+
+    async fn doc_info(&self, doc: &Hash) -> DbResult<Option<DocInfo>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.docs.get(doc).map(|stored| DocInfo {
+            schema: stored.doc.schema_hash().cloned(),
+            bytes: stored.data.len() as u64,
+            tier: stored.tier,
+            access: stored.access.clone(),
+            cached_until: stored.cached_until,
+        }))
+    }
+
+    async fn tree_stats(&self, root: &Hash) -> DbResult<Option<TreeStats>> {
+        let now = self.shared.now();
+        Ok(self.shared.state.lock().unwrap().tree_stats(root, now))
+    }
+
+    async fn tree_fingerprint(&self, root: &Hash) -> DbResult<Option<TreeFingerprint>> {
+        Ok(self.shared.tree_fingerprint(root))
+    }
+
+    fn query(&self, doc: &Hash, query: DbQuery) -> Box<dyn CursorQuery> {
+        // Queries on the database can back out into a cursor, so one is
+        // opened on the document. If it isn't in the database, the cursor is
+        // left on an empty document, and the query finds nothing.
+        let parent = self.shared.read_doc(doc).unwrap_or_else(|| {
+            let empty = NewDocument::new(None, ()).expect("empty document should always encode");
+            Arc::new(
+                NoSchema::validate_new_doc(empty).expect("empty document should always be valid"),
+            )
+        });
+        let cursor = MemCursor {
+            id: self.shared.trace_id(),
+            shared: self.shared.clone(),
+            stack: vec![parent],
+        };
+        Box::new(MemQuery::new(cursor, doc, query))
+    }
+
+    async fn entry_count(&self, doc: &Hash, key: &str, query: Option<&NewQuery>) -> DbResult<u64> {
+        let now = self.shared.now();
+        let state = self.shared.state.lock().unwrap();
+        let compiled = match query {
+            Some(query) => match state.compile(doc, query) {
+                Some(compiled) => Some(compiled),
+                None => return Ok(0),
+            },
+            None => None,
+        };
+        let count = state
+            .entries
+            .get(doc)
+            .into_iter()
+            .flat_map(|e| e.values())
+            .filter(|e| e.entry.key() == key && now.is_live(e))
+            .filter(|e| compiled.as_ref().is_none_or(|q| state.matches(&e.entry, q)))
+            .count();
+        Ok(count as u64)
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.schemas.get(schema).map(|s| s.schema.clone()))
+    }
+
+    async fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+        self.shared.writable()?;
+        let compiled = match Schema::from_doc(&schema) {
+            Ok(compiled) => Arc::new(compiled),
+            Err(err) => return Ok(Err(err)),
+        };
+        let mut state = self.shared.state.lock().unwrap();
+        let stored = state
+            .schemas
+            .entry(schema.hash().clone())
+            .or_insert_with(|| StoredSchema {
+                schema: compiled,
+                compression: None,
+                weak_refs: None,
+                retention: BTreeMap::new(),
+            });
+        Ok(Ok(stored.schema.clone()))
+    }
+
+    async fn schema_del(&self, schema: &Hash) -> DbResult<bool> {
+        self.shared.writable()?;
+        Ok(self
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .schemas
+            .remove(schema)
+            .is_some())
+    }
+
+    async fn schema_list(&self) -> DbResult<Vec<Hash>> {
+        let state = self.shared.state.lock().unwrap();
+        let mut list: Vec<Hash> = state.schemas.keys().cloned().collect();
+        list.sort();
+        Ok(list)
+    }
+
+    async fn schema_set_compression(
+        &self,
+        schema: &Hash,
+        policy: CompressionPolicy,
+    ) -> DbResult<bool> {
+        self.shared.writable()?;
+        let mut state = self.shared.state.lock().unwrap();
+        let Some(stored) = state.schemas.get_mut(schema) else {
+            return Ok(false);
+        };
+        stored.compression = Some(policy);
+        Ok(true)
+    }
+
+    async fn schema_get_compression(&self, schema: &Hash) -> DbResult<Option<CompressionPolicy>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state
+            .schemas
+            .get(schema)
+            .map(|s| s.compression.unwrap_or(self.shared.config.compression)))
+    }
+
+    async fn schema_set_weak_refs(
+        &self,
+        schema: &Hash,
+        defaults: WeakRefDefaults,
+    ) -> DbResult<bool> {
+        self.shared.writable()?;
+        let mut state = self.shared.state.lock().unwrap();
+        let Some(stored) = state.schemas.get_mut(schema) else {
+            return Ok(false);
+        };
+        stored.weak_refs = Some(defaults);
+        Ok(true)
+    }
+
+    async fn schema_get_weak_refs(&self, schema: &Hash) -> DbResult<Option<WeakRefDefaults>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state
+            .schemas
+            .get(schema)
+            .map(|s| s.weak_refs.clone().unwrap_or_default()))
+    }
+
+    async fn schema_set_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+        policy: RetentionPolicy,
+    ) -> DbResult<bool> {
+        self.shared.writable()?;
+        let now = self.shared.now();
+        let mut state = self.shared.state.lock().unwrap();
+        let Some(stored) = state.schemas.get_mut(schema) else {
+            return Ok(false);
+        };
+        if policy.is_unlimited() {
+            stored.retention.remove(key);
+        } else {
+            stored.retention.insert(key.to_owned(), policy);
+            state.enforce_retention(now);
+        }
+        Ok(true)
+    }
+
+    async fn schema_get_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+    ) -> DbResult<Option<RetentionPolicy>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state
+            .schemas
+            .get(schema)
+            .map(|s| s.retention.get(key).copied().unwrap_or_default()))
+    }
+
+    async fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.names.get(name).map(|n| n.hash.clone()))
+    }
+
+    async fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Result<Option<Hash>, NameError>> {
+        self.shared.set_name(name, hash, false)
+    }
+
+    async fn name_add_reserved(
+        &self,
+        name: &str,
+        hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, NameError>> {
+        self.shared.set_name(name, hash, true)
+    }
+
+    async fn naming_policy(&self) -> DbResult<NamingPolicy> {
+        Ok(self.shared.naming.lock().unwrap().clone())
+    }
+
+    async fn set_naming_policy(&self, policy: NamingPolicy) -> DbResult<()> {
+        *self.shared.naming.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    async fn name_del(&self, hash: &Hash) -> DbResult<Option<Hash>> {
+        self.shared.writable()?;
+        let now = self.shared.now();
+        let mut state = self.shared.state.lock().unwrap();
+        // Names a prepared transaction is changing are left alone, and so is
+        // every other name for the document, so the removal is all or nothing.
+        let held = state.names.iter().any(|(name, info)| {
+            info.hash == *hash && state.prepared.values().any(|r| r.names.contains(name))
+        });
+        if held {
+            return Err(Box::new(DbError::Internal(Box::new(NameError::Held))));
+        }
+        let before = state.names.len();
+        state.names.retain(|_, info| info.hash != *hash);
+        if state.names.len() == before {
+            return Ok(None);
+        }
+        self.shared.maintain(&mut state, now);
+        Ok(Some(hash.clone()))
+    }
+
+    async fn name_list(&self) -> DbResult<Vec<(String, Hash)>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state
+            .names
+            .iter()
+            .map(|(name, info)| (name.clone(), info.hash.clone()))
+            .collect())
+    }
+
+    async fn name_list_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Hash)>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state
+            .names
+            .range(prefix.to_owned()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .map(|(name, info)| (name.clone(), info.hash.clone()))
+            .collect())
+    }
+
+    async fn name_info(&self, name: &str) -> DbResult<Option<NameInfo>> {
+        Ok(self.shared.state.lock().unwrap().names.get(name).cloned())
+    }
+
+    async fn name_set_meta(&self, name: &str, meta: NameMeta) -> DbResult<bool> {
+        self.shared.writable()?;
+        let mut state = self.shared.state.lock().unwrap();
+        let Some(info) = state.names.get_mut(name) else {
+            return Ok(false);
+        };
+        info.meta = meta;
+        Ok(true)
+    }
+}
+
+struct MemCommit {
+    shared: Arc<Shared>,
+}
+
+#[async_trait]
+impl DbCommit for MemCommit {
+    async fn commit(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        _durability: Durability,
+    ) -> DbResult<Result<CommitReceipt, CommitErrors>> {
+        let mut results = self.shared.commit(vec![(docs, entries, names)])?;
+        Ok(results.pop().expect("one result per change set"))
+    }
+
+    async fn commit_many(
+        self: Box<Self>,
+        changes: Vec<ChangeSet>,
+        _durability: Durability,
+    ) -> DbResult<Vec<Result<CommitReceipt, CommitErrors>>> {
+        self.shared.commit(changes)
+    }
+
+    async fn prepare(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        _durability: Durability,
+    ) -> DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>> {
+        self.shared.prepare((docs, entries, names))
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.schemas.get(schema).map(|s| s.schema.clone()))
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.docs.get(doc).map(|d| d.doc.clone()))
+    }
+
+    async fn schema_weak_refs(&self, schema: &Hash) -> DbResult<Option<WeakRefDefaults>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.schemas.get(schema).and_then(|s| s.weak_refs.clone()))
+    }
+
+    fn validator(&self) -> Option<Arc<dyn Validator>> {
+        Some(Arc::new(MemValidator {
+            shared: self.shared.clone(),
+        }))
+    }
+
+    fn db_id(&self) -> usize {
+        Arc::as_ptr(&self.shared) as usize
+    }
+}
+
+/// A prepared transaction. Until it's committed or rolled back, the entries
+/// and names it changes can't be changed by other transactions, and the
+/// documents it depends on aren't evicted.
+struct MemPrepared {
+    shared: Arc<Shared>,
+    id: u64,
+    plan: Option<Plan>,
+}
+
+#[async_trait]
+impl PreparedCommit for MemPrepared {
+    async fn commit(mut self: Box<Self>) -> DbResult<CommitReceipt> {
+        let now = self.shared.now();
+        let plan = self
+            .plan
+            .take()
+            .expect("prepared commits are only applied once");
+        let mut state = self.shared.state.lock().unwrap();
+        state.prepared.remove(&self.id);
+        let receipt = state.apply(plan, now);
+        self.shared.maintain(&mut state, now);
+        Ok(receipt)
+    }
+
+    async fn rollback(self: Box<Self>) -> DbResult<()> {
+        // Dropping releases the reservation.
+        Ok(())
+    }
+}
+
+impl Drop for MemPrepared {
+    fn drop(&mut self) {
+        if self.plan.is_some() {
+            self.shared.state.lock().unwrap().prepared.remove(&self.id);
+        }
+    }
+}
+
+struct MemImport {
+    shared: Arc<Shared>,
+    docs: Vec<Arc<Document>>,
+    entries: Vec<Entry>,
+    names: Vec<(String, Hash)>,
+    progress: ImportProgress,
+}
+
+#[async_trait]
+impl BulkImport for MemImport {
+    async fn add_doc(&mut self, doc: Arc<Document>) -> DbResult<()> {
+        // Documents whose schemas haven't been added yet can't be encoded, so
+        // aren't counted in the staged bytes.
+        let encoded = self.shared.state.lock().unwrap().encode_doc(&doc);
+        self.progress.docs += 1;
+        self.progress.bytes += encoded.map_or(0, |e| e.data().len() as u64);
+        self.docs.push(doc);
+        Ok(())
+    }
+
+    async fn add_entry(&mut self, entry: Entry) -> DbResult<()> {
+        let schema = {
+            let state = self.shared.state.lock().unwrap();
+            state
+                .schemas
+                .get(entry.schema_hash())
+                .map(|s| s.schema.clone())
+        };
+        self.progress.entries += 1;
+        if let Some(schema) = schema {
+            let (encoded, _) = EncodedEntry::from_entry(&schema, entry.clone());
+            self.progress.bytes += encoded.data().len() as u64;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn set_name(&mut self, name: &str, target: &Hash) {
+        self.names.push((name.to_owned(), target.clone()));
+    }
+
+    fn progress(&self) -> ImportProgress {
+        self.progress
+    }
+
+    async fn finish(
+        self: Box<Self>,
+        _durability: Durability,
+    ) -> DbResult<Result<CommitSeq, Vec<CommitError>>> {
+        let mut docs = HashMap::new();
+        let mut entries = HashMap::new();
+        let mut errors = Vec::new();
+        {
+            let state = self.shared.state.lock().unwrap();
+            for doc in self.docs {
+                let Some(encoded) = state.encode_doc(&doc) else {
+                    errors.push(CommitError::MissingSchema {
+                        doc: doc.hash().clone(),
+                        schema: doc
+                            .schema_hash()
+                            .cloned()
+                            .expect("only documents with schemas can fail to encode"),
+                    });
+                    continue;
+                };
+                let weak_ref = doc
+                    .schema_hash()
+                    .and_then(|s| state.schemas.get(s)?.weak_refs.as_ref())
+                    .map(|d| d.weak_links(&doc))
+                    .unwrap_or_default();
+                let change = DocChange::Add {
+                    encoded: Box::new(encoded),
+                    doc: doc.clone(),
+                    weak_ref,
+                    tier: None,
+                };
+                docs.insert(doc.hash().clone(), change);
+            }
+            for entry in self.entries {
+                let Some(schema) = state.schemas.get(entry.schema_hash()) else {
+                    errors.push(CommitError::MissingSchema {
+                        doc: entry.parent().clone(),
+                        schema: entry.schema_hash().clone(),
+                    });
+                    continue;
+                };
+                let (encoded, e_ref) = EncodedEntry::from_entry(&schema.schema, entry);
+                let change = EntryChange::Add {
+                    entry: Box::new(encoded),
+                    ttl: None,
+                    policy: None,
+                };
+                entries.insert(e_ref, change);
+            }
+        }
+        if !errors.is_empty() {
+            return Ok(Err(errors));
+        }
+        let names = self
+            .names
+            .into_iter()
+            .map(|(name, target)| {
+                let change = NameChange {
+                    target: Some(target),
+                    expect: None,
+                    reserved: false,
+                };
+                (name, change)
+            })
+            .collect();
+        let mut results = self.shared.commit(vec![(docs, entries, names)])?;
+        match results.pop().expect("one result per change set") {
+            Ok(receipt) => Ok(Ok(receipt.seq)),
+            Err(errs) => Ok(Err(errs.errors)),
+        }
+    }
+}
+
+struct MemValidator {
+    shared: Arc<Shared>,
+}
+
+#[async_trait]
+impl Validator for MemValidator {
+    fn schema(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(state.schemas.get(schema).map(|s| s.schema.clone()))
+    }
+
+    async fn validate_docs(
+        &self,
+        docs: Vec<NewDocument>,
+    ) -> DbResult<Vec<Result<Document, SchemaError>>> {
+        let state = self.shared.state.lock().unwrap();
+        Ok(docs
+            .into_iter()
+            .map(|doc| match doc.schema_hash() {
+                Some(hash) => match state.schemas.get(hash) {
+                    Some(schema) => Ok(schema.schema.validate_new_doc(doc)?),
+                    None => Err(SchemaError::MissingSchema(hash.clone())),
+                },
+                None => Ok(NoSchema::validate_new_doc(doc)?),
+            })
+            .collect())
+    }
+
+    async fn validate_entries(
+        &self,
+        entries: Vec<NewEntry>,
+        docs: &HashMap<Hash, Arc<Document>>,
+    ) -> DbResult<Vec<Result<Entry, EntryError>>> {
+        let state = self.shared.state.lock().unwrap();
+        let validate = |entry: NewEntry| -> Result<Entry, EntryError> {
+            let Some(schema) = state.schemas.get(entry.schema_hash()) else {
+                return Err(EntryError::MissingEntrySchema(entry.schema_hash().clone()));
+            };
+            let mut checklist = schema.schema.validate_new_entry(entry)?;
+            for (link, item) in checklist.iter() {
+                let doc = match docs.get(&link) {
+                    Some(doc) => doc.clone(),
+                    None => match state.docs.get(&link) {
+                        Some(stored) => stored.doc.clone(),
+                        None => return Err(EntryError::MissingDoc(link)),
+                    },
+                };
+                item.check(&doc)
+                    .map_err(|source| EntryError::DocValidationFail {
+                        doc: link.clone(),
+                        source,
+                    })?;
+            }
+            Ok(checklist.complete()?)
+        };
+        Ok(entries.into_iter().map(validate).collect())
+    }
+
+    fn stats(&self) -> ValidatorStats {
+        ValidatorStats {
+            workers: 0,
+            cached_schemas: self.shared.state.lock().unwrap().schemas.len() as u32,
+            pending: 0,
+        }
+    }
+}
+
+/// Wait forever, once a channel has closed and nothing more will come.
+async fn never<T>() -> T {
+    match futures::future::pending::<Infallible>().await {}
+}
+
+struct MemFeed {
+    rx: AsyncMutex<UnboundedReceiver<CommitRecord>>,
+}
+
+#[async_trait]
+impl ChangeFeed for MemFeed {
+    async fn next(&self) -> DbResult<CommitRecord> {
+        match self.rx.lock().await.next().await {
+            Some(record) => Ok(record),
+            None => never().await,
+        }
+    }
+
+    fn try_next(&self) -> DbResult<Option<CommitRecord>> {
+        Ok(self.rx.try_lock().and_then(|mut rx| rx.try_recv().ok()))
+    }
+}
+
+struct MemWatch {
+    rx: AsyncMutex<UnboundedReceiver<EntryEvent>>,
+}
+
+#[async_trait]
+impl EntryWatch for MemWatch {
+    async fn next(&self) -> DbResult<EntryEvent> {
+        match self.rx.lock().await.next().await {
+            Some(event) => Ok(event),
+            None => never().await,
+        }
+    }
+
+    fn try_next(&self) -> DbResult<Option<EntryEvent>> {
+        Ok(self.rx.try_lock().and_then(|mut rx| rx.try_recv().ok()))
+    }
+}
+
+struct MemRetention {
+    rx: AsyncMutex<UnboundedReceiver<RetentionEviction>>,
+}
+
+#[async_trait]
+impl RetentionEvents for MemRetention {
+    async fn next(&self) -> RetentionEviction {
+        match self.rx.lock().await.next().await {
+            Some(eviction) => eviction,
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn try_next(&self) -> Option<RetentionEviction> {
+        self.rx.try_lock().and_then(|mut rx| rx.try_recv().ok())
+    }
+}
+
+// A database's health never changes once it's open.
+struct MemHealth;
+
+#[async_trait]
+impl HealthEvents for MemHealth {
+    async fn next(&self) -> Health {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<Health> {
+        None
+    }
+}
+
+struct MemRuntime;
+
+impl NodeRuntime for MemRuntime {
+    fn set_limits(&self, _limits: NodeLimits) {}
+
+    fn limits(&self) -> NodeLimits {
+        NodeLimits::default()
+    }
+
+    fn groups(&self) -> Vec<GroupSummary> {
+        Vec::new()
+    }
+
+    fn connections(&self) -> Vec<Connection> {
+        Vec::new()
+    }
+
+    fn disconnect(&self, _node: &NodeAddr) -> bool {
+        false
+    }
+
+    fn events(&self) -> Box<dyn NodeEvents> {
+        Box::new(MemNodeEvents)
+    }
+}
+
+struct MemNodeEvents;
+
+#[async_trait]
+impl NodeEvents for MemNodeEvents {
+    async fn next(&self) -> NodeEvent {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<NodeEvent> {
+        None
+    }
+}
+
+/// A group opened on a [`MemDb`]. It has no other members, so it only ever
+/// reaches the database it was opened on.
+struct MemGroup {
+    shared: Arc<Shared>,
+    quota: Mutex<Option<StorageQuota>>,
+    skew: Mutex<Option<SkewPolicy>>,
+}
+
+impl Group for MemGroup {
+    fn gate(&self, gate: &Hash, _settings: Option<GateSettings>) -> Option<Box<dyn Gate>> {
+        let mut state = self.shared.state.lock().unwrap();
+        *state.gates.entry(gate.clone()).or_default() += 1;
+        Some(Box::new(MemGate {
+            shared: self.shared.clone(),
+            gate: gate.clone(),
+        }))
+    }
+
+    fn cursor(&self, gate: &Hash, _opts: CursorOpts) -> Box<dyn ForkCursor> {
+        self.shared.fork(gate, None)
+    }
+
+    fn set_storage_quota(&self, quota: Option<StorageQuota>) {
+        *self.quota.lock().unwrap() = quota;
+    }
+
+    fn storage_quota(&self) -> Option<StorageQuota> {
+        *self.quota.lock().unwrap()
+    }
+
+    fn storage_usage(&self) -> QuotaUsage {
+        // Nothing is held on the group's behalf.
+        QuotaUsage::default()
+    }
+
+    fn pin_request(&self, _node: &NodeAddr, _root: &Hash, _ttl: Duration) -> Box<dyn PinRequest> {
+        Box::new(MemPin)
+    }
+
+    fn set_pin_policy(&self, _policy: Box<dyn PinPolicy>) {}
+
+    fn hosted_pins(&self) -> Vec<HostedPin> {
+        Vec::new()
+    }
+
+    fn set_skew_policy(&self, policy: Option<SkewPolicy>) {
+        *self.skew.lock().unwrap() = policy;
+    }
+
+    fn skew_policy(&self) -> SkewPolicy {
+        let policy = *self.skew.lock().unwrap();
+        policy.unwrap_or_else(|| *self.shared.skew.lock().unwrap())
+    }
+
+    fn find_schema(&self, schema: &Hash) -> Box<dyn SchemaRequest> {
+        Box::new(MemSchemaRequest(schema.clone()))
+    }
+
+    fn advertise_resources(&self, _resources: Option<Resources>) {}
+
+    fn members_by_resource(&self, _filter: &ResourceFilter) -> Vec<(NodeInfo, Resources)> {
+        Vec::new()
+    }
+
+    fn advertise_services(&self, _services: Vec<Arc<Document>>) {}
+
+    fn services(&self, _filter: ServiceFilter) -> Vec<(NodeAddr, ServiceDescriptor)> {
+        Vec::new()
+    }
+
+    fn summary(&self, root: &Hash) -> AvailabilitySummary {
+        self.shared.summary(root)
+    }
+
+    fn exchange_summary(
+        &self,
+        _node: &NodeAddr,
+        _have: AvailabilitySummary,
+    ) -> Box<dyn SummaryExchange> {
+        Box::new(MemSummaryExchange)
+    }
+
+    fn barrier(&self, root: &Hash, min_peers: usize, _timeout: Duration) -> Box<dyn Barrier> {
+        Box::new(MemBarrier {
+            shared: self.shared.clone(),
+            root: root.clone(),
+            min_peers,
+        })
+    }
+
+    fn warm(&self, nodes: &[NodeAddr]) -> Box<dyn Warmup> {
+        Box::new(MemWarmup(nodes.to_vec()))
+    }
+}
+
+// With no other members, a barrier either needs no one to confirm, or can
+// never complete, so there's no point waiting out the timeout.
+struct MemBarrier {
+    shared: Arc<Shared>,
+    root: Hash,
+    min_peers: usize,
+}
+
+#[async_trait]
+impl Barrier for MemBarrier {
+    async fn complete(self: Box<Self>) -> Result<BarrierReport, BarrierError> {
+        let fingerprint = self
+            .shared
+            .tree_fingerprint(&self.root)
+            .ok_or_else(|| BarrierError::NotHeld(self.root.clone()))?;
+        if self.min_peers > 0 {
+            return Err(BarrierError::TimedOut {
+                confirmed: Vec::new(),
+                required: self.min_peers,
+            });
+        }
+        Ok(BarrierReport {
+            fingerprint,
+            confirmed: Vec::new(),
+        })
+    }
+}
+
+struct MemWarmup(Vec<NodeAddr>);
+
+#[async_trait]
+impl Warmup for MemWarmup {
+    async fn complete(self: Box<Self>) -> Vec<Warmed> {
+        self.0
+            .into_iter()
+            .map(|node| Warmed {
+                node,
+                result: Err(WarmError::Unreachable),
+            })
+            .collect()
+    }
+}
+
+struct MemPin;
+
+#[async_trait]
+impl PinRequest for MemPin {
+    async fn complete(self: Box<Self>) -> Result<PinGrant, PinError> {
+        Err(PinError::Unreachable)
+    }
+}
+
+struct MemSchemaRequest(Hash);
+
+#[async_trait]
+impl SchemaRequest for MemSchemaRequest {
+    async fn complete(self: Box<Self>) -> Result<Document, SchemaFetchError> {
+        Err(SchemaFetchError::NotFound(self.0))
+    }
+}
+
+struct MemSummaryExchange;
+
+#[async_trait]
+impl SummaryExchange for MemSummaryExchange {
+    async fn complete(self: Box<Self>) -> Result<SummaryReply, SummaryError> {
+        Err(SummaryError::Unreachable)
+    }
+}
+
+/// A gate opened on a [`MemGroup`]. Closing or dropping it removes it from
+/// the database.
+struct MemGate {
+    shared: Arc<Shared>,
+    gate: Hash,
+}
+
+impl Gate for MemGate {
+    fn attached(&self) -> Vec<(NodeInfo, u32)> {
+        Vec::new()
+    }
+
+    fn total_cursors(&self) -> u32 {
+        0
+    }
+
+    fn tier(&self, _node: &NodeInfo) -> Option<Tier> {
+        None
+    }
+
+    fn query_hook(&self, _doc: &Hash, _hook: Box<dyn QueryHook>) {}
+
+    fn events(&self) -> Box<dyn GateEvents> {
+        Box::new(MemGateEvents)
+    }
+
+    fn anomaly_detector(&self, _detector: Box<dyn AnomalyDetector>) {}
+
+    fn set_honeypots(&self, _docs: Vec<Hash>) {}
+
+    fn receipts(&self, _doc: &Hash, _key: &str, _enabled: bool) {}
+
+    fn forward_groups(&self, _groups: Vec<Arc<dyn Group>>) {}
+
+    fn close(self) {}
+}
+
+impl Drop for MemGate {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(count) = state.gates.get_mut(&self.gate) {
+            *count -= 1;
+            if *count == 0 {
+                state.gates.remove(&self.gate);
+            }
+        }
+    }
+}
+
+struct MemGateEvents;
+
+#[async_trait]
+impl GateEvents for MemGateEvents {
+    async fn next(&self) -> GateEvent {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<GateEvent> {
+        None
+    }
+}
+
+struct MemFork {
+    shared: Arc<Shared>,
+    hash: Hash,
+    error: Option<CursorError>,
+}
+
+#[async_trait]
+impl ForkCursor for MemFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        let hash = self.hash.clone();
+        self.complete_local()?.ok_or(CursorError::Unavailable(hash))
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        Ok(self
+            .shared
+            .read_doc(&self.hash)
+            .map(|doc| self.shared.cursor(doc)))
+    }
+}
+
+struct MemCursor {
+    id: TraceId,
+    shared: Arc<Shared>,
+    stack: Vec<Arc<Document>>,
+}
+
+impl MemCursor {
+    fn check_link(&self, hash: &Hash) -> Result<(), CursorError> {
+        if self.current().find_hashes().contains(hash) {
+            Ok(())
+        } else {
+            Err(CursorError::NotInDoc(hash.clone()))
+        }
+    }
+}
+
+#[async_trait]
+impl Cursor for MemCursor {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        self.forward_local(hash)?
+            .ok_or_else(|| CursorError::Unavailable(hash.clone()))
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        self.check_link(hash)?;
+        let doc = self.shared.read_doc(hash);
+        if let Some(doc) = &doc {
+            self.stack.push(doc.clone());
+        }
+        Ok(doc)
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+            Ok(())
+        } else {
+            Err(CursorBackError)
+        }
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        self.shared.fork(hash, self.check_link(hash).err())
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.stack.last().unwrap().clone()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        let doc = self.current();
+        let state = self.shared.state.lock().unwrap();
+        let weak = state.docs.get(doc.hash()).map(|d| &d.weak);
+        doc.find_hashes()
+            .into_iter()
+            .map(|hash| {
+                let strength = match weak {
+                    Some(weak) if weak.contains(&hash) => LinkStrength::Weak,
+                    Some(_) => LinkStrength::Strong,
+                    None => LinkStrength::Unknown,
+                };
+                (hash, strength)
+            })
+            .collect()
+    }
+
+    fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery> {
+        let parent = self.current().hash().clone();
+        Box::new(MemQuery::new(*self, &parent, query))
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream> {
+        let finished = match self.check_link(hash) {
+            Ok(()) => None,
+            Err(err) => Some(ChunkUpdate::Failed(err)),
+        };
+        let data = self
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .docs
+            .get(hash)
+            .map(|d| d.data.clone());
+        Box::new(MemChunks {
+            hash: hash.clone(),
+            data,
+            state: Mutex::new(ChunkState { offset, finished }),
+        })
+    }
+
+    fn cache_current(&self, ttl: Duration) -> DbResult<()> {
+        self.shared.writable()?;
+        let now = self.shared.now();
+        let doc = self.current();
+        let until = later(now.time, ttl);
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.docs.contains_key(doc.hash()) {
+            // The document was evicted since the cursor reached it, so store
+            // it again. If its schema is gone too, there's nothing to store.
+            let Some(encoded) = state.encode_doc(&doc) else {
+                return Ok(());
+            };
+            let stored = StoredDoc::new(
+                doc.clone(),
+                encoded.data().clone(),
+                HashSet::new(),
+                now.time,
+            );
+            state.docs.insert(doc.hash().clone(), stored);
+            state.fingerprints.invalidate(doc.hash());
+        }
+        let stored = state.docs.get_mut(doc.hash()).unwrap();
+        if stored.cached_until.is_none_or(|t| t < until) {
+            stored.cached_until = Some(until);
+        }
+        Ok(())
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.id
+    }
+}
+
+struct ChunkState {
+    offset: u64,
+    finished: Option<ChunkUpdate>,
+}
+
+struct MemChunks {
+    hash: Hash,
+    data: Option<Bytes>,
+    state: Mutex<ChunkState>,
+}
+
+#[async_trait]
+impl ChunkStream for MemChunks {
+    async fn next(&self) -> ChunkUpdate {
+        let mut state = self.state.lock().unwrap();
+        if let Some(finished) = &state.finished {
+            return finished.clone();
+        }
+        let Some(data) = &self.data else {
+            let failed = ChunkUpdate::Failed(CursorError::Unavailable(self.hash.clone()));
+            state.finished = Some(failed.clone());
+            return failed;
+        };
+        let (offset, len) = (state.offset, data.len() as u64);
+        if offset > len {
+            let failed = ChunkUpdate::Failed(CursorError::BadOffset { offset, len });
+            state.finished = Some(failed.clone());
+            return failed;
+        }
+        if offset == len {
+            state.finished = Some(ChunkUpdate::Done);
+            return ChunkUpdate::Done;
+        }
+        let end = (offset as usize + CHUNK_SIZE).min(data.len());
+        state.offset = end as u64;
+        ChunkUpdate::Chunk(DocChunk {
+            offset,
+            len,
+            data: data.slice(offset as usize..end),
+        })
+    }
+
+    fn try_next(&self) -> Option<ChunkUpdate> {
+        self.state.lock().unwrap().finished.clone()
+    }
+}
+
+/// A query on the database. Entries already held are returned straight away,
+/// in canonical order, followed by matching entries as they're committed.
+struct MemQuery {
+    id: TraceId,
+    cursor: MemCursor,
+    query: Option<Query>,
+    opts: DbQuery,
+    queue: Mutex<VecDeque<QueryUpdate>>,
+    watch: Option<AsyncMutex<UnboundedReceiver<EntryEvent>>>,
+}
+
+impl MemQuery {
+    fn new(cursor: MemCursor, parent: &Hash, opts: DbQuery) -> Self {
+        let now = cursor.shared.now();
+        let mut queue = VecDeque::new();
+        let mut watch = None;
+        let compiled = {
+            let mut state = cursor.shared.state.lock().unwrap();
+            let compiled = state.compile(parent, &opts.query);
+            if let Some(query) = &compiled {
+                let mut found: Vec<&StoredEntry> = state
+                    .entries
+                    .get(parent)
+                    .into_iter()
+                    .flat_map(|e| e.values())
+                    .filter(|e| state.accept(e, query, &opts, now))
+                    .collect();
+                match opts.sample {
+                    Some(sample) => {
+                        let random = RandomState::new();
+                        found.sort_by_cached_key(|e| random.hash_one(e.entry.hash()));
+                        found.truncate(sample.get() as usize);
+                    }
+                    None => {
+                        let order = ResultOrd::for_query(&opts);
+                        found.sort_by(|a, b| order.cmp_entries(&a.entry, &b.entry));
+                    }
+                }
+                for stored in found {
+                    let result = make_result(&cursor.shared, stored);
+                    queue.push_back(QueryUpdate::Result(Box::new(result)));
+                }
+                // Watch from under the same lock, so no commit slips between
+                // the entries found and the ones watched for.
+                let (tx, rx) = mpsc::unbounded();
+                state
+                    .watches
+                    .push((parent.clone(), query.key().to_owned(), tx));
+                watch = Some(AsyncMutex::new(rx));
+            }
+            compiled
+        };
+        Self {
+            id: cursor.shared.trace_id(),
+            cursor,
+            query: compiled,
+            opts,
+            queue: Mutex::new(queue),
+            watch,
+        }
+    }
+
+    /// Turn a change to a watched entry into a result, if it's one the query
+    /// should return.
+    fn result(&self, event: EntryEvent) -> Option<QueryResult> {
+        let query = self.query.as_ref()?;
+        if !matches!(
+            event.change,
+            EntryRecord::Added(_) | EntryRecord::Restored(_)
+        ) {
+            return None;
+        }
+        let now = self.cursor.shared.now();
+        let state = self.cursor.shared.state.lock().unwrap();
+        let stored = state.entry(event.change.entry())?;
+        state
+            .accept(stored, query, &self.opts, now)
+            .then(|| make_result(&self.cursor.shared, stored))
+    }
+}
+
+fn make_result(shared: &Arc<Shared>, stored: &StoredEntry) -> QueryResult {
+    QueryResult {
+        entry: stored.entry.clone(),
+        docs: Vec::new(),
+        source: local_info(),
+        expires: stored.ttl,
+        deleted: stored.deleted.map(|d| d.at),
+        stale: false,
+        provenance: None,
+        relays: Vec::new(),
+        useful: Box::new(NoReport),
+        fork_spawner: Box::new(MemSpawner {
+            shared: shared.clone(),
+            hash: stored.entry.parent().clone(),
+        }),
+    }
+}
+
+#[async_trait]
+impl CursorQuery for MemQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        Box::new(self.cursor)
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        loop {
+            if let Some(update) = self.queue.lock().unwrap().pop_front() {
+                return update;
+            }
+            let Some(watch) = &self.watch else {
+                return futures::future::pending().await;
+            };
+            let Some(event) = watch.lock().await.next().await else {
+                return futures::future::pending().await;
+            };
+            if let Some(result) = self.result(event) {
+                return QueryUpdate::Result(Box::new(result));
+            }
+        }
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        if let Some(update) = self.queue.lock().unwrap().pop_front() {
+            return Some(update);
+        }
+        let mut watch = self.watch.as_ref()?.try_lock()?;
+        while let Ok(event) = watch.try_recv() {
+            if let Some(result) = self.result(event) {
+                return Some(QueryUpdate::Result(Box::new(result)));
+            }
+        }
+        None
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        self.opts.merge.unwrap_or_default()
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.id
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.cursor.id
+    }
+}
+
+struct NoReport;
+
+impl UsefulReport for NoReport {
+    fn report(self: Box<Self>, _useful: Usefulness) {}
+}
+
+/// Forks a cursor at a query result's parent document.
+struct MemSpawner {
+    shared: Arc<Shared>,
+    hash: Hash,
+}
+
+impl ForkSpawner for MemSpawner {
+    fn fork(&self) -> Box<dyn ForkCursor> {
+        self.shared.fork(&self.hash, None)
+    }
+}
@@ -0,0 +1,223 @@
+//! Interceptors layered around cursors.
+//!
+//! Logging, metrics, caching, and rewriting queries all want to see what a
+//! cursor is doing, but writing a full [`Cursor`] and [`CursorQuery`]
+//! implementation that delegates every method just to watch a few of them is
+//! tedious and easy to get wrong. An [`Interceptor`] only implements the hooks
+//! it cares about, and [`CursorExt::with`][crate::rpc::CursorExt::with] wraps
+//! any cursor with it. Wrapping an already-wrapped cursor stacks
+//! interceptors, with the outermost one seeing each call first.
+//!
+//! Interceptors follow the cursor around: cursors forked from a wrapped one,
+//! queries made through it, and cursors returned to from those queries are all
+//! wrapped with the same interceptor.
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use fog_pack::{document::Document, types::*};
+
+use crate::{
+    cursor::{
+        ChunkStream, Cursor, CursorBackError, CursorError, CursorQuery, DbQuery, ForkCursor,
+        LinkStrength, MergeStrategy, NewCursor, QueryUpdate, TraceId,
+    },
+    DbResult,
+};
+
+/// Hooks called as a wrapped cursor is used. Every hook has a default that
+/// does nothing, or passes its input through unchanged.
+pub trait Interceptor: Send + Sync {
+    /// Called before the cursor navigates to a document, through
+    /// [`forward`][Cursor::forward], [`forward_local`][Cursor::forward_local],
+    /// or a fork.
+    fn on_forward(&self, _hash: &Hash) {}
+
+    /// Called when the cursor, or a fork of it, arrives at a document.
+    fn on_document(&self, _doc: &Arc<Document>) {}
+
+    /// Called when navigation fails.
+    fn on_error(&self, _err: &CursorError) {}
+
+    /// Called before a query is made on `doc`, and can rewrite it.
+    fn on_query(&self, _doc: &Hash, query: DbQuery) -> DbQuery {
+        query
+    }
+
+    /// Called on each update from a query, and can rewrite it, or drop it by
+    /// returning `None`.
+    fn on_update(&self, update: QueryUpdate) -> Option<QueryUpdate> {
+        Some(update)
+    }
+}
+
+/// A cursor wrapped with an [`Interceptor`].
+pub struct Intercepted {
+    inner: Box<dyn Cursor>,
+    interceptor: Arc<dyn Interceptor>,
+}
+
+impl Intercepted {
+    /// Wrap a cursor with an interceptor.
+    pub fn new(inner: Box<dyn Cursor>, interceptor: Arc<dyn Interceptor>) -> Self {
+        Self { inner, interceptor }
+    }
+
+    /// Remove the interceptor, returning the cursor it was wrapped around.
+    pub fn into_inner(self) -> Box<dyn Cursor> {
+        self.inner
+    }
+
+    fn navigated(&self, res: Result<Option<&Arc<Document>>, &CursorError>) {
+        match res {
+            Ok(Some(doc)) => self.interceptor.on_document(doc),
+            Ok(None) => (),
+            Err(e) => self.interceptor.on_error(e),
+        }
+    }
+}
+
+#[async_trait]
+impl Cursor for Intercepted {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        self.interceptor.on_forward(hash);
+        let res = self.inner.forward(hash).await;
+        self.navigated(res.as_ref().map(Some));
+        res
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        self.interceptor.on_forward(hash);
+        let res = self.inner.forward_local(hash);
+        self.navigated(res.as_ref().map(Option::as_ref));
+        res
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        self.inner.back()
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        self.interceptor.on_forward(hash);
+        Box::new(InterceptedFork {
+            inner: self.inner.fork(hash),
+            interceptor: self.interceptor.clone(),
+        })
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.inner.current()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        self.inner.links()
+    }
+
+    fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery> {
+        let query = self
+            .interceptor
+            .on_query(self.inner.current().hash(), query);
+        Box::new(InterceptedQuery {
+            inner: self.inner.query(query),
+            interceptor: self.interceptor,
+        })
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream> {
+        self.inner.fetch_chunked(hash, offset)
+    }
+
+    fn cache_current(&self, ttl: Duration) -> DbResult<()> {
+        self.inner.cache_current(ttl)
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.inner.trace_id()
+    }
+}
+
+/// A fork of an intercepted cursor, which wraps the new cursor once it opens.
+struct InterceptedFork {
+    inner: Box<dyn ForkCursor>,
+    interceptor: Arc<dyn Interceptor>,
+}
+
+fn wrap(
+    res: Result<Option<NewCursor>, CursorError>,
+    interceptor: &Arc<dyn Interceptor>,
+) -> Result<Option<NewCursor>, CursorError> {
+    match res {
+        Ok(Some((cursor, doc))) => {
+            interceptor.on_document(&doc);
+            let cursor = Intercepted::new(cursor, interceptor.clone());
+            Ok(Some((Box::new(cursor), doc)))
+        }
+        Ok(None) => Ok(None),
+        Err(e) => {
+            interceptor.on_error(&e);
+            Err(e)
+        }
+    }
+}
+
+#[async_trait]
+impl ForkCursor for InterceptedFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        match self.inner.complete().await {
+            Ok((cursor, doc)) => {
+                self.interceptor.on_document(&doc);
+                let cursor = Intercepted::new(cursor, self.interceptor);
+                Ok((Box::new(cursor), doc))
+            }
+            Err(e) => {
+                self.interceptor.on_error(&e);
+                Err(e)
+            }
+        }
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        wrap(self.inner.complete_local(), &self.interceptor)
+    }
+}
+
+/// A query made through an intercepted cursor.
+struct InterceptedQuery {
+    inner: Box<dyn CursorQuery>,
+    interceptor: Arc<dyn Interceptor>,
+}
+
+#[async_trait]
+impl CursorQuery for InterceptedQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        Box::new(Intercepted::new(self.inner.back(), self.interceptor))
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        loop {
+            if let Some(update) = self.interceptor.on_update(self.inner.next().await) {
+                return update;
+            }
+        }
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        loop {
+            if let Some(update) = self.interceptor.on_update(self.inner.try_next()?) {
+                return Some(update);
+            }
+        }
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        self.inner.merge_strategy()
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.inner.trace_id()
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.inner.cursor_trace_id()
+    }
+}
@@ -0,0 +1,107 @@
+//! Mixnet provider interface.
+//!
+//! A [`GroupSpec`] can require that a mixnet be used for finding group members
+//! (`mixnet_locator`) and for communicating with them (`mixnet_comms`). This
+//! module defines what a mixnet provider must supply to meet those
+//! requirements:
+//!
+//! - Anonymous rendezvous: a node can be found through a [`Rendezvous`] point
+//!   without revealing its network location.
+//! - Bidirectional circuits: framed [`Connection`]s routed through the mixnet.
+//! - Padding: a [`PaddingPolicy`] applied to circuit traffic, to resist traffic
+//!   analysis.
+//!
+//! Before opening a group, the database should use [`Mixnet::check`] to verify
+//! that the specification's mixnet flags can actually be satisfied.
+
+use std::{num::NonZeroU32, time::Duration};
+
+use async_trait::async_trait;
+use fog_pack::types::*;
+use thiserror::Error;
+
+use crate::{group::GroupSpec, transport::Connection, transport::TransportError};
+
+/// Failure within a mixnet provider.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum MixnetError {
+    /// The group requires mixnet-based discovery, which this provider can't do.
+    #[error("Mixnet can't provide anonymous rendezvous")]
+    NoLocator,
+    /// The group requires mixnet-based communication, which this provider can't
+    /// do.
+    #[error("Mixnet can't provide anonymous circuits")]
+    NoComms,
+    /// The group requires a mixnet, but none is available.
+    #[error("No mixnet is available")]
+    Missing,
+    /// The rendezvous point couldn't be reached.
+    #[error("Rendezvous point is unreachable")]
+    Unreachable,
+    /// Failure in the underlying transport.
+    #[error("Mixnet transport failure")]
+    Transport(#[from] TransportError),
+}
+
+/// Padding to apply to traffic sent over a mixnet circuit.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaddingPolicy {
+    /// Pad every frame up to a multiple of this many bytes.
+    pub frame_size: Option<NonZeroU32>,
+    /// When a circuit is idle, send cover traffic at this interval.
+    pub cover_interval: Option<Duration>,
+}
+
+/// An opaque, provider-specific description of a rendezvous point. It can be
+/// shared with other nodes so they may open circuits to it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RendezvousPoint(pub Vec<u8>);
+
+/// An established rendezvous point, through which other nodes can anonymously
+/// open circuits to this one. Dropping it tears down the rendezvous.
+#[async_trait]
+pub trait Rendezvous: Send + Sync {
+    /// The shareable description of this rendezvous point.
+    fn point(&self) -> RendezvousPoint;
+
+    /// Wait for the next incoming circuit.
+    async fn accept(&self) -> Result<Box<dyn Connection>, MixnetError>;
+}
+
+/// A mixnet provider.
+#[async_trait]
+pub trait Mixnet: Send + Sync {
+    /// Whether this mixnet can be used to anonymously locate group members.
+    fn supports_locator(&self) -> bool;
+
+    /// Whether this mixnet can carry anonymous bidirectional circuits.
+    fn supports_comms(&self) -> bool;
+
+    /// Establish a rendezvous point for the given group key. Members of the
+    /// group that know the key should be able to find the point.
+    async fn rendezvous(&self, key: &Hash) -> Result<Box<dyn Rendezvous>, MixnetError>;
+
+    /// Find rendezvous points that have been established for the given group
+    /// key.
+    async fn locate(&self, key: &Hash) -> Result<Vec<RendezvousPoint>, MixnetError>;
+
+    /// Open a circuit to a rendezvous point, using the given padding policy.
+    async fn circuit(
+        &self,
+        point: &RendezvousPoint,
+        padding: &PaddingPolicy,
+    ) -> Result<Box<dyn Connection>, MixnetError>;
+
+    /// Check whether this mixnet can satisfy the mixnet requirements of a
+    /// group specification.
+    fn check(&self, spec: &GroupSpec) -> Result<(), MixnetError> {
+        if spec.mixnet_locator && !self.supports_locator() {
+            return Err(MixnetError::NoLocator);
+        }
+        if spec.mixnet_comms && !self.supports_comms() {
+            return Err(MixnetError::NoComms);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//! Conventions for the database's root namespace.
+//!
+//! Names are the roots of the database's document tree, and every application
+//! sharing a database shares the one namespace. To keep them from stepping on
+//! each other, and on the database itself, names are split up by prefix:
+//!
+//! - [`SYS_PREFIX`] is for the database's own bookkeeping, including
+//!   [saved gates][crate::saved_gates].
+//! - [`CERTS_PREFIX`] is for certificate databases.
+//! - [`SCHEMA_PREFIX`] is for published schema registries.
+//! - [`APP_PREFIX`] is for applications, each of which should keep to its own
+//!   `app/<application>/` prefix, as made by [`app_name`].
+//!
+//! The first three are reserved: [`Db::name_add`][crate::Db::name_add] refuses
+//! them, and they can only be set through
+//! [`Db::name_add_reserved`][crate::Db::name_add_reserved] by the components
+//! that own them. Every new name is also checked against the database's
+//! [`NamingPolicy`].
+//!
+//! Names can also be changed as part of a transaction, with
+//! [`Transaction::set_name`] and [`Transaction::swap_name`], or
+//! [`Transaction::swap_name_reserved`] for reserved names. The most common
+//! case, publishing a new version of a document tree under a name, is handled
+//! by [`rotate_root`].
+
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    transaction::{CommitErrors, Durability, Transaction},
+    Db, DbResult,
+};
+
+/// Prefix for names used by the database itself.
+pub const SYS_PREFIX: &str = "sys/";
+
+/// Prefix for names used by certificate databases.
+pub const CERTS_PREFIX: &str = "certs/";
+
+/// Prefix for names used by schema registries.
+pub const SCHEMA_PREFIX: &str = "schema/";
+
+/// Prefix for names used by applications.
+pub const APP_PREFIX: &str = "app/";
+
+/// The prefixes reserved by default.
+pub const RESERVED_PREFIXES: &[&str] = &[SYS_PREFIX, CERTS_PREFIX, SCHEMA_PREFIX];
+
+/// The default maximum length of a name, in bytes.
+pub const DEFAULT_MAX_LEN: usize = 255;
+
+/// Make a name in an application's part of the namespace.
+pub fn app_name(app: &str, name: &str) -> String {
+    format!("{APP_PREFIX}{app}/{name}")
+}
+
+/// Commit a transaction holding a new version of a document tree, and point
+/// `name` at its new root document in the same commit. Returns the root the
+/// name pointed to before, so the caller can keep a history of versions.
+///
+/// If the name is changed by someone else between reading its current root and
+/// committing, the commit fails with
+/// [`CommitError::NameChanged`][crate::transaction::CommitError::NameChanged]
+/// and nothing is changed. The transaction can then be reloaded from the
+/// errors and retried.
+pub async fn rotate_root<D: Db + ?Sized>(
+    db: &D,
+    mut txn: Transaction,
+    name: &str,
+    root: &Hash,
+    durability: Durability,
+) -> DbResult<Result<Option<Hash>, CommitErrors>> {
+    let current = db.name_get(name).await?;
+    txn.swap_name(name, Some(root), current.as_ref());
+    Ok(txn.commit(durability).await?.map(|_| current))
+}
+
+/// Optional descriptive metadata for a name, set with
+/// [`Db::name_set_meta`][crate::Db::name_set_meta].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameMeta {
+    /// Who added the name.
+    pub creator: Option<Identity>,
+    /// A human-readable description of what the name is for.
+    pub description: Option<String>,
+}
+
+/// Everything the database knows about a name.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameInfo {
+    /// The document the name currently points to.
+    pub hash: Hash,
+    /// When the name was first added. Pointing it to a new document doesn't
+    /// change this.
+    pub created: Timestamp,
+    /// When the name was last pointed to a document.
+    pub updated: Timestamp,
+    /// Descriptive metadata, if any has been set.
+    pub meta: NameMeta,
+}
+
+/// A name that couldn't be added.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum NameError {
+    /// The name was empty.
+    #[error("Name is empty")]
+    Empty,
+    /// The name was longer than the policy allows.
+    #[error("Name is too long (max {max}, actual {actual})")]
+    TooLong { max: usize, actual: usize },
+    /// The name contained a control character, started with `/`, or had an
+    /// empty segment between two `/`.
+    #[error("Name is malformed")]
+    Malformed,
+    /// The name falls under a reserved prefix.
+    #[error("Name is under the reserved prefix {0:?}")]
+    Reserved(String),
+    /// The name doesn't fall under any of the prefixes the policy permits.
+    #[error("Name isn't under a permitted prefix")]
+    NotPermitted,
+    /// The name is being changed by a prepared transaction, and can't be
+    /// changed otherwise until that transaction is committed or rolled back.
+    #[error("Name is held by a prepared transaction")]
+    Held,
+}
+
+/// Rules for which names may be added to a database.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NamingPolicy {
+    /// Maximum length of a name, in bytes.
+    pub max_len: usize,
+    /// Prefixes that may only be set with
+    /// [`Db::name_add_reserved`][crate::Db::name_add_reserved].
+    pub reserved: Vec<String>,
+    /// If not empty, names added with [`Db::name_add`][crate::Db::name_add]
+    /// must be under one of these prefixes.
+    pub permitted: Vec<String>,
+}
+
+impl Default for NamingPolicy {
+    fn default() -> Self {
+        Self {
+            max_len: DEFAULT_MAX_LEN,
+            reserved: RESERVED_PREFIXES.iter().map(|p| p.to_string()).collect(),
+            permitted: Vec::new(),
+        }
+    }
+}
+
+impl NamingPolicy {
+    /// A policy that only permits application names, under [`APP_PREFIX`].
+    pub fn apps_only() -> Self {
+        Self {
+            permitted: vec![APP_PREFIX.into()],
+            ..Self::default()
+        }
+    }
+
+    /// Check a name to be added with [`Db::name_add`][crate::Db::name_add].
+    pub fn check(&self, name: &str) -> Result<(), NameError> {
+        if let Some(prefix) = self.reserved.iter().find(|p| name.starts_with(p.as_str())) {
+            return Err(NameError::Reserved(prefix.clone()));
+        }
+        self.check_reserved(name)?;
+        if !self.permitted.is_empty()
+            && !self.permitted.iter().any(|p| name.starts_with(p.as_str()))
+        {
+            return Err(NameError::NotPermitted);
+        }
+        Ok(())
+    }
+
+    /// Check a name to be added with
+    /// [`Db::name_add_reserved`][crate::Db::name_add_reserved]. This applies
+    /// every rule except for the reserved and permitted prefixes.
+    pub fn check_reserved(&self, name: &str) -> Result<(), NameError> {
+        if name.is_empty() {
+            return Err(NameError::Empty);
+        }
+        if name.len() > self.max_len {
+            return Err(NameError::TooLong {
+                max: self.max_len,
+                actual: name.len(),
+            });
+        }
+        if name.chars().any(char::is_control) || name.starts_with('/') || name.contains("//") {
+            return Err(NameError::Malformed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! Persistent peer address book.
+//!
+//! Discovery finds peers from scratch, which can take a while on a DHT or a
+//! sparse local network. A [`PeerStore`] remembers the peers a node has seen
+//! before, how they were reached, and how well they behaved, so the next
+//! session can go straight to dialing them. A group whose
+//! [`GroupSpec::peers`][crate::group::GroupSpec::peers] is set asks the store
+//! for [bootstrap candidates][PeerStore::bootstrap] before (and alongside)
+//! running discovery, and reports what happens when it dials and talks to
+//! peers back to the store with [`PeerStore::update`].
+//!
+//! How the store is kept is up to the implementation; the records are
+//! serializable so they can simply be written to disk, or kept as documents in
+//! the database.
+
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{transport::PeerCandidate, NetInfo, NetType, NodeAddr};
+
+/// One way of reaching a peer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerAddress {
+    /// Name of the transport that can dial this address.
+    pub transport: String,
+    /// The network the address is on.
+    pub net: NetType,
+    /// Transport-specific address.
+    pub addr: Vec<u8>,
+    /// When the address was last found through discovery.
+    pub last_seen: Timestamp,
+    /// When the peer was last successfully connected to at this address.
+    pub last_connected: Option<Timestamp>,
+    /// How many times in a row dialing this address has failed.
+    pub failures: u32,
+}
+
+impl PeerAddress {
+    /// Turn this address back into a candidate that can be dialed.
+    pub fn candidate(&self, node: &NodeAddr) -> PeerCandidate {
+        PeerCandidate {
+            transport: self.transport.clone(),
+            net: self.net.clone(),
+            addr: self.addr.clone(),
+            node: Some(node.clone()),
+        }
+    }
+}
+
+/// Everything a store knows about a peer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    /// The peer.
+    pub node: NodeAddr,
+    /// The ways the peer has been reached, most recently seen first.
+    pub addrs: Vec<PeerAddress>,
+    /// The peer's last known [reputation score][crate::reputation::PeerScore],
+    /// between 0 and 1.
+    pub score: Option<f64>,
+    /// When the peer was first seen.
+    pub first_seen: Timestamp,
+}
+
+impl PeerRecord {
+    /// When the peer was last seen at any of its addresses.
+    pub fn last_seen(&self) -> Option<Timestamp> {
+        self.addrs.iter().map(|a| a.last_seen).max()
+    }
+}
+
+/// Something observed about a peer, to be recorded in a [`PeerStore`].
+#[derive(Clone, Debug)]
+pub enum PeerUpdate {
+    /// The peer was found at an address. Candidates without a known node
+    /// address aren't recorded.
+    Seen(PeerCandidate),
+    /// The peer was successfully connected to at an address.
+    Connected(PeerCandidate),
+    /// Dialing the peer at an address failed.
+    Failed(PeerCandidate),
+    /// The peer's reputation score changed.
+    Score(NodeAddr, f64),
+}
+
+/// A persistent record of previously seen peers.
+pub trait PeerStore: Send + Sync {
+    /// Record something observed about a peer, as of the time `now`.
+    fn update(&self, update: PeerUpdate, now: Timestamp);
+
+    /// Get what's known about a peer.
+    fn get(&self, node: &NodeAddr) -> Option<PeerRecord>;
+
+    /// Get up to `limit` candidates worth dialing on the networks permitted by
+    /// `net`, best first. Peers are ranked by score, then by how recently they
+    /// were connected to, and addresses that keep failing to dial are left
+    /// out.
+    fn bootstrap(&self, net: &NetInfo, limit: usize) -> Vec<PeerCandidate>;
+
+    /// Forget a peer entirely. Returns false if the peer wasn't in the store.
+    fn forget(&self, node: &NodeAddr) -> bool;
+
+    /// Forget addresses not seen since `before`, and peers left with no
+    /// addresses. Returns how many peers were forgotten.
+    fn prune(&self, before: Timestamp) -> usize;
+}
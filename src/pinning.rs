@@ -0,0 +1,100 @@
+//! Keeping documents resident on behalf of other nodes.
+//!
+//! Small devices often can't hold everything they produce, and would rather a
+//! bigger node in their group held it for them. A node can ask a group member
+//! to pin a document tree with
+//! [`Group::pin_request`][crate::group::Group::pin_request]. The member fetches
+//! the tree through the group and keeps it resident until the pin expires,
+//! after which it is treated like any other content fetched through the group.
+//! Pins are renewed by asking again before they expire.
+//!
+//! On the receiving side, every request is put to the group's [`PinPolicy`],
+//! set with [`Group::set_pin_policy`][crate::group::Group::set_pin_policy].
+//! Without one, every request is refused. Pinned documents count against the
+//! group's [storage quota][crate::quota], and are only evicted to make room once
+//! the group holds nothing else that can be evicted.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use fog_pack::types::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::NodeInfo;
+
+/// Failure to get a document tree pinned by another node.
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum PinError {
+    /// The node refused the request.
+    #[error("Pin request refused: {0}")]
+    Refused(String),
+    /// The node was willing, but the document tree wouldn't fit in its quota
+    /// for the group.
+    #[error("Pin request exceeds the node's storage quota")]
+    QuotaExceeded,
+    /// The node couldn't be reached through the group.
+    #[error("Node couldn't be reached")]
+    Unreachable,
+    /// Some other failure occurred.
+    #[error("Pin request failed: {0}")]
+    Other(String),
+}
+
+/// A pin granted by another node.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinGrant {
+    /// The root of the pinned document tree.
+    pub root: Hash,
+    /// When the pin expires. This may be sooner than was asked for.
+    pub expires: Timestamp,
+}
+
+/// An outgoing pin request, waiting on the other node's answer.
+#[async_trait]
+pub trait PinRequest: Send + Sync {
+    /// Wait for the other node to grant or refuse the pin.
+    async fn complete(self: Box<Self>) -> Result<PinGrant, PinError>;
+}
+
+/// A request from another node to pin a document tree.
+#[derive(Clone, Debug)]
+pub struct IncomingPin {
+    /// The node making the request.
+    pub node: NodeInfo,
+    /// The root of the document tree to pin.
+    pub root: Hash,
+    /// How long the node would like the tree pinned for.
+    pub ttl: Duration,
+}
+
+/// A decision on whether to pin a document tree for another node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinDecision {
+    /// Pin the tree for at most the given duration. A duration shorter than the
+    /// one requested is granted as-is.
+    Grant(Duration),
+    /// Refuse the request, giving a reason to pass back to the node.
+    Refuse(String),
+}
+
+/// Decides which pin requests a node will grant.
+pub trait PinPolicy: Send + Sync {
+    /// Decide on an incoming pin request. Granted requests may still fail
+    /// with [`PinError::QuotaExceeded`] once the tree's size is known.
+    fn decide(&self, request: &IncomingPin) -> PinDecision;
+}
+
+/// A pin this node is holding on behalf of another node.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HostedPin {
+    /// The node the pin is held for.
+    pub node: NodeInfo,
+    /// The root of the pinned document tree.
+    pub root: Hash,
+    /// When the pin expires.
+    pub expires: Timestamp,
+    /// Storage used by the pinned tree, in bytes.
+    pub bytes: u64,
+}
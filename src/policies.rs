@@ -0,0 +1,166 @@
+//! Policy templates shared across entries.
+//!
+//! Many entries end up with the same access [`Policy`], and embedding a copy in
+//! every entry change bloats transactions and leaves no way to change the
+//! policy for all of them at once. Instead, a policy can be published once as a
+//! template with [`PolicyRegistry::publish`], and entries can refer to it by
+//! hash with
+//! [`Transaction::set_policy_template`][crate::transaction::Transaction::set_policy_template].
+//!
+//! Templates are kept by name in a single schema-less [`PolicyTemplates`]
+//! document under [`POLICY_REGISTRY_NAME`]. Publishing a new policy under an
+//! existing name makes it the current version of that template, and entries
+//! referring to any earlier version are then held to the new one. Databases
+//! use [`PolicyRegistry::resolve`] to find the policy to enforce for an entry.
+
+use std::collections::BTreeMap;
+
+use fog_pack::{document::NewDocument, types::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cert::{EntryPolicy, Policy},
+    transaction::{CommitErrors, Durability},
+    Db, DbResult,
+};
+
+/// The name the policy template document is kept under, under the reserved
+/// [`SYS_PREFIX`][crate::names::SYS_PREFIX].
+pub const POLICY_REGISTRY_NAME: &str = "sys/policies";
+
+/// Every version of one policy template.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyTemplate {
+    /// The policy document for the current version.
+    pub current: Hash,
+    /// The policy documents for every earlier version, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous: Vec<Hash>,
+}
+
+/// The policy template document, as stored under [`POLICY_REGISTRY_NAME`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyTemplates {
+    /// Each template, by name.
+    pub templates: BTreeMap<String, PolicyTemplate>,
+}
+
+impl PolicyTemplates {
+    /// Find the current version of the template a policy document belongs
+    /// to. A document that isn't part of any template is returned as-is.
+    pub fn current<'a>(&'a self, policy: &'a Hash) -> &'a Hash {
+        self.templates
+            .values()
+            .find(|t| &t.current == policy || t.previous.contains(policy))
+            .map_or(policy, |t| &t.current)
+    }
+}
+
+/// Publishing and resolution of policy templates, layered over a database.
+pub struct PolicyRegistry<'a, D: Db + ?Sized> {
+    db: &'a D,
+}
+
+impl<'a, D: Db + ?Sized> PolicyRegistry<'a, D> {
+    /// Use the policy templates of the given database.
+    pub fn new(db: &'a D) -> Self {
+        Self { db }
+    }
+
+    /// Read the current template document. An unpublished or unreadable
+    /// template document is treated as empty.
+    pub async fn templates(&self) -> DbResult<PolicyTemplates> {
+        Ok(self.load().await?.map(|(_, t)| t).unwrap_or_default())
+    }
+
+    async fn load(&self) -> DbResult<Option<(Hash, PolicyTemplates)>> {
+        let Some(hash) = self.db.name_get(POLICY_REGISTRY_NAME).await? else {
+            return Ok(None);
+        };
+        let Some(doc) = self.db.doc_get(&hash).await? else {
+            return Ok(None);
+        };
+        Ok(doc.deserialize().ok().map(|t| (hash, t)))
+    }
+
+    /// Look up the current version of a template by name, returning the hash
+    /// to refer to it by.
+    pub async fn lookup(&self, name: &str) -> DbResult<Option<Hash>> {
+        Ok(self
+            .templates()
+            .await?
+            .templates
+            .get(name)
+            .map(|t| t.current.clone()))
+    }
+
+    /// Get the policy stored in a policy document.
+    pub async fn get(&self, policy: &Hash) -> DbResult<Option<Policy>> {
+        Ok(self
+            .db
+            .doc_get(policy)
+            .await?
+            .and_then(|doc| doc.deserialize().ok()))
+    }
+
+    /// Find the policy to enforce for an entry. Templates resolve to the
+    /// current version of the template they belong to. Returns `None` if the
+    /// template's policy document isn't in the database.
+    pub async fn resolve(&self, policy: &EntryPolicy) -> DbResult<Option<Policy>> {
+        match policy {
+            EntryPolicy::Inline(policy) => Ok(Some(policy.clone())),
+            EntryPolicy::Template(hash) => {
+                let templates = self.templates().await?;
+                self.get(templates.current(hash)).await
+            }
+        }
+    }
+
+    /// Publish a policy as the current version of the named template,
+    /// returning the hash entries should refer to it by.
+    ///
+    /// Fails with [`CommitError::NameChanged`][crate::transaction::CommitError::NameChanged]
+    /// if the templates were updated by someone else in the meantime, in
+    /// which case nothing is changed.
+    pub async fn publish(
+        &self,
+        name: &str,
+        policy: &Policy,
+        durability: Durability,
+    ) -> DbResult<Result<Hash, CommitErrors>> {
+        let (current, mut templates) = match self.load().await? {
+            Some((hash, t)) => (Some(hash), t),
+            None => (None, PolicyTemplates::default()),
+        };
+        let mut txn = self.db.txn();
+        let doc = NewDocument::new(None, policy).expect("Policy should always be serializable");
+        let doc = txn
+            .add_new_doc(doc).await?
+            .expect("Policy documents have no schema and are always valid");
+        let hash = doc.hash().clone();
+        match templates.templates.get_mut(name) {
+            Some(t) if t.current == hash => return Ok(Ok(hash)),
+            Some(t) => {
+                let prev = std::mem::replace(&mut t.current, hash.clone());
+                t.previous.push(prev);
+            }
+            None => {
+                templates.templates.insert(
+                    name.to_owned(),
+                    PolicyTemplate {
+                        current: hash.clone(),
+                        previous: Vec::new(),
+                    },
+                );
+            }
+        }
+        let reg = NewDocument::new(None, &templates)
+            .expect("PolicyTemplates should always be serializable");
+        let reg = txn
+            .add_new_doc(reg).await?
+            .expect("Template documents have no schema and are always valid");
+        txn.swap_name_reserved(POLICY_REGISTRY_NAME, Some(reg.hash()), current.as_ref());
+        Ok(txn.commit(durability).await?.map(|_| hash))
+    }
+}
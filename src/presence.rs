@@ -0,0 +1,235 @@
+//! Presence beacons: who in a group is around, and what they're up to.
+//!
+//! Each member publishes a small signed [`Beacon`] as an entry on a shared
+//! presence document, with a short time-to-live. Members re-publish their
+//! beacon before it expires for as long as they're present, and simply stop
+//! when they leave; a member that disappears without saying goodbye drops out
+//! once its last beacon expires. Anyone watching the presence document
+//! through a group cursor feeds the results of a [`presence_query`] into a
+//! [`PresenceSet`] to track the current members.
+//!
+//! The presence document's schema must include an entry type for the presence
+//! key using [`Beacon::validator`]. Beacons are identified by the Identity
+//! that signed them, so unsigned beacons are ignored.
+
+use std::{collections::HashMap, time::Duration};
+
+use fog_crypto::identity::IdentityKey;
+use fog_pack::{
+    document::Document,
+    entry::EntryRef,
+    error::Error as FogError,
+    query::NewQuery,
+    types::*,
+    validator::{ArrayValidator, MapValidator, StrValidator, TimeValidator, Validator},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    crdt::stage,
+    cursor::{DbQuery, QueryResult},
+    transaction::{EntryError, Transaction},
+    DbResult,
+};
+
+/// How long a beacon lasts by default. Members should re-publish well before
+/// this runs out.
+pub const DEFAULT_BEACON_TTL: Duration = Duration::from_secs(60);
+
+/// A member's availability.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Status {
+    /// Present and available.
+    #[default]
+    Online,
+    /// Present, but not paying attention.
+    Away,
+    /// Present, but not to be disturbed.
+    Busy,
+    /// Leaving. A member publishes this as its last beacon so watchers don't
+    /// have to wait for the previous one to expire.
+    Offline,
+}
+
+/// A presence beacon published by a group member.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Beacon<T> {
+    /// When the beacon was published.
+    pub time: Timestamp,
+    /// The member's availability.
+    pub status: Status,
+    /// Application-defined capabilities the member is offering.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub capabilities: Vec<String>,
+    /// Application-defined data.
+    pub payload: T,
+}
+
+impl<T: Serialize> Beacon<T> {
+    /// The entry validator for beacons, given a validator for the payload.
+    pub fn validator(payload: Validator) -> Validator {
+        MapValidator::new()
+            .req_add("time", TimeValidator::new().build())
+            .req_add("status", StrValidator::new().build())
+            .opt_add(
+                "capabilities",
+                ArrayValidator::new()
+                    .items(StrValidator::new().build())
+                    .build(),
+            )
+            .req_add("payload", payload)
+            .build()
+    }
+
+    /// Stage publishing this beacon under `key` on the presence document, as
+    /// the member `signer`. The beacon expires `ttl` after its timestamp.
+    /// Members should delete their previous beacon, which
+    /// [`PresenceSet::publish`] does automatically.
+    pub async fn publish(
+        &self,
+        txn: &mut Transaction,
+        parent: &Document,
+        key: &str,
+        signer: &IdentityKey,
+        ttl: Duration,
+    ) -> DbResult<Result<EntryRef, EntryError>> {
+        let e_ref = match stage(txn, parent, key, self, Some(signer)).await? {
+            Ok(e_ref) => e_ref,
+            Err(e) => return Ok(Err(e)),
+        };
+        let ttl = i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX);
+        txn.set_ttl(&e_ref, Some(self.time + ttl));
+        Ok(Ok(e_ref))
+    }
+}
+
+/// A query returning every beacon under `key`. Beacons about to expire are
+/// skipped.
+pub fn presence_query(key: &str) -> DbQuery {
+    DbQuery {
+        query: NewQuery::new(key, Validator::Any),
+        rev_order: false,
+        ordering: None,
+        min_ttl: Some(Duration::from_secs(1)),
+        signer_policy: None,
+        sample: None,
+        include_history: false,
+        merge: None,
+        sources: None,
+        revalidate: false,
+        forward: None,
+    }
+}
+
+/// The most recent beacon seen from one member.
+#[derive(Clone, Debug)]
+struct Member<T> {
+    beacon: Beacon<T>,
+    expires: Option<Timestamp>,
+    entry: EntryRef,
+}
+
+/// The set of members currently present, built from their beacons.
+#[derive(Clone, Debug)]
+pub struct PresenceSet<T> {
+    members: HashMap<Identity, Member<T>>,
+}
+
+impl<T> Default for PresenceSet<T> {
+    fn default() -> Self {
+        Self {
+            members: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> PresenceSet<T> {
+    /// Create a new, empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold a beacon, as returned from a [`presence_query`], into the set.
+    /// Returns true if it changed the member's beacon.
+    pub fn apply(&mut self, result: &QueryResult) -> Result<bool, FogError> {
+        let Some(signer) = result.entry.signer() else {
+            return Ok(false);
+        };
+        let beacon: Beacon<T> = result.entry.deserialize()?;
+        if self
+            .members
+            .get(signer)
+            .is_some_and(|m| m.beacon.time >= beacon.time)
+        {
+            return Ok(false);
+        }
+        self.members.insert(
+            signer.to_owned(),
+            Member {
+                beacon,
+                expires: result.expires,
+                entry: result.entry.reference().to_owned(),
+            },
+        );
+        Ok(true)
+    }
+
+    /// Remove members whose beacons have expired as of `now`, or whose last
+    /// beacon said they were leaving.
+    pub fn prune(&mut self, now: Timestamp) {
+        self.members
+            .retain(|_, m| m.beacon.status != Status::Offline && m.expires.is_none_or(|e| e > now));
+    }
+
+    /// Get a member's most recent beacon.
+    pub fn get(&self, member: &Identity) -> Option<&Beacon<T>> {
+        self.members.get(member).map(|m| &m.beacon)
+    }
+
+    /// Iterate over every member and their most recent beacon. Call
+    /// [`prune`][Self::prune] first to leave out members that are gone.
+    pub fn iter(&self) -> impl Iterator<Item = (&Identity, &Beacon<T>)> {
+        self.members.iter().map(|(id, m)| (id, &m.beacon))
+    }
+
+    /// The number of members in the set.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Check if the set has no members.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Stage publishing a beacon as the member `signer`, deleting their
+    /// previous beacon if it's in the set.
+    pub async fn publish(
+        &mut self,
+        txn: &mut Transaction,
+        parent: &Document,
+        key: &str,
+        signer: &IdentityKey,
+        beacon: Beacon<T>,
+        ttl: Duration,
+    ) -> DbResult<Result<(), EntryError>> {
+        let e_ref = match beacon.publish(txn, parent, key, signer, ttl).await? {
+            Ok(e_ref) => e_ref,
+            Err(e) => return Ok(Err(e)),
+        };
+        let ttl = i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX);
+        let expires = Some(beacon.time + ttl);
+        let old = self.members.insert(
+            signer.id().to_owned(),
+            Member {
+                beacon,
+                expires,
+                entry: e_ref,
+            },
+        );
+        if let Some(old) = old {
+            txn.del_entry(&old.entry);
+        }
+        Ok(Ok(()))
+    }
+}
@@ -0,0 +1,67 @@
+//! Storage quotas for content held on behalf of a group.
+//!
+//! A node taking part in a public group will end up storing documents it
+//! didn't ask for: documents fetched by cursors opened through the group, and
+//! documents kept resident at the request of the group's members. None of
+//! these are reachable from the node's own roots, so they would normally be
+//! evicted right away, but keeping them around is what makes the node useful
+//! to the rest of the group. A [`StorageQuota`], set with
+//! [`Group::set_storage_quota`][crate::group::Group::set_storage_quota], caps
+//! how much of this content the group may hold and decides what gets evicted
+//! first once the cap is reached.
+//!
+//! Each group's content is accounted separately. A document held by more than
+//! one group counts against each of them, and a document reachable from a
+//! local root doesn't count against any group, nor is it ever evicted by one.
+
+use serde::{Deserialize, Serialize};
+
+/// The order in which a group's content is evicted once it exceeds its quota.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EvictionOrder {
+    /// Evict whatever was least recently read, locally or by a remote node.
+    #[default]
+    LeastRecentlyUsed,
+    /// Evict whatever was stored first.
+    OldestFirst,
+    /// Evict the largest documents first, keeping as many documents as
+    /// possible.
+    LargestFirst,
+}
+
+/// A limit on the storage used by a group's content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StorageQuota {
+    /// Maximum total encoded size of the group's documents, in bytes.
+    pub max_bytes: u64,
+    /// Maximum number of documents, if any.
+    pub max_docs: Option<u64>,
+    /// What to evict first once a limit is exceeded.
+    pub eviction: EvictionOrder,
+}
+
+impl StorageQuota {
+    /// A quota limiting only the total size of the group's documents, evicting
+    /// the least recently used ones first.
+    pub fn bytes(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            max_docs: None,
+            eviction: EvictionOrder::default(),
+        }
+    }
+
+    /// Check if the given usage is within this quota.
+    pub fn allows(&self, usage: &QuotaUsage) -> bool {
+        usage.bytes <= self.max_bytes && self.max_docs.is_none_or(|max| usage.docs <= max)
+    }
+}
+
+/// Storage currently used by a group's content.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    /// Number of documents held.
+    pub docs: u64,
+    /// Total encoded size of the documents held, in bytes.
+    pub bytes: u64,
+}
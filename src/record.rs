@@ -0,0 +1,2825 @@
+//! Recording calls on a database, and replaying them later.
+//!
+//! Bugs involving remote nodes are hard to reproduce: by the time someone
+//! looks at a report, the network that caused it has moved on. A
+//! [`RecordingDb`] passes every call through to the database it wraps, and
+//! writes each call and its response to a [`RecordSink`]. The same goes for
+//! the transactions, cursors, queries, and groups opened through it. A
+//! [`ReplayDb`] built from the recorded log then answers the same calls with
+//! the same responses, without any database or network behind it.
+//!
+//! The log is a sequence of frames, each holding a single [`Record`] encoded
+//! as a fog-pack document. Documents and entries are stored in their database
+//! encoding, so decoding them needs their schemas; the log starts with every
+//! schema in the database when recording began, and picks up schemas added
+//! through the recorder afterwards. Schemas are written out by reading their
+//! documents with [`Db::doc_get`], so schemas that can't be read that way
+//! aren't recorded, and neither are calls returning documents that use them.
+//! [`RecordingDb::dropped`] counts calls that couldn't be recorded.
+//!
+//! Replay matches each call against the earliest unused record for the same
+//! call: the same document, name, or cursor. Cursors, forks, and queries are
+//! given identifiers when recorded, and replayed ones take on the identifier
+//! of the record they matched, so calls made through them line up even if the
+//! application opens things in a slightly different order. Calls with no
+//! matching record fail with a [`NotRecorded`] error, or with
+//! [`CursorError::Unavailable`] for cursor operations.
+//!
+//! Recorded calls are document, schema, and name lookups and changes on the
+//! database, commits, everything done through cursors and queries except
+//! chunked fetches, and every call on groups. Pin requests, schema requests,
+//! summary exchanges, barriers, and warmups made on a group are recorded once
+//! they complete. Gates opened on a group are recorded, but calls on the
+//! gates themselves aren't, and replayed gates never have anything attached.
+//! Everything else is passed through unrecorded, and a [`ReplayDb`] gives
+//! inert answers for it: empty registries and default policies. Group calls
+//! with no matching record get the same kind of answers, with requests
+//! refused.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fog_pack::{
+    document::{Document, NewDocument},
+    entry::{Entry, EntryRef, NewEntry},
+    error::Error as FogError,
+    query::NewQuery,
+    schema::{NoSchema, Schema},
+    types::*,
+};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    access::{self, DocInfo},
+    anomaly::AnomalyDetector,
+    availability::{
+        AvailabilitySummary, SummaryError, SummaryExchange, SummaryReply,
+        DEFAULT_FALSE_POSITIVE_RATE,
+    },
+    backpressure::{self, Unlimited},
+    barrier::{Barrier, BarrierError, BarrierReport},
+    capabilities, changes,
+    changes::CommitSeq,
+    complexity::{ComplexityError, QueryRejected},
+    compression,
+    coordinator::PreparedCommit,
+    cursor::{
+        ChunkStream, ChunkUpdate, CountEstimate, Cursor, CursorBackError, CursorError, CursorOpts,
+        CursorQuery, DbQuery, ForkCursor, ForkSpawner, LinkStrength, MergeStrategy, NewCursor,
+        Provenance, QueryResult, QueryUpdate, Refresh, RelayHop, TraceId, UsefulReport, Usefulness,
+    },
+    discovery::{self, DiscoveryRegistry},
+    eviction::{self, EvictionRegistry},
+    expiry,
+    fetch::{self, PriorityScheduler, SchedulerConfig},
+    fingerprint,
+    forward::{ForwardStop, ForwardStopped},
+    gate::{Gate, GateEvent, GateEvents, GateSettings, QueryHook, Tier},
+    gc, group,
+    group::Group,
+    health, import, journal, mixnet,
+    names::{self, NameError, NameInfo, NamingPolicy},
+    pinning::{HostedPin, PinError, PinGrant, PinPolicy, PinRequest},
+    quota::{QuotaUsage, StorageQuota},
+    remote::{self, WireDoc},
+    resources::{ResourceFilter, Resources},
+    retention, runtime,
+    schema_fetch::{SchemaFetchError, SchemaRequest},
+    service::{ServiceDescriptor, ServiceFilter},
+    skew::{self, SkewPolicy},
+    stats,
+    transaction::{
+        ChangeSet, CommitError, CommitErrors, CommitReceipt, DocChange, Durability, EntryChange,
+        EntryError, NameChange, SchemaError, Transaction,
+    },
+    transport::{self, TransportRegistry},
+    validate,
+    warm::{WarmError, Warmed, Warmup},
+    weak_refs,
+    wire::{WireDbError, WireEntryRef},
+    Db, DbCommit, DbError, DbResult, GroupSpec, NodeAddr, NodeInfo,
+};
+
+/// The error held by [`DbError::Internal`] when a replayed call has no
+/// matching record.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("No recorded response for {0}")]
+pub struct NotRecorded(pub &'static str);
+
+/// Where recorded frames are written. Each frame is a single fog-pack document
+/// holding a [`Record`].
+pub trait RecordSink: Send + Sync {
+    /// Write a frame to the log.
+    fn record(&self, frame: Bytes);
+}
+
+/// A log kept in memory. Cloning gives another handle to the same log.
+#[derive(Clone, Default)]
+pub struct MemoryLog {
+    frames: Arc<Mutex<Vec<Bytes>>>,
+}
+
+impl MemoryLog {
+    /// Create an empty log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get every frame written so far.
+    pub fn frames(&self) -> Vec<Bytes> {
+        self.frames.lock().unwrap().clone()
+    }
+}
+
+impl RecordSink for MemoryLog {
+    fn record(&self, frame: Bytes) {
+        self.frames.lock().unwrap().push(frame);
+    }
+}
+
+/// An entry in its database encoding. Its parent document is the one the
+/// query it was returned by was made on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WireEntry {
+    pub key: String,
+    pub hash: Hash,
+    pub data: Bytes,
+}
+
+/// One frame in a recorded log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Record {
+    /// A schema document, needed to decode the documents and entries that use
+    /// it.
+    Schema(Bytes),
+    /// A call and its response.
+    Call(Box<Call>),
+}
+
+/// A recorded call, along with its response.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Call {
+    CurrentSeq(CommitSeq),
+    DocGet {
+        doc: Hash,
+        result: WireResult<Option<WireDoc>>,
+    },
+    DocInfo {
+        doc: Hash,
+        result: WireResult<Option<DocInfo>>,
+    },
+    SchemaGet {
+        schema: Hash,
+        result: WireResult<bool>,
+    },
+    SchemaAdd {
+        schema: Hash,
+        result: WireResult<bool>,
+    },
+    NameGet {
+        name: String,
+        result: WireResult<Option<Hash>>,
+    },
+    NameAdd {
+        name: String,
+        hash: Hash,
+        result: WireResult<Result<Option<Hash>, NameError>>,
+    },
+    NameDel {
+        hash: Hash,
+        result: WireResult<Option<Hash>>,
+    },
+    NameList {
+        prefix: Option<String>,
+        result: WireResult<Vec<(String, Hash)>>,
+    },
+    NameInfo {
+        name: String,
+        result: WireResult<Option<NameInfo>>,
+    },
+    /// A transaction commit. The changes themselves aren't recorded.
+    Commit {
+        result: WireResult<Result<CommitReceipt, Vec<CommitError>>>,
+    },
+    CommitMany {
+        result: WireResult<Vec<Result<CommitReceipt, Vec<CommitError>>>>,
+    },
+    /// A cursor opened on the database.
+    Cursor {
+        cursor: u64,
+        doc: Hash,
+        result: WireResult<Option<WireDoc>>,
+    },
+    /// A cursor opened by forking another cursor, or on a group's gate if
+    /// `parent` is `None`.
+    Fork {
+        parent: Option<u64>,
+        cursor: u64,
+        hash: Hash,
+        local: bool,
+        result: Result<Option<WireDoc>, CursorError>,
+    },
+    Forward {
+        cursor: u64,
+        hash: Hash,
+        local: bool,
+        result: Result<Option<WireDoc>, CursorError>,
+    },
+    /// A query made with a cursor, or directly on the database if `db` is
+    /// set. Queries made on the database are given a cursor identifier for
+    /// the cursor they can be backed out into.
+    Query {
+        db: bool,
+        doc: Hash,
+        cursor: u64,
+        query: u64,
+        parent: Option<WireDoc>,
+    },
+    QueryUpdate {
+        query: u64,
+        update: RecordedUpdate,
+    },
+    /// A group opened on the database.
+    OpenGroup {
+        group: u64,
+    },
+    /// A call made on a group.
+    Group {
+        group: u64,
+        call: GroupCall,
+    },
+}
+
+/// A recorded call on a [`Group`], along with its response. Calls whose
+/// response arrives later are recorded once it does.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum GroupCall {
+    /// A gate opened on the group. Calls on the gate itself aren't recorded.
+    Gate {
+        gate: Hash,
+        settings: Option<GateSettings>,
+        opened: bool,
+    },
+    SetStorageQuota(Option<StorageQuota>),
+    StorageQuota(Option<StorageQuota>),
+    StorageUsage(QuotaUsage),
+    PinRequest {
+        node: NodeAddr,
+        root: Hash,
+        ttl: Duration,
+        result: Result<PinGrant, PinError>,
+    },
+    /// A pin policy was set. The policy itself isn't recorded.
+    SetPinPolicy,
+    HostedPins(Vec<HostedPin>),
+    SetSkewPolicy(Option<SkewPolicy>),
+    SkewPolicy(SkewPolicy),
+    FindSchema {
+        schema: Hash,
+        result: Result<WireDoc, SchemaFetchError>,
+    },
+    AdvertiseResources(Option<Resources>),
+    MembersByResource {
+        filter: ResourceFilter,
+        result: Vec<(NodeInfo, Resources)>,
+    },
+    /// Services were advertised, given by the hashes of their descriptors.
+    AdvertiseServices(Vec<Hash>),
+    Services {
+        filter: ServiceFilter,
+        result: Vec<(NodeAddr, ServiceDescriptor)>,
+    },
+    Summary {
+        root: Hash,
+        result: AvailabilitySummary,
+    },
+    ExchangeSummary {
+        node: NodeAddr,
+        root: Hash,
+        result: Result<SummaryReply, SummaryError>,
+    },
+    Barrier {
+        root: Hash,
+        min_peers: usize,
+        result: Result<BarrierReport, BarrierError>,
+    },
+    Warm {
+        nodes: Vec<NodeAddr>,
+        result: Vec<Warmed>,
+    },
+}
+
+/// A recorded [`QueryResult`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedResult {
+    pub entry: WireEntry,
+    pub docs: Vec<WireDoc>,
+    pub source: NodeInfo,
+    pub expires: Option<Timestamp>,
+    pub deleted: Option<Timestamp>,
+    pub stale: bool,
+    pub provenance: Option<Provenance>,
+    pub relays: Vec<RelayHop>,
+}
+
+/// A recorded [`QueryUpdate`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecordedUpdate {
+    Result(Box<RecordedResult>),
+    NewConnection(NodeInfo),
+    LostConnection(NodeInfo),
+    Count {
+        count: u64,
+        exact: bool,
+        source: NodeInfo,
+    },
+    Rejected {
+        source: NodeInfo,
+        reason: ComplexityError,
+    },
+    Refreshed(Vec<WireEntryRef>),
+    NotForwarded {
+        source: NodeInfo,
+        reason: ForwardStop,
+    },
+}
+
+/// Recorded results, with the error boxed to keep calls small.
+type WireResult<T> = Result<T, Box<WireDbError>>;
+
+fn not_recorded(call: &'static str) -> Box<DbError> {
+    Box::new(DbError::Internal(Box::new(NotRecorded(call))))
+}
+
+fn wire<T, U>(result: &DbResult<T>, f: impl FnOnce(&T) -> U) -> WireResult<U> {
+    match result {
+        Ok(v) => Ok(f(v)),
+        Err(e) => Err(Box::new(WireDbError::from(e.as_ref()))),
+    }
+}
+
+fn from_wire<T>(result: WireResult<T>) -> DbResult<T> {
+    result.map_err(|e| Box::new(DbError::from(*e)))
+}
+
+struct Recorder {
+    sink: Box<dyn RecordSink>,
+    next_id: AtomicU64,
+    dropped: AtomicU64,
+    schemas: Mutex<HashMap<Hash, Arc<Schema>>>,
+}
+
+impl Recorder {
+    fn id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn write(&self, record: &Record) {
+        match remote::encode(record) {
+            Ok(frame) => self.sink.record(frame),
+            Err(_) => self.drop_call(),
+        }
+    }
+
+    fn call(&self, call: Call) {
+        self.write(&Record::Call(Box::new(call)));
+    }
+
+    /// Note a call that couldn't be recorded.
+    fn drop_call(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn add_schema(&self, doc: &Document) {
+        let Ok(schema) = Schema::from_doc(doc) else {
+            return;
+        };
+        let Ok((_, data)) = NoSchema::encode_doc(doc.clone()) else {
+            return;
+        };
+        let hash = doc.hash().clone();
+        let new = self
+            .schemas
+            .lock()
+            .unwrap()
+            .insert(hash, Arc::new(schema))
+            .is_none();
+        if new {
+            self.write(&Record::Schema(Bytes::from(data)));
+        }
+    }
+
+    fn doc(&self, doc: &Document) -> Option<WireDoc> {
+        let (_, data) = match doc.schema_hash() {
+            Some(hash) => {
+                let schema = self.schemas.lock().unwrap().get(hash).cloned()?;
+                schema.encode_doc(doc.clone()).ok()?
+            }
+            None => NoSchema::encode_doc(doc.clone()).ok()?,
+        };
+        Some(WireDoc {
+            schema: doc.schema_hash().cloned(),
+            data: Bytes::from(data),
+        })
+    }
+
+    fn entry(&self, entry: &Entry) -> Option<WireEntry> {
+        let schema = self
+            .schemas
+            .lock()
+            .unwrap()
+            .get(entry.schema_hash())
+            .cloned()?;
+        let (_, data, _) = schema.encode_entry(entry.clone()).ok()?;
+        Some(WireEntry {
+            key: entry.key().to_owned(),
+            hash: entry.hash().clone(),
+            data: Bytes::from(data),
+        })
+    }
+
+    /// Encode a looked-up document, returning `None` if this couldn't be done.
+    fn lookup<E: Clone>(
+        &self,
+        result: &Result<Option<Arc<Document>>, E>,
+    ) -> Option<Result<Option<WireDoc>, E>> {
+        match result {
+            Ok(Some(doc)) => self.doc(doc).map(|d| Ok(Some(d))),
+            Ok(None) => Some(Ok(None)),
+            Err(e) => Some(Err(e.clone())),
+        }
+    }
+
+    fn update(&self, update: &QueryUpdate) -> Option<RecordedUpdate> {
+        Some(match update {
+            QueryUpdate::Result(r) => RecordedUpdate::Result(Box::new(RecordedResult {
+                entry: self.entry(&r.entry)?,
+                docs: r
+                    .docs
+                    .iter()
+                    .map(|d| self.doc(d))
+                    .collect::<Option<Vec<_>>>()?,
+                source: r.source.clone(),
+                expires: r.expires,
+                deleted: r.deleted,
+                stale: r.stale,
+                provenance: r.provenance.clone(),
+                relays: r.relays.clone(),
+            })),
+            QueryUpdate::NewConnection(node) => RecordedUpdate::NewConnection(node.clone()),
+            QueryUpdate::LostConnection(node) => RecordedUpdate::LostConnection(node.clone()),
+            QueryUpdate::Count(c) => RecordedUpdate::Count {
+                count: c.count,
+                exact: c.exact,
+                source: c.source.clone(),
+            },
+            QueryUpdate::Rejected(r) => RecordedUpdate::Rejected {
+                source: r.source.clone(),
+                reason: r.reason.clone(),
+            },
+            QueryUpdate::Refreshed(r) => {
+                RecordedUpdate::Refreshed(r.unconfirmed.iter().map(WireEntryRef::from).collect())
+            }
+            QueryUpdate::NotForwarded(f) => RecordedUpdate::NotForwarded {
+                source: f.source.clone(),
+                reason: f.reason.clone(),
+            },
+        })
+    }
+}
+
+/// A [`Db`] that records calls made on it, and on everything opened through
+/// it.
+pub struct RecordingDb<D> {
+    inner: D,
+    rec: Arc<Recorder>,
+}
+
+impl<D: Db> RecordingDb<D> {
+    /// Start recording calls on a database. Every schema currently in the
+    /// database is written to the log first.
+    pub async fn new(inner: D, sink: Box<dyn RecordSink>) -> Self {
+        let rec = Arc::new(Recorder {
+            sink,
+            next_id: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            schemas: Mutex::new(HashMap::new()),
+        });
+        for schema in inner.schema_list().await.unwrap_or_default() {
+            if let Ok(Some(doc)) = inner.doc_get(&schema).await {
+                rec.add_schema(&doc);
+            }
+        }
+        Self { inner, rec }
+    }
+
+    /// Get the wrapped database.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Stop recording and unwrap the database.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Get the number of calls that couldn't be recorded so far.
+    pub fn dropped(&self) -> u64 {
+        self.rec.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl<D: Db> Db for RecordingDb<D> {
+    fn txn(&self) -> Transaction {
+        let rec = self.rec.clone();
+        self.inner
+            .txn()
+            .wrap_commit(|inner| Box::new(RecordingCommit { inner, rec }))
+    }
+
+    fn commit_permit(&self) -> Box<dyn backpressure::PermitRequest> {
+        self.inner.commit_permit()
+    }
+
+    fn bulk_import(&self) -> Box<dyn import::BulkImport> {
+        self.inner.bulk_import()
+    }
+
+    async fn current_seq(&self) -> DbResult<CommitSeq> {
+        let seq = self.inner.current_seq().await?;
+        self.rec.call(Call::CurrentSeq(seq));
+        Ok(seq)
+    }
+
+    async fn changes_since(
+        &self,
+        seq: CommitSeq,
+    ) -> DbResult<Result<Box<dyn changes::ChangeFeed>, changes::SeqTooOld>> {
+        self.inner.changes_since(seq).await
+    }
+
+    async fn entry_watch(&self, doc: &Hash, key: &str) -> DbResult<Box<dyn changes::EntryWatch>> {
+        self.inner.entry_watch(doc, key).await
+    }
+
+    fn group(&self, spec: GroupSpec) -> Box<dyn group::Group> {
+        let group = self.rec.id();
+        self.rec.call(Call::OpenGroup { group });
+        Box::new(RecordingGroup {
+            inner: self.inner.group(spec),
+            rec: GroupRecorder {
+                rec: self.rec.clone(),
+                group,
+            },
+        })
+    }
+
+    fn transports(&self) -> &transport::TransportRegistry {
+        self.inner.transports()
+    }
+
+    fn discovery(&self) -> &discovery::DiscoveryRegistry {
+        self.inner.discovery()
+    }
+
+    fn runtime(&self) -> &dyn runtime::NodeRuntime {
+        self.inner.runtime()
+    }
+
+    fn mixnet(&self) -> Option<&dyn mixnet::Mixnet> {
+        self.inner.mixnet()
+    }
+
+    fn journal(&self) -> Option<&dyn journal::Journal> {
+        self.inner.journal()
+    }
+
+    fn eviction_policies(&self) -> &eviction::EvictionRegistry {
+        self.inner.eviction_policies()
+    }
+
+    async fn gc_preview(&self, change: gc::ProposedChange) -> DbResult<gc::GcPreview> {
+        self.inner.gc_preview(change).await
+    }
+
+    async fn capabilities(&self) -> DbResult<capabilities::DbCapabilities> {
+        self.inner.capabilities().await
+    }
+
+    async fn skew_policy(&self) -> DbResult<skew::SkewPolicy> {
+        self.inner.skew_policy().await
+    }
+
+    async fn set_skew_policy(&self, policy: skew::SkewPolicy) -> DbResult<()> {
+        self.inner.set_skew_policy(policy).await
+    }
+
+    async fn health(&self) -> DbResult<health::Health> {
+        self.inner.health().await
+    }
+
+    fn health_events(&self) -> Box<dyn health::HealthEvents> {
+        self.inner.health_events()
+    }
+
+    async fn ttl_sweep(&self, budget: expiry::SweepBudget) -> DbResult<expiry::SweepReport> {
+        self.inner.ttl_sweep(budget).await
+    }
+
+    fn retention_events(&self) -> Box<dyn retention::RetentionEvents> {
+        self.inner.retention_events()
+    }
+
+    fn fetch_scheduler(&self) -> Arc<dyn fetch::FetchScheduler> {
+        self.inner.fetch_scheduler()
+    }
+
+    fn validator(&self) -> Arc<dyn validate::Validator> {
+        self.inner.validator()
+    }
+
+    async fn cursor(&self, doc: &Hash, opts: CursorOpts) -> DbResult<Option<NewCursor>> {
+        let result = self.inner.cursor(doc, opts).await;
+        let id = self.rec.id();
+        let found = wire(&result, |c| c.as_ref().map(|(_, doc)| doc.clone()));
+        match self.rec.lookup(&found) {
+            Some(result) => self.rec.call(Call::Cursor {
+                cursor: id,
+                doc: doc.clone(),
+                result,
+            }),
+            None => self.rec.drop_call(),
+        }
+        Ok(result?.map(|(cursor, doc)| {
+            let cursor: Box<dyn Cursor> = Box::new(RecordingCursor {
+                inner: cursor,
+                rec: self.rec.clone(),
+                id,
+            });
+            (cursor, doc)
+        }))
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        let result = self.inner.doc_get(doc).await;
+        match self.rec.lookup(&wire(&result, Clone::clone)) {
+            Some(result) => self.rec.call(Call::DocGet {
+                doc: doc.clone(),
+                result,
+            }),
+            None => self.rec.drop_call(),
+        }
+        result
+    }
+
+    async fn doc_info(&self, doc: &Hash) -> DbResult<Option<access::DocInfo>> {
+        let result = self.inner.doc_info(doc).await;
+        self.rec.call(Call::DocInfo {
+            doc: doc.clone(),
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn tree_stats(&self, root: &Hash) -> DbResult<Option<stats::TreeStats>> {
+        self.inner.tree_stats(root).await
+    }
+
+    async fn tree_fingerprint(
+        &self,
+        root: &Hash,
+    ) -> DbResult<Option<fingerprint::TreeFingerprint>> {
+        self.inner.tree_fingerprint(root).await
+    }
+
+    fn query(&self, doc: &Hash, query: DbQuery) -> Box<dyn CursorQuery> {
+        let cursor = self.rec.id();
+        let id = self.rec.id();
+        // Queries on the database are opened synchronously, so the parent
+        // document is only recorded if the database has it ready without
+        // waiting. Results of a query recorded without it are replayed as
+        // lost connections.
+        let parent = self.inner.doc_get(doc).now_or_never().and_then(|r| r.ok()).flatten();
+        self.rec.call(Call::Query {
+            db: true,
+            doc: doc.clone(),
+            cursor,
+            query: id,
+            parent: parent.as_deref().and_then(|d| self.rec.doc(d)),
+        });
+        Box::new(RecordingQuery {
+            inner: self.inner.query(doc, query),
+            rec: self.rec.clone(),
+            cursor,
+            id,
+        })
+    }
+
+    async fn entry_count(&self, doc: &Hash, key: &str, query: Option<&NewQuery>) -> DbResult<u64> {
+        self.inner.entry_count(doc, key, query).await
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        let result = self.inner.schema_get(schema).await;
+        self.rec.call(Call::SchemaGet {
+            schema: schema.clone(),
+            result: wire(&result, Option::is_some),
+        });
+        result
+    }
+
+    async fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+        let result = self.inner.schema_add(schema.clone()).await;
+        if let Ok(Ok(_)) = &result {
+            self.rec.add_schema(&schema);
+        }
+        self.rec.call(Call::SchemaAdd {
+            schema: schema.hash().clone(),
+            result: wire(&result, Result::is_ok),
+        });
+        result
+    }
+
+    async fn schema_del(&self, schema: &Hash) -> DbResult<bool> {
+        self.inner.schema_del(schema).await
+    }
+
+    async fn schema_list(&self) -> DbResult<Vec<Hash>> {
+        self.inner.schema_list().await
+    }
+
+    async fn schema_set_compression(
+        &self,
+        schema: &Hash,
+        policy: compression::CompressionPolicy,
+    ) -> DbResult<bool> {
+        self.inner.schema_set_compression(schema, policy).await
+    }
+
+    async fn schema_get_compression(
+        &self,
+        schema: &Hash,
+    ) -> DbResult<Option<compression::CompressionPolicy>> {
+        self.inner.schema_get_compression(schema).await
+    }
+
+    async fn schema_set_weak_refs(
+        &self,
+        schema: &Hash,
+        defaults: weak_refs::WeakRefDefaults,
+    ) -> DbResult<bool> {
+        self.inner.schema_set_weak_refs(schema, defaults).await
+    }
+
+    async fn schema_get_weak_refs(&self, schema: &Hash) -> DbResult<Option<weak_refs::WeakRefDefaults>> {
+        self.inner.schema_get_weak_refs(schema).await
+    }
+
+    async fn schema_set_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+        policy: retention::RetentionPolicy,
+    ) -> DbResult<bool> {
+        self.inner.schema_set_retention(schema, key, policy).await
+    }
+
+    async fn schema_get_retention(
+        &self,
+        schema: &Hash,
+        key: &str,
+    ) -> DbResult<Option<retention::RetentionPolicy>> {
+        self.inner.schema_get_retention(schema, key).await
+    }
+
+    async fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+        let result = self.inner.name_get(name).await;
+        self.rec.call(Call::NameGet {
+            name: name.to_owned(),
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Result<Option<Hash>, NameError>> {
+        let result = self.inner.name_add(name, hash).await;
+        self.rec.call(Call::NameAdd {
+            name: name.to_owned(),
+            hash: hash.clone(),
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn name_add_reserved(
+        &self,
+        name: &str,
+        hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, NameError>> {
+        self.inner.name_add_reserved(name, hash).await
+    }
+
+    async fn naming_policy(&self) -> DbResult<names::NamingPolicy> {
+        self.inner.naming_policy().await
+    }
+
+    async fn set_naming_policy(&self, policy: names::NamingPolicy) -> DbResult<()> {
+        self.inner.set_naming_policy(policy).await
+    }
+
+    async fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>> {
+        let result = self.inner.name_del(schema).await;
+        self.rec.call(Call::NameDel {
+            hash: schema.clone(),
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn name_list(&self) -> DbResult<Vec<(String, Hash)>> {
+        let result = self.inner.name_list().await;
+        self.rec.call(Call::NameList {
+            prefix: None,
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn name_list_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Hash)>> {
+        let result = self.inner.name_list_prefix(prefix).await;
+        self.rec.call(Call::NameList {
+            prefix: Some(prefix.to_owned()),
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn name_info(&self, name: &str) -> DbResult<Option<names::NameInfo>> {
+        let result = self.inner.name_info(name).await;
+        self.rec.call(Call::NameInfo {
+            name: name.to_owned(),
+            result: wire(&result, Clone::clone),
+        });
+        result
+    }
+
+    async fn name_set_meta(&self, name: &str, meta: names::NameMeta) -> DbResult<bool> {
+        self.inner.name_set_meta(name, meta).await
+    }
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+fn commit_result(
+    result: &DbResult<Result<CommitReceipt, CommitErrors>>,
+) -> WireResult<Result<CommitReceipt, Vec<CommitError>>> {
+    match result {
+        Ok(Ok(receipt)) => Ok(Ok(receipt.clone())),
+        Ok(Err(errs)) => Ok(Err(errs.errors.clone())),
+        Err(e) => Err(Box::new(WireDbError::from(e.as_ref()))),
+    }
+}
+
+struct RecordingCommit {
+    inner: Box<dyn DbCommit>,
+    rec: Arc<Recorder>,
+}
+
+// As with fault injection, the wrapped connection isn't `Send`, so the inner
+// commit is started before building the future that awaits it.
+#[async_trait]
+impl DbCommit for RecordingCommit {
+    fn commit<'async_trait>(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        durability: Durability,
+    ) -> BoxFuture<'async_trait, DbResult<Result<CommitReceipt, CommitErrors>>>
+    where
+        Self: 'async_trait,
+    {
+        let rec = self.rec;
+        let commit = self.inner.commit(docs, entries, names, durability);
+        Box::pin(async move {
+            let result = commit.await;
+            rec.call(Call::Commit {
+                result: commit_result(&result),
+            });
+            result
+        })
+    }
+
+    fn commit_many<'async_trait>(
+        self: Box<Self>,
+        changes: Vec<ChangeSet>,
+        durability: Durability,
+    ) -> BoxFuture<'async_trait, DbResult<Vec<Result<CommitReceipt, CommitErrors>>>>
+    where
+        Self: 'async_trait,
+    {
+        let rec = self.rec;
+        let commit = self.inner.commit_many(changes, durability);
+        Box::pin(async move {
+            let result = commit.await;
+            let recorded = match &result {
+                Ok(results) => Ok(results
+                    .iter()
+                    .map(|r| match r {
+                        Ok(receipt) => Ok(receipt.clone()),
+                        Err(errs) => Err(errs.errors.clone()),
+                    })
+                    .collect()),
+                Err(e) => Err(Box::new(WireDbError::from(e.as_ref()))),
+            };
+            rec.call(Call::CommitMany { result: recorded });
+            result
+        })
+    }
+
+    fn prepare<'async_trait>(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        durability: Durability,
+    ) -> BoxFuture<'async_trait, DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>>>
+    where
+        Self: 'async_trait,
+    {
+        self.inner.prepare(docs, entries, names, durability)
+    }
+
+    fn schema_get<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        schema: &'life1 Hash,
+    ) -> BoxFuture<'async_trait, DbResult<Option<Arc<Schema>>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.schema_get(schema)
+    }
+
+    fn doc_get<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        doc: &'life1 Hash,
+    ) -> BoxFuture<'async_trait, DbResult<Option<Arc<Document>>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.doc_get(doc)
+    }
+
+    fn schema_weak_refs<'life0, 'life1, 'async_trait>(
+        &'life0 self,
+        schema: &'life1 Hash,
+    ) -> BoxFuture<'async_trait, DbResult<Option<weak_refs::WeakRefDefaults>>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        self.inner.schema_weak_refs(schema)
+    }
+
+    fn validator(&self) -> Option<Arc<dyn validate::Validator>> {
+        self.inner.validator()
+    }
+
+    fn db_id(&self) -> usize {
+        self.inner.db_id()
+    }
+}
+
+/// Records calls made on one group.
+#[derive(Clone)]
+struct GroupRecorder {
+    rec: Arc<Recorder>,
+    group: u64,
+}
+
+impl GroupRecorder {
+    fn call(&self, call: GroupCall) {
+        self.rec.call(Call::Group {
+            group: self.group,
+            call,
+        })
+    }
+}
+
+struct RecordingGroup {
+    inner: Box<dyn Group>,
+    rec: GroupRecorder,
+}
+
+impl Group for RecordingGroup {
+    fn gate(&self, gate: &Hash, settings: Option<GateSettings>) -> Option<Box<dyn Gate>> {
+        let result = self.inner.gate(gate, settings.clone());
+        self.rec.call(GroupCall::Gate {
+            gate: gate.clone(),
+            settings,
+            opened: result.is_some(),
+        });
+        result
+    }
+
+    fn cursor(&self, gate: &Hash, opts: CursorOpts) -> Box<dyn ForkCursor> {
+        Box::new(RecordingFork {
+            inner: self.inner.cursor(gate, opts),
+            rec: self.rec.rec.clone(),
+            parent: None,
+            hash: gate.clone(),
+        })
+    }
+
+    fn set_storage_quota(&self, quota: Option<StorageQuota>) {
+        self.inner.set_storage_quota(quota);
+        self.rec.call(GroupCall::SetStorageQuota(quota));
+    }
+
+    fn storage_quota(&self) -> Option<StorageQuota> {
+        let result = self.inner.storage_quota();
+        self.rec.call(GroupCall::StorageQuota(result));
+        result
+    }
+
+    fn storage_usage(&self) -> QuotaUsage {
+        let result = self.inner.storage_usage();
+        self.rec.call(GroupCall::StorageUsage(result));
+        result
+    }
+
+    fn pin_request(
+        &self,
+        node: &crate::NodeAddr,
+        root: &Hash,
+        ttl: Duration,
+    ) -> Box<dyn PinRequest> {
+        Box::new(RecordingPin {
+            inner: self.inner.pin_request(node, root, ttl),
+            rec: self.rec.clone(),
+            node: node.clone(),
+            root: root.clone(),
+            ttl,
+        })
+    }
+
+    fn set_pin_policy(&self, policy: Box<dyn PinPolicy>) {
+        self.inner.set_pin_policy(policy);
+        self.rec.call(GroupCall::SetPinPolicy);
+    }
+
+    fn hosted_pins(&self) -> Vec<HostedPin> {
+        let result = self.inner.hosted_pins();
+        self.rec.call(GroupCall::HostedPins(result.clone()));
+        result
+    }
+
+    fn set_skew_policy(&self, policy: Option<SkewPolicy>) {
+        self.inner.set_skew_policy(policy);
+        self.rec.call(GroupCall::SetSkewPolicy(policy));
+    }
+
+    fn skew_policy(&self) -> SkewPolicy {
+        let result = self.inner.skew_policy();
+        self.rec.call(GroupCall::SkewPolicy(result));
+        result
+    }
+
+    fn find_schema(&self, schema: &Hash) -> Box<dyn SchemaRequest> {
+        Box::new(RecordingSchemaRequest {
+            inner: self.inner.find_schema(schema),
+            rec: self.rec.clone(),
+            schema: schema.clone(),
+        })
+    }
+
+    fn advertise_resources(&self, resources: Option<Resources>) {
+        self.inner.advertise_resources(resources);
+        self.rec.call(GroupCall::AdvertiseResources(resources));
+    }
+
+    fn members_by_resource(&self, filter: &ResourceFilter) -> Vec<(NodeInfo, Resources)> {
+        let result = self.inner.members_by_resource(filter);
+        self.rec.call(GroupCall::MembersByResource {
+            filter: *filter,
+            result: result.clone(),
+        });
+        result
+    }
+
+    fn advertise_services(&self, services: Vec<Arc<Document>>) {
+        let hashes = services.iter().map(|s| s.hash().clone()).collect();
+        self.inner.advertise_services(services);
+        self.rec.call(GroupCall::AdvertiseServices(hashes));
+    }
+
+    fn services(&self, filter: ServiceFilter) -> Vec<(NodeAddr, ServiceDescriptor)> {
+        let result = self.inner.services(filter.clone());
+        self.rec.call(GroupCall::Services {
+            filter,
+            result: result.clone(),
+        });
+        result
+    }
+
+    fn summary(&self, root: &Hash) -> AvailabilitySummary {
+        let result = self.inner.summary(root);
+        self.rec.call(GroupCall::Summary {
+            root: root.clone(),
+            result: result.clone(),
+        });
+        result
+    }
+
+    fn exchange_summary(
+        &self,
+        node: &crate::NodeAddr,
+        have: AvailabilitySummary,
+    ) -> Box<dyn SummaryExchange> {
+        let root = have.root.clone();
+        Box::new(RecordingSummaryExchange {
+            inner: self.inner.exchange_summary(node, have),
+            rec: self.rec.clone(),
+            node: node.clone(),
+            root,
+        })
+    }
+
+    fn barrier(&self, root: &Hash, min_peers: usize, timeout: Duration) -> Box<dyn Barrier> {
+        Box::new(RecordingBarrier {
+            inner: self.inner.barrier(root, min_peers, timeout),
+            rec: self.rec.clone(),
+            root: root.clone(),
+            min_peers,
+        })
+    }
+
+    fn warm(&self, nodes: &[NodeAddr]) -> Box<dyn Warmup> {
+        Box::new(RecordingWarmup {
+            inner: self.inner.warm(nodes),
+            rec: self.rec.clone(),
+            nodes: nodes.to_vec(),
+        })
+    }
+}
+
+struct RecordingPin {
+    inner: Box<dyn PinRequest>,
+    rec: GroupRecorder,
+    node: NodeAddr,
+    root: Hash,
+    ttl: Duration,
+}
+
+#[async_trait]
+impl PinRequest for RecordingPin {
+    async fn complete(self: Box<Self>) -> Result<PinGrant, PinError> {
+        let result = self.inner.complete().await;
+        self.rec.call(GroupCall::PinRequest {
+            node: self.node,
+            root: self.root,
+            ttl: self.ttl,
+            result: result.clone(),
+        });
+        result
+    }
+}
+
+struct RecordingSchemaRequest {
+    inner: Box<dyn SchemaRequest>,
+    rec: GroupRecorder,
+    schema: Hash,
+}
+
+#[async_trait]
+impl SchemaRequest for RecordingSchemaRequest {
+    async fn complete(self: Box<Self>) -> Result<Document, SchemaFetchError> {
+        let result = self.inner.complete().await;
+        let recorded = match &result {
+            Ok(doc) => self.rec.rec.doc(doc).map(Ok),
+            Err(e) => Some(Err(e.clone())),
+        };
+        match recorded {
+            Some(result) => self.rec.call(GroupCall::FindSchema {
+                schema: self.schema,
+                result,
+            }),
+            None => self.rec.rec.drop_call(),
+        }
+        result
+    }
+}
+
+struct RecordingSummaryExchange {
+    inner: Box<dyn SummaryExchange>,
+    rec: GroupRecorder,
+    node: NodeAddr,
+    root: Hash,
+}
+
+#[async_trait]
+impl SummaryExchange for RecordingSummaryExchange {
+    async fn complete(self: Box<Self>) -> Result<SummaryReply, SummaryError> {
+        let result = self.inner.complete().await;
+        self.rec.call(GroupCall::ExchangeSummary {
+            node: self.node,
+            root: self.root,
+            result: result.clone(),
+        });
+        result
+    }
+}
+
+struct RecordingBarrier {
+    inner: Box<dyn Barrier>,
+    rec: GroupRecorder,
+    root: Hash,
+    min_peers: usize,
+}
+
+#[async_trait]
+impl Barrier for RecordingBarrier {
+    async fn complete(self: Box<Self>) -> Result<BarrierReport, BarrierError> {
+        let result = self.inner.complete().await;
+        self.rec.call(GroupCall::Barrier {
+            root: self.root,
+            min_peers: self.min_peers,
+            result: result.clone(),
+        });
+        result
+    }
+}
+
+struct RecordingWarmup {
+    inner: Box<dyn Warmup>,
+    rec: GroupRecorder,
+    nodes: Vec<NodeAddr>,
+}
+
+#[async_trait]
+impl Warmup for RecordingWarmup {
+    async fn complete(self: Box<Self>) -> Vec<Warmed> {
+        let result = self.inner.complete().await;
+        self.rec.call(GroupCall::Warm {
+            nodes: self.nodes,
+            result: result.clone(),
+        });
+        result
+    }
+}
+
+struct RecordingFork {
+    inner: Box<dyn ForkCursor>,
+    rec: Arc<Recorder>,
+    parent: Option<u64>,
+    hash: Hash,
+}
+
+/// Record a cursor being opened by a fork, returning its identifier.
+fn record_fork(
+    rec: &Recorder,
+    parent: Option<u64>,
+    hash: &Hash,
+    local: bool,
+    result: &Result<Option<NewCursor>, CursorError>,
+) -> u64 {
+    let id = rec.id();
+    let found = result
+        .as_ref()
+        .map(|c| c.as_ref().map(|(_, doc)| doc.clone()))
+        .map_err(Clone::clone);
+    match rec.lookup(&found) {
+        Some(result) => rec.call(Call::Fork {
+            parent,
+            cursor: id,
+            hash: hash.clone(),
+            local,
+            result,
+        }),
+        None => rec.drop_call(),
+    }
+    id
+}
+
+fn wrap_cursor(rec: &Arc<Recorder>, id: u64, cursor: NewCursor) -> NewCursor {
+    let (inner, doc) = cursor;
+    let cursor = RecordingCursor {
+        inner,
+        rec: rec.clone(),
+        id,
+    };
+    (Box::new(cursor), doc)
+}
+
+#[async_trait]
+impl ForkCursor for RecordingFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        let result = self.inner.complete().await.map(Some);
+        let id = record_fork(&self.rec, self.parent, &self.hash, false, &result);
+        Ok(wrap_cursor(&self.rec, id, result?.unwrap()))
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        let result = self.inner.complete_local();
+        let id = record_fork(&self.rec, self.parent, &self.hash, true, &result);
+        Ok(result?.map(|c| wrap_cursor(&self.rec, id, c)))
+    }
+}
+
+struct RecordingCursor {
+    inner: Box<dyn Cursor>,
+    rec: Arc<Recorder>,
+    id: u64,
+}
+
+impl RecordingCursor {
+    fn record_forward(
+        &self,
+        hash: &Hash,
+        local: bool,
+        result: &Result<Option<Arc<Document>>, CursorError>,
+    ) {
+        match self.rec.lookup(result) {
+            Some(result) => self.rec.call(Call::Forward {
+                cursor: self.id,
+                hash: hash.clone(),
+                local,
+                result,
+            }),
+            None => self.rec.drop_call(),
+        }
+    }
+}
+
+#[async_trait]
+impl Cursor for RecordingCursor {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        let result = self.inner.forward(hash).await;
+        self.record_forward(hash, false, &result.clone().map(Some));
+        result
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        let result = self.inner.forward_local(hash);
+        self.record_forward(hash, true, &result);
+        result
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        self.inner.back()
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        Box::new(RecordingFork {
+            inner: self.inner.fork(hash),
+            rec: self.rec.clone(),
+            parent: Some(self.id),
+            hash: hash.clone(),
+        })
+    }
+
+    fn fork_local(&self, hash: &Hash) -> Result<Option<NewCursor>, CursorError> {
+        let result = self.inner.fork_local(hash);
+        let id = record_fork(&self.rec, Some(self.id), hash, true, &result);
+        Ok(result?.map(|c| wrap_cursor(&self.rec, id, c)))
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.inner.current()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        self.inner.links()
+    }
+
+    fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery> {
+        let id = self.rec.id();
+        let current = self.inner.current();
+        self.rec.call(Call::Query {
+            db: false,
+            doc: current.hash().clone(),
+            cursor: self.id,
+            query: id,
+            parent: self.rec.doc(&current),
+        });
+        Box::new(RecordingQuery {
+            inner: self.inner.query(query),
+            rec: self.rec,
+            cursor: self.id,
+            id,
+        })
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream> {
+        self.inner.fetch_chunked(hash, offset)
+    }
+
+    fn cache_current(&self, ttl: Duration) -> DbResult<()> {
+        self.inner.cache_current(ttl)
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.inner.trace_id()
+    }
+}
+
+struct RecordingQuery {
+    inner: Box<dyn CursorQuery>,
+    rec: Arc<Recorder>,
+    cursor: u64,
+    id: u64,
+}
+
+impl RecordingQuery {
+    fn record(&self, update: &QueryUpdate) {
+        match self.rec.update(update) {
+            Some(update) => self.rec.call(Call::QueryUpdate {
+                query: self.id,
+                update,
+            }),
+            None => self.rec.drop_call(),
+        }
+    }
+}
+
+#[async_trait]
+impl CursorQuery for RecordingQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        Box::new(RecordingCursor {
+            inner: self.inner.back(),
+            rec: self.rec,
+            id: self.cursor,
+        })
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        let update = self.inner.next().await;
+        self.record(&update);
+        update
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        let update = self.inner.try_next()?;
+        self.record(&update);
+        Some(update)
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        self.inner.merge_strategy()
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.inner.trace_id()
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.inner.cursor_trace_id()
+    }
+}
+
+struct ReplayState {
+    schemas: HashMap<Hash, Arc<Schema>>,
+    calls: Mutex<VecDeque<Call>>,
+}
+
+/// A recorded log, loaded for replay. Cloning gives another handle to the same
+/// log, and records used through any handle are used up for all of them.
+#[derive(Clone)]
+pub struct ReplayLog {
+    inner: Arc<ReplayState>,
+}
+
+impl ReplayLog {
+    /// Load a log from its frames, in the order they were recorded.
+    pub fn new<I, B>(frames: I) -> Result<Self, FogError>
+    where
+        I: IntoIterator<Item = B>,
+        B: AsRef<[u8]>,
+    {
+        let mut schemas = HashMap::new();
+        let mut calls = VecDeque::new();
+        for frame in frames {
+            match remote::decode::<Record>(frame.as_ref())? {
+                Record::Schema(data) => {
+                    let doc = NoSchema::decode_doc(data.to_vec())?;
+                    let schema = Schema::from_doc(&doc)?;
+                    schemas.insert(doc.hash().clone(), Arc::new(schema));
+                }
+                Record::Call(call) => calls.push_back(*call),
+            }
+        }
+        Ok(Self {
+            inner: Arc::new(ReplayState {
+                schemas,
+                calls: Mutex::new(calls),
+            }),
+        })
+    }
+
+    /// Get the number of recorded calls that haven't been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.inner.calls.lock().unwrap().len()
+    }
+
+    /// Remove and return the earliest record matching `f`.
+    fn take(&self, f: impl FnMut(&Call) -> bool) -> Option<Call> {
+        let mut calls = self.inner.calls.lock().unwrap();
+        let pos = calls.iter().position(f)?;
+        calls.remove(pos)
+    }
+
+    fn doc(&self, wire: &WireDoc) -> Option<Arc<Document>> {
+        let doc = match &wire.schema {
+            Some(schema) => self
+                .inner
+                .schemas
+                .get(schema)?
+                .decode_doc(wire.data.to_vec())
+                .ok()?,
+            None => NoSchema::decode_doc(wire.data.to_vec()).ok()?,
+        };
+        Some(Arc::new(doc))
+    }
+
+    fn entry(&self, parent: &Document, wire: &WireEntry) -> Option<Entry> {
+        let schema = self.inner.schemas.get(parent.schema_hash()?)?;
+        schema
+            .trusted_decode_entry(wire.data.to_vec(), &wire.key, parent, &wire.hash)
+            .ok()
+    }
+
+    /// Decode a recorded document lookup. A document that can't be decoded is
+    /// treated as unavailable.
+    fn lookup<E>(
+        &self,
+        result: Result<Option<WireDoc>, E>,
+        unavailable: impl FnOnce() -> E,
+    ) -> Result<Option<Arc<Document>>, E> {
+        match result? {
+            Some(wire) => self.doc(&wire).map(Some).ok_or_else(unavailable),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A [`Db`] that answers calls from a recorded log.
+pub struct ReplayDb {
+    log: ReplayLog,
+    transports: TransportRegistry,
+    discovery: DiscoveryRegistry,
+    eviction: EvictionRegistry,
+    fetch: Arc<dyn fetch::FetchScheduler>,
+    skew: Mutex<SkewPolicy>,
+    naming: Mutex<NamingPolicy>,
+}
+
+impl ReplayDb {
+    /// Create a database that replays a log.
+    pub fn new(log: ReplayLog) -> Self {
+        Self {
+            log,
+            transports: TransportRegistry::new(),
+            discovery: DiscoveryRegistry::new(),
+            eviction: EvictionRegistry::new(),
+            fetch: Arc::new(PriorityScheduler::new(SchedulerConfig::default())),
+            skew: Mutex::new(SkewPolicy::default()),
+            naming: Mutex::new(NamingPolicy::default()),
+        }
+    }
+
+    /// Get the log being replayed.
+    pub fn log(&self) -> &ReplayLog {
+        &self.log
+    }
+}
+
+#[async_trait]
+impl Db for ReplayDb {
+    fn txn(&self) -> Transaction {
+        Transaction::new(Box::new(ReplayCommit {
+            log: self.log.clone(),
+        }))
+    }
+
+    fn commit_permit(&self) -> Box<dyn backpressure::PermitRequest> {
+        Box::new(Unlimited)
+    }
+
+    fn bulk_import(&self) -> Box<dyn import::BulkImport> {
+        Box::new(ReplayImport)
+    }
+
+    async fn current_seq(&self) -> DbResult<CommitSeq> {
+        match self.log.take(|c| matches!(c, Call::CurrentSeq(_))) {
+            Some(Call::CurrentSeq(seq)) => Ok(seq),
+            _ => Err(not_recorded("current_seq")),
+        }
+    }
+
+    async fn changes_since(
+        &self,
+        _seq: CommitSeq,
+    ) -> DbResult<Result<Box<dyn changes::ChangeFeed>, changes::SeqTooOld>> {
+        Err(not_recorded("changes_since"))
+    }
+
+    async fn entry_watch(&self, _doc: &Hash, _key: &str) -> DbResult<Box<dyn changes::EntryWatch>> {
+        Err(not_recorded("entry_watch"))
+    }
+
+    fn group(&self, _spec: GroupSpec) -> Box<dyn group::Group> {
+        let group = match self.log.take(|c| matches!(c, Call::OpenGroup { .. })) {
+            Some(Call::OpenGroup { group }) => Some(group),
+            _ => None,
+        };
+        Box::new(ReplayGroup {
+            log: GroupLog {
+                log: self.log.clone(),
+                group,
+            },
+        })
+    }
+
+    fn transports(&self) -> &transport::TransportRegistry {
+        &self.transports
+    }
+
+    fn discovery(&self) -> &discovery::DiscoveryRegistry {
+        &self.discovery
+    }
+
+    fn runtime(&self) -> &dyn runtime::NodeRuntime {
+        &ReplayRuntime
+    }
+
+    fn mixnet(&self) -> Option<&dyn mixnet::Mixnet> {
+        None
+    }
+
+    fn journal(&self) -> Option<&dyn journal::Journal> {
+        None
+    }
+
+    fn eviction_policies(&self) -> &eviction::EvictionRegistry {
+        &self.eviction
+    }
+
+    async fn gc_preview(&self, _change: gc::ProposedChange) -> DbResult<gc::GcPreview> {
+        Err(not_recorded("gc_preview"))
+    }
+
+    async fn capabilities(&self) -> DbResult<capabilities::DbCapabilities> {
+        Ok(capabilities::DbCapabilities::default())
+    }
+
+    async fn skew_policy(&self) -> DbResult<skew::SkewPolicy> {
+        Ok(*self.skew.lock().unwrap())
+    }
+
+    async fn set_skew_policy(&self, policy: skew::SkewPolicy) -> DbResult<()> {
+        *self.skew.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    async fn health(&self) -> DbResult<health::Health> {
+        Ok(health::Health::default())
+    }
+
+    fn health_events(&self) -> Box<dyn health::HealthEvents> {
+        Box::new(ReplayHealth)
+    }
+
+    async fn ttl_sweep(&self, _budget: expiry::SweepBudget) -> DbResult<expiry::SweepReport> {
+        Err(not_recorded("ttl_sweep"))
+    }
+
+    fn retention_events(&self) -> Box<dyn retention::RetentionEvents> {
+        Box::new(ReplayRetention)
+    }
+
+    fn fetch_scheduler(&self) -> Arc<dyn fetch::FetchScheduler> {
+        self.fetch.clone()
+    }
+
+    fn validator(&self) -> Arc<dyn validate::Validator> {
+        Arc::new(ReplayValidator {
+            log: self.log.clone(),
+        })
+    }
+
+    async fn cursor(&self, doc: &Hash, _opts: CursorOpts) -> DbResult<Option<NewCursor>> {
+        let Some(Call::Cursor { cursor, result, .. }) = self
+            .log
+            .take(|c| matches!(c, Call::Cursor { doc: d, .. } if d == doc))
+        else {
+            return Err(not_recorded("cursor"));
+        };
+        let found = self
+            .log
+            .lookup(from_wire(result), || not_recorded("cursor"))?;
+        Ok(found.map(|doc| replay_cursor(&self.log, cursor, doc)))
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        let Some(Call::DocGet { result, .. }) = self
+            .log
+            .take(|c| matches!(c, Call::DocGet { doc: d, .. } if d == doc))
+        else {
+            return Err(not_recorded("doc_get"));
+        };
+        self.log
+            .lookup(from_wire(result), || not_recorded("doc_get"))
+    }
+
+    async fn doc_info(&self, doc: &Hash) -> DbResult<Option<access::DocInfo>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::DocInfo { doc: d, .. } if d == doc))
+        {
+            Some(Call::DocInfo { result, .. }) => from_wire(result),
+            _ => Err(not_recorded("doc_info")),
+        }
+    }
+
+    async fn tree_stats(&self, _root: &Hash) -> DbResult<Option<stats::TreeStats>> {
+        Err(not_recorded("tree_stats"))
+    }
+
+    async fn tree_fingerprint(
+        &self,
+        _root: &Hash,
+    ) -> DbResult<Option<fingerprint::TreeFingerprint>> {
+        Err(not_recorded("tree_fingerprint"))
+    }
+
+    fn query(&self, doc: &Hash, _query: DbQuery) -> Box<dyn CursorQuery> {
+        let call = self
+            .log
+            .take(|c| matches!(c, Call::Query { db: true, doc: d, .. } if d == doc));
+        Box::new(ReplayQuery::new(&self.log, call))
+    }
+
+    async fn entry_count(&self, _doc: &Hash, _key: &str, _query: Option<&NewQuery>) -> DbResult<u64> {
+        Err(not_recorded("entry_count"))
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::SchemaGet { schema: s, .. } if s == schema))
+        {
+            Some(Call::SchemaGet { result, .. }) => {
+                if !from_wire(result)? {
+                    return Ok(None);
+                }
+                match self.log.inner.schemas.get(schema) {
+                    Some(schema) => Ok(Some(schema.clone())),
+                    None => Err(not_recorded("schema_get")),
+                }
+            }
+            _ => Err(not_recorded("schema_get")),
+        }
+    }
+
+    async fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+        let hash = schema.hash();
+        match self
+            .log
+            .take(|c| matches!(c, Call::SchemaAdd { schema: s, .. } if s == hash))
+        {
+            Some(Call::SchemaAdd { result, .. }) => {
+                // A schema that was rejected when recorded is rejected again
+                // for whatever reason it fails now.
+                let added = from_wire(result)?;
+                let parsed = Schema::from_doc(&schema).map(Arc::new);
+                Ok(match parsed {
+                    Ok(schema) if added => Ok(schema),
+                    Ok(_) => Err(FogError::FailValidate(
+                        "schema was rejected when recorded".into(),
+                    )),
+                    Err(e) => Err(e),
+                })
+            }
+            _ => Err(not_recorded("schema_add")),
+        }
+    }
+
+    async fn schema_del(&self, _schema: &Hash) -> DbResult<bool> {
+        Err(not_recorded("schema_del"))
+    }
+
+    async fn schema_list(&self) -> DbResult<Vec<Hash>> {
+        Ok(self.log.inner.schemas.keys().cloned().collect())
+    }
+
+    async fn schema_set_compression(
+        &self,
+        _schema: &Hash,
+        _policy: compression::CompressionPolicy,
+    ) -> DbResult<bool> {
+        Err(not_recorded("schema_set_compression"))
+    }
+
+    async fn schema_get_compression(
+        &self,
+        _schema: &Hash,
+    ) -> DbResult<Option<compression::CompressionPolicy>> {
+        Err(not_recorded("schema_get_compression"))
+    }
+
+    async fn schema_set_weak_refs(
+        &self,
+        _schema: &Hash,
+        _defaults: weak_refs::WeakRefDefaults,
+    ) -> DbResult<bool> {
+        Err(not_recorded("schema_set_weak_refs"))
+    }
+
+    async fn schema_get_weak_refs(&self, _schema: &Hash) -> DbResult<Option<weak_refs::WeakRefDefaults>> {
+        Err(not_recorded("schema_get_weak_refs"))
+    }
+
+    async fn schema_set_retention(
+        &self,
+        _schema: &Hash,
+        _key: &str,
+        _policy: retention::RetentionPolicy,
+    ) -> DbResult<bool> {
+        Err(not_recorded("schema_set_retention"))
+    }
+
+    async fn schema_get_retention(
+        &self,
+        _schema: &Hash,
+        _key: &str,
+    ) -> DbResult<Option<retention::RetentionPolicy>> {
+        Err(not_recorded("schema_get_retention"))
+    }
+
+    async fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::NameGet { name: n, .. } if n == name))
+        {
+            Some(Call::NameGet { result, .. }) => from_wire(result),
+            _ => Err(not_recorded("name_get")),
+        }
+    }
+
+    async fn name_add(&self, name: &str, hash: &Hash) -> DbResult<Result<Option<Hash>, NameError>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::NameAdd { name: n, hash: h, .. } if n == name && h == hash))
+        {
+            Some(Call::NameAdd { result, .. }) => from_wire(result),
+            _ => Err(not_recorded("name_add")),
+        }
+    }
+
+    async fn name_add_reserved(
+        &self,
+        _name: &str,
+        _hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, NameError>> {
+        Err(not_recorded("name_add_reserved"))
+    }
+
+    async fn naming_policy(&self) -> DbResult<names::NamingPolicy> {
+        Ok(self.naming.lock().unwrap().clone())
+    }
+
+    async fn set_naming_policy(&self, policy: names::NamingPolicy) -> DbResult<()> {
+        *self.naming.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    async fn name_del(&self, schema: &Hash) -> DbResult<Option<Hash>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::NameDel { hash: h, .. } if h == schema))
+        {
+            Some(Call::NameDel { result, .. }) => from_wire(result),
+            _ => Err(not_recorded("name_del")),
+        }
+    }
+
+    async fn name_list(&self) -> DbResult<Vec<(String, Hash)>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::NameList { prefix: None, .. }))
+        {
+            Some(Call::NameList { result, .. }) => from_wire(result),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    async fn name_list_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Hash)>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::NameList { prefix: Some(p), .. } if p == prefix))
+        {
+            Some(Call::NameList { result, .. }) => from_wire(result),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    async fn name_info(&self, name: &str) -> DbResult<Option<names::NameInfo>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::NameInfo { name: n, .. } if n == name))
+        {
+            Some(Call::NameInfo { result, .. }) => from_wire(result),
+            _ => Err(not_recorded("name_info")),
+        }
+    }
+
+    async fn name_set_meta(&self, _name: &str, _meta: names::NameMeta) -> DbResult<bool> {
+        Err(not_recorded("name_set_meta"))
+    }
+}
+
+struct ReplayCommit {
+    log: ReplayLog,
+}
+
+/// Hand a set of changes back with the errors they failed with.
+fn commit_errors(changes: ChangeSet, errors: Vec<CommitError>) -> CommitErrors {
+    let (docs, entries, names) = changes;
+    CommitErrors {
+        docs,
+        entries,
+        names,
+        errors,
+    }
+}
+
+#[async_trait]
+impl DbCommit for ReplayCommit {
+    async fn commit(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        _durability: Durability,
+    ) -> DbResult<Result<CommitReceipt, CommitErrors>> {
+        let Some(Call::Commit { result }) = self.log.take(|c| matches!(c, Call::Commit { .. }))
+        else {
+            return Err(not_recorded("commit"));
+        };
+        Ok(from_wire(result)?.map_err(|errs| commit_errors((docs, entries, names), errs)))
+    }
+
+    async fn commit_many(
+        self: Box<Self>,
+        changes: Vec<ChangeSet>,
+        _durability: Durability,
+    ) -> DbResult<Vec<Result<CommitReceipt, CommitErrors>>> {
+        let Some(Call::CommitMany { result }) =
+            self.log.take(|c| matches!(c, Call::CommitMany { .. }))
+        else {
+            return Err(not_recorded("commit_many"));
+        };
+        let results = from_wire(result)?;
+        if results.len() != changes.len() {
+            return Err(not_recorded("commit_many"));
+        }
+        let mut merged = Vec::with_capacity(changes.len());
+        for (result, changes) in results.into_iter().zip(changes) {
+            merged.push(result.map_err(|errs| commit_errors(changes, errs)));
+        }
+        Ok(merged)
+    }
+
+    async fn prepare(
+        self: Box<Self>,
+        _docs: HashMap<Hash, DocChange>,
+        _entries: HashMap<EntryRef, EntryChange>,
+        _names: HashMap<String, NameChange>,
+        _durability: Durability,
+    ) -> DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>> {
+        Err(not_recorded("prepare"))
+    }
+
+    // Transactions look up schemas and documents to validate what's added to
+    // them. Schemas come from the log; documents that were looked up while
+    // recording are handed out again in the same order.
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        Ok(self.log.inner.schemas.get(schema).cloned())
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        match self
+            .log
+            .take(|c| matches!(c, Call::DocGet { doc: d, .. } if d == doc))
+        {
+            Some(Call::DocGet { result, .. }) => self
+                .log
+                .lookup(from_wire(result), || not_recorded("doc_get")),
+            _ => Ok(None),
+        }
+    }
+
+    async fn schema_weak_refs(&self, _schema: &Hash) -> DbResult<Option<weak_refs::WeakRefDefaults>> {
+        Ok(None)
+    }
+
+    fn db_id(&self) -> usize {
+        Arc::as_ptr(&self.log.inner) as usize
+    }
+}
+
+struct ReplayImport;
+
+#[async_trait]
+impl import::BulkImport for ReplayImport {
+    async fn add_doc(&mut self, _doc: Arc<Document>) -> DbResult<()> {
+        Err(not_recorded("bulk_import"))
+    }
+
+    async fn add_entry(&mut self, _entry: Entry) -> DbResult<()> {
+        Err(not_recorded("bulk_import"))
+    }
+
+    fn set_name(&mut self, _name: &str, _target: &Hash) {}
+
+    fn progress(&self) -> import::ImportProgress {
+        import::ImportProgress::default()
+    }
+
+    async fn finish(
+        self: Box<Self>,
+        _durability: Durability,
+    ) -> DbResult<Result<CommitSeq, Vec<CommitError>>> {
+        Err(not_recorded("bulk_import"))
+    }
+}
+
+struct ReplayRuntime;
+
+impl runtime::NodeRuntime for ReplayRuntime {
+    fn set_limits(&self, _limits: runtime::NodeLimits) {}
+
+    fn limits(&self) -> runtime::NodeLimits {
+        runtime::NodeLimits::default()
+    }
+
+    fn groups(&self) -> Vec<runtime::GroupSummary> {
+        Vec::new()
+    }
+
+    fn connections(&self) -> Vec<runtime::Connection> {
+        Vec::new()
+    }
+
+    fn disconnect(&self, _node: &crate::NodeAddr) -> bool {
+        false
+    }
+
+    fn events(&self) -> Box<dyn runtime::NodeEvents> {
+        Box::new(ReplayNodeEvents)
+    }
+}
+
+struct ReplayNodeEvents;
+
+#[async_trait]
+impl runtime::NodeEvents for ReplayNodeEvents {
+    async fn next(&self) -> runtime::NodeEvent {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<runtime::NodeEvent> {
+        None
+    }
+}
+
+// Documents can be validated with the schemas in the log, but validating
+// entries needs the documents they link to, and those lookups aren't recorded.
+struct ReplayValidator {
+    log: ReplayLog,
+}
+
+#[async_trait]
+impl validate::Validator for ReplayValidator {
+    fn schema(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        Ok(self.log.inner.schemas.get(schema).cloned())
+    }
+
+    async fn validate_docs(
+        &self,
+        docs: Vec<NewDocument>,
+    ) -> DbResult<Vec<Result<Document, SchemaError>>> {
+        Ok(docs
+            .into_iter()
+            .map(|doc| match doc.schema_hash() {
+                Some(hash) => match self.log.inner.schemas.get(hash) {
+                    Some(schema) => Ok(schema.validate_new_doc(doc)?),
+                    None => Err(SchemaError::MissingSchema(hash.clone())),
+                },
+                None => Ok(NoSchema::validate_new_doc(doc)?),
+            })
+            .collect())
+    }
+
+    async fn validate_entries(
+        &self,
+        _entries: Vec<NewEntry>,
+        _docs: &HashMap<Hash, Arc<Document>>,
+    ) -> DbResult<Vec<Result<Entry, EntryError>>> {
+        Err(not_recorded("validate_entries"))
+    }
+
+    fn stats(&self) -> validate::ValidatorStats {
+        validate::ValidatorStats::default()
+    }
+}
+
+struct ReplayHealth;
+
+#[async_trait]
+impl health::HealthEvents for ReplayHealth {
+    async fn next(&self) -> health::Health {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<health::Health> {
+        None
+    }
+}
+
+struct ReplayRetention;
+
+#[async_trait]
+impl retention::RetentionEvents for ReplayRetention {
+    async fn next(&self) -> retention::RetentionEviction {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<retention::RetentionEviction> {
+        None
+    }
+}
+
+/// The calls recorded on one group. Groups opened on replay take on the
+/// identifier of the earliest unused recorded group, or have no recorded
+/// calls if there isn't one.
+#[derive(Clone)]
+struct GroupLog {
+    log: ReplayLog,
+    group: Option<u64>,
+}
+
+impl GroupLog {
+    /// Remove and return the earliest record of a call on this group matching
+    /// `f`.
+    fn take(&self, mut f: impl FnMut(&GroupCall) -> bool) -> Option<GroupCall> {
+        let group = self.group?;
+        let found = self
+            .log
+            .take(|c| matches!(c, Call::Group { group: g, call } if *g == group && f(call)))?;
+        match found {
+            Call::Group { call, .. } => Some(call),
+            _ => None,
+        }
+    }
+}
+
+struct ReplayGroup {
+    log: GroupLog,
+}
+
+impl Group for ReplayGroup {
+    fn gate(&self, gate: &Hash, _settings: Option<GateSettings>) -> Option<Box<dyn Gate>> {
+        match self
+            .log
+            .take(|c| matches!(c, GroupCall::Gate { gate: g, .. } if g == gate))
+        {
+            Some(GroupCall::Gate { opened: true, .. }) => Some(Box::new(ReplayGate)),
+            _ => None,
+        }
+    }
+
+    fn cursor(&self, gate: &Hash, _opts: CursorOpts) -> Box<dyn ForkCursor> {
+        Box::new(ReplayFork {
+            log: self.log.log.clone(),
+            parent: None,
+            hash: gate.clone(),
+        })
+    }
+
+    fn set_storage_quota(&self, _quota: Option<StorageQuota>) {
+        self.log
+            .take(|c| matches!(c, GroupCall::SetStorageQuota(_)));
+    }
+
+    fn storage_quota(&self) -> Option<StorageQuota> {
+        match self.log.take(|c| matches!(c, GroupCall::StorageQuota(_))) {
+            Some(GroupCall::StorageQuota(quota)) => quota,
+            _ => None,
+        }
+    }
+
+    fn storage_usage(&self) -> QuotaUsage {
+        match self.log.take(|c| matches!(c, GroupCall::StorageUsage(_))) {
+            Some(GroupCall::StorageUsage(usage)) => usage,
+            _ => QuotaUsage::default(),
+        }
+    }
+
+    fn pin_request(
+        &self,
+        node: &crate::NodeAddr,
+        root: &Hash,
+        _ttl: Duration,
+    ) -> Box<dyn PinRequest> {
+        Box::new(ReplayPin {
+            log: self.log.clone(),
+            node: node.clone(),
+            root: root.clone(),
+        })
+    }
+
+    fn set_pin_policy(&self, _policy: Box<dyn PinPolicy>) {
+        self.log.take(|c| matches!(c, GroupCall::SetPinPolicy));
+    }
+
+    fn hosted_pins(&self) -> Vec<HostedPin> {
+        match self.log.take(|c| matches!(c, GroupCall::HostedPins(_))) {
+            Some(GroupCall::HostedPins(pins)) => pins,
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_skew_policy(&self, _policy: Option<SkewPolicy>) {
+        self.log.take(|c| matches!(c, GroupCall::SetSkewPolicy(_)));
+    }
+
+    fn skew_policy(&self) -> SkewPolicy {
+        match self.log.take(|c| matches!(c, GroupCall::SkewPolicy(_))) {
+            Some(GroupCall::SkewPolicy(policy)) => policy,
+            _ => SkewPolicy::default(),
+        }
+    }
+
+    fn find_schema(&self, schema: &Hash) -> Box<dyn SchemaRequest> {
+        Box::new(ReplaySchemaRequest {
+            log: self.log.clone(),
+            schema: schema.clone(),
+        })
+    }
+
+    fn advertise_resources(&self, _resources: Option<Resources>) {
+        self.log
+            .take(|c| matches!(c, GroupCall::AdvertiseResources(_)));
+    }
+
+    fn members_by_resource(&self, filter: &ResourceFilter) -> Vec<(NodeInfo, Resources)> {
+        match self
+            .log
+            .take(|c| matches!(c, GroupCall::MembersByResource { filter: f, .. } if f == filter))
+        {
+            Some(GroupCall::MembersByResource { result, .. }) => result,
+            _ => Vec::new(),
+        }
+    }
+
+    fn advertise_services(&self, _services: Vec<Arc<Document>>) {
+        self.log
+            .take(|c| matches!(c, GroupCall::AdvertiseServices(_)));
+    }
+
+    fn services(&self, filter: ServiceFilter) -> Vec<(NodeAddr, ServiceDescriptor)> {
+        match self
+            .log
+            .take(|c| matches!(c, GroupCall::Services { filter: f, .. } if *f == filter))
+        {
+            Some(GroupCall::Services { result, .. }) => result,
+            _ => Vec::new(),
+        }
+    }
+
+    fn summary(&self, root: &Hash) -> AvailabilitySummary {
+        match self
+            .log
+            .take(|c| matches!(c, GroupCall::Summary { root: r, .. } if r == root))
+        {
+            Some(GroupCall::Summary { result, .. }) => result,
+            _ => AvailabilitySummary::new(root, [].iter(), false, DEFAULT_FALSE_POSITIVE_RATE),
+        }
+    }
+
+    fn exchange_summary(
+        &self,
+        node: &crate::NodeAddr,
+        have: AvailabilitySummary,
+    ) -> Box<dyn SummaryExchange> {
+        Box::new(ReplaySummaryExchange {
+            log: self.log.clone(),
+            node: node.clone(),
+            root: have.root,
+        })
+    }
+
+    fn barrier(&self, root: &Hash, min_peers: usize, _timeout: Duration) -> Box<dyn Barrier> {
+        Box::new(ReplayBarrier {
+            log: self.log.clone(),
+            root: root.clone(),
+            min_peers,
+        })
+    }
+
+    fn warm(&self, nodes: &[NodeAddr]) -> Box<dyn Warmup> {
+        Box::new(ReplayWarmup {
+            log: self.log.clone(),
+            nodes: nodes.to_vec(),
+        })
+    }
+}
+
+/// A replayed gate. Calls on gates aren't recorded, so it never has anything
+/// attached and never reports events.
+struct ReplayGate;
+
+impl Gate for ReplayGate {
+    fn attached(&self) -> Vec<(NodeInfo, u32)> {
+        Vec::new()
+    }
+
+    fn total_cursors(&self) -> u32 {
+        0
+    }
+
+    fn tier(&self, _node: &NodeInfo) -> Option<Tier> {
+        None
+    }
+
+    fn query_hook(&self, _doc: &Hash, _hook: Box<dyn QueryHook>) {}
+
+    fn events(&self) -> Box<dyn GateEvents> {
+        Box::new(ReplayGateEvents)
+    }
+
+    fn anomaly_detector(&self, _detector: Box<dyn AnomalyDetector>) {}
+
+    fn set_honeypots(&self, _docs: Vec<Hash>) {}
+
+    fn receipts(&self, _doc: &Hash, _key: &str, _enabled: bool) {}
+
+    fn forward_groups(&self, _groups: Vec<Arc<dyn Group>>) {}
+
+    fn close(self) {}
+}
+
+struct ReplayGateEvents;
+
+#[async_trait]
+impl GateEvents for ReplayGateEvents {
+    async fn next(&self) -> GateEvent {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<GateEvent> {
+        None
+    }
+}
+
+struct ReplayBarrier {
+    log: GroupLog,
+    root: Hash,
+    min_peers: usize,
+}
+
+#[async_trait]
+impl Barrier for ReplayBarrier {
+    async fn complete(self: Box<Self>) -> Result<BarrierReport, BarrierError> {
+        match self.log.take(|c| {
+            matches!(c, GroupCall::Barrier { root, min_peers, .. }
+                if *root == self.root && *min_peers == self.min_peers)
+        }) {
+            Some(GroupCall::Barrier { result, .. }) => result,
+            _ => Err(BarrierError::Refused("Barrier wasn't recorded".into())),
+        }
+    }
+}
+
+struct ReplayWarmup {
+    log: GroupLog,
+    nodes: Vec<NodeAddr>,
+}
+
+#[async_trait]
+impl Warmup for ReplayWarmup {
+    async fn complete(self: Box<Self>) -> Vec<Warmed> {
+        match self
+            .log
+            .take(|c| matches!(c, GroupCall::Warm { nodes, .. } if *nodes == self.nodes))
+        {
+            Some(GroupCall::Warm { result, .. }) => result,
+            _ => self
+                .nodes
+                .into_iter()
+                .map(|node| Warmed {
+                    node,
+                    result: Err(WarmError::Refused("Warmup wasn't recorded".into())),
+                })
+                .collect(),
+        }
+    }
+}
+
+struct ReplaySummaryExchange {
+    log: GroupLog,
+    node: NodeAddr,
+    root: Hash,
+}
+
+#[async_trait]
+impl SummaryExchange for ReplaySummaryExchange {
+    async fn complete(self: Box<Self>) -> Result<SummaryReply, SummaryError> {
+        match self.log.take(|c| {
+            matches!(c, GroupCall::ExchangeSummary { node, root, .. }
+                if *node == self.node && *root == self.root)
+        }) {
+            Some(GroupCall::ExchangeSummary { result, .. }) => result,
+            _ => Err(SummaryError::Refused(
+                "Summary exchange wasn't recorded".into(),
+            )),
+        }
+    }
+}
+
+struct ReplayPin {
+    log: GroupLog,
+    node: NodeAddr,
+    root: Hash,
+}
+
+#[async_trait]
+impl PinRequest for ReplayPin {
+    async fn complete(self: Box<Self>) -> Result<PinGrant, PinError> {
+        match self.log.take(|c| {
+            matches!(c, GroupCall::PinRequest { node, root, .. }
+                if *node == self.node && *root == self.root)
+        }) {
+            Some(GroupCall::PinRequest { result, .. }) => result,
+            _ => Err(PinError::Refused("Pin wasn't recorded".into())),
+        }
+    }
+}
+
+struct ReplaySchemaRequest {
+    log: GroupLog,
+    schema: Hash,
+}
+
+#[async_trait]
+impl SchemaRequest for ReplaySchemaRequest {
+    async fn complete(self: Box<Self>) -> Result<Document, SchemaFetchError> {
+        let not_found = || SchemaFetchError::NotFound(self.schema.clone());
+        match self
+            .log
+            .take(|c| matches!(c, GroupCall::FindSchema { schema, .. } if *schema == self.schema))
+        {
+            Some(GroupCall::FindSchema { result, .. }) => {
+                let wire = result?;
+                let doc = self.log.log.doc(&wire).ok_or_else(not_found)?;
+                Ok(Arc::unwrap_or_clone(doc))
+            }
+            _ => Err(not_found()),
+        }
+    }
+}
+
+fn replay_cursor(log: &ReplayLog, id: u64, doc: Arc<Document>) -> NewCursor {
+    let cursor = ReplayCursor {
+        log: log.clone(),
+        id,
+        stack: vec![doc.clone()],
+    };
+    (Box::new(cursor), doc)
+}
+
+struct ReplayFork {
+    log: ReplayLog,
+    parent: Option<u64>,
+    hash: Hash,
+}
+
+impl ReplayFork {
+    fn replay(&self, local: bool) -> Result<Option<NewCursor>, CursorError> {
+        let unavailable = || CursorError::Unavailable(self.hash.clone());
+        let Some(Call::Fork { cursor, result, .. }) = self.log.take(|c| {
+            matches!(c, Call::Fork { parent, hash, local: l, .. }
+                if *parent == self.parent && *hash == self.hash && *l == local)
+        }) else {
+            return Err(unavailable());
+        };
+        let found = self.log.lookup(result, unavailable)?;
+        Ok(found.map(|doc| replay_cursor(&self.log, cursor, doc)))
+    }
+}
+
+#[async_trait]
+impl ForkCursor for ReplayFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        self.replay(false)?
+            .ok_or_else(|| CursorError::Unavailable(self.hash.clone()))
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        self.replay(true)
+    }
+}
+
+struct ReplayCursor {
+    log: ReplayLog,
+    id: u64,
+    stack: Vec<Arc<Document>>,
+}
+
+impl ReplayCursor {
+    fn replay(&mut self, hash: &Hash, local: bool) -> Result<Option<Arc<Document>>, CursorError> {
+        let unavailable = || CursorError::Unavailable(hash.clone());
+        let Some(Call::Forward { result, .. }) = self.log.take(|c| {
+            matches!(c, Call::Forward { cursor, hash: h, local: l, .. }
+                if *cursor == self.id && h == hash && *l == local)
+        }) else {
+            return Err(unavailable());
+        };
+        let found = self.log.lookup(result, unavailable)?;
+        if let Some(doc) = &found {
+            self.stack.push(doc.clone());
+        }
+        Ok(found)
+    }
+}
+
+#[async_trait]
+impl Cursor for ReplayCursor {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        self.replay(hash, false)?
+            .ok_or_else(|| CursorError::Unavailable(hash.clone()))
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        self.replay(hash, true)
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+            Ok(())
+        } else {
+            Err(CursorBackError)
+        }
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        Box::new(ReplayFork {
+            log: self.log.clone(),
+            parent: Some(self.id),
+            hash: hash.clone(),
+        })
+    }
+
+    fn fork_local(&self, hash: &Hash) -> Result<Option<NewCursor>, CursorError> {
+        ReplayFork {
+            log: self.log.clone(),
+            parent: Some(self.id),
+            hash: hash.clone(),
+        }
+        .replay(true)
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.stack.last().unwrap().clone()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        self.current()
+            .find_hashes()
+            .into_iter()
+            .map(|h| (h, LinkStrength::Unknown))
+            .collect()
+    }
+
+    fn query(self: Box<Self>, _query: DbQuery) -> Box<dyn CursorQuery> {
+        let id = self.id;
+        let call = self
+            .log
+            .take(|c| matches!(c, Call::Query { db: false, cursor, .. } if *cursor == id));
+        let mut query = ReplayQuery::new(&self.log, call);
+        query.cursor = Some(*self);
+        Box::new(query)
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, _offset: u64) -> Box<dyn ChunkStream> {
+        Box::new(ReplayChunks(hash.clone()))
+    }
+
+    fn cache_current(&self, _ttl: Duration) -> DbResult<()> {
+        Ok(())
+    }
+
+    /// Replayed cursors go by the identifier they were recorded under.
+    fn trace_id(&self) -> TraceId {
+        TraceId(self.id)
+    }
+}
+
+struct ReplayChunks(Hash);
+
+#[async_trait]
+impl ChunkStream for ReplayChunks {
+    async fn next(&self) -> ChunkUpdate {
+        ChunkUpdate::Failed(CursorError::Unavailable(self.0.clone()))
+    }
+
+    fn try_next(&self) -> Option<ChunkUpdate> {
+        Some(ChunkUpdate::Failed(CursorError::Unavailable(
+            self.0.clone(),
+        )))
+    }
+}
+
+struct ReplayQuery {
+    log: ReplayLog,
+    /// The query's identifier, or `None` if the query wasn't recorded.
+    id: Option<u64>,
+    parent: Option<Arc<Document>>,
+    /// The cursor the query was made with. Queries made on the database are
+    /// backed out into a new cursor on the parent document instead.
+    cursor: Option<ReplayCursor>,
+    cursor_id: u64,
+}
+
+impl ReplayQuery {
+    fn new(log: &ReplayLog, call: Option<Call>) -> Self {
+        let (id, cursor_id, parent) = match call {
+            Some(Call::Query {
+                query,
+                cursor,
+                parent,
+                ..
+            }) => (Some(query), cursor, parent.and_then(|p| log.doc(&p))),
+            _ => (None, u64::MAX, None),
+        };
+        Self {
+            log: log.clone(),
+            id,
+            parent,
+            cursor: None,
+            cursor_id,
+        }
+    }
+
+    fn take_update(&self) -> Option<QueryUpdate> {
+        let id = self.id?;
+        let Some(Call::QueryUpdate { update, .. }) = self
+            .log
+            .take(|c| matches!(c, Call::QueryUpdate { query, .. } if *query == id))
+        else {
+            return None;
+        };
+        Some(match update {
+            RecordedUpdate::Result(result) => {
+                let RecordedResult {
+                    entry,
+                    docs,
+                    source,
+                    expires,
+                    deleted,
+                    stale,
+                    provenance,
+                    relays,
+                } = *result;
+                // An entry that can't be decoded is replayed as a lost
+                // connection to the node that sent it.
+                let Some(entry) = self.parent.as_ref().and_then(|p| self.log.entry(p, &entry))
+                else {
+                    return Some(QueryUpdate::LostConnection(source));
+                };
+                let docs = docs.iter().filter_map(|d| self.log.doc(d)).collect();
+                let fork_spawner = Box::new(ReplaySpawner(entry.parent().clone()));
+                QueryUpdate::Result(Box::new(QueryResult {
+                    entry,
+                    docs,
+                    source,
+                    expires,
+                    deleted,
+                    stale,
+                    provenance,
+                    relays,
+                    useful: Box::new(NoReport),
+                    fork_spawner,
+                }))
+            }
+            RecordedUpdate::NewConnection(node) => QueryUpdate::NewConnection(node),
+            RecordedUpdate::LostConnection(node) => QueryUpdate::LostConnection(node),
+            RecordedUpdate::Count {
+                count,
+                exact,
+                source,
+            } => QueryUpdate::Count(CountEstimate {
+                count,
+                exact,
+                source,
+            }),
+            RecordedUpdate::Rejected { source, reason } => {
+                QueryUpdate::Rejected(QueryRejected { source, reason })
+            }
+            RecordedUpdate::Refreshed(unconfirmed) => QueryUpdate::Refreshed(Refresh {
+                unconfirmed: unconfirmed.into_iter().map(EntryRef::from).collect(),
+            }),
+            RecordedUpdate::NotForwarded { source, reason } => {
+                QueryUpdate::NotForwarded(ForwardStopped { source, reason })
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl CursorQuery for ReplayQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        if let Some(cursor) = self.cursor {
+            return Box::new(cursor);
+        }
+        // There's always a parent document for a query made through a
+        // cursor, so this is a database query. If its document wasn't
+        // recorded, the cursor is left on an empty document.
+        let parent = match self.parent {
+            Some(parent) => parent,
+            None => Arc::new(
+                NoSchema::validate_new_doc(fog_pack::document::NewDocument::new(None, ()).unwrap())
+                    .unwrap(),
+            ),
+        };
+        replay_cursor(&self.log, self.cursor_id, parent).0
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        match self.take_update() {
+            Some(update) => update,
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        self.take_update()
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        MergeStrategy::Arrival
+    }
+
+    fn trace_id(&self) -> TraceId {
+        TraceId(self.id.unwrap_or(u64::MAX))
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        TraceId(self.cursor.as_ref().map_or(self.cursor_id, |c| c.id))
+    }
+}
+
+struct NoReport;
+
+impl UsefulReport for NoReport {
+    fn report(self: Box<Self>, _useful: Usefulness) {}
+}
+
+/// Forks are only replayed through cursors, so forks spawned from replayed
+/// query results are never available.
+struct ReplaySpawner(Hash);
+
+impl ForkSpawner for ReplaySpawner {
+    fn fork(&self) -> Box<dyn ForkCursor> {
+        Box::new(ReplayFork {
+            log: ReplayLog {
+                inner: Arc::new(ReplayState {
+                    schemas: HashMap::new(),
+                    calls: Mutex::new(VecDeque::new()),
+                }),
+            },
+            parent: None,
+            hash: self.0.clone(),
+        })
+    }
+}
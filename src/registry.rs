@@ -0,0 +1,208 @@
+//! A shared registry mapping schema names to schema hashes.
+//!
+//! Schemas are stored by hash, which is exact but not something an
+//! application can know ahead of time without hardcoding it. The
+//! [`SchemaRegistry`] keeps a single schema-less [`Registry`] document under
+//! [`REGISTRY_NAME`], mapping human-readable names and version numbers to the
+//! hash of the schema published under them. Because every application reads
+//! the same document under the same name, a schema published by one can be
+//! found by any other.
+//!
+//! Names should be chosen the same way as database names, prefixed with the
+//! publishing application, like `"example-app/message"`. Once a version of a
+//! name is published it can't be pointed at a different schema; publish a new
+//! version instead.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use fog_pack::{
+    document::{Document, NewDocument},
+    error::Error as FogError,
+    schema::Schema,
+    types::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    transaction::{CommitError, Durability},
+    Db, DbResult,
+};
+
+/// The name the registry document is kept under, under the reserved
+/// [`SCHEMA_PREFIX`][crate::names::SCHEMA_PREFIX].
+pub const REGISTRY_NAME: &str = "schema/registry";
+
+/// One published version of a schema.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    /// The version number.
+    pub version: u64,
+    /// The hash of the schema document.
+    pub hash: Hash,
+}
+
+/// The registry document, as stored under [`REGISTRY_NAME`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Registry {
+    /// Every published version of each schema name, in ascending version
+    /// order.
+    pub schemas: BTreeMap<String, Vec<RegistryEntry>>,
+}
+
+impl Registry {
+    /// Look up a specific version of a schema, or the latest version if no
+    /// version is given.
+    pub fn lookup(&self, name: &str, version: Option<u64>) -> Option<&RegistryEntry> {
+        let versions = self.schemas.get(name)?;
+        match version {
+            Some(version) => versions.iter().find(|e| e.version == version),
+            None => versions.last(),
+        }
+    }
+
+    /// Find the name and version a schema was published under.
+    pub fn find(&self, schema: &Hash) -> Option<(&str, u64)> {
+        self.schemas.iter().find_map(|(name, versions)| {
+            versions
+                .iter()
+                .find(|e| &e.hash == schema)
+                .map(|e| (name.as_str(), e.version))
+        })
+    }
+
+    /// Insert an entry, keeping versions in order. Fails with the existing
+    /// hash if the version was already published with a different schema.
+    fn insert(&mut self, name: &str, version: u64, hash: &Hash) -> Result<bool, Hash> {
+        let versions = self.schemas.entry(name.to_owned()).or_default();
+        match versions.binary_search_by_key(&version, |e| e.version) {
+            Ok(i) if &versions[i].hash == hash => Ok(false),
+            Ok(i) => Err(versions[i].hash.clone()),
+            Err(i) => {
+                versions.insert(
+                    i,
+                    RegistryEntry {
+                        version,
+                        hash: hash.clone(),
+                    },
+                );
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Failure to publish a schema to the registry.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum RegistryError {
+    /// The schema document wasn't a valid schema.
+    #[error("Invalid schema document")]
+    InvalidSchema(#[source] FogError),
+    /// The version was already published with a different schema.
+    #[error("Schema {name:?} version {version} is already published as {existing}")]
+    VersionTaken {
+        name: String,
+        version: u64,
+        existing: Hash,
+    },
+    /// The commit updating the registry failed. This includes the registry
+    /// being updated by someone else at the same time, in which case
+    /// publishing can just be tried again.
+    #[error("Failed to commit the registry update")]
+    Commit(Vec<CommitError>),
+}
+
+/// Lookup and publishing of schemas by name, layered over a database's schema
+/// storage.
+pub struct SchemaRegistry<'a, D: Db + ?Sized> {
+    db: &'a D,
+}
+
+impl<'a, D: Db + ?Sized> SchemaRegistry<'a, D> {
+    /// Use the registry of the given database.
+    pub fn new(db: &'a D) -> Self {
+        Self { db }
+    }
+
+    /// Read the current registry document. An unpublished or unreadable
+    /// registry is treated as empty.
+    pub async fn registry(&self) -> DbResult<Registry> {
+        Ok(self.load().await?.map(|(_, reg)| reg).unwrap_or_default())
+    }
+
+    async fn load(&self) -> DbResult<Option<(Hash, Registry)>> {
+        let Some(hash) = self.db.name_get(REGISTRY_NAME).await? else {
+            return Ok(None);
+        };
+        let Some(doc) = self.db.doc_get(&hash).await? else {
+            return Ok(None);
+        };
+        Ok(decode(&doc).map(|reg| (hash, reg)))
+    }
+
+    /// Look up the hash of a schema by name, at a specific version or the
+    /// latest one.
+    pub async fn lookup(&self, name: &str, version: Option<u64>) -> DbResult<Option<Hash>> {
+        Ok(self
+            .registry()
+            .await?
+            .lookup(name, version)
+            .map(|e| e.hash.clone()))
+    }
+
+    /// Look up a schema by name and get it from the database. Returns `None`
+    /// if it isn't registered, or is registered but not in the database.
+    pub async fn get(&self, name: &str, version: Option<u64>) -> DbResult<Option<Arc<Schema>>> {
+        match self.lookup(name, version).await? {
+            Some(hash) => self.db.schema_get(&hash).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Add a schema to the database and publish it under a name and version.
+    /// Publishing the same schema under the same name and version again does
+    /// nothing.
+    pub async fn publish(
+        &self,
+        name: &str,
+        version: u64,
+        schema: Arc<Document>,
+        durability: Durability,
+    ) -> DbResult<Result<Arc<Schema>, RegistryError>> {
+        let schema = match self.db.schema_add(schema).await? {
+            Ok(schema) => schema,
+            Err(e) => return Ok(Err(RegistryError::InvalidSchema(e))),
+        };
+        let (current, mut reg) = match self.load().await? {
+            Some((hash, reg)) => (Some(hash), reg),
+            None => (None, Registry::default()),
+        };
+        match reg.insert(name, version, schema.hash()) {
+            Ok(true) => (),
+            Ok(false) => return Ok(Ok(schema)),
+            Err(existing) => {
+                return Ok(Err(RegistryError::VersionTaken {
+                    name: name.to_owned(),
+                    version,
+                    existing,
+                }))
+            }
+        }
+        let doc = NewDocument::new(None, &reg).expect("Registry should always be serializable");
+        let mut txn = self.db.txn();
+        let doc = txn
+            .add_new_doc(doc).await?
+            .expect("Registry documents have no schema and are always valid");
+        txn.swap_name_reserved(REGISTRY_NAME, Some(doc.hash()), current.as_ref());
+        Ok(match txn.commit(durability).await? {
+            Ok(_) => Ok(schema),
+            Err(e) => Err(RegistryError::Commit(e.errors)),
+        })
+    }
+}
+
+fn decode(doc: &Document) -> Option<Registry> {
+    doc.deserialize().ok()
+}
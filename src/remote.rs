@@ -0,0 +1,1931 @@
+//! Access to a database living in another process.
+//!
+//! A single FogDB daemon can serve many local processes: each client opens a
+//! [`Connection`] to the daemon (a Unix socket, a pipe, anything ordered and
+//! framed) and wraps it in a [`RemoteDb`], while the daemon hands each
+//! accepted connection to a [`RemoteServer`] that dispatches requests to its
+//! [`Db`].
+//!
+//! Every frame on the connection is a single fog-pack document holding either
+//! a [`RequestFrame`] or a [`ResponseFrame`]. Documents and entries are carried
+//! in their database encoding, and are validated again by the receiving side.
+//! Since frames are fog-pack documents, a response carrying a document close
+//! to the maximum document size won't fit in a frame, and the client gets an
+//! error in its place. Transactions can be much larger than a single
+//! document, so their changes are [staged][Request::Stage] across as many
+//! frames as they need before being committed; only a single document or
+//! entry close to the maximum size fails to fit.
+//!
+//! [`RemoteDb`] implements [`Db`], covering direct document access, schemas,
+//! names, transactions (including two-phase commits), and cursors, which fetch
+//! each document from the server as they move to it. Everything else fails
+//! with [`Unsupported`], or, where a method can't fail, gets an inert stand-in:
+//! groups with no members, queries and event streams that never produce
+//! anything, and empty registries. [`Db::capabilities`] only reports the
+//! server's features that the protocol carries.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fog_pack::{
+    document::{Document, NewDocument},
+    entry::{Entry, EntryRef, NewEntry},
+    error::Error as FogError,
+    query::NewQuery,
+    schema::{NoSchema, Schema},
+    types::*,
+    MAX_DOC_SIZE,
+};
+use futures::lock::Mutex as AsyncMutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    access::{CacheTier, DocInfo},
+    availability::{
+        AvailabilitySummary, SummaryError, SummaryExchange, SummaryReply,
+        DEFAULT_FALSE_POSITIVE_RATE,
+    },
+    backpressure::{PermitRequest, Unlimited},
+    barrier::{Barrier, BarrierError, BarrierReport},
+    capabilities::DbCapabilities,
+    cert::EntryPolicy,
+    changes::{ChangeFeed, CommitSeq, EntryWatch, SeqTooOld},
+    compression::CompressionPolicy,
+    coordinator::PreparedCommit,
+    cursor::{
+        ChunkStream, ChunkUpdate, Cursor, CursorBackError, CursorError, CursorOpts, CursorQuery,
+        DbQuery, ForkCursor, LinkStrength, MergeStrategy, NewCursor, QueryUpdate, TraceId,
+    },
+    discovery::DiscoveryRegistry,
+    eviction::EvictionRegistry,
+    expiry::{SweepBudget, SweepReport},
+    fetch::{FetchScheduler, PriorityScheduler, SchedulerConfig},
+    fingerprint::TreeFingerprint,
+    gate::{Gate, GateSettings},
+    gc::{GcPreview, ProposedChange},
+    group::Group,
+    health::{Health, HealthEvents},
+    import::{BulkImport, ImportProgress},
+    journal::Journal,
+    mixnet::Mixnet,
+    names::{NameError, NameInfo, NameMeta, NamingPolicy},
+    pinning::{HostedPin, PinError, PinGrant, PinPolicy, PinRequest},
+    quota::{QuotaUsage, StorageQuota},
+    resources::{ResourceFilter, Resources},
+    retention::{RetentionEvents, RetentionEviction, RetentionPolicy},
+    runtime::{self, GroupSummary, NodeEvent, NodeEvents, NodeLimits, NodeRuntime},
+    schema_fetch::{SchemaFetchError, SchemaRequest},
+    service::{ServiceDescriptor, ServiceFilter},
+    skew::SkewPolicy,
+    stats::TreeStats,
+    transaction::{
+        ChangeSet, CommitError, CommitErrors, CommitReceipt, DocChange, Durability, EntryChange,
+        EntryError, NameChange, SchemaError, Transaction,
+    },
+    transport::{Connection, TransportError, TransportRegistry},
+    validate::{Validator, ValidatorStats},
+    warm::{WarmError, Warmed, Warmup},
+    weak_refs::WeakRefDefaults,
+    wire::{WireDbError, WireEntryRef, WireFogError},
+    Db, DbCommit, DbError, DbResult, GroupSpec, NodeAddr, NodeInfo,
+};
+
+/// A request sent from a client to the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestFrame {
+    /// Identifier for matching up the response.
+    pub id: u64,
+    /// The request itself.
+    pub req: Request,
+}
+
+/// A response sent from the server to a client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResponseFrame {
+    /// The identifier of the request being responded to.
+    pub id: u64,
+    /// The response, or the internal database error that prevented one.
+    pub resp: Result<Response, WireDbError>,
+}
+
+/// An encoded document, along with the schema needed to decode it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WireDoc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Hash>,
+    pub data: Bytes,
+}
+
+/// A change to a document within a transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WireDocChange {
+    Add {
+        doc: WireDoc,
+        weak_ref: Vec<Hash>,
+        tier: Option<CacheTier>,
+    },
+    Modify {
+        weak_ref: Vec<(Hash, bool)>,
+        tier: Option<CacheTier>,
+    },
+}
+
+/// A change to an entry within a transaction.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WireEntryChange {
+    Add {
+        data: Bytes,
+        ttl: Option<Timestamp>,
+        policy: Option<EntryPolicy>,
+    },
+    Modify {
+        set_ttl: bool,
+        ttl: Option<Timestamp>,
+        set_policy: bool,
+        policy: Option<EntryPolicy>,
+    },
+    Delete {
+        retain: Option<Duration>,
+    },
+    Restore {
+        set_ttl: bool,
+        ttl: Option<Timestamp>,
+        set_policy: bool,
+        policy: Option<EntryPolicy>,
+    },
+}
+
+/// The changes making up a single transaction.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WireChangeSet {
+    pub docs: Vec<(Hash, WireDocChange)>,
+    pub entries: Vec<(WireEntryRef, WireEntryChange)>,
+    pub names: Vec<(String, NameChange)>,
+}
+
+impl WireChangeSet {
+    pub(crate) fn append(&mut self, other: WireChangeSet) {
+        self.docs.extend(other.docs);
+        self.entries.extend(other.entries);
+        self.names.extend(other.names);
+    }
+}
+
+/// How much document and entry data goes in a single frame when a change set
+/// is split up. Frames are fog-pack documents themselves, so this leaves room
+/// under the maximum document size for the rest of the frame.
+const PART_SIZE: usize = MAX_DOC_SIZE / 2;
+
+/// Split a change set into parts small enough to each be sent in a frame.
+/// There's always at least one part, even for an empty change set. A
+/// document or entry too large to share a frame gets a part of its own.
+pub(crate) fn split_changes(changes: WireChangeSet) -> Vec<WireChangeSet> {
+    // Hashes, references, and framing are counted as a fixed overhead per
+    // change, which is generous for everything but very long names.
+    const OVERHEAD: usize = 256;
+    let mut parts = vec![WireChangeSet::default()];
+    let mut size = 0;
+    let mut room = |len: usize, parts: &mut Vec<WireChangeSet>| {
+        let len = len + OVERHEAD;
+        if size > 0 && size + len > PART_SIZE {
+            parts.push(WireChangeSet::default());
+            size = 0;
+        }
+        size += len;
+    };
+    for (hash, change) in changes.docs {
+        let len = match &change {
+            WireDocChange::Add { doc, weak_ref, .. } => doc.data.len() + weak_ref.len() * 64,
+            WireDocChange::Modify { weak_ref, .. } => weak_ref.len() * 64,
+        };
+        room(len, &mut parts);
+        parts.last_mut().unwrap().docs.push((hash, change));
+    }
+    for (e_ref, change) in changes.entries {
+        let len = match &change {
+            WireEntryChange::Add { data, .. } => data.len(),
+            _ => 0,
+        };
+        room(len, &mut parts);
+        parts.last_mut().unwrap().entries.push((e_ref, change));
+    }
+    for (name, change) in changes.names {
+        room(name.len(), &mut parts);
+        parts.last_mut().unwrap().names.push((name, change));
+    }
+    parts
+}
+
+/// The error held by [`DbError::Internal`] when a [`RemoteDb`] is asked for
+/// something the protocol doesn't carry.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("{0} isn't supported by remote databases")]
+pub struct Unsupported(pub &'static str);
+
+fn unsupported(call: &'static str) -> Box<DbError> {
+    Box::new(DbError::Internal(Box::new(Unsupported(call))))
+}
+
+/// A request to the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Request {
+    Capabilities,
+    Health,
+    CurrentSeq,
+    DocGet(Hash),
+    DocInfo(Hash),
+    SchemaGet(Hash),
+    SchemaAdd(Bytes),
+    SchemaDel(Hash),
+    SchemaList,
+    NameGet(String),
+    NameAdd(String, Hash),
+    NameDel(Hash),
+    NameList,
+    NameListPrefix(String),
+    NameInfo(String),
+    NameSetMeta(String, NameMeta),
+    SkewPolicy,
+    NamingPolicy,
+    /// Add part of a change set to the one being built up under the given
+    /// identifier, for committing once every part has been sent.
+    Stage(u64, WireChangeSet),
+    Commit(u64, Durability),
+    CommitMany(Vec<u64>, Durability),
+    Prepare(u64, Durability),
+    CommitPrepared(u64),
+    RollbackPrepared(u64),
+}
+
+/// A response from the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Response {
+    Capabilities(DbCapabilities),
+    Health(Health),
+    Seq(CommitSeq),
+    Doc(Option<WireDoc>),
+    DocInfo(Option<DocInfo>),
+    SchemaAdded(Result<(), WireFogError>),
+    Deleted(bool),
+    Hashes(Vec<Hash>),
+    Hash(Option<Hash>),
+    Names(Vec<(String, Hash)>),
+    NameAdded(Result<Option<Hash>, NameError>),
+    NameInfo(Option<NameInfo>),
+    Updated(bool),
+    SkewPolicy(SkewPolicy),
+    NamingPolicy(NamingPolicy),
+    Staged,
+    Committed(Result<CommitReceipt, Vec<CommitError>>),
+    CommittedMany(Vec<Result<CommitReceipt, Vec<CommitError>>>),
+    Receipt(CommitReceipt),
+    Prepared(Result<u64, Vec<CommitError>>),
+    RolledBack,
+}
+
+/// Encode a message as a fog-pack document.
+pub(crate) fn encode<T: Serialize>(msg: &T) -> Result<Bytes, FogError> {
+    let doc = NoSchema::validate_new_doc(NewDocument::new(None, msg)?)?;
+    let (_, data) = NoSchema::encode_doc(doc)?;
+    Ok(Bytes::from(data))
+}
+
+/// Decode a message from a fog-pack document.
+pub(crate) fn decode<T: DeserializeOwned>(frame: &[u8]) -> Result<T, FogError> {
+    let doc = NoSchema::decode_doc(frame.to_vec())?;
+    doc.deserialize()
+}
+
+fn fog_err(context: &str, err: FogError) -> Box<DbError> {
+    Box::new(DbError::FogOther {
+        context: context.into(),
+        err,
+    })
+}
+
+fn transport_err(err: TransportError) -> Box<DbError> {
+    Box::new(DbError::Internal(Box::new(err)))
+}
+
+/// Encode a document in its database encoding.
+fn encode_doc(schema: Option<&Schema>, doc: &Document) -> Result<WireDoc, FogError> {
+    let (_, data) = match schema {
+        Some(schema) => schema.encode_doc(doc.clone())?,
+        None => NoSchema::encode_doc(doc.clone())?,
+    };
+    Ok(WireDoc {
+        schema: doc.schema_hash().cloned(),
+        data: Bytes::from(data),
+    })
+}
+
+pub(crate) fn encode_changes(
+    docs: &HashMap<Hash, DocChange>,
+    entries: &HashMap<EntryRef, EntryChange>,
+    names: &HashMap<String, NameChange>,
+) -> WireChangeSet {
+    let docs = docs
+        .iter()
+        .map(|(hash, change)| {
+            let change = match change {
+                DocChange::Add {
+                    encoded,
+                    weak_ref,
+                    tier,
+                    ..
+                } => WireDocChange::Add {
+                    doc: WireDoc {
+                        schema: encoded.schema().clone(),
+                        data: encoded.data().clone(),
+                    },
+                    weak_ref: weak_ref.iter().cloned().collect(),
+                    tier: *tier,
+                },
+                DocChange::Modify { weak_ref, tier } => WireDocChange::Modify {
+                    weak_ref: weak_ref.iter().map(|(h, w)| (h.clone(), *w)).collect(),
+                    tier: *tier,
+                },
+            };
+            (hash.clone(), change)
+        })
+        .collect();
+    let entries = entries
+        .iter()
+        .map(|(e_ref, change)| {
+            let change = match change {
+                EntryChange::Add { entry, ttl, policy } => WireEntryChange::Add {
+                    data: entry.data().clone(),
+                    ttl: *ttl,
+                    policy: policy.clone(),
+                },
+                EntryChange::Modify { ttl, policy } => WireEntryChange::Modify {
+                    set_ttl: ttl.is_some(),
+                    ttl: ttl.flatten(),
+                    set_policy: policy.is_some(),
+                    policy: policy.clone().flatten(),
+                },
+                EntryChange::Delete { retain } => WireEntryChange::Delete { retain: *retain },
+                EntryChange::Restore { ttl, policy } => WireEntryChange::Restore {
+                    set_ttl: ttl.is_some(),
+                    ttl: ttl.flatten(),
+                    set_policy: policy.is_some(),
+                    policy: policy.clone().flatten(),
+                },
+            };
+            (e_ref.into(), change)
+        })
+        .collect();
+    let names = names
+        .iter()
+        .map(|(name, change)| (name.clone(), change.clone()))
+        .collect();
+    WireChangeSet {
+        docs,
+        entries,
+        names,
+    }
+}
+
+/// A client for a database served by a [`RemoteServer`]. Cloning the client is
+/// cheap, and clones share the same connection.
+#[derive(Clone)]
+pub struct RemoteDb {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    conn: AsyncMutex<Box<dyn Connection>>,
+    next_id: AtomicU64,
+    schemas: std::sync::Mutex<HashMap<Hash, Arc<Schema>>>,
+    docs: std::sync::Mutex<HashMap<Hash, Weak<Document>>>,
+    transports: TransportRegistry,
+    discovery: DiscoveryRegistry,
+    eviction: EvictionRegistry,
+    fetch: Arc<dyn FetchScheduler>,
+}
+
+impl ClientInner {
+    async fn call(&self, req: Request) -> DbResult<Response> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let frame =
+            encode(&RequestFrame { id, req }).map_err(|e| fog_err("encoding remote request", e))?;
+        // Only one request is outstanding at a time, so the next response is
+        // ours, unless an earlier call was dropped before reading its own.
+        // Those late responses are skipped.
+        let conn = self.conn.lock().await;
+        conn.send(frame).await.map_err(transport_err)?;
+        let resp = loop {
+            let frame = conn.recv().await.map_err(transport_err)?;
+            let resp: ResponseFrame =
+                decode(&frame).map_err(|e| fog_err("decoding remote response", e))?;
+            if resp.id == id {
+                break resp;
+            }
+            if resp.id > id {
+                return Err(transport_err(TransportError::Other(format!(
+                    "expected response to request {}, got {}",
+                    id, resp.id
+                ))));
+            }
+        };
+        drop(conn);
+        resp.resp.map_err(|e| Box::new(e.into()))
+    }
+
+    /// Send a change set to the server, split across as many frames as it
+    /// needs, and return the identifier it was staged under.
+    async fn stage(&self, changes: WireChangeSet) -> DbResult<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        for part in split_changes(changes) {
+            match self.call(Request::Stage(id, part)).await? {
+                Response::Staged => (),
+                resp => return Err(unexpected(resp)),
+            }
+        }
+        Ok(id)
+    }
+
+    fn cached_schema(&self, schema: &Hash) -> Option<Arc<Schema>> {
+        self.schemas.lock().unwrap().get(schema).cloned()
+    }
+
+    fn cached_doc(&self, doc: &Hash) -> Option<Arc<Document>> {
+        self.docs.lock().unwrap().get(doc).and_then(Weak::upgrade)
+    }
+
+    fn cache_doc(&self, doc: &Arc<Document>) {
+        let mut docs = self.docs.lock().unwrap();
+        docs.retain(|_, d| d.strong_count() > 0);
+        docs.insert(doc.hash().clone(), Arc::downgrade(doc));
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        if let Some(doc) = self.cached_doc(doc) {
+            return Ok(Some(doc));
+        }
+        let wire = match self.call(Request::DocGet(doc.clone())).await? {
+            Response::Doc(Some(wire)) => wire,
+            Response::Doc(None) => return Ok(None),
+            resp => return Err(unexpected(resp)),
+        };
+        let decoded = match &wire.schema {
+            Some(schema) => {
+                let Some(schema) = self.schema_get(schema).await? else {
+                    return Err(fog_err(
+                        "decoding remote document",
+                        FogError::SchemaMismatch {
+                            actual: None,
+                            expected: Some(schema.clone()),
+                        },
+                    ));
+                };
+                schema.decode_doc(wire.data.to_vec())
+            }
+            None => NoSchema::decode_doc(wire.data.to_vec()),
+        };
+        let decoded = decoded.map_err(|err| {
+            Box::new(DbError::FogDoc {
+                context: "decoding remote document".into(),
+                doc: doc.clone(),
+                err,
+            })
+        })?;
+        if decoded.hash() != doc {
+            return Err(fog_err(
+                "decoding remote document",
+                FogError::FailValidate(format!("expected document {}", doc)),
+            ));
+        }
+        let decoded = Arc::new(decoded);
+        self.cache_doc(&decoded);
+        Ok(Some(decoded))
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        if let Some(schema) = self.cached_schema(schema) {
+            return Ok(Some(schema));
+        }
+        let wire = match self.call(Request::SchemaGet(schema.clone())).await? {
+            Response::Doc(Some(wire)) => wire,
+            Response::Doc(None) => return Ok(None),
+            resp => return Err(unexpected(resp)),
+        };
+        let parsed = NoSchema::decode_doc(wire.data.to_vec())
+            .and_then(|doc| Schema::from_doc(&doc))
+            .map_err(|err| {
+                Box::new(DbError::FogDoc {
+                    context: "decoding remote schema".into(),
+                    doc: schema.clone(),
+                    err,
+                })
+            })?;
+        let parsed = Arc::new(parsed);
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert(schema.clone(), parsed.clone());
+        Ok(Some(parsed))
+    }
+}
+
+/// Return an error for a response that doesn't match the request made.
+fn unexpected(resp: Response) -> Box<DbError> {
+    transport_err(TransportError::Other(format!(
+        "unexpected response from remote database: {:?}",
+        resp
+    )))
+}
+
+impl RemoteDb {
+    /// Create a client talking to a server over the given connection.
+    pub fn new(conn: Box<dyn Connection>) -> Self {
+        Self {
+            inner: Arc::new(ClientInner {
+                conn: AsyncMutex::new(conn),
+                next_id: AtomicU64::new(0),
+                schemas: std::sync::Mutex::new(HashMap::new()),
+                docs: std::sync::Mutex::new(HashMap::new()),
+                transports: TransportRegistry::new(),
+                discovery: DiscoveryRegistry::new(),
+                eviction: EvictionRegistry::new(),
+                fetch: Arc::new(PriorityScheduler::new(SchedulerConfig::default())),
+            }),
+        }
+    }
+
+    fn cursor_on(&self, doc: Arc<Document>) -> NewCursor {
+        let cursor = RemoteCursor {
+            inner: self.inner.clone(),
+            id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+            stack: vec![doc.clone()],
+        };
+        (Box::new(cursor), doc)
+    }
+}
+
+#[async_trait]
+impl Db for RemoteDb {
+    fn txn(&self) -> Transaction {
+        Transaction::new(Box::new(RemoteCommit {
+            inner: self.inner.clone(),
+        }))
+    }
+
+    fn commit_permit(&self) -> Box<dyn PermitRequest> {
+        Box::new(Unlimited)
+    }
+
+    fn bulk_import(&self) -> Box<dyn BulkImport> {
+        Box::new(RemoteImport)
+    }
+
+    async fn current_seq(&self) -> DbResult<CommitSeq> {
+        match self.inner.call(Request::CurrentSeq).await? {
+            Response::Seq(seq) => Ok(seq),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn changes_since(
+        &self,
+        _seq: CommitSeq,
+    ) -> DbResult<Result<Box<dyn ChangeFeed>, SeqTooOld>> {
+        Err(unsupported("changes_since"))
+    }
+
+    async fn entry_watch(&self, _doc: &Hash, _key: &str) -> DbResult<Box<dyn EntryWatch>> {
+        Err(unsupported("entry_watch"))
+    }
+
+    fn group(&self, _spec: GroupSpec) -> Box<dyn Group> {
+        Box::new(RemoteGroup)
+    }
+
+    fn transports(&self) -> &TransportRegistry {
+        &self.inner.transports
+    }
+
+    fn discovery(&self) -> &DiscoveryRegistry {
+        &self.inner.discovery
+    }
+
+    fn runtime(&self) -> &dyn NodeRuntime {
+        &RemoteRuntime
+    }
+
+    fn mixnet(&self) -> Option<&dyn Mixnet> {
+        None
+    }
+
+    fn journal(&self) -> Option<&dyn Journal> {
+        None
+    }
+
+    fn eviction_policies(&self) -> &EvictionRegistry {
+        &self.inner.eviction
+    }
+
+    async fn gc_preview(&self, _change: ProposedChange) -> DbResult<GcPreview> {
+        Err(unsupported("gc_preview"))
+    }
+
+    /// The server's capabilities, less those the protocol doesn't carry.
+    async fn capabilities(&self) -> DbResult<DbCapabilities> {
+        match self.inner.call(Request::Capabilities).await? {
+            Response::Capabilities(caps) => Ok(DbCapabilities {
+                two_phase_commit: caps.two_phase_commit,
+                access_tracking: caps.access_tracking,
+                cache_tiers: caps.cache_tiers,
+                ..DbCapabilities::default()
+            }),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn skew_policy(&self) -> DbResult<SkewPolicy> {
+        match self.inner.call(Request::SkewPolicy).await? {
+            Response::SkewPolicy(policy) => Ok(policy),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn set_skew_policy(&self, _policy: SkewPolicy) -> DbResult<()> {
+        Err(unsupported("set_skew_policy"))
+    }
+
+    async fn health(&self) -> DbResult<Health> {
+        match self.inner.call(Request::Health).await? {
+            Response::Health(health) => Ok(health),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    fn health_events(&self) -> Box<dyn HealthEvents> {
+        Box::new(RemoteHealth)
+    }
+
+    async fn ttl_sweep(&self, _budget: SweepBudget) -> DbResult<SweepReport> {
+        Err(unsupported("ttl_sweep"))
+    }
+
+    fn retention_events(&self) -> Box<dyn RetentionEvents> {
+        Box::new(RemoteRetention)
+    }
+
+    fn fetch_scheduler(&self) -> Arc<dyn FetchScheduler> {
+        self.inner.fetch.clone()
+    }
+
+    fn validator(&self) -> Arc<dyn Validator> {
+        Arc::new(RemoteValidator {
+            inner: self.inner.clone(),
+        })
+    }
+
+    /// Cursors fetch each document from the server as they move to it. The
+    /// cursor options are ignored.
+    async fn cursor(&self, doc: &Hash, _opts: CursorOpts) -> DbResult<Option<NewCursor>> {
+        Ok(self.inner.doc_get(doc).await?.map(|doc| self.cursor_on(doc)))
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        self.inner.doc_get(doc).await
+    }
+
+    async fn doc_info(&self, doc: &Hash) -> DbResult<Option<DocInfo>> {
+        match self.inner.call(Request::DocInfo(doc.clone())).await? {
+            Response::DocInfo(info) => Ok(info),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn tree_stats(&self, _root: &Hash) -> DbResult<Option<TreeStats>> {
+        Err(unsupported("tree_stats"))
+    }
+
+    async fn tree_fingerprint(&self, _root: &Hash) -> DbResult<Option<TreeFingerprint>> {
+        Err(unsupported("tree_fingerprint"))
+    }
+
+    /// Queries aren't carried, so they never get any results. Going back from
+    /// one gives a cursor on the document if this client holds it, and on an
+    /// empty document otherwise.
+    fn query(&self, doc: &Hash, _query: DbQuery) -> Box<dyn CursorQuery> {
+        let doc = self.inner.cached_doc(doc).unwrap_or_else(|| {
+            Arc::new(
+                NoSchema::validate_new_doc(
+                    NewDocument::new(None, ()).expect("empty document should encode"),
+                )
+                .expect("empty document should validate"),
+            )
+        });
+        let cursor = RemoteCursor {
+            inner: self.inner.clone(),
+            id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+            stack: vec![doc],
+        };
+        Box::new(RemoteQuery(Box::new(cursor)))
+    }
+
+    async fn entry_count(&self, _doc: &Hash, _key: &str, _query: Option<&NewQuery>) -> DbResult<u64> {
+        Err(unsupported("entry_count"))
+    }
+
+    /// Schemas are cached once fetched.
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        self.inner.schema_get(schema).await
+    }
+
+    async fn schema_add(&self, schema: Arc<Document>) -> DbResult<Result<Arc<Schema>, FogError>> {
+        let parsed = match Schema::from_doc(&schema) {
+            Ok(parsed) => Arc::new(parsed),
+            Err(e) => return Ok(Err(e)),
+        };
+        let wire = encode_doc(None, &schema).map_err(|e| fog_err("encoding schema", e))?;
+        match self.inner.call(Request::SchemaAdd(wire.data)).await? {
+            Response::SchemaAdded(Ok(())) => {
+                self.inner
+                    .schemas
+                    .lock()
+                    .unwrap()
+                    .insert(schema.hash().clone(), parsed.clone());
+                Ok(Ok(parsed))
+            }
+            Response::SchemaAdded(Err(e)) => Ok(Err(e.into())),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn schema_del(&self, schema: &Hash) -> DbResult<bool> {
+        self.inner.schemas.lock().unwrap().remove(schema);
+        match self.inner.call(Request::SchemaDel(schema.clone())).await? {
+            Response::Deleted(deleted) => Ok(deleted),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn schema_list(&self) -> DbResult<Vec<Hash>> {
+        match self.inner.call(Request::SchemaList).await? {
+            Response::Hashes(list) => Ok(list),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn schema_set_compression(
+        &self,
+        _schema: &Hash,
+        _policy: CompressionPolicy,
+    ) -> DbResult<bool> {
+        Err(unsupported("schema_set_compression"))
+    }
+
+    async fn schema_get_compression(&self, _schema: &Hash) -> DbResult<Option<CompressionPolicy>> {
+        Err(unsupported("schema_get_compression"))
+    }
+
+    async fn schema_set_weak_refs(
+        &self,
+        _schema: &Hash,
+        _defaults: WeakRefDefaults,
+    ) -> DbResult<bool> {
+        Err(unsupported("schema_set_weak_refs"))
+    }
+
+    async fn schema_get_weak_refs(&self, _schema: &Hash) -> DbResult<Option<WeakRefDefaults>> {
+        Err(unsupported("schema_get_weak_refs"))
+    }
+
+    async fn schema_set_retention(
+        &self,
+        _schema: &Hash,
+        _key: &str,
+        _policy: RetentionPolicy,
+    ) -> DbResult<bool> {
+        Err(unsupported("schema_set_retention"))
+    }
+
+    async fn schema_get_retention(
+        &self,
+        _schema: &Hash,
+        _key: &str,
+    ) -> DbResult<Option<RetentionPolicy>> {
+        Err(unsupported("schema_get_retention"))
+    }
+
+    async fn name_get(&self, name: &str) -> DbResult<Option<Hash>> {
+        match self.inner.call(Request::NameGet(name.into())).await? {
+            Response::Hash(hash) => Ok(hash),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn name_add(
+        &self,
+        name: &str,
+        hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, NameError>> {
+        match self
+            .inner
+            .call(Request::NameAdd(name.into(), hash.clone()))
+            .await?
+        {
+            Response::NameAdded(res) => Ok(res),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    /// Reserved names can't be set remotely.
+    async fn name_add_reserved(
+        &self,
+        _name: &str,
+        _hash: &Hash,
+    ) -> DbResult<Result<Option<Hash>, NameError>> {
+        Err(unsupported("name_add_reserved"))
+    }
+
+    async fn naming_policy(&self) -> DbResult<NamingPolicy> {
+        match self.inner.call(Request::NamingPolicy).await? {
+            Response::NamingPolicy(policy) => Ok(policy),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn set_naming_policy(&self, _policy: NamingPolicy) -> DbResult<()> {
+        Err(unsupported("set_naming_policy"))
+    }
+
+    async fn name_del(&self, hash: &Hash) -> DbResult<Option<Hash>> {
+        match self.inner.call(Request::NameDel(hash.clone())).await? {
+            Response::Hash(hash) => Ok(hash),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn name_list(&self) -> DbResult<Vec<(String, Hash)>> {
+        match self.inner.call(Request::NameList).await? {
+            Response::Names(list) => Ok(list),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn name_list_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Hash)>> {
+        match self
+            .inner
+            .call(Request::NameListPrefix(prefix.into()))
+            .await?
+        {
+            Response::Names(list) => Ok(list),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn name_info(&self, name: &str) -> DbResult<Option<NameInfo>> {
+        match self.inner.call(Request::NameInfo(name.into())).await? {
+            Response::NameInfo(info) => Ok(info),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn name_set_meta(&self, name: &str, meta: NameMeta) -> DbResult<bool> {
+        match self
+            .inner
+            .call(Request::NameSetMeta(name.into(), meta))
+            .await?
+        {
+            Response::Updated(updated) => Ok(updated),
+            resp => Err(unexpected(resp)),
+        }
+    }
+}
+
+/// The [`DbCommit`] used by transactions on a [`RemoteDb`].
+struct RemoteCommit {
+    inner: Arc<ClientInner>,
+}
+
+#[async_trait]
+impl DbCommit for RemoteCommit {
+    async fn commit(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        durability: Durability,
+    ) -> DbResult<Result<CommitReceipt, CommitErrors>> {
+        let staged = self
+            .inner
+            .stage(encode_changes(&docs, &entries, &names))
+            .await?;
+        match self
+            .inner
+            .call(Request::Commit(staged, durability))
+            .await?
+        {
+            Response::Committed(Ok(receipt)) => Ok(Ok(receipt)),
+            Response::Committed(Err(errors)) => Ok(Err(CommitErrors {
+                docs,
+                entries,
+                names,
+                errors,
+            })),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn commit_many(
+        self: Box<Self>,
+        changes: Vec<ChangeSet>,
+        durability: Durability,
+    ) -> DbResult<Vec<Result<CommitReceipt, CommitErrors>>> {
+        let mut staged = Vec::with_capacity(changes.len());
+        for (docs, entries, names) in changes.iter() {
+            staged.push(
+                self.inner
+                    .stage(encode_changes(docs, entries, names))
+                    .await?,
+            );
+        }
+        let results = match self
+            .inner
+            .call(Request::CommitMany(staged, durability))
+            .await?
+        {
+            Response::CommittedMany(results) if results.len() == changes.len() => results,
+            resp => return Err(unexpected(resp)),
+        };
+        let mut out = Vec::with_capacity(results.len());
+        for (res, (docs, entries, names)) in results.into_iter().zip(changes) {
+            out.push(res.map_err(|errors| CommitErrors {
+                docs,
+                entries,
+                names,
+                errors,
+            }));
+        }
+        Ok(out)
+    }
+
+    async fn prepare(
+        self: Box<Self>,
+        docs: HashMap<Hash, DocChange>,
+        entries: HashMap<EntryRef, EntryChange>,
+        names: HashMap<String, NameChange>,
+        durability: Durability,
+    ) -> DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>> {
+        let staged = self
+            .inner
+            .stage(encode_changes(&docs, &entries, &names))
+            .await?;
+        match self
+            .inner
+            .call(Request::Prepare(staged, durability))
+            .await?
+        {
+            Response::Prepared(Ok(token)) => Ok(Ok(Box::new(RemotePrepared {
+                inner: self.inner.clone(),
+                token,
+            }))),
+            Response::Prepared(Err(errors)) => Ok(Err(CommitErrors {
+                docs,
+                entries,
+                names,
+                errors,
+            })),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn schema_get(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        self.inner.schema_get(schema).await
+    }
+
+    async fn doc_get(&self, doc: &Hash) -> DbResult<Option<Arc<Document>>> {
+        self.inner.doc_get(doc).await
+    }
+
+    async fn schema_weak_refs(&self, _schema: &Hash) -> DbResult<Option<WeakRefDefaults>> {
+        // The server applies its own defaults when it loads the transaction.
+        Ok(None)
+    }
+
+    fn db_id(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+}
+
+/// A transaction prepared on a remote database. If dropped, it is rolled back
+/// once the connection to the server closes.
+struct RemotePrepared {
+    inner: Arc<ClientInner>,
+    token: u64,
+}
+
+#[async_trait]
+impl PreparedCommit for RemotePrepared {
+    async fn commit(self: Box<Self>) -> DbResult<CommitReceipt> {
+        match self.inner.call(Request::CommitPrepared(self.token)).await? {
+            Response::Receipt(receipt) => Ok(receipt),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    async fn rollback(self: Box<Self>) -> DbResult<()> {
+        match self
+            .inner
+            .call(Request::RollbackPrepared(self.token))
+            .await?
+        {
+            Response::RolledBack => Ok(()),
+            resp => Err(unexpected(resp)),
+        }
+    }
+}
+
+// Documents are validated with schemas fetched from the server, and entries
+// check their links against documents fetched the same way.
+struct RemoteValidator {
+    inner: Arc<ClientInner>,
+}
+
+#[async_trait]
+impl Validator for RemoteValidator {
+    fn schema(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>> {
+        Ok(self.inner.cached_schema(schema))
+    }
+
+    async fn validate_docs(
+        &self,
+        docs: Vec<NewDocument>,
+    ) -> DbResult<Vec<Result<Document, SchemaError>>> {
+        let mut out = Vec::with_capacity(docs.len());
+        for doc in docs {
+            out.push(match doc.schema_hash() {
+                Some(hash) => match self.inner.schema_get(hash).await? {
+                    Some(schema) => schema.validate_new_doc(doc).map_err(SchemaError::from),
+                    None => Err(SchemaError::MissingSchema(hash.clone())),
+                },
+                None => NoSchema::validate_new_doc(doc).map_err(SchemaError::from),
+            });
+        }
+        Ok(out)
+    }
+
+    async fn validate_entries(
+        &self,
+        entries: Vec<NewEntry>,
+        docs: &HashMap<Hash, Arc<Document>>,
+    ) -> DbResult<Vec<Result<Entry, EntryError>>> {
+        let mut out = Vec::with_capacity(entries.len());
+        'entries: for entry in entries {
+            let Some(schema) = self.inner.schema_get(entry.schema_hash()).await? else {
+                out.push(Err(EntryError::MissingEntrySchema(
+                    entry.schema_hash().clone(),
+                )));
+                continue;
+            };
+            let mut checklist = match schema.validate_new_entry(entry) {
+                Ok(checklist) => checklist,
+                Err(e) => {
+                    out.push(Err(e.into()));
+                    continue;
+                }
+            };
+            for (link, item) in checklist.iter() {
+                let doc = match docs.get(&link) {
+                    Some(doc) => doc.clone(),
+                    None => match self.inner.doc_get(&link).await? {
+                        Some(doc) => doc,
+                        None => {
+                            out.push(Err(EntryError::MissingDoc(link)));
+                            continue 'entries;
+                        }
+                    },
+                };
+                if let Err(source) = item.check(&doc) {
+                    out.push(Err(EntryError::DocValidationFail { doc: link, source }));
+                    continue 'entries;
+                }
+            }
+            out.push(checklist.complete().map_err(EntryError::from));
+        }
+        Ok(out)
+    }
+
+    fn stats(&self) -> ValidatorStats {
+        ValidatorStats {
+            workers: 0,
+            cached_schemas: self.inner.schemas.lock().unwrap().len() as u32,
+            pending: 0,
+        }
+    }
+}
+
+/// A cursor on a remote database, fetching documents from the server as it
+/// moves to them.
+struct RemoteCursor {
+    inner: Arc<ClientInner>,
+    id: u64,
+    stack: Vec<Arc<Document>>,
+}
+
+#[async_trait]
+impl Cursor for RemoteCursor {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        if let Some(doc) = self.forward_local(hash)? {
+            return Ok(doc);
+        }
+        // Database errors can't be passed through a cursor, so they're
+        // reported as the document being unavailable.
+        let Ok(Some(doc)) = self.inner.doc_get(hash).await else {
+            return Err(CursorError::Unavailable(hash.clone()));
+        };
+        self.stack.push(doc.clone());
+        Ok(doc)
+    }
+
+    /// Only documents this client already holds can be reached without a
+    /// round trip to the server.
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        if !self.current().find_hashes().contains(hash) {
+            return Err(CursorError::NotInDoc(hash.clone()));
+        }
+        let doc = self.inner.cached_doc(hash);
+        if let Some(doc) = &doc {
+            self.stack.push(doc.clone());
+        }
+        Ok(doc)
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+            Ok(())
+        } else {
+            Err(CursorBackError)
+        }
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        if !self.current().find_hashes().contains(hash) {
+            return Box::new(Unreachable(CursorError::NotInDoc(hash.clone())));
+        }
+        Box::new(RemoteFork {
+            inner: self.inner.clone(),
+            hash: hash.clone(),
+        })
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.stack.last().unwrap().clone()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        self.current()
+            .find_hashes()
+            .into_iter()
+            .map(|h| (h, LinkStrength::Unknown))
+            .collect()
+    }
+
+    fn query(self: Box<Self>, _query: DbQuery) -> Box<dyn CursorQuery> {
+        Box::new(RemoteQuery(self))
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, _offset: u64) -> Box<dyn ChunkStream> {
+        Box::new(RemoteChunks(hash.clone()))
+    }
+
+    fn cache_current(&self, _ttl: Duration) -> DbResult<()> {
+        Err(unsupported("cache_current"))
+    }
+
+    fn trace_id(&self) -> TraceId {
+        TraceId(self.id)
+    }
+}
+
+struct RemoteFork {
+    inner: Arc<ClientInner>,
+    hash: Hash,
+}
+
+#[async_trait]
+impl ForkCursor for RemoteFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        let Ok(Some(doc)) = self.inner.doc_get(&self.hash).await else {
+            return Err(CursorError::Unavailable(self.hash));
+        };
+        let db = RemoteDb { inner: self.inner };
+        Ok(db.cursor_on(doc))
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        let doc = self.inner.cached_doc(&self.hash);
+        let db = RemoteDb { inner: self.inner };
+        Ok(doc.map(|doc| db.cursor_on(doc)))
+    }
+}
+
+/// A fork that fails as soon as it's completed.
+struct Unreachable(CursorError);
+
+#[async_trait]
+impl ForkCursor for Unreachable {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        Err(self.0)
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        Err(self.0)
+    }
+}
+
+struct RemoteChunks(Hash);
+
+#[async_trait]
+impl ChunkStream for RemoteChunks {
+    async fn next(&self) -> ChunkUpdate {
+        ChunkUpdate::Failed(CursorError::Unavailable(self.0.clone()))
+    }
+
+    fn try_next(&self) -> Option<ChunkUpdate> {
+        Some(ChunkUpdate::Failed(CursorError::Unavailable(
+            self.0.clone(),
+        )))
+    }
+}
+
+/// A query on a remote database, which never gets any results.
+struct RemoteQuery(Box<RemoteCursor>);
+
+#[async_trait]
+impl CursorQuery for RemoteQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        self.0
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        None
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        MergeStrategy::Arrival
+    }
+
+    fn trace_id(&self) -> TraceId {
+        TraceId(u64::MAX)
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.0.trace_id()
+    }
+}
+
+struct RemoteImport;
+
+#[async_trait]
+impl BulkImport for RemoteImport {
+    async fn add_doc(&mut self, _doc: Arc<Document>) -> DbResult<()> {
+        Err(unsupported("bulk_import"))
+    }
+
+    async fn add_entry(&mut self, _entry: Entry) -> DbResult<()> {
+        Err(unsupported("bulk_import"))
+    }
+
+    fn set_name(&mut self, _name: &str, _target: &Hash) {}
+
+    fn progress(&self) -> ImportProgress {
+        ImportProgress::default()
+    }
+
+    async fn finish(
+        self: Box<Self>,
+        _durability: Durability,
+    ) -> DbResult<Result<CommitSeq, Vec<CommitError>>> {
+        Err(unsupported("bulk_import"))
+    }
+}
+
+struct RemoteRuntime;
+
+impl NodeRuntime for RemoteRuntime {
+    fn set_limits(&self, _limits: NodeLimits) {}
+
+    fn limits(&self) -> NodeLimits {
+        NodeLimits::default()
+    }
+
+    fn groups(&self) -> Vec<GroupSummary> {
+        Vec::new()
+    }
+
+    fn connections(&self) -> Vec<runtime::Connection> {
+        Vec::new()
+    }
+
+    fn disconnect(&self, _node: &NodeAddr) -> bool {
+        false
+    }
+
+    fn events(&self) -> Box<dyn NodeEvents> {
+        Box::new(RemoteNodeEvents)
+    }
+}
+
+struct RemoteNodeEvents;
+
+#[async_trait]
+impl NodeEvents for RemoteNodeEvents {
+    async fn next(&self) -> NodeEvent {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<NodeEvent> {
+        None
+    }
+}
+
+struct RemoteHealth;
+
+#[async_trait]
+impl HealthEvents for RemoteHealth {
+    async fn next(&self) -> Health {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<Health> {
+        None
+    }
+}
+
+struct RemoteRetention;
+
+#[async_trait]
+impl RetentionEvents for RemoteRetention {
+    async fn next(&self) -> RetentionEviction {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<RetentionEviction> {
+        None
+    }
+}
+
+/// A group opened through a remote database. Groups aren't carried, so it has
+/// no members: gates can't be opened, and every request to other nodes is
+/// refused.
+struct RemoteGroup;
+
+impl Group for RemoteGroup {
+    fn gate(&self, _gate: &Hash, _settings: Option<GateSettings>) -> Option<Box<dyn Gate>> {
+        None
+    }
+
+    fn cursor(&self, gate: &Hash, _opts: CursorOpts) -> Box<dyn ForkCursor> {
+        Box::new(Unreachable(CursorError::Unavailable(gate.clone())))
+    }
+
+    fn set_storage_quota(&self, _quota: Option<StorageQuota>) {}
+
+    fn storage_quota(&self) -> Option<StorageQuota> {
+        None
+    }
+
+    fn storage_usage(&self) -> QuotaUsage {
+        QuotaUsage::default()
+    }
+
+    fn pin_request(&self, _node: &NodeAddr, _root: &Hash, _ttl: Duration) -> Box<dyn PinRequest> {
+        Box::new(Refused)
+    }
+
+    fn set_pin_policy(&self, _policy: Box<dyn PinPolicy>) {}
+
+    fn hosted_pins(&self) -> Vec<HostedPin> {
+        Vec::new()
+    }
+
+    fn set_skew_policy(&self, _policy: Option<SkewPolicy>) {}
+
+    fn skew_policy(&self) -> SkewPolicy {
+        SkewPolicy::default()
+    }
+
+    fn find_schema(&self, schema: &Hash) -> Box<dyn SchemaRequest> {
+        Box::new(NoSchemaFound(schema.clone()))
+    }
+
+    fn advertise_resources(&self, _resources: Option<Resources>) {}
+
+    fn members_by_resource(&self, _filter: &ResourceFilter) -> Vec<(NodeInfo, Resources)> {
+        Vec::new()
+    }
+
+    fn advertise_services(&self, _services: Vec<Arc<Document>>) {}
+
+    fn services(&self, _filter: ServiceFilter) -> Vec<(NodeAddr, ServiceDescriptor)> {
+        Vec::new()
+    }
+
+    fn summary(&self, root: &Hash) -> AvailabilitySummary {
+        AvailabilitySummary::new(root, [].iter(), false, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    fn exchange_summary(
+        &self,
+        _node: &NodeAddr,
+        _have: AvailabilitySummary,
+    ) -> Box<dyn SummaryExchange> {
+        Box::new(Refused)
+    }
+
+    fn barrier(&self, _root: &Hash, _min_peers: usize, _timeout: Duration) -> Box<dyn Barrier> {
+        Box::new(Refused)
+    }
+
+    fn warm(&self, nodes: &[NodeAddr]) -> Box<dyn Warmup> {
+        Box::new(RefusedWarmup(nodes.to_vec()))
+    }
+}
+
+/// A request to another group member, refused because groups aren't carried.
+struct Refused;
+
+const REFUSED: &str = "Groups aren't supported by remote databases";
+
+#[async_trait]
+impl PinRequest for Refused {
+    async fn complete(self: Box<Self>) -> Result<PinGrant, PinError> {
+        Err(PinError::Refused(REFUSED.into()))
+    }
+}
+
+#[async_trait]
+impl SummaryExchange for Refused {
+    async fn complete(self: Box<Self>) -> Result<SummaryReply, SummaryError> {
+        Err(SummaryError::Refused(REFUSED.into()))
+    }
+}
+
+#[async_trait]
+impl Barrier for Refused {
+    async fn complete(self: Box<Self>) -> Result<BarrierReport, BarrierError> {
+        Err(BarrierError::Refused(REFUSED.into()))
+    }
+}
+
+struct RefusedWarmup(Vec<NodeAddr>);
+
+#[async_trait]
+impl Warmup for RefusedWarmup {
+    async fn complete(self: Box<Self>) -> Vec<Warmed> {
+        self.0
+            .into_iter()
+            .map(|node| Warmed {
+                node,
+                result: Err(WarmError::Refused(REFUSED.into())),
+            })
+            .collect()
+    }
+}
+
+struct NoSchemaFound(Hash);
+
+#[async_trait]
+impl SchemaRequest for NoSchemaFound {
+    async fn complete(self: Box<Self>) -> Result<Document, SchemaFetchError> {
+        Err(SchemaFetchError::NotFound(self.0))
+    }
+}
+
+/// Serves a [`Db`] to [`RemoteDb`] clients.
+pub struct RemoteServer<D: Db> {
+    db: D,
+}
+
+/// State kept for a single client connection.
+#[derive(Default)]
+struct Session {
+    next_token: u64,
+    prepared: HashMap<u64, Box<dyn PreparedCommit>>,
+    staged: HashMap<u64, WireChangeSet>,
+}
+
+impl Session {
+    /// Take a change set the client finished staging.
+    fn take_staged(&mut self, id: u64) -> DbResult<WireChangeSet> {
+        self.staged.remove(&id).ok_or_else(|| {
+            transport_err(TransportError::Other(format!(
+                "no changes staged under {}",
+                id
+            )))
+        })
+    }
+}
+
+impl<D: Db> RemoteServer<D> {
+    /// Create a server for the given database.
+    pub fn new(db: D) -> Self {
+        Self { db }
+    }
+
+    /// The database being served.
+    pub fn db(&self) -> &D {
+        &self.db
+    }
+
+    /// Serve requests from a client until the connection closes. Any
+    /// transactions the client prepared but didn't commit are rolled back.
+    pub async fn serve(&self, conn: &dyn Connection) -> Result<(), TransportError> {
+        let mut session = Session::default();
+        let result = loop {
+            let frame = match conn.recv().await {
+                Ok(frame) => frame,
+                Err(TransportError::Closed) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            let req: RequestFrame = match decode(&frame) {
+                Ok(req) => req,
+                Err(e) => break Err(TransportError::Other(format!("bad request frame: {}", e))),
+            };
+            let resp = self
+                .dispatch(&mut session, req.req)
+                .await
+                .map_err(|e| WireDbError::from(e.as_ref()));
+            let frame = match encode(&ResponseFrame { id: req.id, resp }) {
+                Ok(frame) => frame,
+                // A response that won't fit in a frame is answered with an
+                // error instead, keeping the session going.
+                Err(err) => {
+                    let err = DbError::FogOther {
+                        context: "encoding response for remote client".into(),
+                        err,
+                    };
+                    let resp = Err(WireDbError::from(&err));
+                    match encode(&ResponseFrame { id: req.id, resp }) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            break Err(TransportError::Other(format!(
+                                "can't encode response: {}",
+                                e
+                            )))
+                        }
+                    }
+                }
+            };
+            if let Err(e) = conn.send(frame).await {
+                break Err(e);
+            }
+        };
+        for (_, prepared) in session.prepared.drain() {
+            let _ = prepared.rollback().await;
+        }
+        result
+    }
+
+    async fn dispatch(&self, session: &mut Session, req: Request) -> DbResult<Response> {
+        let db = &self.db;
+        Ok(match req {
+            Request::Capabilities => Response::Capabilities(db.capabilities().await?),
+            Request::Health => Response::Health(db.health().await?),
+            Request::CurrentSeq => Response::Seq(db.current_seq().await?),
+            Request::DocGet(hash) => {
+                let Some(doc) = db.doc_get(&hash).await? else {
+                    return Ok(Response::Doc(None));
+                };
+                let schema = match doc.schema_hash() {
+                    Some(schema) => db.schema_get(schema).await?,
+                    None => None,
+                };
+                let wire = encode_doc(schema.as_deref(), &doc).map_err(|err| {
+                    Box::new(DbError::FogDoc {
+                        context: "encoding document for remote client".into(),
+                        doc: hash,
+                        err,
+                    })
+                })?;
+                Response::Doc(Some(wire))
+            }
+            Request::DocInfo(hash) => Response::DocInfo(db.doc_info(&hash).await?),
+            Request::SchemaGet(hash) => {
+                // Schemas are served as their original documents.
+                if db.schema_get(&hash).await?.is_none() {
+                    return Ok(Response::Doc(None));
+                }
+                match db.doc_get(&hash).await? {
+                    Some(doc) => Response::Doc(Some(
+                        encode_doc(None, &doc)
+                            .map_err(|e| fog_err("encoding schema for remote client", e))?,
+                    )),
+                    None => Response::Doc(None),
+                }
+            }
+            Request::SchemaAdd(data) => {
+                let doc = match NoSchema::decode_doc(data.to_vec()) {
+                    Ok(doc) => doc,
+                    Err(e) => return Ok(Response::SchemaAdded(Err((&e).into()))),
+                };
+                let res = db.schema_add(Arc::new(doc)).await?;
+                Response::SchemaAdded(res.map(|_| ()).map_err(|e| (&e).into()))
+            }
+            Request::SchemaDel(hash) => Response::Deleted(db.schema_del(&hash).await?),
+            Request::SchemaList => Response::Hashes(db.schema_list().await?),
+            Request::NameGet(name) => Response::Hash(db.name_get(&name).await?),
+            Request::NameAdd(name, hash) => Response::NameAdded(db.name_add(&name, &hash).await?),
+            Request::NameDel(hash) => Response::Hash(db.name_del(&hash).await?),
+            Request::NameList => Response::Names(db.name_list().await?),
+            Request::NameListPrefix(prefix) => Response::Names(db.name_list_prefix(&prefix).await?),
+            Request::NameInfo(name) => Response::NameInfo(db.name_info(&name).await?),
+            Request::NameSetMeta(name, meta) => Response::Updated(db.name_set_meta(&name, meta).await?),
+            Request::SkewPolicy => Response::SkewPolicy(db.skew_policy().await?),
+            Request::NamingPolicy => Response::NamingPolicy(db.naming_policy().await?),
+            Request::Stage(id, part) => {
+                session.staged.entry(id).or_default().append(part);
+                Response::Staged
+            }
+            Request::Commit(staged, durability) => {
+                let changes = session.take_staged(staged)?;
+                let txn = match self.load(changes).await? {
+                    Ok(txn) => txn,
+                    Err(errors) => return Ok(Response::Committed(Err(errors))),
+                };
+                Response::Committed(txn.commit(durability).await?.map_err(|e| e.errors))
+            }
+            Request::CommitMany(staged, durability) => {
+                // Transactions that fail to load are reported without being
+                // submitted, and the rest are committed together.
+                let changes = staged
+                    .into_iter()
+                    .map(|id| session.take_staged(id))
+                    .collect::<DbResult<Vec<_>>>()?;
+                let mut results = Vec::with_capacity(changes.len());
+                let mut txns = Vec::new();
+                for changes in changes {
+                    match self.load(changes).await? {
+                        Ok(txn) => {
+                            txns.push(txn);
+                            results.push(None);
+                        }
+                        Err(errors) => results.push(Some(Err(errors))),
+                    }
+                }
+                // Every transaction was loaded on this server's database, so
+                // they can't be mixed.
+                let mut committed = Transaction::commit_many(txns, durability)
+                    .await?
+                    .map_err(|e| transport_err(TransportError::Other(e.to_string())))?
+                    .into_iter()
+                    .map(|res| res.map_err(|e| e.errors));
+                let results = results
+                    .into_iter()
+                    .map(|res| match res {
+                        Some(res) => Ok(res),
+                        None => committed.next().ok_or_else(|| {
+                            transport_err(TransportError::Other(
+                                "database returned too few commit results".into(),
+                            ))
+                        }),
+                    })
+                    .collect::<DbResult<Vec<_>>>()?;
+                Response::CommittedMany(results)
+            }
+            Request::Prepare(staged, durability) => {
+                let changes = session.take_staged(staged)?;
+                let txn = match self.load(changes).await? {
+                    Ok(txn) => txn,
+                    Err(errors) => return Ok(Response::Prepared(Err(errors))),
+                };
+                match txn.prepare(durability).await? {
+                    Ok(prepared) => {
+                        let token = session.next_token;
+                        session.next_token += 1;
+                        session.prepared.insert(token, prepared);
+                        Response::Prepared(Ok(token))
+                    }
+                    Err(e) => Response::Prepared(Err(e.errors)),
+                }
+            }
+            Request::CommitPrepared(token) => {
+                let Some(prepared) = session.prepared.remove(&token) else {
+                    return Err(transport_err(TransportError::Other(format!(
+                        "no prepared transaction {}",
+                        token
+                    ))));
+                };
+                Response::Receipt(prepared.commit().await?)
+            }
+            Request::RollbackPrepared(token) => {
+                if let Some(prepared) = session.prepared.remove(&token) {
+                    prepared.rollback().await?;
+                }
+                Response::RolledBack
+            }
+        })
+    }
+
+    /// Decode and validate a client's changes into a transaction on the
+    /// database.
+    async fn load(&self, changes: WireChangeSet) -> DbResult<Result<Transaction, Vec<CommitError>>> {
+        load_changes(&self.db, changes, "remote client", false).await
+    }
+}
+
+/// Decode and validate a set of changes into a transaction on a database,
+/// checking every document and entry against the database's schemas. `source`
+/// names where the changes came from, for error context. Changes to reserved
+/// names are refused unless `allow_reserved` is set.
+pub(crate) async fn load_changes(
+    db: &dyn Db,
+    changes: WireChangeSet,
+    source: &str,
+    allow_reserved: bool,
+) -> DbResult<Result<Transaction, Vec<CommitError>>> {
+    let mut txn = db.txn();
+    let mut errors = Vec::new();
+    let mut new_docs = HashMap::new();
+
+    for (hash, change) in changes.docs {
+        match change {
+            WireDocChange::Add {
+                doc,
+                weak_ref,
+                tier,
+            } => {
+                let schema = match &doc.schema {
+                    Some(schema) => match db.schema_get(schema).await? {
+                        Some(schema) => Some(schema),
+                        None => {
+                            errors.push(CommitError::MissingSchema {
+                                doc: hash,
+                                schema: schema.clone(),
+                            });
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                let decoded = match &schema {
+                    Some(schema) => schema.decode_doc(doc.data.to_vec()),
+                    None => NoSchema::decode_doc(doc.data.to_vec()),
+                };
+                let decoded = decoded.map_err(|err| {
+                    Box::new(DbError::FogDoc {
+                        context: format!("decoding document from {}", source),
+                        doc: hash.clone(),
+                        err,
+                    })
+                })?;
+                let decoded = Arc::new(decoded);
+                if let Err(e) = txn.add_doc(decoded.clone()).await? {
+                    errors.push(CommitError::MissingSchema {
+                        doc: hash,
+                        schema: e.0,
+                    });
+                    continue;
+                }
+                for target in weak_ref {
+                    txn.set_weak_ref(decoded.hash(), &target, true);
+                }
+                if let Some(tier) = tier {
+                    txn.set_cache_tier(decoded.hash(), tier);
+                }
+                new_docs.insert(decoded.hash().clone(), decoded);
+            }
+            WireDocChange::Modify { weak_ref, tier } => {
+                for (target, weak) in weak_ref {
+                    txn.set_weak_ref(&hash, &target, weak);
+                }
+                if let Some(tier) = tier {
+                    txn.set_cache_tier(&hash, tier);
+                }
+            }
+        }
+    }
+
+    for (e_ref, change) in changes.entries {
+        let e_ref = EntryRef::from(e_ref);
+        match change {
+            WireEntryChange::Add { data, ttl, policy } => {
+                let parent = match new_docs.get(&e_ref.parent) {
+                    Some(doc) => Some(doc.clone()),
+                    None => db.doc_get(&e_ref.parent).await?,
+                };
+                let schema = match parent.as_ref().and_then(|p| p.schema_hash()) {
+                    Some(schema) => db.schema_get(schema).await?,
+                    None => None,
+                };
+                let (Some(parent), Some(schema)) = (parent, schema) else {
+                    errors.push(CommitError::MissingParent(e_ref));
+                    continue;
+                };
+                let entry_err = |err| {
+                    Box::new(DbError::FogEntry {
+                        context: format!("decoding entry from {}", source),
+                        entry: e_ref.clone(),
+                        err,
+                    })
+                };
+                let mut checklist = schema
+                    .decode_entry(data.to_vec(), &e_ref.key, &parent)
+                    .map_err(entry_err)?;
+                for (link, item) in checklist.iter() {
+                    let doc = match new_docs.get(&link) {
+                        Some(doc) => Some(doc.clone()),
+                        None => db.doc_get(&link).await?,
+                    };
+                    match doc {
+                        Some(doc) => item.check(&doc).map_err(entry_err)?,
+                        None => {
+                            errors.push(CommitError::MissingDoc(link));
+                        }
+                    }
+                }
+                let Ok(entry) = checklist.complete() else {
+                    continue;
+                };
+                // The entry's schema was already found, so this can only
+                // fail if it was removed in the meantime.
+                if txn.add_entry(entry).await?.is_err() {
+                    errors.push(CommitError::MissingParent(e_ref));
+                    continue;
+                }
+                txn.set_ttl(&e_ref, ttl);
+                txn.set_entry_policy(&e_ref, policy);
+            }
+            WireEntryChange::Modify {
+                set_ttl,
+                ttl,
+                set_policy,
+                policy,
+            } => {
+                if set_ttl {
+                    txn.set_ttl(&e_ref, ttl);
+                }
+                if set_policy {
+                    txn.set_entry_policy(&e_ref, policy);
+                }
+            }
+            WireEntryChange::Delete { retain } => txn.del_entry_retained(&e_ref, retain),
+            WireEntryChange::Restore {
+                set_ttl,
+                ttl,
+                set_policy,
+                policy,
+            } => {
+                txn.restore_entry(&e_ref);
+                if set_ttl {
+                    txn.set_ttl(&e_ref, ttl);
+                }
+                if set_policy {
+                    txn.set_entry_policy(&e_ref, policy);
+                }
+            }
+        }
+    }
+
+    for (name, change) in changes.names {
+        if change.reserved && !allow_reserved {
+            let prefix = db
+                .naming_policy()
+                .await?
+                .reserved
+                .into_iter()
+                .find(|p| name.starts_with(p.as_str()))
+                .unwrap_or_default();
+            errors.push(CommitError::InvalidName {
+                name,
+                err: NameError::Reserved(prefix),
+            });
+            continue;
+        }
+        match (change.expect, change.reserved) {
+            (Some(expect), true) => {
+                txn.swap_name_reserved(&name, change.target.as_ref(), expect.as_ref())
+            }
+            (Some(expect), false) => {
+                txn.swap_name(&name, change.target.as_ref(), expect.as_ref())
+            }
+            (None, _) => txn.set_name(&name, change.target.as_ref()),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Ok(txn))
+    } else {
+        Ok(Err(errors))
+    }
+}
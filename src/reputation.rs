@@ -0,0 +1,269 @@
+//! Default peer scoring from query usefulness, latency, and failures.
+//!
+//! Queries report how [useful][Usefulness] each result was, but it's up to the
+//! network implementation to turn those reports into a choice of which peers to
+//! ask next. [`ReputationEngine`] is a baseline for doing so, so that
+//! implementations share the same behavior unless they have a reason not to:
+//!
+//! - Each peer keeps a running average of its usefulness reports, of how often
+//!   its requests succeed, and of its response latency.
+//! - These are combined into a single score between 0 and 1, weighted as set
+//!   in [`ReputationConfig`].
+//! - A peer that hasn't been heard from drifts back towards a neutral score
+//!   over time, so that old failures are eventually forgiven and old successes
+//!   don't last forever.
+//!
+//! Peers that have never been seen get the neutral score, so that new peers
+//! get a chance to prove themselves.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cursor::{UsefulReport, Usefulness},
+    NodeAddr,
+};
+
+/// The score given to peers with no history.
+pub const NEUTRAL_SCORE: f64 = 0.5;
+
+/// Settings for a [`ReputationEngine`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReputationConfig {
+    /// How much each new observation moves a running average, between 0 and 1.
+    pub smoothing: f64,
+    /// How long it takes for a peer's score to get halfway back to neutral
+    /// when nothing new is heard from it.
+    pub half_life: Duration,
+    /// The latency at which a peer's latency component is 0.5. Faster peers
+    /// score higher, slower ones lower.
+    pub latency_target: Duration,
+    /// Weight of the usefulness component.
+    pub usefulness_weight: f64,
+    /// Weight of the success rate component.
+    pub success_weight: f64,
+    /// Weight of the latency component.
+    pub latency_weight: f64,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.1,
+            half_life: Duration::from_secs(3600),
+            latency_target: Duration::from_millis(250),
+            usefulness_weight: 0.5,
+            success_weight: 0.3,
+            latency_weight: 0.2,
+        }
+    }
+}
+
+/// Something observed about a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// A query result from the peer was reported on.
+    Report(Usefulness),
+    /// The peer answered a request after the given delay.
+    Response(Duration),
+    /// A request to the peer failed or timed out.
+    Failure,
+}
+
+/// A peer's current standing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerScore {
+    /// The combined score, between 0 and 1. Higher is better.
+    pub score: f64,
+    /// Running average of usefulness, between 0 and 1.
+    pub usefulness: f64,
+    /// Running average of the fraction of requests that succeeded.
+    pub success_rate: f64,
+    /// Running average of response latency, if any responses have been seen.
+    pub latency: Option<Duration>,
+    /// How many events have been recorded for the peer.
+    pub events: u64,
+}
+
+/// The value a usefulness report contributes to the running average.
+fn usefulness_value(useful: Usefulness) -> f64 {
+    match useful {
+        Usefulness::Useful => 1.0,
+        Usefulness::Stale => 0.7,
+        Usefulness::Irrelevant => 0.3,
+        Usefulness::Incorrect => 0.0,
+    }
+}
+
+struct PeerState {
+    usefulness: f64,
+    success: f64,
+    latency: Option<f64>,
+    events: u64,
+    updated: Instant,
+}
+
+impl PeerState {
+    fn new(now: Instant) -> Self {
+        Self {
+            usefulness: NEUTRAL_SCORE,
+            success: NEUTRAL_SCORE,
+            latency: None,
+            events: 0,
+            updated: now,
+        }
+    }
+
+    /// Pull the averages back towards neutral for the time since the last
+    /// update.
+    fn decay(&mut self, config: &ReputationConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let half_life = config.half_life.as_secs_f64();
+        if half_life > 0.0 {
+            let keep = 0.5f64.powf(elapsed / half_life);
+            self.usefulness = NEUTRAL_SCORE + (self.usefulness - NEUTRAL_SCORE) * keep;
+            self.success = NEUTRAL_SCORE + (self.success - NEUTRAL_SCORE) * keep;
+        }
+        self.updated = now;
+    }
+
+    fn record(&mut self, config: &ReputationConfig, event: PeerEvent) {
+        let a = config.smoothing.clamp(0.0, 1.0);
+        let avg = |old: f64, new: f64| old + (new - old) * a;
+        match event {
+            PeerEvent::Report(useful) => {
+                self.usefulness = avg(self.usefulness, usefulness_value(useful))
+            }
+            PeerEvent::Response(latency) => {
+                self.success = avg(self.success, 1.0);
+                let latency = latency.as_secs_f64();
+                self.latency = Some(self.latency.map_or(latency, |l| avg(l, latency)));
+            }
+            PeerEvent::Failure => self.success = avg(self.success, 0.0),
+        }
+        self.events = self.events.saturating_add(1);
+    }
+
+    fn score(&self, config: &ReputationConfig) -> PeerScore {
+        let target = config.latency_target.as_secs_f64();
+        let latency = match self.latency {
+            Some(l) if target + l > 0.0 => target / (target + l),
+            _ => NEUTRAL_SCORE,
+        };
+        let weights = config.usefulness_weight + config.success_weight + config.latency_weight;
+        let score = if weights > 0.0 {
+            (self.usefulness * config.usefulness_weight
+                + self.success * config.success_weight
+                + latency * config.latency_weight)
+                / weights
+        } else {
+            NEUTRAL_SCORE
+        };
+        PeerScore {
+            score: score.clamp(0.0, 1.0),
+            usefulness: self.usefulness,
+            success_rate: self.success,
+            latency: self.latency.map(Duration::from_secs_f64),
+            events: self.events,
+        }
+    }
+}
+
+struct Inner {
+    config: ReputationConfig,
+    peers: Mutex<HashMap<NodeAddr, PeerState>>,
+}
+
+/// Tracks peer scores for a group. Cloning gives another handle to the same
+/// scores.
+#[derive(Clone)]
+pub struct ReputationEngine {
+    inner: Arc<Inner>,
+}
+
+impl ReputationEngine {
+    /// Create a new engine with no peer history.
+    pub fn new(config: ReputationConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                peers: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Get the engine's settings.
+    pub fn config(&self) -> &ReputationConfig {
+        &self.inner.config
+    }
+
+    /// Record something observed about a peer.
+    pub fn record(&self, peer: &NodeAddr, event: PeerEvent) {
+        let config = &self.inner.config;
+        let now = Instant::now();
+        let mut peers = self.inner.peers.lock().unwrap();
+        let state = peers
+            .entry(peer.clone())
+            .or_insert_with(|| PeerState::new(now));
+        state.decay(config, now);
+        state.record(config, event);
+    }
+
+    /// Get a peer's current score, or `None` if nothing has been recorded for
+    /// it.
+    pub fn score(&self, peer: &NodeAddr) -> Option<PeerScore> {
+        let config = &self.inner.config;
+        let mut peers = self.inner.peers.lock().unwrap();
+        let state = peers.get_mut(peer)?;
+        state.decay(config, Instant::now());
+        Some(state.score(config))
+    }
+
+    /// Sort peers from best to worst score. Peers with no history are given
+    /// the [neutral score][NEUTRAL_SCORE].
+    pub fn rank(&self, peers: &[NodeAddr]) -> Vec<(NodeAddr, f64)> {
+        let mut ranked: Vec<_> = peers
+            .iter()
+            .map(|p| {
+                let score = self.score(p).map_or(NEUTRAL_SCORE, |s| s.score);
+                (p.clone(), score)
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked
+    }
+
+    /// Forget everything recorded about a peer.
+    pub fn forget(&self, peer: &NodeAddr) {
+        self.inner.peers.lock().unwrap().remove(peer);
+    }
+
+    /// Make a [`UsefulReport`] that records its report against a peer, for
+    /// attaching to the query results that peer returns.
+    pub fn reporter(&self, peer: &NodeAddr) -> Box<dyn UsefulReport> {
+        Box::new(Reporter {
+            engine: self.clone(),
+            peer: peer.clone(),
+        })
+    }
+}
+
+impl Default for ReputationEngine {
+    fn default() -> Self {
+        Self::new(ReputationConfig::default())
+    }
+}
+
+struct Reporter {
+    engine: ReputationEngine,
+    peer: NodeAddr,
+}
+
+impl UsefulReport for Reporter {
+    fn report(self: Box<Self>, useful: Usefulness) {
+        self.engine.record(&self.peer, PeerEvent::Report(useful));
+    }
+}
@@ -0,0 +1,127 @@
+//! Advertising what a node can offer to the rest of its group.
+//!
+//! Asking every member of a group to [pin][crate::pinning] a document tree, or
+//! to replicate it, wastes everyone's time when most members are phones that
+//! can't hold it anyway. Instead, each node can advertise its [`Resources`]:
+//! how much storage it has free for pinning, how much bandwidth it has, and how
+//! much of the time it expects to be online. The advertisement is set with
+//! [`Group::advertise_resources`][crate::group::Group::advertise_resources],
+//! signed with the identity the node uses in the group, and carried in its
+//! [`NodeInfo::resources`][crate::NodeInfo::resources]. Other members then find
+//! capable nodes with
+//! [`Group::members_by_resource`][crate::group::Group::members_by_resource].
+//!
+//! Advertisements are claims, not guarantees. A node advertising lots of
+//! storage can still refuse a pin, and should be judged on what it actually
+//! does by [reputation][crate::reputation] tracking.
+
+use bytes::Bytes;
+use fog_crypto::identity::{IdentityKey, UnverifiedSignature};
+use fog_pack::{document::NewDocument, types::*};
+use serde::{Deserialize, Serialize};
+
+/// How much bandwidth a node has to spare, from least to most.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum BandwidthClass {
+    /// Data costs the node money, like a mobile connection with a data cap.
+    #[default]
+    Metered,
+    /// Unmetered, but slow or shared.
+    Low,
+    /// A typical home or office connection.
+    Medium,
+    /// A server or datacenter connection.
+    High,
+}
+
+/// How much of the time a node expects to be online, from least to most.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum UptimeClass {
+    /// Online now and then, like a phone or laptop.
+    #[default]
+    Occasional,
+    /// Online for most of each day.
+    Usually,
+    /// Meant to be online all the time.
+    Always,
+}
+
+/// What a node can offer to its group.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Resources {
+    /// Bytes of storage free for pinning on behalf of group members.
+    pub pin_storage: u64,
+    /// How much bandwidth the node has to spare.
+    pub bandwidth: BandwidthClass,
+    /// How much of the time the node expects to be online.
+    pub uptime: UptimeClass,
+}
+
+/// The least a node must advertise to be picked by
+/// [`Group::members_by_resource`][crate::group::Group::members_by_resource].
+/// The default matches every node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ResourceFilter {
+    /// The least free pinning storage, in bytes.
+    pub pin_storage: u64,
+    /// The lowest bandwidth class.
+    pub bandwidth: BandwidthClass,
+    /// The lowest uptime class.
+    pub uptime: UptimeClass,
+}
+
+impl ResourceFilter {
+    /// Check if advertised resources pass the filter.
+    pub fn allows(&self, resources: &Resources) -> bool {
+        resources.pin_storage >= self.pin_storage
+            && resources.bandwidth >= self.bandwidth
+            && resources.uptime >= self.uptime
+    }
+}
+
+/// A node's signed advertisement of its [`Resources`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceAd {
+    /// The advertised resources.
+    pub resources: Resources,
+    /// When the advertisement was made. Newer advertisements replace older
+    /// ones.
+    pub time: Timestamp,
+    /// The encoded signature.
+    pub signature: Bytes,
+}
+
+impl ResourceAd {
+    /// The hash signed over by an advertisement.
+    pub fn signed_hash(resources: &Resources, time: Timestamp) -> Hash {
+        NewDocument::new(None, (resources, time))
+            .expect("ResourceAd should always be serializable")
+            .hash()
+            .to_owned()
+    }
+
+    /// Sign an advertisement of the given resources.
+    pub fn sign(key: &IdentityKey, resources: Resources, time: Timestamp) -> Self {
+        let mut signature = Vec::new();
+        key.sign(&Self::signed_hash(&resources, time))
+            .encode_vec(&mut signature);
+        Self {
+            resources,
+            time,
+            signature: signature.into(),
+        }
+    }
+
+    /// Verify the signature, returning the signing identity if it's valid.
+    pub fn verify(&self) -> Option<Identity> {
+        let sig = UnverifiedSignature::try_from(&self.signature[..]).ok()?;
+        let sig = sig
+            .verify(&Self::signed_hash(&self.resources, self.time))
+            .ok()?;
+        Some(sig.signer().to_owned())
+    }
+}
@@ -0,0 +1,116 @@
+//! Automatic pruning of log- and feed-style entry streams.
+//!
+//! Entries under a key like a chat log or a sensor feed pile up forever
+//! unless something deletes them. Setting a time-to-live on every entry works,
+//! but only bounds age, and relies on every writer remembering to set it.
+//! Instead, a [`RetentionPolicy`] can be set for a key of a schema with
+//! [`Db::schema_set_retention`][crate::Db::schema_set_retention], bounding how
+//! many entries each document keeps under the key, how old they can get, and
+//! how many bytes they can take up. The database enforces the policy itself,
+//! evicting the oldest entries first, and reports each eviction as a
+//! [`RetentionEviction`] on its [retention event
+//! stream][crate::Db::retention_events].
+//!
+//! Evicted entries are deleted locally, just as if a transaction had deleted
+//! them, but without leaving a [tombstone][crate::tombstone]: other nodes are
+//! free to keep their own copies for as long as their own policies allow.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use fog_pack::{entry::EntryRef, types::*};
+use serde::{Deserialize, Serialize};
+
+/// Limits on the entries kept under one key of each document of a schema.
+/// Each limit is applied separately, and unset limits aren't enforced. The
+/// default enforces nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// The most entries to keep under the key of each document.
+    pub max_count: Option<u64>,
+    /// The longest to keep an entry after it was stored.
+    pub max_age: Option<Duration>,
+    /// The most bytes of entries to keep under the key of each document.
+    pub max_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Check if the policy enforces nothing.
+    pub fn is_unlimited(&self) -> bool {
+        self.max_count.is_none() && self.max_age.is_none() && self.max_bytes.is_none()
+    }
+
+    /// Pick which entries to evict from under a key, as of the time `now`.
+    /// The entries must be in the order they were stored, oldest first.
+    /// Entries past the age limit are evicted, then the oldest remaining
+    /// entries are evicted until the rest fit the count and byte limits.
+    pub fn select(&self, entries: &[RetainedEntry], now: Timestamp) -> Vec<RetentionEviction> {
+        let mut evicted = Vec::new();
+        let mut count = entries.len() as u64;
+        let mut bytes: u64 = entries.iter().map(|e| e.bytes).sum();
+        let oldest = self
+            .max_age
+            .map(|age| now - age.as_secs().min(i64::MAX as u64) as i64);
+        for entry in entries {
+            let limit = if oldest.is_some_and(|oldest| entry.stored < oldest) {
+                RetentionLimit::Age
+            } else if self.max_count.is_some_and(|max| count > max) {
+                RetentionLimit::Count
+            } else if self.max_bytes.is_some_and(|max| bytes > max) {
+                RetentionLimit::Bytes
+            } else {
+                continue;
+            };
+            count -= 1;
+            bytes -= entry.bytes;
+            evicted.push(RetentionEviction {
+                entry: entry.entry.clone(),
+                limit,
+            });
+        }
+        evicted
+    }
+}
+
+/// An entry held under a key with a [`RetentionPolicy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetainedEntry {
+    /// The entry.
+    pub entry: EntryRef,
+    /// When the entry was stored in the database.
+    pub stored: Timestamp,
+    /// The encoded size of the entry, in bytes.
+    pub bytes: u64,
+}
+
+/// Which limit of a [`RetentionPolicy`] an entry was evicted for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RetentionLimit {
+    /// There were too many entries under the key.
+    Count,
+    /// The entry was too old.
+    Age,
+    /// The entries under the key took up too many bytes.
+    Bytes,
+}
+
+/// An entry evicted to enforce a [`RetentionPolicy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetentionEviction {
+    /// The evicted entry.
+    pub entry: EntryRef,
+    /// The limit it was evicted for.
+    pub limit: RetentionLimit,
+}
+
+/// A stream of entries evicted by the database's retention policies.
+#[async_trait]
+pub trait RetentionEvents: Send + Sync {
+    /// Wait for the next eviction.
+    async fn next(&self) -> RetentionEviction;
+
+    /// Try to get the next eviction, returning `None` if there hasn't been
+    /// one.
+    fn try_next(&self) -> Option<RetentionEviction>;
+}
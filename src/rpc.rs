@@ -0,0 +1,444 @@
+//! Request/response calls built on the query system.
+//!
+//! A [query hook][crate::gate::Gate::query_hook] can answer queries with
+//! entries it generates on the spot, which makes any document reachable
+//! through a gate usable as an RPC endpoint. This module fixes a convention
+//! for doing so, and handles both ends of it:
+//!
+//! - The caller runs a query under [`RPC_KEY`] on the endpoint document. The
+//!   query requires an `id` field matching a hash unique to the call, and
+//!   carries the encoded request as the only permitted value of an optional
+//!   `req` field.
+//! - The hook pulls the id and request out of the query with [`request`], and
+//!   answers with an entry made by [`response`], holding the id and the
+//!   response value. Since the entry carries the matching id and no `req`
+//!   field, it passes the query.
+//!
+//! The endpoint document's schema must allow entries under [`RPC_KEY`], and
+//! must permit the query. [`entry_validator`] builds a suitable entry
+//! validator for it. Callers use [`CursorExt::call`], which takes the first
+//! valid response it receives, from whichever node answers first.
+//!
+//! # Streamed responses
+//!
+//! A response too large for a single entry, or one produced a piece at a time,
+//! can be streamed instead. Streaming calls are queried under
+//! [`RPC_STREAM_KEY`], and answered with a sequence of entries each holding the
+//! call id, a sequence number starting from 0, and either one item of the
+//! response or nothing at all. The entry holding nothing marks the end of the
+//! stream. Hooks send these with a [`StreamResponder`], and callers receive
+//! them in order through an [`RpcStream`] from [`CursorExt::call_stream`].
+//! Entries with the same id and sequence number are duplicates, even if they
+//! come from different nodes, so a stream should only be answered by one node.
+//! Callers hold on to entries that arrive out of order, but only up to
+//! [`MAX_STREAM_AHEAD`] past the item they're waiting on; entries further
+//! ahead are treated as incorrect.
+//! Schemas use [`stream_entry_validator`] to allow these entries.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use fog_crypto::hash::HashState;
+use fog_pack::{
+    document::Document,
+    entry::NewEntry,
+    error::Error as FogError,
+    query::{NewQuery, Query},
+    schema::Schema,
+    types::*,
+    validator::{BinValidator, HashValidator, IntValidator, MapValidator, Validator},
+};
+use futures::future::{self, Either};
+use futures_timer::Delay;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    cursor::{Cursor, CursorError, CursorQuery, DbQuery, QueryUpdate, Usefulness},
+    gate::{Response, ResponseStream},
+    middleware::{Intercepted, Interceptor},
+    remote::{decode, encode},
+};
+
+/// The entry key RPC queries and responses are made under.
+pub const RPC_KEY: &str = "rpc";
+
+/// The entry key streamed RPC queries and responses are made under.
+pub const RPC_STREAM_KEY: &str = "rpc-stream";
+
+/// How long [`CursorExt::call`] waits for a response.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How far past the next expected item an [`RpcStream`] accepts a streamed
+/// response entry.
+pub const MAX_STREAM_AHEAD: u64 = 64;
+
+/// Failure while making an RPC call, or handling one.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum RpcError {
+    /// The cursor couldn't reach the endpoint document.
+    #[error("Couldn't reach the endpoint document")]
+    Cursor(#[from] CursorError),
+    /// The request couldn't be encoded or decoded.
+    #[error("Failed to encode or decode the request")]
+    Request(#[source] FogError),
+    /// The query isn't an RPC request.
+    #[error("Query isn't an RPC request")]
+    NotRequest,
+    /// A response entry couldn't be made or failed validation.
+    #[error("Failed to make a response entry")]
+    Response(#[source] FogError),
+    /// The caller stopped listening for responses.
+    #[error("Response stream is closed")]
+    Closed,
+    /// No valid response arrived in time.
+    #[error("No response within {0:?}")]
+    Timeout(Duration),
+}
+
+/// The contents of a response entry.
+#[derive(Serialize, Deserialize)]
+struct RpcEntry<R> {
+    id: Hash,
+    resp: R,
+}
+
+/// Build the validator for RPC entries on an endpoint document, given a
+/// validator for the response values.
+pub fn entry_validator(resp: Validator) -> Validator {
+    MapValidator::new()
+        .req_add("id", HashValidator::new().query(true).build())
+        .req_add("resp", resp)
+        .opt_add("req", BinValidator::new().query(true).build())
+        .map_ok(true)
+        .build()
+}
+
+/// The contents of a streamed response entry. An empty item marks the end of
+/// the stream.
+#[derive(Serialize, Deserialize)]
+struct StreamEntry<R> {
+    id: Hash,
+    seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item: Option<R>,
+}
+
+/// Build the validator for streamed RPC entries on an endpoint document, given
+/// a validator for each item of the response.
+pub fn stream_entry_validator(item: Validator) -> Validator {
+    MapValidator::new()
+        .req_add("id", HashValidator::new().query(true).build())
+        .req_add("seq", IntValidator::new().min(0).build())
+        .opt_add("item", item)
+        .opt_add("req", BinValidator::new().query(true).build())
+        .map_ok(true)
+        .build()
+}
+
+/// Build the query for a call, given its id and encoded request.
+fn request_query(key: &str, id: &Hash, req: &[u8]) -> NewQuery {
+    let validator = MapValidator::new()
+        .req_add("id", HashValidator::new().in_add(id.clone()).build())
+        .opt_add("req", BinValidator::new().in_add(req).build())
+        .build();
+    NewQuery::new(key, validator)
+}
+
+fn call_query(key: &str, id: &Hash, req: &[u8]) -> DbQuery {
+    DbQuery {
+        query: request_query(key, id, req),
+        rev_order: false,
+        ordering: None,
+        min_ttl: None,
+        signer_policy: None,
+        sample: None,
+        include_history: false,
+        merge: None,
+        sources: None,
+        revalidate: false,
+        forward: None,
+    }
+}
+
+/// Make an id for a call that won't be reused, even for identical requests.
+fn request_id(endpoint: &Hash, req: &[u8]) -> Hash {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut state = HashState::new();
+    state.update(endpoint.as_ref());
+    state.update(req);
+    state.update(count.to_le_bytes());
+    if let Some(now) = Timestamp::now() {
+        state.update(now.timestamp_utc().to_le_bytes());
+        state.update(now.timestamp_subsec_nanos().to_le_bytes());
+    }
+    state.finalize()
+}
+
+/// Extract the call id and request from an incoming RPC query, streamed or
+/// not.
+pub fn request<T: DeserializeOwned>(query: &Query) -> Result<(Hash, T), RpcError> {
+    if query.key() != RPC_KEY && query.key() != RPC_STREAM_KEY {
+        return Err(RpcError::NotRequest);
+    }
+    let Validator::Map(map) = query.validator() else {
+        return Err(RpcError::NotRequest);
+    };
+    let id = match map.req.get("id") {
+        Some(Validator::Hash(id)) if id.in_list.len() == 1 => id.in_list[0].clone(),
+        _ => return Err(RpcError::NotRequest),
+    };
+    let req = match map.opt.get("req") {
+        Some(Validator::Bin(req)) if req.in_list.len() == 1 => &req.in_list[0],
+        _ => return Err(RpcError::NotRequest),
+    };
+    let req = decode(req).map_err(RpcError::Request)?;
+    Ok((id, req))
+}
+
+/// Make the response entry for a call. It still needs to be validated against
+/// the endpoint document's schema before being sent.
+pub fn response<R: Serialize>(
+    endpoint: &Document,
+    id: &Hash,
+    resp: &R,
+) -> Result<NewEntry, FogError> {
+    NewEntry::new(
+        RPC_KEY,
+        endpoint,
+        RpcEntry {
+            id: id.clone(),
+            resp,
+        },
+    )
+}
+
+/// Helpers for making RPC calls through a cursor, and for wrapping it with
+/// [interceptors][crate::middleware].
+#[async_trait]
+pub trait CursorExt: Cursor {
+    /// Wrap the cursor with an interceptor. Calling this on an already-wrapped
+    /// cursor stacks the new interceptor on the outside.
+    fn with(self, interceptor: impl Interceptor + 'static) -> Intercepted
+    where
+        Self: Sized + 'static,
+    {
+        Intercepted::new(Box::new(self), Arc::new(interceptor))
+    }
+
+    /// Call the RPC endpoint at `endpoint_doc`, which must be linked to by the
+    /// cursor's current document, and wait up to [`DEFAULT_TIMEOUT`] for the
+    /// response.
+    async fn call<T, R>(&self, endpoint_doc: &Hash, request: T) -> Result<R, RpcError>
+    where
+        T: Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        self.call_timeout(endpoint_doc, request, DEFAULT_TIMEOUT)
+            .await
+    }
+
+    /// Call the RPC endpoint at `endpoint_doc`, which must be linked to by the
+    /// cursor's current document, and wait up to `timeout` for the response.
+    /// The timeout includes the time taken to reach the endpoint document.
+    async fn call_timeout<T, R>(
+        &self,
+        endpoint_doc: &Hash,
+        request: T,
+        timeout: Duration,
+    ) -> Result<R, RpcError>
+    where
+        T: Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let req = encode(&request).map_err(RpcError::Request)?;
+        let id = request_id(endpoint_doc, &req);
+        let fork = self.fork(endpoint_doc);
+        let call = async move {
+            let (cursor, _) = fork.complete().await?;
+            let query = cursor.query(call_query(RPC_KEY, &id, &req));
+            loop {
+                let QueryUpdate::Result(result) = query.next().await else {
+                    continue;
+                };
+                let result = *result;
+                match result.entry.deserialize::<RpcEntry<R>>() {
+                    Ok(entry) if entry.id == id => {
+                        result.useful.report(Usefulness::Useful);
+                        return Ok(entry.resp);
+                    }
+                    _ => result.useful.report(Usefulness::Incorrect),
+                }
+            }
+        };
+        match future::select(Box::pin(call), Delay::new(timeout)).await {
+            Either::Left((res, _)) => res,
+            Either::Right(_) => Err(RpcError::Timeout(timeout)),
+        }
+    }
+
+    /// Call the streaming RPC endpoint at `endpoint_doc`, which must be linked
+    /// to by the cursor's current document. The returned stream gives up once
+    /// `timeout` passes without the next item of the response arriving.
+    async fn call_stream<T, R>(
+        &self,
+        endpoint_doc: &Hash,
+        request: T,
+        timeout: Duration,
+    ) -> Result<RpcStream<R>, RpcError>
+    where
+        T: Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let req = encode(&request).map_err(RpcError::Request)?;
+        let id = request_id(endpoint_doc, &req);
+        let fork = self.fork(endpoint_doc).complete();
+        let cursor = match future::select(fork, Delay::new(timeout)).await {
+            Either::Left((res, _)) => res?.0,
+            Either::Right(_) => return Err(RpcError::Timeout(timeout)),
+        };
+        Ok(RpcStream {
+            query: cursor.query(call_query(RPC_STREAM_KEY, &id, &req)),
+            id,
+            timeout,
+            next: 0,
+            pending: BTreeMap::new(),
+            done: false,
+        })
+    }
+}
+
+impl<C: Cursor + ?Sized> CursorExt for C {}
+
+/// The responses to a streaming RPC call, in order.
+pub struct RpcStream<R> {
+    query: Box<dyn CursorQuery>,
+    id: Hash,
+    timeout: Duration,
+    next: u64,
+    pending: BTreeMap<u64, Option<R>>,
+    done: bool,
+}
+
+impl<R: DeserializeOwned + Send> RpcStream<R> {
+    /// Wait for the next item of the response. Returns `None` once the end of
+    /// the stream has been reached.
+    pub async fn next(&mut self) -> Result<Option<R>, RpcError> {
+        loop {
+            if self.done {
+                return Ok(None);
+            }
+            if let Some(item) = self.pending.remove(&self.next) {
+                self.next += 1;
+                self.done = item.is_none();
+                if item.is_some() {
+                    return Ok(item);
+                }
+                continue;
+            }
+            let update = match future::select(self.query.next(), Delay::new(self.timeout)).await {
+                Either::Left((update, _)) => update,
+                Either::Right(_) => return Err(RpcError::Timeout(self.timeout)),
+            };
+            let QueryUpdate::Result(result) = update else {
+                continue;
+            };
+            let result = *result;
+            match result.entry.deserialize::<StreamEntry<R>>() {
+                Ok(entry) if entry.id == self.id => {
+                    if entry.seq >= self.next.saturating_add(MAX_STREAM_AHEAD) {
+                        result.useful.report(Usefulness::Incorrect);
+                    } else if entry.seq >= self.next && !self.pending.contains_key(&entry.seq) {
+                        self.pending.insert(entry.seq, entry.item);
+                        result.useful.report(Usefulness::Useful);
+                    } else {
+                        result.useful.report(Usefulness::Stale);
+                    }
+                }
+                _ => result.useful.report(Usefulness::Incorrect),
+            }
+        }
+    }
+
+    /// Check if the end of the stream has been reached.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+/// Sends a streamed response to an RPC call from within a query hook.
+pub struct StreamResponder {
+    stream: Box<dyn ResponseStream>,
+    endpoint: Arc<Document>,
+    schema: Arc<Schema>,
+    id: Hash,
+    seq: u64,
+}
+
+impl StreamResponder {
+    /// Start responding to the call with the given id, made on the endpoint
+    /// document. `schema` is the endpoint document's schema, which the
+    /// response entries are validated against.
+    pub fn new(
+        stream: Box<dyn ResponseStream>,
+        endpoint: Arc<Document>,
+        schema: Arc<Schema>,
+        id: Hash,
+    ) -> Self {
+        Self {
+            stream,
+            endpoint,
+            schema,
+            id,
+            seq: 0,
+        }
+    }
+
+    /// Send the next item of the response.
+    pub async fn send<R: Serialize>(&mut self, item: &R) -> Result<(), RpcError> {
+        self.send_entry(Some(item)).await
+    }
+
+    /// Mark the end of the response.
+    pub async fn finish(mut self) -> Result<(), RpcError> {
+        self.send_entry::<()>(None).await
+    }
+
+    /// Check if the caller has stopped listening for responses.
+    pub fn is_closed(&self) -> bool {
+        self.stream.is_closed()
+    }
+
+    async fn send_entry<R: Serialize>(&mut self, item: Option<&R>) -> Result<(), RpcError> {
+        let entry = NewEntry::new(
+            RPC_STREAM_KEY,
+            &self.endpoint,
+            StreamEntry {
+                id: self.id.clone(),
+                seq: self.seq,
+                item,
+            },
+        )
+        .and_then(|entry| self.schema.validate_new_entry(entry))
+        .and_then(|checklist| checklist.complete())
+        .map_err(RpcError::Response)?;
+        self.stream
+            .send(Response {
+                entry,
+                docs: Vec::new(),
+            })
+            .await
+            .map_err(|_| RpcError::Closed)?;
+        self.seq += 1;
+        Ok(())
+    }
+}
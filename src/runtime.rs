@@ -0,0 +1,126 @@
+//! Node-wide management of connections across every open group.
+//!
+//! Each [`Group`][crate::group::Group] is opened with its own
+//! [`GroupSpec`][crate::group::GroupSpec], but the groups on a node share the
+//! same network, and often the same peers. The [`NodeRuntime`] from
+//! [`Db::runtime`][crate::Db::runtime] is the one place that sees all of them
+//! at once. It holds a single connection to each peer, shared by every group
+//! the peer is reached through, so opening a second group with the same
+//! members doesn't open a second set of connections. It also enforces
+//! node-wide [`NodeLimits`] on top of the limits in each group's spec, and
+//! reports connection activity for the whole node through one event stream.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{limits::RateLimit, NetType, NodeAddr, NodeInfo};
+
+/// Limits on network use across every group on a node. These apply on top of
+/// each group's own limits: a group's
+/// [`bandwidth`][crate::group::GroupSpec::bandwidth] is a share of the node's,
+/// not an addition to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodeLimits {
+    /// Maximum number of peers to be connected to at once, across all groups.
+    pub max_peers: Option<u32>,
+    /// Limit on the bytes transferred across all connections on the node.
+    pub bandwidth: Option<RateLimit>,
+}
+
+/// A node-wide limit that was reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NodeLimit {
+    /// The node was connected to [`max_peers`][NodeLimits::max_peers] peers,
+    /// so a new connection was refused or an idle one closed.
+    Peers,
+    /// The node's [`bandwidth`][NodeLimits::bandwidth] was used up, so
+    /// transfers are being held back.
+    Bandwidth,
+}
+
+/// An open connection to a peer.
+#[derive(Clone, Debug)]
+pub struct Connection {
+    /// The peer connected to.
+    pub node: NodeInfo,
+    /// The network the connection runs over.
+    pub net: NetType,
+    /// How many open groups are using the connection.
+    pub groups: u32,
+    /// Bytes sent over the connection so far.
+    pub sent: u64,
+    /// Bytes received over the connection so far.
+    pub received: u64,
+}
+
+/// A summary of an open group, as seen by the runtime.
+#[derive(Clone, Debug)]
+pub struct GroupSummary {
+    /// An identifier for the group, unique among the groups opened on this
+    /// node for as long as the runtime is running.
+    pub id: u64,
+    /// How many peers the group is connected to.
+    pub peers: u32,
+    /// Bytes transferred for the group so far.
+    pub transferred: u64,
+}
+
+/// Something that happened on the node's network.
+#[derive(Clone, Debug)]
+pub enum NodeEvent {
+    /// A group was opened.
+    GroupOpened(u64),
+    /// A group was closed, by dropping it.
+    GroupClosed(u64),
+    /// A new connection to a peer was opened.
+    Connected(NodeInfo),
+    /// A connection to a peer was closed, because no group was using it, the
+    /// peer went away, or it was [disconnected][NodeRuntime::disconnect].
+    Disconnected(NodeInfo),
+    /// A group started using an existing connection to a peer instead of
+    /// opening a new one.
+    Reused {
+        /// The group using the connection.
+        group: u64,
+        /// The peer connected to.
+        node: NodeInfo,
+    },
+    /// A node-wide limit was reached.
+    Limited(NodeLimit),
+}
+
+/// A stream of events from a [`NodeRuntime`].
+#[async_trait]
+pub trait NodeEvents: Send + Sync {
+    /// Wait for the next event.
+    async fn next(&self) -> NodeEvent;
+
+    /// Try to get the next event, returning `None` if there isn't one yet.
+    fn try_next(&self) -> Option<NodeEvent>;
+}
+
+/// The connection manager for every group opened on a node.
+pub trait NodeRuntime: Send + Sync {
+    /// Set the node-wide limits. If the node is already over the new peer
+    /// limit, idle connections are closed first, then the connections used by
+    /// the fewest groups.
+    fn set_limits(&self, limits: NodeLimits);
+
+    /// Get the node-wide limits.
+    fn limits(&self) -> NodeLimits;
+
+    /// Get every open group.
+    fn groups(&self) -> Vec<GroupSummary>;
+
+    /// Get every open connection.
+    fn connections(&self) -> Vec<Connection>;
+
+    /// Close the connection to a peer for every group using it. Groups may
+    /// reconnect to the peer later, following their
+    /// [backoff][crate::group::GroupSpec::reconnect]. Returns false if there
+    /// was no connection to the peer.
+    fn disconnect(&self, node: &NodeAddr) -> bool;
+
+    /// Watch for events on the node's network.
+    fn events(&self) -> Box<dyn NodeEvents>;
+}
@@ -0,0 +1,257 @@
+//! Gates that are reopened when a node restarts.
+//!
+//! Gates close when dropped, so a service-like node - a relay, an archive, a
+//! pinning host - has to open every one of its gates again each time it
+//! starts. Rather than keeping that list in its own configuration, a node can
+//! save each group it serves as a [`SavedGroup`], holding everything needed to
+//! open the group again along with the gates to open in it. Saved groups are
+//! stored in the database itself, each under its own name in
+//! [`GATES_PREFIX`], so they survive restarts along with the documents the
+//! gates lead to. On startup, [`reopen_all`] opens every saved group and its
+//! gates.
+//!
+//! Identity keys are never saved. A saved group only records which identity
+//! it was opened with, and the key has to be supplied again when reopening.
+
+use fog_crypto::identity::IdentityKey;
+use fog_pack::{document::NewDocument, types::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    cert::Policy,
+    gate::{Gate, GateSettings},
+    group::{Group, GroupSpec},
+    limits::{Backoff, RateLimit},
+    transaction::{CommitErrors, Durability},
+    Db, DbError, DbResult, NetInfo,
+};
+
+/// Prefix for the names that saved groups are kept under. It falls under the
+/// reserved [`SYS_PREFIX`][crate::names::SYS_PREFIX].
+pub const GATES_PREFIX: &str = "sys/gates/";
+
+/// Get the name a saved group with the given label is kept under.
+pub fn saved_name(label: &str) -> String {
+    format!("{GATES_PREFIX}{label}")
+}
+
+/// A gate to open when reopening a [`SavedGroup`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedGate {
+    /// The document the gate is opened on.
+    pub gate: Hash,
+    /// The settings to open the gate with. If `None`, the group's defaults
+    /// are used.
+    pub settings: Option<GateSettings>,
+}
+
+/// A group to reopen, and the gates to open in it.
+///
+/// This holds the parts of a [`GroupSpec`] that can be written down. The
+/// group's peer store and bootstrap peers aren't saved, and neither is any
+/// capability token it was joined with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedGroup {
+    /// The identity the group was opened with, if any. The matching key must
+    /// be supplied to reopen it.
+    pub identity: Option<Identity>,
+    /// The policy limiting who may be in the group, if any.
+    pub policy: Option<Policy>,
+    /// The networks the group uses.
+    pub net: NetInfo,
+    /// Which discovery providers, by name, the group uses.
+    pub discovery: Option<Vec<String>>,
+    /// Whether a mixnet must be used when finding group members.
+    pub mixnet_locator: bool,
+    /// Whether a mixnet must be used when communicating with group members.
+    pub mixnet_comms: bool,
+    /// Limit on the bytes transferred across all connections in the group.
+    pub bandwidth: Option<RateLimit>,
+    /// How to back off when reconnecting to a group member.
+    pub reconnect: Backoff,
+    /// The gates to open in the group.
+    pub gates: Vec<SavedGate>,
+}
+
+/// Failure to reopen a saved group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum ReopenError {
+    /// The key for the identity the group was opened with wasn't supplied.
+    #[error("No key was supplied for the group's identity")]
+    MissingKey,
+}
+
+/// A saved group that has been opened again.
+pub struct Reopened {
+    /// The group.
+    pub group: Box<dyn Group>,
+    /// The gates that were opened. Dropping them closes them.
+    pub gates: Vec<Box<dyn Gate>>,
+    /// The saved gates that the group refused to open, usually because they
+    /// overlap with a gate that was opened some other way.
+    pub refused: Vec<SavedGate>,
+}
+
+impl SavedGroup {
+    /// Describe a group from the spec it was opened with, with no gates yet.
+    pub fn from_spec(spec: &GroupSpec) -> Self {
+        let (identity, policy) = match &spec.policy_settings {
+            Some((key, policy)) => (Some(key.id().clone()), policy.clone()),
+            None => (None, None),
+        };
+        Self {
+            identity,
+            policy,
+            net: spec.net.clone(),
+            discovery: spec.discovery.clone(),
+            mixnet_locator: spec.mixnet_locator,
+            mixnet_comms: spec.mixnet_comms,
+            bandwidth: spec.bandwidth,
+            reconnect: spec.reconnect,
+            gates: Vec::new(),
+        }
+    }
+
+    /// Build the spec to reopen the group with. If the group was opened with
+    /// an identity, `key` must be that identity's key; otherwise it's
+    /// ignored.
+    pub fn spec(&self, key: Option<IdentityKey>) -> Result<GroupSpec, ReopenError> {
+        let policy_settings = match &self.identity {
+            Some(id) => match key {
+                Some(key) if key.id() == id => Some((key, self.policy.clone())),
+                _ => return Err(ReopenError::MissingKey),
+            },
+            None => None,
+        };
+        Ok(GroupSpec {
+            policy_settings,
+            net: self.net.clone(),
+            discovery: self.discovery.clone(),
+            mixnet_locator: self.mixnet_locator,
+            mixnet_comms: self.mixnet_comms,
+            bandwidth: self.bandwidth,
+            reconnect: self.reconnect,
+            peers: None,
+            bootstrap: Vec::new(),
+            token: None,
+        })
+    }
+
+    /// Open the group through a database, then open each of its gates.
+    pub fn reopen<D: Db + ?Sized>(
+        &self,
+        db: &D,
+        key: Option<IdentityKey>,
+    ) -> Result<Reopened, ReopenError> {
+        let group = db.group(self.spec(key)?);
+        let mut gates = Vec::with_capacity(self.gates.len());
+        let mut refused = Vec::new();
+        for saved in &self.gates {
+            match group.gate(&saved.gate, saved.settings.clone()) {
+                Some(gate) => gates.push(gate),
+                None => refused.push(saved.clone()),
+            }
+        }
+        Ok(Reopened {
+            group,
+            gates,
+            refused,
+        })
+    }
+}
+
+/// Save a group under the given label, replacing any group already saved
+/// under it.
+///
+/// If the saved group is changed by someone else between reading it and
+/// committing, the commit fails with
+/// [`CommitError::NameChanged`][crate::transaction::CommitError::NameChanged]
+/// and nothing is changed.
+pub async fn save<D: Db + ?Sized>(
+    db: &D,
+    label: &str,
+    group: &SavedGroup,
+    durability: Durability,
+) -> DbResult<Result<(), CommitErrors>> {
+    let name = saved_name(label);
+    let current = db.name_get(&name).await?;
+    let doc = NewDocument::new(None, group).map_err(|err| {
+        Box::new(DbError::FogOther {
+            context: "encoding saved group".into(),
+            err,
+        })
+    })?;
+    let mut txn = db.txn();
+    let doc = txn
+        .add_new_doc(doc).await?
+        .map_err(|e| Box::new(DbError::Internal(Box::new(e))))?;
+    txn.swap_name_reserved(&name, Some(doc.hash()), current.as_ref());
+    Ok(txn.commit(durability).await?.map(|_| ()))
+}
+
+/// Stop reopening the group saved under the given label. Any of its gates
+/// that are open stay open. Returns false if no group was saved under the
+/// label.
+pub async fn forget<D: Db + ?Sized>(
+    db: &D,
+    label: &str,
+    durability: Durability,
+) -> DbResult<Result<bool, CommitErrors>> {
+    let name = saved_name(label);
+    let Some(current) = db.name_get(&name).await? else {
+        return Ok(Ok(false));
+    };
+    let mut txn = db.txn();
+    txn.swap_name_reserved(&name, None, Some(&current));
+    Ok(txn.commit(durability).await?.map(|_| true))
+}
+
+/// Load the group saved under the given label, if there is one.
+pub async fn load<D: Db + ?Sized>(db: &D, label: &str) -> DbResult<Option<SavedGroup>> {
+    match db.name_get(&saved_name(label)).await? {
+        Some(hash) => read(db, &hash).await,
+        None => Ok(None),
+    }
+}
+
+/// Load every saved group, along with its label, in order by label.
+pub async fn list<D: Db + ?Sized>(db: &D) -> DbResult<Vec<(String, SavedGroup)>> {
+    let mut groups = Vec::new();
+    for (name, hash) in db.name_list_prefix(GATES_PREFIX).await? {
+        if let Some(group) = read(db, &hash).await? {
+            groups.push((name[GATES_PREFIX.len()..].to_owned(), group));
+        }
+    }
+    Ok(groups)
+}
+
+/// Reopen every saved group and its gates. `keys` is asked for the key of
+/// each identity a group was opened with. The results are in order by label.
+pub async fn reopen_all<D: Db + ?Sized>(
+    db: &D,
+    keys: impl Fn(&Identity) -> Option<IdentityKey>,
+) -> DbResult<Vec<(String, Result<Reopened, ReopenError>)>> {
+    Ok(list(db)
+        .await?
+        .into_iter()
+        .map(|(label, group)| {
+            let key = group.identity.as_ref().and_then(&keys);
+            let reopened = group.reopen(db, key);
+            (label, reopened)
+        })
+        .collect())
+}
+
+async fn read<D: Db + ?Sized>(db: &D, hash: &Hash) -> DbResult<Option<SavedGroup>> {
+    let Some(doc) = db.doc_get(hash).await? else {
+        return Ok(None);
+    };
+    doc.deserialize().map(Some).map_err(|err| {
+        Box::new(DbError::FogDoc {
+            context: "decoding saved group".into(),
+            doc: hash.clone(),
+            err,
+        })
+    })
+}
@@ -0,0 +1,69 @@
+//! Finding schemas held by other group members.
+//!
+//! Documents and entries can't be validated, or even decoded, without their
+//! schema. A cursor that reaches a document whose schema isn't in the local
+//! database would otherwise be stuck, so groups can ask their members for the
+//! schema document with [`Group::find_schema`][crate::group::Group::find_schema].
+//!
+//! Installing a schema from another node is a trust decision: schemas can hold
+//! regular expressions that are slow to run, and an installed schema is used
+//! for every document that names it. Cursors only install schemas on their own
+//! when allowed by the [`SchemaInstall`] policy in their
+//! [`CursorOpts`][crate::cursor::CursorOpts], and never do by default.
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use fog_pack::{document::Document, types::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Which schemas a cursor may fetch from group members and install in the
+/// local database when it finds a document whose schema it lacks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SchemaInstall {
+    /// Never install schemas; navigating to such a document fails with
+    /// [`CursorError::MissingSchema`][crate::cursor::CursorError::MissingSchema].
+    #[default]
+    Never,
+    /// Only install the listed schemas.
+    Listed(HashSet<Hash>),
+    /// Install any schema.
+    Any,
+}
+
+impl SchemaInstall {
+    /// Check if a schema may be installed.
+    pub fn allows(&self, schema: &Hash) -> bool {
+        match self {
+            SchemaInstall::Never => false,
+            SchemaInstall::Listed(list) => list.contains(schema),
+            SchemaInstall::Any => true,
+        }
+    }
+}
+
+/// Failure to find a schema in a group.
+#[derive(Clone, Debug, Error, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum SchemaFetchError {
+    /// No member of the group that was asked had the schema.
+    #[error("No group member has schema {0}")]
+    NotFound(Hash),
+    /// A member returned a document that wasn't a valid schema.
+    #[error("Group member returned an invalid schema for {0}")]
+    Invalid(Hash),
+    /// Some other failure occurred.
+    #[error("Schema request failed: {0}")]
+    Other(String),
+}
+
+/// An outgoing request for a schema, waiting on the group members' answers.
+#[async_trait]
+pub trait SchemaRequest: Send + Sync {
+    /// Wait for a group member to return the schema document. The document
+    /// has been checked to match the requested hash, but still needs to be
+    /// added with [`Db::schema_add`][crate::Db::schema_add] before it can be
+    /// used.
+    async fn complete(self: Box<Self>) -> Result<Document, SchemaFetchError>;
+}
@@ -0,0 +1,254 @@
+//! Describing what a gate serves.
+//!
+//! Opening a cursor through a gate only gets a node the gate's root document.
+//! Knowing which schemas to fetch, which documents have [query
+//! hooks][crate::gate::QueryHook] behind them, and what the service is even
+//! for has so far needed knowledge passed along some other way. A
+//! [`ServiceDescriptor`] writes all of that down as a standard document, so a
+//! client can find out how to use a gate from the gate's operator directly.
+//!
+//! Descriptors adhere to the schema from [`ServiceDescriptor::schema`], and are
+//! signed by the identity serving the gate, so a client can check who is
+//! making the claims before acting on them. They're usually made with a
+//! [`ServiceBuilder`], and advertised to the rest of a group with
+//! [`Group::advertise_services`][crate::group::Group::advertise_services].
+//! Other members find them with [`Group::services`][crate::group::Group::services],
+//! picking out the ones they want with a [`ServiceFilter`]: by the schemas a
+//! service uses, or by its tags. Tags are free-form key/value pairs, like
+//! `service = chat-index`, that groups settle on among themselves.
+//!
+//! Like [resource advertisements][crate::resources], descriptors are claims,
+//! not guarantees. A gate may serve less than its descriptor says, or have
+//! been closed since.
+
+use std::collections::BTreeMap;
+
+use fog_crypto::identity::IdentityKey;
+use fog_pack::{
+    document::{Document, NewDocument},
+    error::Error as FogError,
+    schema::SchemaBuilder,
+    types::*,
+    validator::{ArrayValidator, HashValidator, MapValidator, StrValidator, TimeValidator},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A document with a query hook behind it, open to queries through the gate.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryEndpoint {
+    /// The document to query.
+    pub doc: Hash,
+    /// The entry key to query on.
+    pub key: String,
+    /// What the endpoint answers, for people reading the descriptor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A description of what a gate serves.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceDescriptor {
+    /// A short, human-readable name for the service.
+    pub name: String,
+    /// A longer human-readable description of the service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The document the gate is open on.
+    pub root: Hash,
+    /// The schemas a client needs to make use of the service.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schemas: Vec<Hash>,
+    /// The documents with query hooks behind them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<QueryEndpoint>,
+    /// Free-form key/value tags for finding the service by what it does.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// When the descriptor was made. Newer descriptors for the same root
+    /// replace older ones from the same signer.
+    pub time: Timestamp,
+    /// When the descriptor stops being valid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<Timestamp>,
+}
+
+/// What a service must have to be picked by
+/// [`Group::services`][crate::group::Group::services]. The default matches
+/// every service.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceFilter {
+    /// Schemas the service must list, all of them.
+    pub schemas: Vec<Hash>,
+    /// Tags the service must have, each set to the given value.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl ServiceFilter {
+    /// Require the service to list a schema.
+    pub fn schema(mut self, schema: &Hash) -> Self {
+        self.schemas.push(schema.clone());
+        self
+    }
+
+    /// Require the service to have a tag set to a value.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Check if a descriptor passes the filter.
+    pub fn allows(&self, desc: &ServiceDescriptor) -> bool {
+        self.schemas.iter().all(|s| desc.schemas.contains(s))
+            && self.tags.iter().all(|(k, v)| desc.tags.get(k) == Some(v))
+    }
+}
+
+/// Failure to read a service descriptor from a document.
+#[derive(Clone, Debug, Error)]
+pub enum ServiceError {
+    /// The document doesn't adhere to the expected descriptor schema.
+    #[error("Document doesn't use the service descriptor schema")]
+    WrongSchema,
+    /// The document isn't signed, so nobody is vouching for it.
+    #[error("Service descriptor isn't signed")]
+    Unsigned,
+    /// The document's content couldn't be read as a descriptor.
+    #[error("Service descriptor couldn't be read")]
+    Fog(#[from] FogError),
+}
+
+impl ServiceDescriptor {
+    /// Check if the descriptor has expired as of the time `now`.
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires.is_some_and(|expires| now > expires)
+    }
+
+    /// Build the schema document that service descriptors adhere to.
+    pub fn schema() -> Document {
+        let endpoint = MapValidator::new()
+            .req_add("doc", HashValidator::new().build())
+            .req_add("key", StrValidator::new().build())
+            .opt_add("description", StrValidator::new().build())
+            .build();
+        let doc = MapValidator::new()
+            .req_add("name", StrValidator::new().build())
+            .opt_add("description", StrValidator::new().build())
+            .req_add("root", HashValidator::new().build())
+            .opt_add(
+                "schemas",
+                ArrayValidator::new()
+                    .items(HashValidator::new().build())
+                    .build(),
+            )
+            .opt_add("endpoints", ArrayValidator::new().items(endpoint).build())
+            .opt_add(
+                "tags",
+                MapValidator::new()
+                    .values(StrValidator::new().build())
+                    .build(),
+            )
+            .req_add("time", TimeValidator::new().build())
+            .opt_add("expires", TimeValidator::new().build())
+            .build();
+        SchemaBuilder::new(doc)
+            .name("fog-db service descriptor")
+            .version(1u8)
+            .build()
+            .expect("service descriptor schema should always be valid")
+    }
+
+    /// Make a descriptor document adhering to the given descriptor schema,
+    /// signed by the identity serving the gate.
+    pub fn to_doc(&self, schema: &Hash, key: &IdentityKey) -> Result<NewDocument, FogError> {
+        NewDocument::new(Some(schema), self)?.sign(key)
+    }
+
+    /// Read a descriptor from a document, along with the identity that signed
+    /// it. The document should already have been validated against the
+    /// descriptor schema, whose hash is `schema`.
+    pub fn from_doc(doc: &Document, schema: &Hash) -> Result<(Self, Identity), ServiceError> {
+        if doc.schema_hash() != Some(schema) {
+            return Err(ServiceError::WrongSchema);
+        }
+        let signer = doc.signer().ok_or(ServiceError::Unsigned)?.clone();
+        Ok((doc.deserialize()?, signer))
+    }
+}
+
+/// Builds a [`ServiceDescriptor`] one piece at a time.
+#[derive(Clone, Debug)]
+pub struct ServiceBuilder {
+    desc: ServiceDescriptor,
+}
+
+impl ServiceBuilder {
+    /// Start describing the service behind a gate open on `root`, made at the
+    /// time `time`.
+    pub fn new(name: impl Into<String>, root: &Hash, time: Timestamp) -> Self {
+        Self {
+            desc: ServiceDescriptor {
+                name: name.into(),
+                description: None,
+                root: root.clone(),
+                schemas: Vec::new(),
+                endpoints: Vec::new(),
+                tags: BTreeMap::new(),
+                time,
+                expires: None,
+            },
+        }
+    }
+
+    /// Set the human-readable description of the service.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.desc.description = Some(description.into());
+        self
+    }
+
+    /// Add a schema needed to make use of the service.
+    pub fn schema(mut self, schema: &Hash) -> Self {
+        if !self.desc.schemas.contains(schema) {
+            self.desc.schemas.push(schema.clone());
+        }
+        self
+    }
+
+    /// Add a document with a query hook behind it, queried on `key`.
+    pub fn endpoint(
+        mut self,
+        doc: &Hash,
+        key: impl Into<String>,
+        description: Option<String>,
+    ) -> Self {
+        self.desc.endpoints.push(QueryEndpoint {
+            doc: doc.clone(),
+            key: key.into(),
+            description,
+        });
+        self
+    }
+
+    /// Set a tag, replacing any previous value for the same key.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.desc.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set when the descriptor stops being valid.
+    pub fn expires(mut self, expires: Timestamp) -> Self {
+        self.desc.expires = Some(expires);
+        self
+    }
+
+    /// Finish the descriptor without signing it.
+    pub fn build(self) -> ServiceDescriptor {
+        self.desc
+    }
+
+    /// Finish the descriptor as a document adhering to the given descriptor
+    /// schema, signed by the identity serving the gate.
+    pub fn sign(self, schema: &Hash, key: &IdentityKey) -> Result<NewDocument, FogError> {
+        self.desc.to_doc(schema, key)
+    }
+}
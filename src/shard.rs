@@ -0,0 +1,154 @@
+//! Spreading a heavily written entry key across several parent documents.
+//!
+//! Every entry lives under a single parent document, so a key that takes a
+//! steady stream of writes, like a chat log or an event feed, piles all of
+//! them onto one document. Past a few million entries, that one document
+//! becomes a bottleneck: every write contends on it, every query scans it,
+//! and every node syncing it has to hold the whole thing.
+//!
+//! The convention for avoiding this is to shard the key. The application
+//! makes several parent documents, all using the same schema but differing in
+//! some field so they hash differently, and publishes a [`ShardDescriptor`]
+//! document listing them. Writers pick a shard for each entry with
+//! [`ShardDescriptor::route`], and readers open a cursor on the descriptor
+//! and run their query on every shard at once with [`query_shards`].
+//!
+//! The descriptor links to its shards, so pinning the descriptor keeps every
+//! shard resident. The number of shards is fixed once the descriptor is
+//! published; to change it, publish a new descriptor with new shards, and
+//! move or re-route entries as needed.
+
+use fog_pack::{
+    document::{Document, NewDocument},
+    error::Error as FogError,
+    query::NewQuery,
+    types::*,
+};
+use futures::future::{join_all, select_all};
+use serde::{Deserialize, Serialize};
+
+use crate::cursor::{Cursor, CursorError, CursorQuery, DbQuery, QueryUpdate};
+
+/// A document listing the parent documents an entry key is sharded across.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardDescriptor {
+    /// The entry key being sharded.
+    pub key: String,
+    /// The shard documents, in order. Routing depends on this order, so it
+    /// must never change for a published descriptor.
+    pub shards: Vec<Hash>,
+}
+
+impl ShardDescriptor {
+    /// Describe a key sharded across the given documents.
+    pub fn new(key: impl Into<String>, shards: Vec<Hash>) -> Self {
+        Self {
+            key: key.into(),
+            shards,
+        }
+    }
+
+    /// The number of shards.
+    pub fn count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Pick the shard an entry belongs in, given some routing value for it,
+    /// like the identity of its author or a conversation ID. The same routing
+    /// value always goes to the same shard. Returns `None` if there are no
+    /// shards.
+    pub fn route(&self, routing: impl AsRef<[u8]>) -> Option<&Hash> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let hash = Hash::new(routing);
+        let mut bytes = [0u8; 8];
+        let digest = hash.digest();
+        let len = digest.len().min(8);
+        bytes[..len].copy_from_slice(&digest[..len]);
+        let index = u64::from_le_bytes(bytes) % self.shards.len() as u64;
+        self.shards.get(index as usize)
+    }
+
+    /// Make the descriptor document.
+    pub fn to_doc(&self) -> NewDocument {
+        NewDocument::new(None, self).expect("ShardDescriptor should always be serializable")
+    }
+
+    /// Read a descriptor from a document.
+    pub fn from_doc(doc: &Document) -> Result<Self, FogError> {
+        doc.deserialize()
+    }
+}
+
+/// Run a query on every shard listed in a descriptor. The cursor must be on
+/// the descriptor document. The query's key is replaced with the descriptor's
+/// key. Fails if any of the shards can't be reached.
+pub async fn query_shards(
+    cursor: &dyn Cursor,
+    desc: &ShardDescriptor,
+    query: DbQuery,
+) -> Result<ShardedQuery, CursorError> {
+    let forks = desc.shards.iter().map(|shard| cursor.fork(shard).complete());
+    let mut queries = Vec::with_capacity(desc.shards.len());
+    for fork in join_all(forks).await {
+        let (cursor, _) = fork?;
+        let mut query = query.clone();
+        query.query = NewQuery::new(&desc.key, query.query.validator().clone());
+        queries.push(cursor.query(query));
+    }
+    Ok(ShardedQuery { queries, next: 0 })
+}
+
+/// A query running on every shard of a key, from [`query_shards`].
+///
+/// Updates are returned as they arrive from each shard, tagged with the index
+/// of the shard they came from. Results from different shards aren't
+/// ordered with respect to each other; collect them and sort with
+/// [`ResultOrd`][crate::cursor::ResultOrd] if the order matters.
+pub struct ShardedQuery {
+    queries: Vec<Box<dyn CursorQuery>>,
+    next: usize,
+}
+
+impl ShardedQuery {
+    /// The number of shards being queried.
+    pub fn count(&self) -> usize {
+        self.queries.len()
+    }
+
+    /// The query running on one shard.
+    pub fn shard(&self, index: usize) -> Option<&dyn CursorQuery> {
+        self.queries.get(index).map(|q| q.as_ref())
+    }
+
+    /// Get the next update from any shard, along with the shard's index.
+    /// Waits forever if there are no shards.
+    pub async fn next(&self) -> (usize, QueryUpdate) {
+        if self.queries.is_empty() {
+            return futures::future::pending().await;
+        }
+        let (update, index, _) = select_all(self.queries.iter().map(|q| q.next())).await;
+        (index, update)
+    }
+
+    /// Try to get the next update from any shard, returning `None` if none
+    /// have one ready. Shards are checked in turn, so a busy shard doesn't
+    /// hold back the others.
+    pub fn try_next(&mut self) -> Option<(usize, QueryUpdate)> {
+        let count = self.queries.len();
+        for _ in 0..count {
+            let index = self.next;
+            self.next = (self.next + 1) % count;
+            if let Some(update) = self.queries[index].try_next() {
+                return Some((index, update));
+            }
+        }
+        None
+    }
+
+    /// Give up on the query, returning a cursor on each shard document.
+    pub fn back(self) -> Vec<Box<dyn Cursor>> {
+        self.queries.into_iter().map(|q| q.back()).collect()
+    }
+}
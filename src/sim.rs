@@ -0,0 +1,1477 @@
+//! A deterministic network simulator, for testing code built on groups, gates,
+//! and cursors.
+//!
+//! A [`SimNetwork`] holds a set of simulated nodes, each with its own documents,
+//! entries, and open gates. [`SimNetwork::group`] gives a [`Group`] as seen
+//! from one of those nodes, and cursors opened on it fetch documents and run
+//! queries against the other nodes. Every request between nodes takes a
+//! configurable amount of time and may be lost, and nodes can be taken offline,
+//! split into partitions, or churned at random.
+//!
+//! Nothing here uses real time or real randomness. Delays run on the
+//! [`Scheduler`]'s virtual clock, and every random choice comes from a
+//! [`SimRng`] seeded by [`SimConfig::seed`], so a test run with the same seed
+//! and the same sequence of calls always plays out the same way. Futures
+//! returned by simulated cursors only make progress while being driven by
+//! [`Scheduler::run`] or [`Scheduler::run_until`].
+//!
+//! The simulation covers document retrieval and entry queries. Resource
+//! advertisements are taken at face value, without signing, and service
+//! descriptors never expire. Pins are always refused, gates never report
+//! attached cursors or events or forward queries, and query options beyond
+//! [`sources`][crate::cursor::DbQuery::sources] and
+//! [`revalidate`][crate::cursor::DbQuery::revalidate] are ignored. Barriers
+//! ask every reachable node for its fingerprint, and a summary of its holdings
+//! if that doesn't match, once per round trip, and wait 100ms of virtual time
+//! between rounds. Simulated connections cost nothing
+//! to open, so warming one only checks that the node is reachable.
+//!
+//! This module is only available with the `sim` feature.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use fog_pack::{
+    document::Document,
+    entry::{Entry, EntryRef},
+    error::Error as FogError,
+    query::Query,
+    schema::Schema,
+    types::*,
+};
+use futures::future::{join_all, select, Either};
+
+use crate::{
+    anomaly::AnomalyDetector,
+    availability::{
+        AvailabilitySummary, SummaryError, SummaryExchange, SummaryReply,
+        DEFAULT_FALSE_POSITIVE_RATE,
+    },
+    barrier::{Barrier, BarrierError, BarrierReport},
+    cursor::{
+        ChunkStream, ChunkUpdate, Cursor, CursorBackError, CursorError, CursorOpts, CursorQuery,
+        DbQuery, DocChunk, ForkCursor, ForkSpawner, LinkStrength, MergeStrategy, NewCursor,
+        QueryResult, QueryUpdate, Refresh, TraceId, UsefulReport, Usefulness,
+    },
+    fingerprint::{doc_fingerprint, TreeFingerprint},
+    gate::{Gate, GateEvent, GateEvents, GateSettings, QueryHook, Tier},
+    group::Group,
+    pinning::{HostedPin, PinError, PinGrant, PinPolicy, PinRequest},
+    quota::{QuotaUsage, StorageQuota},
+    resources::{ResourceFilter, Resources},
+    schema_fetch::{SchemaFetchError, SchemaRequest},
+    service::{ServiceDescriptor, ServiceFilter},
+    skew::SkewPolicy,
+    transaction::EncodedDoc,
+    warm::{WarmError, Warmed, Warmup},
+    DbResult, NetType, NodeAddr, NodeInfo,
+};
+
+/// A small, fast, seedable random number generator (SplitMix64). Not suitable
+/// for anything but simulation.
+#[derive(Clone, Debug)]
+pub struct SimRng(u64);
+
+impl SimRng {
+    /// Create a generator from a seed.
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Get the next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Get a random number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Return true with probability `p`.
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    /// Get a random duration between zero and `max`, inclusive.
+    pub fn duration(&mut self, max: Duration) -> Duration {
+        let max = u64::try_from(max.as_nanos()).unwrap_or(u64::MAX - 1);
+        if max == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.next_u64() % (max + 1))
+    }
+}
+
+#[derive(Default)]
+struct SchedState {
+    now: Duration,
+    seq: u64,
+    timers: BinaryHeap<Reverse<(Duration, u64)>>,
+    fired: HashSet<u64>,
+    cancelled: HashSet<u64>,
+}
+
+/// A virtual clock. Time only moves forward when the scheduler is advanced,
+/// and it always jumps straight to the next pending timer. Cloning gives
+/// another handle to the same clock.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    inner: Arc<Mutex<SchedState>>,
+}
+
+impl Scheduler {
+    /// Create a new scheduler, starting at time zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The time elapsed since the scheduler was created.
+    pub fn now(&self) -> Duration {
+        self.inner.lock().unwrap().now
+    }
+
+    /// Get a future that completes once `delay` has passed on the virtual
+    /// clock.
+    pub fn sleep(&self, delay: Duration) -> Sleep {
+        let mut state = self.inner.lock().unwrap();
+        let id = state.seq;
+        state.seq += 1;
+        let at = state.now + delay;
+        state.timers.push(Reverse((at, id)));
+        Sleep {
+            sched: self.clone(),
+            id,
+            done: false,
+        }
+    }
+
+    /// Move the clock forward to the next pending timer and fire it. Returns
+    /// false if there are no pending timers.
+    pub fn advance(&self) -> bool {
+        self.advance_to(None)
+    }
+
+    fn advance_to(&self, deadline: Option<Duration>) -> bool {
+        let mut state = self.inner.lock().unwrap();
+        while let Some(Reverse((at, id))) = state.timers.peek().copied() {
+            if deadline.is_some_and(|d| at > d) {
+                break;
+            }
+            state.timers.pop();
+            if state.cancelled.remove(&id) {
+                continue;
+            }
+            state.now = state.now.max(at);
+            state.fired.insert(id);
+            return true;
+        }
+        if let Some(deadline) = deadline {
+            state.now = state.now.max(deadline);
+        }
+        false
+    }
+
+    /// Drive a future to completion, advancing the clock whenever it's
+    /// waiting. Returns `None` if the future is still waiting once there are
+    /// no timers left to fire.
+    pub fn run<F: Future>(&self, fut: F) -> Option<F::Output> {
+        self.drive(fut, None)
+    }
+
+    /// Drive a future until it completes or the clock reaches `deadline`,
+    /// whichever comes first. Returns `None` if the deadline was reached
+    /// first, in which case the clock is left at the deadline.
+    pub fn run_until<F: Future>(&self, deadline: Duration, fut: F) -> Option<F::Output> {
+        self.drive(fut, Some(deadline))
+    }
+
+    fn drive<F: Future>(&self, fut: F, deadline: Option<Duration>) -> Option<F::Output> {
+        let mut fut = std::pin::pin!(fut);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return Some(out);
+            }
+            if !self.advance_to(deadline) {
+                return None;
+            }
+        }
+    }
+}
+
+/// A future that completes at a set time on a [`Scheduler`]'s clock.
+pub struct Sleep {
+    sched: Scheduler,
+    id: u64,
+    done: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.done {
+            return Poll::Ready(());
+        }
+        let fired = self.sched.inner.lock().unwrap().fired.remove(&self.id);
+        if fired {
+            self.done = true;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut state = self.sched.inner.lock().unwrap();
+            if !state.fired.remove(&self.id) {
+                state.cancelled.insert(self.id);
+            }
+        }
+    }
+}
+
+/// Settings for a [`SimNetwork`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimConfig {
+    /// Seed for every random choice made by the network.
+    pub seed: u64,
+    /// Base round-trip time of a request between two nodes.
+    pub latency: Duration,
+    /// Up to this much extra time is randomly added to each round trip.
+    pub jitter: Duration,
+    /// Probability that a request is lost, between 0 and 1.
+    pub loss: f64,
+    /// How many times a lost request is retried before giving up.
+    pub retries: u32,
+    /// Size of the chunks delivered by chunked fetches, in bytes.
+    pub chunk_size: usize,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(10),
+            loss: 0.0,
+            retries: 3,
+            chunk_size: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SimNode {
+    online: bool,
+    partition: usize,
+    docs: HashMap<Hash, Arc<Document>>,
+    entries: HashMap<Hash, Vec<Entry>>,
+    gates: HashSet<Hash>,
+    resources: Option<Resources>,
+    services: Vec<Arc<Document>>,
+}
+
+struct NetState {
+    config: SimConfig,
+    rng: SimRng,
+    order: Vec<NodeAddr>,
+    nodes: HashMap<NodeAddr, SimNode>,
+    schemas: HashMap<Hash, Arc<Schema>>,
+}
+
+impl NetState {
+    fn reachable(&self, from: &NodeAddr, to: &NodeAddr) -> bool {
+        match (self.nodes.get(from), self.nodes.get(to)) {
+            (Some(a), Some(b)) => a.online && b.online && a.partition == b.partition,
+            _ => false,
+        }
+    }
+
+    /// Remote nodes reachable from `local`, in the order they were added.
+    fn peers<'a>(&'a self, local: &'a NodeAddr) -> impl Iterator<Item = &'a NodeAddr> + 'a {
+        self.order
+            .iter()
+            .filter(move |n| *n != local && self.reachable(local, n))
+    }
+
+    fn local_doc(&self, local: &NodeAddr, hash: &Hash) -> Option<Arc<Document>> {
+        self.nodes.get(local)?.docs.get(hash).cloned()
+    }
+
+    /// Find a reachable node holding a document. If `gate` is set, only nodes
+    /// with a gate open on that document are considered.
+    fn remote_doc(&self, local: &NodeAddr, hash: &Hash, gate: bool) -> Option<Arc<Document>> {
+        self.peers(local).find_map(|n| {
+            let node = &self.nodes[n];
+            if gate && !node.gates.contains(hash) {
+                return None;
+            }
+            node.docs.get(hash).cloned()
+        })
+    }
+
+    /// Find the documents a node holds in the tree under `root`, following
+    /// links from documents and their entries, and whether it holds all of
+    /// them.
+    fn tree(&self, node: &NodeAddr, root: &Hash) -> (HashSet<Hash>, bool) {
+        let mut held = HashSet::new();
+        let mut complete = true;
+        if let Some(node) = self.nodes.get(node) {
+            let mut queue = vec![root.clone()];
+            while let Some(hash) = queue.pop() {
+                if held.contains(&hash) {
+                    continue;
+                }
+                let Some(doc) = node.docs.get(&hash) else {
+                    complete = false;
+                    continue;
+                };
+                queue.extend(doc.find_hashes());
+                for entry in node.entries.get(&hash).into_iter().flatten() {
+                    queue.extend(entry.find_hashes());
+                }
+                held.insert(hash);
+            }
+        }
+        (held, complete)
+    }
+
+    fn summary(&self, node: &NodeAddr, root: &Hash) -> AvailabilitySummary {
+        let (held, complete) = self.tree(node, root);
+        AvailabilitySummary::new(root, held.iter(), complete, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    /// Get the hashes of every document and entry a node holds in the tree
+    /// under `root`.
+    fn holdings(&self, node: &NodeAddr, root: &Hash) -> HashSet<Hash> {
+        let (docs, _) = self.tree(node, root);
+        let Some(node) = self.nodes.get(node) else {
+            return docs;
+        };
+        let entries: Vec<Hash> = docs
+            .iter()
+            .flat_map(|doc| node.entries.get(doc).into_iter().flatten())
+            .map(|entry| entry.hash().clone())
+            .collect();
+        docs.into_iter().chain(entries).collect()
+    }
+
+    /// Get a node's fingerprint of the tree under `root`, or `None` if it
+    /// doesn't hold the root.
+    fn fingerprint(&self, node: &NodeAddr, root: &Hash) -> Option<TreeFingerprint> {
+        let node = self.nodes.get(node)?;
+        let (fingerprint, complete) = doc_fingerprint_of(node, root)?;
+        Some(TreeFingerprint {
+            root: root.clone(),
+            fingerprint,
+            complete,
+        })
+    }
+
+    /// Encode a document, or return `None` if its schema isn't known to the
+    /// network.
+    fn encode(&self, doc: &Document) -> Option<Bytes> {
+        let schema = match doc.schema_hash() {
+            Some(hash) => Some(self.schemas.get(hash)?.as_ref()),
+            None => None,
+        };
+        let (doc, _) = EncodedDoc::from_doc(schema, doc.clone());
+        Some(doc.data().clone())
+    }
+}
+
+/// Get the fingerprint of a document held by a node, and whether everything
+/// below it is held.
+fn doc_fingerprint_of(node: &SimNode, root: &Hash) -> Option<(Hash, bool)> {
+    // Each document is visited twice: once to queue the documents it links
+    // to, and again once their fingerprints are all known.
+    let mut done: HashMap<Hash, (Hash, bool)> = HashMap::new();
+    let mut stack = vec![(root.clone(), false)];
+    while let Some((doc, ready)) = stack.pop() {
+        if done.contains_key(&doc) {
+            continue;
+        }
+        let Some(held) = node.docs.get(&doc) else {
+            continue;
+        };
+        let links: BTreeSet<Hash> = held.find_hashes().into_iter().collect();
+        if !ready {
+            stack.push((doc, true));
+            stack.extend(links.into_iter().map(|link| (link, false)));
+            continue;
+        }
+        let mut entries: BTreeMap<String, BTreeSet<Hash>> = BTreeMap::new();
+        for entry in node.entries.get(&doc).into_iter().flatten() {
+            entries
+                .entry(entry.key().to_owned())
+                .or_default()
+                .insert(entry.hash().clone());
+        }
+        let mut linked = BTreeMap::new();
+        let mut complete = true;
+        for link in links {
+            let found = done.get(&link);
+            complete &= found.is_some_and(|(_, c)| *c);
+            linked.insert(link, found.map(|(f, _)| f.clone()));
+        }
+        let fingerprint = doc_fingerprint(&doc, &entries, &linked);
+        done.insert(doc, (fingerprint, complete));
+    }
+    done.remove(root)
+}
+
+struct NetInner {
+    sched: Scheduler,
+    state: Mutex<NetState>,
+    next_trace: AtomicU64,
+}
+
+/// A simulated network of database nodes. Cloning gives another handle to the
+/// same network.
+#[derive(Clone)]
+pub struct SimNetwork {
+    inner: Arc<NetInner>,
+}
+
+impl SimNetwork {
+    /// Create an empty network.
+    pub fn new(config: SimConfig) -> Self {
+        Self {
+            inner: Arc::new(NetInner {
+                sched: Scheduler::new(),
+                state: Mutex::new(NetState {
+                    config,
+                    rng: SimRng::new(config.seed),
+                    order: Vec::new(),
+                    nodes: HashMap::new(),
+                    schemas: HashMap::new(),
+                }),
+                next_trace: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Get the scheduler driving the network.
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.inner.sched
+    }
+
+    /// Hand out the next trace ID for a cursor or query. IDs count up from 0
+    /// in the order things are opened, so they're the same on every run.
+    fn trace_id(&self) -> TraceId {
+        TraceId(self.inner.next_trace.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Get the network's settings.
+    pub fn config(&self) -> SimConfig {
+        self.inner.state.lock().unwrap().config
+    }
+
+    /// Change the network's settings. The random number generator is not
+    /// reseeded.
+    pub fn set_config(&self, config: SimConfig) {
+        self.inner.state.lock().unwrap().config = config;
+    }
+
+    /// Add an online node with nothing in it. Does nothing if the node is
+    /// already in the network.
+    pub fn add_node(&self, node: NodeAddr) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.nodes.contains_key(&node) {
+            return;
+        }
+        state.order.push(node.clone());
+        state.nodes.insert(
+            node,
+            SimNode {
+                online: true,
+                ..SimNode::default()
+            },
+        );
+    }
+
+    /// Get every node in the network, in the order they were added.
+    pub fn nodes(&self) -> Vec<NodeAddr> {
+        self.inner.state.lock().unwrap().order.clone()
+    }
+
+    /// Make a schema known to every node in the network.
+    pub fn add_schema(&self, doc: &Document) -> Result<Hash, FogError> {
+        let schema = Schema::from_doc(doc)?;
+        let hash = doc.hash().clone();
+        let mut state = self.inner.state.lock().unwrap();
+        state.schemas.insert(hash.clone(), Arc::new(schema));
+        Ok(hash)
+    }
+
+    /// Store a document on a node. Returns false if the node isn't in the
+    /// network.
+    pub fn add_doc(&self, node: &NodeAddr, doc: Arc<Document>) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        let Some(node) = state.nodes.get_mut(node) else {
+            return false;
+        };
+        node.docs.insert(doc.hash().clone(), doc);
+        true
+    }
+
+    /// Store an entry on a node. Returns false if the node isn't in the
+    /// network.
+    pub fn add_entry(&self, node: &NodeAddr, entry: Entry) -> bool {
+        let mut state = self.inner.state.lock().unwrap();
+        let Some(node) = state.nodes.get_mut(node) else {
+            return false;
+        };
+        let entries = node.entries.entry(entry.parent().clone()).or_default();
+        if !entries.iter().any(|e| e.hash() == entry.hash()) {
+            entries.push(entry);
+        }
+        true
+    }
+
+    /// Remove an entry from a node.
+    pub fn del_entry(&self, node: &NodeAddr, entry: &EntryRef) {
+        let mut state = self.inner.state.lock().unwrap();
+        if let Some(entries) = state
+            .nodes
+            .get_mut(node)
+            .and_then(|n| n.entries.get_mut(&entry.parent))
+        {
+            entries.retain(|e| e.hash() != &entry.hash);
+        }
+    }
+
+    /// Open a gate on a node, as if that node's application had done so.
+    pub fn open_gate(&self, node: &NodeAddr, doc: &Hash) {
+        if let Some(node) = self.inner.state.lock().unwrap().nodes.get_mut(node) {
+            node.gates.insert(doc.clone());
+        }
+    }
+
+    /// Close a gate on a node.
+    pub fn close_gate(&self, node: &NodeAddr, doc: &Hash) {
+        if let Some(node) = self.inner.state.lock().unwrap().nodes.get_mut(node) {
+            node.gates.remove(doc);
+        }
+    }
+
+    /// Take a node on or offline. Offline nodes can't be reached, and can't
+    /// reach anyone else.
+    pub fn set_online(&self, node: &NodeAddr, online: bool) {
+        if let Some(node) = self.inner.state.lock().unwrap().nodes.get_mut(node) {
+            node.online = online;
+        }
+    }
+
+    /// Check if a node is online.
+    pub fn is_online(&self, node: &NodeAddr) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        state.nodes.get(node).is_some_and(|n| n.online)
+    }
+
+    /// Split the network. Nodes in each listed side can only reach other
+    /// nodes on the same side, and nodes not listed anywhere share a side of
+    /// their own. Replaces any previous partitioning.
+    pub fn partition(&self, sides: &[&[NodeAddr]]) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.nodes.values_mut().for_each(|n| n.partition = 0);
+        for (i, side) in sides.iter().enumerate() {
+            for node in side.iter() {
+                if let Some(node) = state.nodes.get_mut(node) {
+                    node.partition = i + 1;
+                }
+            }
+        }
+    }
+
+    /// Remove all partitions.
+    pub fn heal(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.nodes.values_mut().for_each(|n| n.partition = 0);
+    }
+
+    /// Flip each node on or offline with probability `p`, returning the nodes
+    /// that changed.
+    pub fn churn(&self, p: f64) -> Vec<NodeAddr> {
+        let mut state = self.inner.state.lock().unwrap();
+        let state = &mut *state;
+        let mut changed = Vec::new();
+        for addr in state.order.iter() {
+            if state.rng.chance(p) {
+                let node = state.nodes.get_mut(addr).unwrap();
+                node.online = !node.online;
+                changed.push(addr.clone());
+            }
+        }
+        changed
+    }
+
+    /// Get a group as seen from one of the network's nodes. The group covers
+    /// every other node in the network.
+    pub fn group(&self, local: &NodeAddr) -> SimGroup {
+        self.add_node(local.clone());
+        SimGroup {
+            net: self.clone(),
+            local: local.clone(),
+            quota: Mutex::new(None),
+            skew: Mutex::new(None),
+        }
+    }
+
+    /// Make a request from `local`, which takes one round trip and is retried
+    /// if lost. `answer` looks at the network once the request arrives, and
+    /// returns `None` if no answer could be given.
+    async fn request<T>(&self, answer: impl Fn(&NetState) -> Option<T>) -> Option<T> {
+        let retries = self.config().retries;
+        for _ in 0..=retries {
+            let (delay, lost) = {
+                let mut state = self.inner.state.lock().unwrap();
+                let config = state.config;
+                let delay = config.latency + state.rng.duration(config.jitter);
+                (delay, state.rng.chance(config.loss))
+            };
+            self.inner.sched.sleep(delay).await;
+            if !lost {
+                return answer(&self.inner.state.lock().unwrap());
+            }
+        }
+        None
+    }
+}
+
+fn node_info(node: &NodeAddr) -> NodeInfo {
+    NodeInfo {
+        net: NetType::Other("sim".into()),
+        perm_id: Some(node.perm_id.clone()),
+        eph_id: Some(node.eph_id.clone()),
+        time: None,
+        resources: None,
+    }
+}
+
+/// A [`Group`] covering a [`SimNetwork`], as seen from one of its nodes.
+pub struct SimGroup {
+    net: SimNetwork,
+    local: NodeAddr,
+    quota: Mutex<Option<StorageQuota>>,
+    skew: Mutex<Option<SkewPolicy>>,
+}
+
+impl SimGroup {
+    /// Get the network this group is on.
+    pub fn network(&self) -> &SimNetwork {
+        &self.net
+    }
+
+    /// Get the node this group is seen from.
+    pub fn local(&self) -> &NodeAddr {
+        &self.local
+    }
+}
+
+impl Group for SimGroup {
+    fn gate(&self, gate: &Hash, _settings: Option<GateSettings>) -> Option<Box<dyn Gate>> {
+        let mut state = self.net.inner.state.lock().unwrap();
+        let node = state.nodes.get_mut(&self.local)?;
+        if !node.gates.insert(gate.clone()) {
+            return None;
+        }
+        Some(Box::new(SimGate {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            gate: gate.clone(),
+        }))
+    }
+
+    fn cursor(&self, gate: &Hash, _opts: CursorOpts) -> Box<dyn ForkCursor> {
+        Box::new(SimFork {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            hash: gate.clone(),
+            gate: true,
+            error: None,
+        })
+    }
+
+    fn set_storage_quota(&self, quota: Option<StorageQuota>) {
+        *self.quota.lock().unwrap() = quota;
+    }
+
+    fn storage_quota(&self) -> Option<StorageQuota> {
+        *self.quota.lock().unwrap()
+    }
+
+    fn storage_usage(&self) -> QuotaUsage {
+        let state = self.net.inner.state.lock().unwrap();
+        let Some(node) = state.nodes.get(&self.local) else {
+            return QuotaUsage::default();
+        };
+        QuotaUsage {
+            docs: node.docs.len() as u64,
+            bytes: node
+                .docs
+                .values()
+                .filter_map(|d| state.encode(d))
+                .map(|d| d.len() as u64)
+                .sum(),
+        }
+    }
+
+    fn pin_request(&self, _node: &NodeAddr, _root: &Hash, _ttl: Duration) -> Box<dyn PinRequest> {
+        Box::new(SimPin)
+    }
+
+    fn set_pin_policy(&self, _policy: Box<dyn PinPolicy>) {}
+
+    fn hosted_pins(&self) -> Vec<HostedPin> {
+        Vec::new()
+    }
+
+    fn set_skew_policy(&self, policy: Option<SkewPolicy>) {
+        *self.skew.lock().unwrap() = policy;
+    }
+
+    fn skew_policy(&self) -> SkewPolicy {
+        self.skew.lock().unwrap().unwrap_or_default()
+    }
+
+    fn find_schema(&self, schema: &Hash) -> Box<dyn SchemaRequest> {
+        Box::new(SimSchemaRequest {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            hash: schema.clone(),
+        })
+    }
+
+    fn advertise_resources(&self, resources: Option<Resources>) {
+        let mut state = self.net.inner.state.lock().unwrap();
+        if let Some(node) = state.nodes.get_mut(&self.local) {
+            node.resources = resources;
+        }
+    }
+
+    fn members_by_resource(&self, filter: &ResourceFilter) -> Vec<(NodeInfo, Resources)> {
+        let state = self.net.inner.state.lock().unwrap();
+        state
+            .peers(&self.local)
+            .filter_map(|n| {
+                let resources = state.nodes[n].resources?;
+                filter
+                    .allows(&resources)
+                    .then(|| (node_info(n), resources))
+            })
+            .collect()
+    }
+
+    fn advertise_services(&self, services: Vec<Arc<Document>>) {
+        let mut state = self.net.inner.state.lock().unwrap();
+        if let Some(node) = state.nodes.get_mut(&self.local) {
+            node.services = services;
+        }
+    }
+
+    fn services(&self, filter: ServiceFilter) -> Vec<(NodeAddr, ServiceDescriptor)> {
+        let schema = ServiceDescriptor::schema().hash().clone();
+        let state = self.net.inner.state.lock().unwrap();
+        state
+            .peers(&self.local)
+            .flat_map(|n| {
+                state.nodes[n]
+                    .services
+                    .iter()
+                    .filter_map(|doc| ServiceDescriptor::from_doc(doc, &schema).ok())
+                    .filter(|(desc, signer)| {
+                        (signer == &n.perm_id || signer == &n.eph_id) && filter.allows(desc)
+                    })
+                    .map(|(desc, _)| (n.clone(), desc))
+            })
+            .collect()
+    }
+
+    fn summary(&self, root: &Hash) -> AvailabilitySummary {
+        self.net.inner.state.lock().unwrap().summary(&self.local, root)
+    }
+
+    fn exchange_summary(&self, node: &NodeAddr, have: AvailabilitySummary) -> Box<dyn SummaryExchange> {
+        Box::new(SimSummaryExchange {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            node: node.clone(),
+            have,
+        })
+    }
+
+    fn barrier(&self, root: &Hash, min_peers: usize, timeout: Duration) -> Box<dyn Barrier> {
+        Box::new(SimBarrier {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            root: root.clone(),
+            min_peers,
+            timeout,
+        })
+    }
+
+    fn warm(&self, nodes: &[NodeAddr]) -> Box<dyn Warmup> {
+        Box::new(SimWarmup {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            nodes: nodes.to_vec(),
+        })
+    }
+}
+
+struct SimPin;
+
+#[async_trait]
+impl PinRequest for SimPin {
+    async fn complete(self: Box<Self>) -> Result<PinGrant, PinError> {
+        Err(PinError::Refused("Pins aren't simulated".into()))
+    }
+}
+
+struct SimSummaryExchange {
+    net: SimNetwork,
+    local: NodeAddr,
+    node: NodeAddr,
+    have: AvailabilitySummary,
+}
+
+#[async_trait]
+impl SummaryExchange for SimSummaryExchange {
+    async fn complete(self: Box<Self>) -> Result<SummaryReply, SummaryError> {
+        let root = self.have.root.clone();
+        let (held, complete) = self
+            .net
+            .request(|s| {
+                s.reachable(&self.local, &self.node)
+                    .then(|| s.tree(&self.node, &root))
+            })
+            .await
+            .ok_or(SummaryError::Unreachable)?;
+        if held.is_empty() {
+            return Err(SummaryError::NotHeld(root));
+        }
+        let missing = self.have.missing(&held);
+        let summary =
+            AvailabilitySummary::new(&root, held.iter(), complete, DEFAULT_FALSE_POSITIVE_RATE);
+        Ok(SummaryReply { summary, missing })
+    }
+}
+
+/// How long a barrier waits between rounds of asking for fingerprints.
+const BARRIER_POLL: Duration = Duration::from_millis(100);
+
+struct SimBarrier {
+    net: SimNetwork,
+    local: NodeAddr,
+    root: Hash,
+    min_peers: usize,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl Barrier for SimBarrier {
+    async fn complete(self: Box<Self>) -> Result<BarrierReport, BarrierError> {
+        let sched = self.net.scheduler().clone();
+        let deadline = sched.now() + self.timeout;
+        let (target, held) = {
+            let state = self.net.inner.state.lock().unwrap();
+            let target = state
+                .fingerprint(&self.local, &self.root)
+                .ok_or_else(|| BarrierError::NotHeld(self.root.clone()))?;
+            (target, state.holdings(&self.local, &self.root))
+        };
+        let mut confirmed: Vec<NodeAddr> = Vec::new();
+        loop {
+            if confirmed.len() >= self.min_peers {
+                return Ok(BarrierReport {
+                    fingerprint: target,
+                    confirmed: confirmed.iter().map(node_info).collect(),
+                });
+            }
+            let remaining = deadline.saturating_sub(sched.now());
+            if remaining.is_zero() {
+                return Err(BarrierError::TimedOut {
+                    confirmed: confirmed.iter().map(node_info).collect(),
+                    required: self.min_peers,
+                });
+            }
+            // Ask every reachable node that hasn't confirmed yet, in one round
+            // trip, giving up on the round if the deadline comes first.
+            let ask = self.net.request(|s| {
+                let matching = s
+                    .peers(&self.local)
+                    .filter(|n| !confirmed.contains(n))
+                    .filter(|n| match s.fingerprint(n, &self.root) {
+                        Some(f) if f.matches(&target) => true,
+                        Some(f) => {
+                            let holdings = s.holdings(n, &self.root);
+                            let summary = AvailabilitySummary::new(
+                                &self.root,
+                                holdings.iter(),
+                                f.complete,
+                                DEFAULT_FALSE_POSITIVE_RATE,
+                            );
+                            summary.missing(&held).is_empty()
+                        }
+                        None => false,
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                Some(matching)
+            });
+            let found = match select(Box::pin(ask), sched.sleep(remaining)).await {
+                Either::Left((found, _)) => found.unwrap_or_default(),
+                Either::Right(_) => Vec::new(),
+            };
+            confirmed.extend(found);
+            if confirmed.len() < self.min_peers {
+                let remaining = deadline.saturating_sub(sched.now());
+                sched.sleep(BARRIER_POLL.min(remaining)).await;
+            }
+        }
+    }
+}
+
+struct SimWarmup {
+    net: SimNetwork,
+    local: NodeAddr,
+    nodes: Vec<NodeAddr>,
+}
+
+#[async_trait]
+impl Warmup for SimWarmup {
+    async fn complete(self: Box<Self>) -> Vec<Warmed> {
+        let sched = self.net.scheduler().clone();
+        let start = sched.now();
+        let pings = self.nodes.iter().map(|node| async {
+            let result = self
+                .net
+                .request(|s| s.reachable(&self.local, node).then_some(()))
+                .await
+                .map(|()| sched.now() - start)
+                .ok_or(WarmError::Unreachable);
+            Warmed {
+                node: node.clone(),
+                result,
+            }
+        });
+        join_all(pings).await
+    }
+}
+
+struct SimSchemaRequest {
+    net: SimNetwork,
+    local: NodeAddr,
+    hash: Hash,
+}
+
+#[async_trait]
+impl SchemaRequest for SimSchemaRequest {
+    async fn complete(self: Box<Self>) -> Result<Document, SchemaFetchError> {
+        let doc = self
+            .net
+            .request(|s| s.remote_doc(&self.local, &self.hash, false))
+            .await
+            .ok_or_else(|| SchemaFetchError::NotFound(self.hash.clone()))?;
+        match Schema::from_doc(&doc) {
+            Ok(_) => Ok((*doc).clone()),
+            Err(_) => Err(SchemaFetchError::Invalid(self.hash.clone())),
+        }
+    }
+}
+
+/// A gate opened on a [`SimGroup`]. Closing or dropping it removes it from
+/// the simulated node.
+pub struct SimGate {
+    net: SimNetwork,
+    local: NodeAddr,
+    gate: Hash,
+}
+
+impl Gate for SimGate {
+    fn attached(&self) -> Vec<(NodeInfo, u32)> {
+        Vec::new()
+    }
+
+    fn total_cursors(&self) -> u32 {
+        0
+    }
+
+    fn tier(&self, _node: &NodeInfo) -> Option<Tier> {
+        None
+    }
+
+    fn query_hook(&self, _doc: &Hash, _hook: Box<dyn QueryHook>) {}
+
+    fn events(&self) -> Box<dyn GateEvents> {
+        Box::new(SimGateEvents)
+    }
+
+    fn anomaly_detector(&self, _detector: Box<dyn AnomalyDetector>) {}
+
+    fn set_honeypots(&self, _docs: Vec<Hash>) {}
+
+    fn receipts(&self, _doc: &Hash, _key: &str, _enabled: bool) {}
+
+    fn forward_groups(&self, _groups: Vec<Arc<dyn Group>>) {}
+
+    fn close(self) {}
+}
+
+impl Drop for SimGate {
+    fn drop(&mut self) {
+        self.net.close_gate(&self.local, &self.gate);
+    }
+}
+
+struct SimGateEvents;
+
+#[async_trait]
+impl GateEvents for SimGateEvents {
+    async fn next(&self) -> GateEvent {
+        futures::future::pending().await
+    }
+
+    fn try_next(&self) -> Option<GateEvent> {
+        None
+    }
+}
+
+struct SimFork {
+    net: SimNetwork,
+    local: NodeAddr,
+    hash: Hash,
+    gate: bool,
+    error: Option<CursorError>,
+}
+
+impl SimFork {
+    fn open(&self, doc: Arc<Document>) -> NewCursor {
+        let cursor = SimCursor {
+            id: self.net.trace_id(),
+            net: self.net.clone(),
+            local: self.local.clone(),
+            stack: vec![doc.clone()],
+        };
+        (Box::new(cursor), doc)
+    }
+}
+
+#[async_trait]
+impl ForkCursor for SimFork {
+    async fn complete(self: Box<Self>) -> Result<NewCursor, CursorError> {
+        if let Some(err) = self.error.clone() {
+            return Err(err);
+        }
+        let doc = self
+            .net
+            .request(|s| s.remote_doc(&self.local, &self.hash, self.gate))
+            .await
+            .ok_or_else(|| CursorError::Unavailable(self.hash.clone()))?;
+        Ok(self.open(doc))
+    }
+
+    fn complete_local(self: Box<Self>) -> Result<Option<NewCursor>, CursorError> {
+        if let Some(err) = self.error.clone() {
+            return Err(err);
+        }
+        let doc = {
+            let state = self.net.inner.state.lock().unwrap();
+            state.local_doc(&self.local, &self.hash)
+        };
+        Ok(doc.map(|doc| self.open(doc)))
+    }
+}
+
+struct SimCursor {
+    id: TraceId,
+    net: SimNetwork,
+    local: NodeAddr,
+    stack: Vec<Arc<Document>>,
+}
+
+impl SimCursor {
+    fn check_link(&self, hash: &Hash) -> Result<(), CursorError> {
+        if self.current().find_hashes().contains(hash) {
+            Ok(())
+        } else {
+            Err(CursorError::NotInDoc(hash.clone()))
+        }
+    }
+}
+
+#[async_trait]
+impl Cursor for SimCursor {
+    async fn forward(&mut self, hash: &Hash) -> Result<Arc<Document>, CursorError> {
+        if let Some(doc) = self.forward_local(hash)? {
+            return Ok(doc);
+        }
+        let doc = self
+            .net
+            .request(|s| s.remote_doc(&self.local, hash, false))
+            .await
+            .ok_or_else(|| CursorError::Unavailable(hash.clone()))?;
+        self.stack.push(doc.clone());
+        Ok(doc)
+    }
+
+    fn forward_local(&mut self, hash: &Hash) -> Result<Option<Arc<Document>>, CursorError> {
+        self.check_link(hash)?;
+        let doc = {
+            let state = self.net.inner.state.lock().unwrap();
+            state.local_doc(&self.local, hash)
+        };
+        if let Some(doc) = &doc {
+            self.stack.push(doc.clone());
+        }
+        Ok(doc)
+    }
+
+    fn back(&mut self) -> Result<(), CursorBackError> {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+            Ok(())
+        } else {
+            Err(CursorBackError)
+        }
+    }
+
+    fn fork(&self, hash: &Hash) -> Box<dyn ForkCursor> {
+        Box::new(SimFork {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            hash: hash.clone(),
+            gate: false,
+            error: self.check_link(hash).err(),
+        })
+    }
+
+    fn current(&self) -> Arc<Document> {
+        self.stack.last().unwrap().clone()
+    }
+
+    fn links(&self) -> Vec<(Hash, LinkStrength)> {
+        self.current()
+            .find_hashes()
+            .into_iter()
+            .map(|h| (h, LinkStrength::Unknown))
+            .collect()
+    }
+
+    fn query(self: Box<Self>, query: DbQuery) -> Box<dyn CursorQuery> {
+        Box::new(SimQuery::new(*self, query))
+    }
+
+    fn fetch_chunked(&self, hash: &Hash, offset: u64) -> Box<dyn ChunkStream> {
+        let finished = self.check_link(hash).err().map(ChunkUpdate::Failed);
+        Box::new(SimChunks {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            hash: hash.clone(),
+            state: Mutex::new(ChunkState { offset, finished }),
+        })
+    }
+
+    fn cache_current(&self, _ttl: Duration) -> DbResult<()> {
+        Ok(())
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.id
+    }
+}
+
+struct ChunkState {
+    offset: u64,
+    finished: Option<ChunkUpdate>,
+}
+
+struct SimChunks {
+    net: SimNetwork,
+    local: NodeAddr,
+    hash: Hash,
+    state: Mutex<ChunkState>,
+}
+
+#[async_trait]
+impl ChunkStream for SimChunks {
+    async fn next(&self) -> ChunkUpdate {
+        let offset = {
+            let state = self.state.lock().unwrap();
+            if let Some(finished) = &state.finished {
+                return finished.clone();
+            }
+            state.offset
+        };
+        // Each chunk is its own round trip, so a fetch can be cut off partway
+        // through by a partition or a node going offline.
+        let data = self
+            .net
+            .request(|s| {
+                let doc = s
+                    .local_doc(&self.local, &self.hash)
+                    .or_else(|| s.remote_doc(&self.local, &self.hash, false))?;
+                Some((s.encode(&doc)?, s.config.chunk_size.max(1)))
+            })
+            .await;
+        let mut state = self.state.lock().unwrap();
+        let Some((data, chunk_size)) = data else {
+            let failed = ChunkUpdate::Failed(CursorError::Unavailable(self.hash.clone()));
+            state.finished = Some(failed.clone());
+            return failed;
+        };
+        let len = data.len() as u64;
+        if offset > len {
+            let failed = ChunkUpdate::Failed(CursorError::BadOffset { offset, len });
+            state.finished = Some(failed.clone());
+            return failed;
+        }
+        if offset == len {
+            state.finished = Some(ChunkUpdate::Done);
+            return ChunkUpdate::Done;
+        }
+        let end = (offset as usize + chunk_size).min(data.len());
+        state.offset = end as u64;
+        ChunkUpdate::Chunk(DocChunk {
+            offset,
+            len,
+            data: data.slice(offset as usize..end),
+        })
+    }
+
+    fn try_next(&self) -> Option<ChunkUpdate> {
+        self.state.lock().unwrap().finished.clone()
+    }
+}
+
+/// Find the entries on a node that match a query.
+fn matching(state: &NetState, node: &NodeAddr, parent: &Hash, query: &Query) -> Vec<Entry> {
+    let Some(node) = state.nodes.get(node) else {
+        return Vec::new();
+    };
+    let Some(entries) = node.entries.get(parent) else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter(|entry| {
+            if entry.key() != query.key() {
+                return false;
+            }
+            let Ok(mut list) = query.query(entry) else {
+                return false;
+            };
+            let linked = list.iter().all(|(hash, item)| {
+                node.docs
+                    .get(&hash)
+                    .is_some_and(|doc| item.check(doc).is_ok())
+            });
+            linked && list.complete().is_ok()
+        })
+        .cloned()
+        .collect()
+}
+
+struct QueryState {
+    queue: VecDeque<QueryUpdate>,
+    pending: Vec<NodeAddr>,
+    stale: Option<HashSet<EntryRef>>,
+}
+
+struct SimQuery {
+    id: TraceId,
+    cursor: SimCursor,
+    parent: Hash,
+    query: Option<Query>,
+    state: Mutex<QueryState>,
+}
+
+impl SimQuery {
+    fn new(cursor: SimCursor, query: DbQuery) -> Self {
+        let doc = cursor.current();
+        let parent = doc.hash().clone();
+        let mut state = QueryState {
+            queue: VecDeque::new(),
+            pending: Vec::new(),
+            stale: None,
+        };
+        let compiled = {
+            let net = cursor.net.inner.state.lock().unwrap();
+            let compiled = doc
+                .schema_hash()
+                .and_then(|h| net.schemas.get(h))
+                .and_then(|schema| {
+                    let encoded = schema.encode_query(query.query.clone()).ok()?;
+                    schema.decode_query(encoded).ok()
+                });
+            // Nodes are asked in the order they were added, regardless of
+            // whether they're reachable right now.
+            state.pending = net
+                .order
+                .iter()
+                .filter(|n| **n != cursor.local)
+                .filter(|n| query.sources.as_ref().is_none_or(|f| f.allows(n)))
+                .rev()
+                .cloned()
+                .collect();
+            if query.revalidate {
+                let mut stale = HashSet::new();
+                if let Some(q) = &compiled {
+                    for entry in matching(&net, &cursor.local, &parent, q) {
+                        stale.insert(entry.reference().clone());
+                        let result = make_result(&cursor, entry, &cursor.local, true);
+                        state.queue.push_back(QueryUpdate::Result(Box::new(result)));
+                    }
+                }
+                state.stale = Some(stale);
+            }
+            compiled
+        };
+        Self {
+            id: cursor.net.trace_id(),
+            cursor,
+            parent,
+            query: compiled,
+            state: Mutex::new(state),
+        }
+    }
+}
+
+fn make_result(cursor: &SimCursor, entry: Entry, source: &NodeAddr, stale: bool) -> QueryResult {
+    let fork_spawner = Box::new(SimSpawner {
+        net: cursor.net.clone(),
+        local: cursor.local.clone(),
+        hash: entry.parent().clone(),
+    });
+    QueryResult {
+        entry,
+        docs: Vec::new(),
+        source: node_info(source),
+        expires: None,
+        deleted: None,
+        stale,
+        provenance: None,
+        relays: Vec::new(),
+        useful: Box::new(NoReport),
+        fork_spawner,
+    }
+}
+
+#[async_trait]
+impl CursorQuery for SimQuery {
+    fn back(self: Box<Self>) -> Box<dyn Cursor> {
+        Box::new(self.cursor)
+    }
+
+    async fn next(&self) -> QueryUpdate {
+        loop {
+            let node = {
+                let mut state = self.state.lock().unwrap();
+                if let Some(update) = state.queue.pop_front() {
+                    return update;
+                }
+                state.pending.pop()
+            };
+            let Some(node) = node else {
+                return futures::future::pending().await;
+            };
+            let local = &self.cursor.local;
+            let results = self
+                .cursor
+                .net
+                .request(|s| {
+                    if !s.reachable(local, &node) {
+                        return None;
+                    }
+                    Some(match &self.query {
+                        Some(q) => matching(s, &node, &self.parent, q),
+                        None => Vec::new(),
+                    })
+                })
+                .await;
+            let mut state = self.state.lock().unwrap();
+            if let Some(results) = results {
+                state
+                    .queue
+                    .push_back(QueryUpdate::NewConnection(node_info(&node)));
+                for entry in results {
+                    if let Some(stale) = &mut state.stale {
+                        stale.remove(entry.reference());
+                    }
+                    let result = make_result(&self.cursor, entry, &node, false);
+                    state.queue.push_back(QueryUpdate::Result(Box::new(result)));
+                }
+            }
+            if state.pending.is_empty() {
+                if let Some(stale) = state.stale.take() {
+                    let unconfirmed = stale.into_iter().collect();
+                    state
+                        .queue
+                        .push_back(QueryUpdate::Refreshed(Refresh { unconfirmed }));
+                }
+            }
+        }
+    }
+
+    fn try_next(&self) -> Option<QueryUpdate> {
+        self.state.lock().unwrap().queue.pop_front()
+    }
+
+    fn merge_strategy(&self) -> MergeStrategy {
+        MergeStrategy::Arrival
+    }
+
+    fn trace_id(&self) -> TraceId {
+        self.id
+    }
+
+    fn cursor_trace_id(&self) -> TraceId {
+        self.cursor.id
+    }
+}
+
+struct NoReport;
+
+impl UsefulReport for NoReport {
+    fn report(self: Box<Self>, _useful: Usefulness) {}
+}
+
+/// Forks a cursor at a query result's parent document.
+struct SimSpawner {
+    net: SimNetwork,
+    local: NodeAddr,
+    hash: Hash,
+}
+
+impl ForkSpawner for SimSpawner {
+    fn fork(&self) -> Box<dyn ForkCursor> {
+        Box::new(SimFork {
+            net: self.net.clone(),
+            local: self.local.clone(),
+            hash: self.hash.clone(),
+            gate: false,
+            error: None,
+        })
+    }
+}
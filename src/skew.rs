@@ -0,0 +1,196 @@
+//! Tolerance for clock skew between nodes.
+//!
+//! Entry time-to-lives, certificate validity windows, and query time filters
+//! all compare timestamps made on one node against the clock of another. No two
+//! clocks agree exactly, and some are wildly off. Without a shared rule for how
+//! much disagreement to tolerate, two nodes can look at the same entry or
+//! certificate and reach different conclusions about whether it's valid. A
+//! [`SkewPolicy`] is that rule.
+//!
+//! The database's policy is set with
+//! [`Db::set_skew_policy`][crate::Db::set_skew_policy], and can be overridden per group with
+//! [`Group::set_skew_policy`][crate::group::Group::set_skew_policy], for
+//! groups whose members are known to keep good (or bad) time.
+//!
+//! A policy only says how much skew to tolerate; it can't say whether a given
+//! node is within it. For that, nodes can exchange a [`TimeAttestation`] when
+//! connecting through a group or gate: a signed statement of what their clock
+//! read at the time. It's carried in the node's
+//! [`NodeInfo::time`][crate::NodeInfo::time], so anything judging a node's
+//! timestamps can see how far off its clock is, and use
+//! [`SkewPolicy::in_sync`] to decide whether to trust them.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use fog_crypto::identity::{IdentityKey, UnverifiedSignature};
+use fog_pack::{document::NewDocument, types::*};
+use serde::{Deserialize, Serialize};
+
+use crate::cert::Cert;
+
+/// What to do with a timestamp too far in the future to be explained by
+/// tolerated clock drift.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FutureTime {
+    /// Treat whatever the timestamp is attached to as invalid.
+    #[default]
+    Reject,
+    /// Treat the timestamp as if it were the latest acceptable time.
+    Clamp,
+    /// Take the timestamp as-is.
+    Accept,
+}
+
+/// How much disagreement between clocks to tolerate, and what to do when it's
+/// exceeded.
+///
+/// Drift is applied in whichever direction favors validity: an entry isn't
+/// considered expired until its time-to-live has passed by more than the
+/// drift, and a certificate is considered valid from `drift` before its start
+/// time until `drift` after its end time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SkewPolicy {
+    /// How far apart two clocks may be while still being considered in
+    /// agreement. Only whole seconds are used.
+    pub max_drift: Duration,
+    /// What to do with timestamps more than `max_drift` in the future that
+    /// should have already passed, like an entry's creation time or the time
+    /// at which a certificate was issued.
+    pub future: FutureTime,
+}
+
+impl Default for SkewPolicy {
+    fn default() -> Self {
+        Self {
+            max_drift: Duration::from_secs(300),
+            future: FutureTime::Reject,
+        }
+    }
+}
+
+impl SkewPolicy {
+    /// A policy that tolerates no drift at all, and takes all timestamps as-is.
+    pub fn exact() -> Self {
+        Self {
+            max_drift: Duration::ZERO,
+            future: FutureTime::Accept,
+        }
+    }
+
+    fn drift(&self) -> i64 {
+        // Keep timestamp arithmetic from overflowing on absurd drifts
+        self.max_drift.as_secs().min(u32::MAX as u64) as i64
+    }
+
+    /// The latest time that can be explained by drift from `now`.
+    pub fn latest(&self, now: Timestamp) -> Timestamp {
+        now + self.drift()
+    }
+
+    /// Check a timestamp that should not be in the future, like the time an
+    /// entry was created. Returns the timestamp to use in its place, or `None`
+    /// if it should be rejected.
+    pub fn check(&self, time: Timestamp, now: Timestamp) -> Option<Timestamp> {
+        let latest = self.latest(now);
+        if time <= latest {
+            return Some(time);
+        }
+        match self.future {
+            FutureTime::Reject => None,
+            FutureTime::Clamp => Some(latest),
+            FutureTime::Accept => Some(time),
+        }
+    }
+
+    /// Check if an entry with the given time-to-live has expired as of `now`.
+    pub fn is_expired(&self, ttl: Timestamp, now: Timestamp) -> bool {
+        ttl + self.drift() < now
+    }
+
+    /// Check if a certificate is valid as of `now`, allowing for drift at both
+    /// ends of its validity window. Certificates received with a start time
+    /// further out than drift allows should be put through
+    /// [`check`][Self::check] before being stored.
+    pub fn cert_valid(&self, cert: &Cert, now: Timestamp) -> bool {
+        let drift = self.drift();
+        cert.valid && cert.start - drift <= now && now <= cert.end + drift
+    }
+
+    /// Check if a node's clock, as attested when it connected, agreed with
+    /// ours to within the tolerated drift. `received` is when we received the
+    /// attestation, by our clock.
+    pub fn in_sync(&self, attestation: &TimeAttestation, received: Timestamp) -> bool {
+        attestation.offset(received).unsigned_abs() <= self.drift() as u64
+    }
+
+    /// Check if a timestamp falls within a query's time filter, allowing for
+    /// drift. Either end of the range may be left open.
+    pub fn in_range(
+        &self,
+        time: Timestamp,
+        start: Option<Timestamp>,
+        end: Option<Timestamp>,
+    ) -> bool {
+        let drift = self.drift();
+        start.is_none_or(|s| time >= s - drift) && end.is_none_or(|e| time <= e + drift)
+    }
+}
+
+/// A node's signed statement of what its clock read, exchanged when
+/// connecting through a group or gate.
+///
+/// The attestation is signed over a context hash so it can't be replayed
+/// elsewhere: the hash of the gate's document for gates, or any hash the
+/// group's members agree on for groups. It says nothing about whether the
+/// clock is right, only what the node claims, so it's useful for spotting
+/// nodes whose clocks are badly off, not for settling what time it is.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeAttestation {
+    /// The time by the node's clock.
+    pub time: Timestamp,
+    /// The context the attestation was made for.
+    pub context: Hash,
+    /// The encoded signature.
+    pub signature: Bytes,
+}
+
+impl TimeAttestation {
+    /// The hash signed over by an attestation.
+    pub fn signed_hash(context: &Hash, time: Timestamp) -> Hash {
+        NewDocument::new(None, (context, time))
+            .expect("TimeAttestation should always be serializable")
+            .hash()
+            .to_owned()
+    }
+
+    /// Attest to the current time by `key`'s node, for the given context.
+    pub fn sign(key: &IdentityKey, context: &Hash, time: Timestamp) -> Self {
+        let mut signature = Vec::new();
+        key.sign(&Self::signed_hash(context, time))
+            .encode_vec(&mut signature);
+        Self {
+            time,
+            context: context.clone(),
+            signature: signature.into(),
+        }
+    }
+
+    /// Verify the signature, returning the signing identity if it's valid.
+    pub fn verify(&self) -> Option<Identity> {
+        let sig = UnverifiedSignature::try_from(&self.signature[..]).ok()?;
+        let sig = sig
+            .verify(&Self::signed_hash(&self.context, self.time))
+            .ok()?;
+        Some(sig.signer().to_owned())
+    }
+
+    /// How many seconds ahead of ours the node's clock was, given when we
+    /// received the attestation by our clock. Negative if it was behind.
+    /// Includes however long the attestation took to arrive.
+    pub fn offset(&self, received: Timestamp) -> i64 {
+        self.time
+            .timestamp_utc()
+            .saturating_sub(received.timestamp_utc())
+    }
+}
@@ -0,0 +1,47 @@
+//! Statistics about the documents stored in a database.
+
+use std::collections::HashMap;
+
+use fog_pack::types::*;
+
+/// Storage used by the documents of a single schema.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchemaStats {
+    /// Number of documents.
+    pub docs: u64,
+    /// Total encoded size of the documents, in bytes.
+    pub bytes: u64,
+}
+
+/// Statistics for every document reachable from a root document, as computed
+/// by [`Db::tree_stats`][crate::Db::tree_stats].
+///
+/// Each distinct document is only counted once, no matter how many times it is
+/// linked to. Links are followed regardless of whether they are weak or strong.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Number of reachable documents in the local database, including the root.
+    pub docs: u64,
+    /// Total encoded size of the reachable documents, in bytes.
+    pub bytes: u64,
+    /// The greatest number of links that must be followed from the root to
+    /// reach any one of the documents.
+    pub max_depth: u32,
+    /// Number of linked documents that aren't in the local database.
+    pub missing: u64,
+    /// Breakdown of documents by schema. Documents without a schema are listed
+    /// under `None`.
+    pub schemas: HashMap<Option<Hash>, SchemaStats>,
+}
+
+impl TreeStats {
+    /// Add a document to the statistics.
+    pub fn add_doc(&mut self, schema: Option<Hash>, bytes: u64, depth: u32) {
+        self.docs += 1;
+        self.bytes += bytes;
+        self.max_depth = self.max_depth.max(depth);
+        let stats = self.schemas.entry(schema).or_default();
+        stats.docs += 1;
+        stats.bytes += bytes;
+    }
+}
@@ -0,0 +1,801 @@
+//! A conformance test suite for [`Db`] implementations.
+//!
+//! Backends can check that they behave the way the traits in this crate
+//! describe by handing [`run`] a factory for fresh, empty databases. Every
+//! [`Case`] runs against its own database, and the [`SuiteReport`] lists which
+//! passed and why the rest failed:
+//!
+//! - Transactions are atomic: a commit that fails changes nothing, and a
+//!   commit that succeeds makes every change at once.
+//! - Documents reachable from a name are kept, and garbage collection would
+//!   evict exactly the documents that are no longer reachable.
+//! - Documents and entries are checked against their schemas on commit.
+//! - Names can be compared-and-swapped.
+//! - Cursors can move forward and back through a document tree, and be forked.
+//! - Entries can be added, counted, queried, and deleted.
+//! - Commits are numbered in order, and appear in the change feed.
+//!
+//! When documents are actually evicted is up to each implementation, so the
+//! suite checks what *would* be collected through [`Db::gc_preview`] rather
+//! than waiting for collection to happen. Cases that rely on an optional
+//! [capability][crate::capabilities::DbCapabilities] pass without checking
+//! anything if the database doesn't report it.
+//!
+//! The suite needs no runtime of its own, so it can be driven by whichever
+//! executor the backend uses. The in-memory [`MemDb`][crate::memory::MemDb]
+//! passes every case, and is a useful reference when one fails.
+//!
+//! This module is only available with the `testsuite` feature.
+
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use fog_pack::{
+    document::{Document, NewDocument},
+    entry::NewEntry,
+    error::Error as FogError,
+    query::NewQuery,
+    schema::{NoSchema, Schema, SchemaBuilder},
+    types::*,
+    validator::{
+        ArrayValidator, HashValidator, IntValidator, MapValidator, StrValidator, Validator,
+    },
+};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    changes::DocRecord,
+    cursor::{CursorOpts, DbQuery, QueryUpdate},
+    gc::ProposedChange,
+    transaction::{CommitError, CommitReceipt, Durability, SchemaError, Transaction},
+    wire::WireDbError,
+    Db, DbError,
+};
+
+/// Why a test case failed.
+#[derive(Debug, Error)]
+pub enum Failure {
+    /// The database returned an error.
+    #[error("Database error: {0:?}")]
+    Db(Box<WireDbError>),
+    /// Test data couldn't be built. This is a bug in the suite, not the
+    /// database.
+    #[error("Couldn't build test data: {0}")]
+    Setup(#[from] FogError),
+    /// The database didn't behave as expected.
+    #[error("{0}")]
+    Check(String),
+}
+
+impl From<Box<DbError>> for Failure {
+    fn from(err: Box<DbError>) -> Self {
+        Self::Db(Box::new(WireDbError::from(err.as_ref())))
+    }
+}
+
+macro_rules! check {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err(Failure::Check(format!($($arg)+)));
+        }
+    };
+}
+
+type CaseFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Failure>> + 'a>>;
+
+/// A single behavioral test, run against one database.
+#[derive(Clone, Copy)]
+pub struct Case {
+    /// A short, unique name for the case.
+    pub name: &'static str,
+    /// What the case checks.
+    pub description: &'static str,
+    run: for<'a> fn(&'a dyn Db) -> CaseFuture<'a>,
+}
+
+impl fmt::Debug for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Case")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+impl Case {
+    /// Run the case against a database. The database should be empty, and
+    /// shouldn't be reused for other cases.
+    pub async fn run(&self, db: &dyn Db) -> Result<(), Failure> {
+        (self.run)(db).await
+    }
+}
+
+/// Get every case in the suite, in the order [`run`] runs them.
+pub fn cases() -> Vec<Case> {
+    vec![
+        Case {
+            name: "txn_atomicity",
+            description: "A failed commit changes nothing, and a successful one changes everything",
+            run: |db| Box::pin(txn_atomicity(db)),
+        },
+        Case {
+            name: "gc_reachability",
+            description: "Documents are kept exactly while they're reachable from a name",
+            run: |db| Box::pin(gc_reachability(db)),
+        },
+        Case {
+            name: "schema_validation",
+            description: "Documents and entries are checked against their schemas on commit",
+            run: |db| Box::pin(schema_validation(db)),
+        },
+        Case {
+            name: "name_cas",
+            description: "Names can be compared-and-swapped in transactions",
+            run: |db| Box::pin(name_cas(db)),
+        },
+        Case {
+            name: "cursor_navigation",
+            description: "Cursors move forward and back through a tree, and can be forked",
+            run: |db| Box::pin(cursor_navigation(db)),
+        },
+        Case {
+            name: "entries",
+            description: "Entries can be added, counted, queried, and deleted",
+            run: |db| Box::pin(entries(db)),
+        },
+        Case {
+            name: "change_feed",
+            description: "Commits are numbered in order and appear in the change feed",
+            run: |db| Box::pin(change_feed(db)),
+        },
+    ]
+}
+
+/// The outcome of one case.
+#[derive(Debug)]
+pub struct CaseResult {
+    /// The name of the case.
+    pub name: &'static str,
+    /// Whether it passed, or why it failed.
+    pub result: Result<(), Failure>,
+}
+
+/// The outcome of a full run of the suite.
+#[derive(Debug, Default)]
+pub struct SuiteReport {
+    /// The outcome of every case, in the order they were run.
+    pub results: Vec<CaseResult>,
+}
+
+impl SuiteReport {
+    /// Check if every case passed.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.result.is_ok())
+    }
+
+    /// Iterate over the cases that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.results.iter().filter(|r| r.result.is_err())
+    }
+
+    /// Panic with a list of the failed cases, if there were any. Meant for
+    /// calling at the end of a `#[test]`.
+    pub fn assert_passed(&self) {
+        let failures: Vec<String> = self
+            .failures()
+            .map(|r| format!("{}: {}", r.name, r.result.as_ref().unwrap_err()))
+            .collect();
+        if !failures.is_empty() {
+            panic!(
+                "{} of {} conformance cases failed:\n{}",
+                failures.len(),
+                self.results.len(),
+                failures.join("\n")
+            );
+        }
+    }
+}
+
+/// Run every case in the suite, each against a fresh database from
+/// `factory`.
+pub async fn run(factory: impl Fn() -> Box<dyn Db>) -> SuiteReport {
+    let mut report = SuiteReport::default();
+    for case in cases() {
+        let db = factory();
+        let result = case.run(db.as_ref()).await;
+        report.results.push(CaseResult {
+            name: case.name,
+            result,
+        });
+    }
+    report
+}
+
+const ROOT: &str = "testsuite-root";
+const OTHER: &str = "testsuite-other";
+const ENTRY_KEY: &str = "item";
+
+#[derive(Serialize)]
+struct TestDoc<'a> {
+    name: &'a str,
+    links: Vec<Hash>,
+}
+
+/// Make a schema-less document linking to other documents.
+fn plain_doc(name: &str, links: &[&Arc<Document>]) -> Result<Arc<Document>, Failure> {
+    let doc = TestDoc {
+        name,
+        links: links.iter().map(|d| d.hash().clone()).collect(),
+    };
+    Ok(Arc::new(NoSchema::validate_new_doc(NewDocument::new(
+        None, doc,
+    )?)?))
+}
+
+/// The schema used for documents with entries. Documents are the same shape
+/// as schema-less ones, and hold integer entries under [`ENTRY_KEY`].
+fn test_schema() -> Result<Document, Failure> {
+    let doc = MapValidator::new()
+        .req_add("name", StrValidator::new().build())
+        .req_add(
+            "links",
+            ArrayValidator::new()
+                .items(HashValidator::new().build())
+                .build(),
+        )
+        .build();
+    Ok(SchemaBuilder::new(doc)
+        .entry_add(ENTRY_KEY, IntValidator::new().build(), None)
+        .name("fog-db conformance test suite")
+        .build()?)
+}
+
+/// Add the test schema to the database.
+async fn add_schema(db: &dyn Db) -> Result<Arc<Schema>, Failure> {
+    match db.schema_add(Arc::new(test_schema()?)).await? {
+        Ok(schema) => Ok(schema),
+        Err(err) => Err(Failure::Check(format!(
+            "Database refused a valid schema: {err}"
+        ))),
+    }
+}
+
+async fn add_doc(txn: &mut Transaction, doc: &Arc<Document>) -> Result<(), Failure> {
+    match txn.add_doc(doc.clone()).await? {
+        Ok(()) => Ok(()),
+        Err(err) => Err(Failure::Check(format!(
+            "Couldn't stage document {}: {err}",
+            doc.hash()
+        ))),
+    }
+}
+
+async fn commit(txn: Transaction) -> Result<CommitReceipt, Failure> {
+    match txn.commit(Durability::default()).await? {
+        Ok(receipt) => Ok(receipt),
+        Err(errs) => Err(Failure::Check(format!(
+            "Valid commit failed: {:?}",
+            errs.errors
+        ))),
+    }
+}
+
+/// Commit a transaction that should fail, returning why it did.
+async fn commit_err(txn: Transaction) -> Result<Vec<CommitError>, Failure> {
+    match txn.commit(Durability::default()).await? {
+        Ok(_) => Err(Failure::Check("Invalid commit succeeded".into())),
+        Err(errs) => Ok(errs.errors),
+    }
+}
+
+/// Commit a set of documents, naming the first one.
+async fn commit_tree(db: &dyn Db, name: &str, docs: &[&Arc<Document>]) -> Result<(), Failure> {
+    let mut txn = db.txn();
+    for doc in docs {
+        add_doc(&mut txn, doc).await?;
+    }
+    txn.set_name(name, Some(docs[0].hash()));
+    commit(txn).await?;
+    Ok(())
+}
+
+async fn held(db: &dyn Db, doc: &Arc<Document>) -> Result<bool, Failure> {
+    Ok(db.doc_get(doc.hash()).await?.is_some())
+}
+
+async fn txn_atomicity(db: &dyn Db) -> Result<(), Failure> {
+    let doc = plain_doc("atomic", &[])?;
+    let missing = plain_doc("never added", &[])?;
+    let seq = db.current_seq().await?;
+
+    let mut txn = db.txn();
+    add_doc(&mut txn, &doc).await?;
+    txn.set_name(ROOT, Some(missing.hash()));
+    let errors = commit_err(txn).await?;
+    check!(
+        errors.contains(&CommitError::MissingNameTarget {
+            name: ROOT.into(),
+            target: missing.hash().clone(),
+        }),
+        "Naming a missing document should fail with MissingNameTarget, got {errors:?}"
+    );
+    check!(
+        !held(db, &doc).await?,
+        "A document from a failed commit was stored"
+    );
+    check!(
+        db.name_get(ROOT).await?.is_none(),
+        "A name from a failed commit was set"
+    );
+    check!(
+        db.current_seq().await? == seq,
+        "A failed commit advanced the sequence number"
+    );
+
+    let mut txn = db.txn();
+    add_doc(&mut txn, &doc).await?;
+    txn.set_name(ROOT, Some(doc.hash()));
+    let receipt = commit(txn).await?;
+    check!(
+        receipt.stored == vec![doc.hash().clone()],
+        "Receipt should list the one stored document, got {:?}",
+        receipt.stored
+    );
+    check!(held(db, &doc).await?, "A committed document wasn't stored");
+    check!(
+        db.name_get(ROOT).await?.as_ref() == Some(doc.hash()),
+        "A committed name wasn't set"
+    );
+    Ok(())
+}
+
+async fn gc_reachability(db: &dyn Db) -> Result<(), Failure> {
+    let leaf = plain_doc("leaf", &[])?;
+    let mid = plain_doc("mid", &[&leaf])?;
+    let root = plain_doc("root", &[&mid])?;
+    commit_tree(db, ROOT, &[&root, &mid, &leaf]).await?;
+    for doc in [&root, &mid, &leaf] {
+        check!(
+            held(db, doc).await?,
+            "Named document tree wasn't kept: missing {}",
+            doc.hash()
+        );
+    }
+
+    let sorted = |docs: &[&Arc<Document>]| {
+        let mut hashes: Vec<Hash> = docs.iter().map(|d| d.hash().clone()).collect();
+        hashes.sort();
+        hashes
+    };
+    let preview = db
+        .gc_preview(ProposedChange::SetName {
+            name: ROOT.into(),
+            target: None,
+        })
+        .await?;
+    let mut evicted = preview.evicted.clone();
+    evicted.sort();
+    check!(
+        evicted == sorted(&[&root, &mid, &leaf]),
+        "Removing the only name should evict the whole tree, got {evicted:?}"
+    );
+
+    let preview = db
+        .gc_preview(ProposedChange::SetWeakRef {
+            doc: root.hash().clone(),
+            target: mid.hash().clone(),
+            weak: true,
+        })
+        .await?;
+    let mut evicted = preview.evicted.clone();
+    evicted.sort();
+    check!(
+        evicted == sorted(&[&mid, &leaf]),
+        "Weakening the only link to a subtree should evict it, got {evicted:?}"
+    );
+
+    let mut txn = db.txn();
+    txn.set_name(OTHER, Some(mid.hash()));
+    commit(txn).await?;
+    let preview = db
+        .gc_preview(ProposedChange::SetName {
+            name: ROOT.into(),
+            target: None,
+        })
+        .await?;
+    check!(
+        preview.evicted == vec![root.hash().clone()],
+        "Documents reachable from another name shouldn't be evicted, got {:?}",
+        preview.evicted
+    );
+
+    let mut txn = db.txn();
+    txn.set_name(ROOT, None);
+    commit(txn).await?;
+    check!(
+        held(db, &mid).await? && held(db, &leaf).await?,
+        "Documents still reachable from a name were evicted"
+    );
+    Ok(())
+}
+
+async fn schema_validation(db: &dyn Db) -> Result<(), Failure> {
+    let not_schema = plain_doc("not a schema", &[])?;
+    check!(
+        db.schema_add(not_schema).await?.is_err(),
+        "Database accepted a document that isn't a schema"
+    );
+    let schema = add_schema(db).await?;
+    let schema_hash = schema.hash().clone();
+    check!(
+        db.schema_list().await?.contains(&schema_hash),
+        "Added schema isn't listed"
+    );
+
+    // Documents that don't match their schema are refused.
+    let mut txn = db.txn();
+    let bad = NewDocument::new(Some(&schema_hash), "not a map")?;
+    check!(
+        matches!(txn.add_new_doc(bad).await?, Err(SchemaError::ValidationFail(_))),
+        "Transaction accepted a document that doesn't match its schema"
+    );
+
+    // A document whose schema is removed before commit is refused.
+    let doc = TestDoc {
+        name: "typed",
+        links: Vec::new(),
+    };
+    let staged = match txn.add_new_doc(NewDocument::new(Some(&schema_hash), doc)?).await? {
+        Ok(doc) => doc,
+        Err(err) => {
+            return Err(Failure::Check(format!(
+                "Transaction refused a valid document: {err}"
+            )))
+        }
+    };
+    check!(
+        db.schema_del(&schema_hash).await?,
+        "Couldn't remove the schema"
+    );
+    let errors = commit_err(txn).await?;
+    check!(
+        errors.contains(&CommitError::MissingSchema {
+            doc: staged.hash().clone(),
+            schema: schema_hash.clone(),
+        }),
+        "Committing a document without its schema should fail with MissingSchema, got {errors:?}"
+    );
+
+    // Entries that don't match their schema are refused.
+    let schema = add_schema(db).await?;
+    let parent = Arc::new(schema.validate_new_doc(NewDocument::new(
+        Some(&schema_hash),
+        TestDoc {
+            name: "parent",
+            links: Vec::new(),
+        },
+    )?)?);
+    commit_tree(db, ROOT, &[&parent]).await?;
+    let mut txn = db.txn();
+    let bad = NewEntry::new(ENTRY_KEY, &parent, "not an integer")?;
+    check!(
+        txn.add_new_entry(bad).await?.is_err(),
+        "Transaction accepted an entry that doesn't match its schema"
+    );
+
+    // Entries whose parent isn't in the database are refused.
+    let orphan_parent = schema.validate_new_doc(NewDocument::new(
+        Some(&schema_hash),
+        TestDoc {
+            name: "orphan parent",
+            links: Vec::new(),
+        },
+    )?)?;
+    let orphan = schema
+        .validate_new_entry(NewEntry::new(ENTRY_KEY, &orphan_parent, 1u32)?)?
+        .complete()?;
+    let orphan_ref = orphan.reference().clone();
+    if let Err(err) = txn.add_entry(orphan).await? {
+        return Err(Failure::Check(format!(
+            "Couldn't stage entry {orphan_ref}: {err}"
+        )));
+    }
+    let errors = commit_err(txn).await?;
+    check!(
+        errors.contains(&CommitError::MissingParent(orphan_ref)),
+        "Committing an entry without its parent should fail with MissingParent, got {errors:?}"
+    );
+    Ok(())
+}
+
+async fn name_cas(db: &dyn Db) -> Result<(), Failure> {
+    let a = plain_doc("a", &[])?;
+    let b = plain_doc("b", &[])?;
+    commit_tree(db, ROOT, &[&a]).await?;
+    commit_tree(db, OTHER, &[&b]).await?;
+    const NAME: &str = "testsuite-cas";
+
+    let mut txn = db.txn();
+    txn.swap_name(NAME, Some(b.hash()), None);
+    commit(txn).await?;
+
+    let mut txn = db.txn();
+    txn.swap_name(NAME, Some(a.hash()), None);
+    let errors = commit_err(txn).await?;
+    let changed = CommitError::NameChanged {
+        name: NAME.into(),
+        current: Some(b.hash().clone()),
+    };
+    check!(
+        errors.contains(&changed),
+        "Swapping with a stale expectation should fail with NameChanged, got {errors:?}"
+    );
+    check!(
+        db.name_get(NAME).await?.as_ref() == Some(b.hash()),
+        "A failed swap changed the name"
+    );
+
+    let mut txn = db.txn();
+    txn.swap_name(NAME, Some(a.hash()), Some(b.hash()));
+    commit(txn).await?;
+    check!(
+        db.name_get(NAME).await?.as_ref() == Some(a.hash()),
+        "A successful swap didn't change the name"
+    );
+
+    let mut txn = db.txn();
+    txn.swap_name(NAME, None, Some(b.hash()));
+    let errors = commit_err(txn).await?;
+    let changed = CommitError::NameChanged {
+        name: NAME.into(),
+        current: Some(a.hash().clone()),
+    };
+    check!(
+        errors.contains(&changed),
+        "Removing with a stale expectation should fail with NameChanged, got {errors:?}"
+    );
+
+    let mut txn = db.txn();
+    txn.swap_name(NAME, None, Some(a.hash()));
+    commit(txn).await?;
+    check!(
+        db.name_get(NAME).await?.is_none(),
+        "A successful swap didn't remove the name"
+    );
+
+    let prev = db.name_add(NAME, b.hash()).await?;
+    check!(
+        matches!(prev, Ok(None)),
+        "Adding a new name should report no previous target, got {prev:?}"
+    );
+    let prev = db.name_add(NAME, a.hash()).await?;
+    check!(
+        matches!(&prev, Ok(Some(hash)) if hash == b.hash()),
+        "Replacing a name should report its previous target, got {prev:?}"
+    );
+    let names = db.name_list().await?;
+    check!(
+        names.contains(&(NAME.to_owned(), a.hash().clone())),
+        "Name list is missing a name, got {names:?}"
+    );
+    Ok(())
+}
+
+async fn cursor_navigation(db: &dyn Db) -> Result<(), Failure> {
+    let leaf = plain_doc("leaf", &[])?;
+    let mid = plain_doc("mid", &[&leaf])?;
+    let root = plain_doc("root", &[&mid])?;
+    commit_tree(db, ROOT, &[&root, &mid, &leaf]).await?;
+
+    let missing = plain_doc("never added", &[])?;
+    check!(
+        db.cursor(missing.hash(), CursorOpts::default())
+            .await?
+            .is_none(),
+        "Opened a cursor on a document that isn't in the database"
+    );
+    let Some((mut cursor, doc)) = db.cursor(root.hash(), CursorOpts::default()).await? else {
+        return Err(Failure::Check(
+            "Couldn't open a cursor on a named document".into(),
+        ));
+    };
+    check!(
+        doc.hash() == root.hash(),
+        "Cursor opened on the wrong document"
+    );
+    check!(
+        cursor.links().iter().any(|(hash, _)| hash == mid.hash()),
+        "Cursor doesn't list the document's links"
+    );
+    check!(
+        cursor.forward(leaf.hash()).await.is_err(),
+        "Cursor moved to a document the current one doesn't link to"
+    );
+
+    for next in [&mid, &leaf] {
+        match cursor.forward(next.hash()).await {
+            Ok(doc) => check!(
+                doc.hash() == next.hash(),
+                "Cursor moved to the wrong document"
+            ),
+            Err(err) => {
+                return Err(Failure::Check(format!(
+                    "Cursor couldn't move forward to {}: {err}",
+                    next.hash()
+                )))
+            }
+        }
+        check!(
+            cursor.current().hash() == next.hash(),
+            "Cursor isn't over the document it moved to"
+        );
+    }
+    for prev in [&mid, &root] {
+        check!(cursor.back().is_ok(), "Cursor couldn't move back");
+        check!(
+            cursor.current().hash() == prev.hash(),
+            "Cursor moved back to the wrong document"
+        );
+    }
+    check!(
+        cursor.back().is_err(),
+        "Cursor moved back past the document it was opened on"
+    );
+
+    match cursor.fork(mid.hash()).complete().await {
+        Ok((fork, doc)) => {
+            check!(
+                doc.hash() == mid.hash(),
+                "Fork opened on the wrong document"
+            );
+            check!(
+                fork.current().hash() == mid.hash(),
+                "Fork isn't over the document it was opened on"
+            );
+        }
+        Err(err) => return Err(Failure::Check(format!("Couldn't fork a cursor: {err}"))),
+    }
+    check!(
+        cursor.fork(leaf.hash()).complete().await.is_err(),
+        "Forked to a document the current one doesn't link to"
+    );
+    Ok(())
+}
+
+async fn entries(db: &dyn Db) -> Result<(), Failure> {
+    let schema = add_schema(db).await?;
+    let parent = Arc::new(schema.validate_new_doc(NewDocument::new(
+        Some(schema.hash()),
+        TestDoc {
+            name: "parent",
+            links: Vec::new(),
+        },
+    )?)?);
+    commit_tree(db, ROOT, &[&parent]).await?;
+
+    let mut txn = db.txn();
+    for i in 0u32..3 {
+        if let Err(err) = txn.add_new_entry(NewEntry::new(ENTRY_KEY, &parent, i)?).await? {
+            return Err(Failure::Check(format!(
+                "Transaction refused a valid entry: {err}"
+            )));
+        }
+    }
+    commit(txn).await?;
+    let count = db.entry_count(parent.hash(), ENTRY_KEY, None).await?;
+    check!(count == 3, "Expected 3 entries to be counted, got {count}");
+
+    let query = DbQuery {
+        query: NewQuery::new(ENTRY_KEY, Validator::Any),
+        rev_order: false,
+        ordering: None,
+        min_ttl: None,
+        signer_policy: None,
+        sample: None,
+        include_history: false,
+        merge: None,
+        sources: None,
+        revalidate: false,
+        forward: None,
+    };
+    let query = db.query(parent.hash(), query);
+    let mut found = Vec::new();
+    while found.len() < 3 {
+        if let QueryUpdate::Result(result) = query.next().await {
+            found.push(result.entry);
+        }
+    }
+    let mut values: Vec<u32> = found
+        .iter()
+        .map(|e| e.deserialize())
+        .collect::<Result<_, _>>()?;
+    values.sort();
+    check!(
+        values == [0, 1, 2],
+        "Query returned the wrong entries: {values:?}"
+    );
+
+    let deleted = found[0].reference().clone();
+    let mut txn = db.txn();
+    txn.del_entry(&deleted);
+    commit(txn).await?;
+    let count = db.entry_count(parent.hash(), ENTRY_KEY, None).await?;
+    check!(
+        count == 2,
+        "Expected 2 entries after deleting one, got {count}"
+    );
+
+    let mut txn = db.txn();
+    txn.set_ttl(&deleted, None);
+    let errors = commit_err(txn).await?;
+    check!(
+        errors.contains(&CommitError::MissingEntry(deleted)),
+        "Modifying a deleted entry should fail with MissingEntry, got {errors:?}"
+    );
+    Ok(())
+}
+
+async fn change_feed(db: &dyn Db) -> Result<(), Failure> {
+    let doc = plain_doc("fed", &[])?;
+    let start = db.current_seq().await?;
+    let feed = db.changes_since(start).await?;
+
+    let mut txn = db.txn();
+    add_doc(&mut txn, &doc).await?;
+    txn.set_name(ROOT, Some(doc.hash()));
+    let receipt = commit(txn).await?;
+    check!(
+        receipt.seq > start,
+        "Commit got sequence number {:?}, not after {start:?}",
+        receipt.seq
+    );
+    check!(
+        db.current_seq().await? == receipt.seq,
+        "Current sequence number isn't the last commit's"
+    );
+
+    let mut txn = db.txn();
+    add_doc(&mut txn, &doc).await?;
+    let again = commit(txn).await?;
+    check!(again.seq > receipt.seq, "Commits weren't numbered in order");
+    check!(
+        again.existing == vec![doc.hash().clone()],
+        "Receipt should list the document as already stored, got {:?}",
+        again.existing
+    );
+
+    if !db.capabilities().await?.change_feed {
+        return Ok(());
+    }
+    let feed = match feed {
+        Ok(feed) => feed,
+        Err(err) => {
+            return Err(Failure::Check(format!(
+                "Change feed from the current sequence number was too old: {err}"
+            )))
+        }
+    };
+    let record = feed.next().await?;
+    check!(
+        record.seq == receipt.seq,
+        "Change feed skipped a commit: got {:?}, expected {:?}",
+        record.seq,
+        receipt.seq
+    );
+    check!(
+        record.docs.contains(&DocRecord::Added(doc.hash().clone())),
+        "Change feed didn't record the added document"
+    );
+    Ok(())
+}
+
+#[cfg(all(test, feature = "testsuite"))]
+mod tests {
+    use super::*;
+    use crate::memory::MemDb;
+
+    #[test]
+    fn mem_db_passes() {
+        futures::executor::block_on(run(|| Box::new(MemDb::default()))).assert_passed();
+    }
+}
@@ -0,0 +1,174 @@
+//! A standard format for recording entry deletions.
+//!
+//! Databases only ever modify themselves, so when one node deletes an entry,
+//! other nodes that have copied it have no way of finding out - the entry
+//! simply stops showing up in the deleting node's query results, which looks
+//! the same as the node not having it yet. Tombstones fill this gap: deleting
+//! an entry with [`Transaction::del_entry_with_tombstone`] also adds a small
+//! [`Tombstone`] entry naming the deleted one, which other nodes find by
+//! querying for it like any other entry.
+//!
+//! By convention, tombstones for entries under a key are stored under
+//! [`tombstone_key`] for that key, and the parent document's schema must
+//! include an entry type for it using [`Tombstone::validator`]. Nodes keep a
+//! [`TombstoneSet`] of tombstones they've seen, and use it to filter results
+//! from their queries on the original key.
+//!
+//! Anyone able to add entries to a document can add a tombstone naming any
+//! entry under it, so a tombstone entry only deletes entries signed by the
+//! same identity that signed the tombstone. Unsigned tombstones only delete
+//! unsigned entries. Tombstones from a trusted source, rather than from a
+//! query, can be [inserted][TombstoneSet::insert] directly, and delete the
+//! entry no matter who signed it.
+//!
+//! [`Transaction::del_entry_with_tombstone`]:
+//!     crate::transaction::Transaction::del_entry_with_tombstone
+
+use std::collections::HashMap;
+
+use fog_crypto::identity::Identity;
+use fog_pack::{
+    entry::Entry,
+    error::Error as FogError,
+    query::NewQuery,
+    types::*,
+    validator::{HashValidator, MapValidator, TimeValidator, Validator},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cursor::QueryResult;
+
+/// Suffix appended to an entry key to get the key its tombstones are stored
+/// under.
+pub const TOMBSTONE_SUFFIX: &str = "/tombstone";
+
+/// Get the key that tombstones for entries under `key` are stored under.
+pub fn tombstone_key(key: &str) -> String {
+    format!("{}{}", key, TOMBSTONE_SUFFIX)
+}
+
+/// A record that an entry was deleted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    /// Hash of the deleted entry.
+    pub entry: Hash,
+    /// When the entry was deleted.
+    pub deleted: Timestamp,
+}
+
+impl Tombstone {
+    /// The entry validator for tombstones.
+    pub fn validator() -> Validator {
+        MapValidator::new()
+            .req_add("entry", HashValidator::new().build())
+            .req_add("deleted", TimeValidator::new().build())
+            .build()
+    }
+
+    /// A query returning every tombstone for entries under `key`.
+    pub fn query(key: &str) -> NewQuery {
+        NewQuery::new(&tombstone_key(key), Validator::Any)
+    }
+}
+
+/// The set of tombstones seen so far, used to filter out deleted entries.
+#[derive(Clone, Debug, Default)]
+pub struct TombstoneSet {
+    deleted: HashMap<Hash, Deletions>,
+}
+
+/// Every deletion seen for a single entry.
+#[derive(Clone, Debug, Default)]
+struct Deletions {
+    /// The earliest deletion from a trusted tombstone.
+    trusted: Option<Timestamp>,
+    /// The earliest deletion from tombstone entries, by their signer.
+    signed: HashMap<Option<Identity>, Timestamp>,
+}
+
+impl TombstoneSet {
+    /// Create a new, empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tombstone from a trusted source to the set. It deletes the entry
+    /// regardless of who signed it. Returns true if the entry wasn't already
+    /// known to be deleted by a trusted tombstone.
+    pub fn insert(&mut self, tomb: Tombstone) -> bool {
+        let deletions = self.deleted.entry(tomb.entry).or_default();
+        let new = deletions.trusted.is_none();
+        let deleted = deletions.trusted.get_or_insert(tomb.deleted);
+        // Keep the earliest deletion time.
+        if tomb.deleted < *deleted {
+            *deleted = tomb.deleted;
+        }
+        new
+    }
+
+    /// Add a tombstone entry, as returned from a [`Tombstone::query`], to the
+    /// set. It only deletes the entry if the entry has the same signer as the
+    /// tombstone. Returns true if the entry wasn't already known to be deleted
+    /// by that signer.
+    pub fn apply(&mut self, entry: &Entry) -> Result<bool, FogError> {
+        let tomb: Tombstone = entry.deserialize()?;
+        Ok(self.insert_signed(tomb, entry.signer().cloned()))
+    }
+
+    fn insert_signed(&mut self, tomb: Tombstone, signer: Option<Identity>) -> bool {
+        let deletions = self.deleted.entry(tomb.entry).or_default();
+        let new = !deletions.signed.contains_key(&signer);
+        let deleted = deletions.signed.entry(signer).or_insert(tomb.deleted);
+        if tomb.deleted < *deleted {
+            *deleted = tomb.deleted;
+        }
+        new
+    }
+
+    /// Merge another set of tombstones into this one.
+    pub fn merge(&mut self, other: &TombstoneSet) {
+        for (entry, deletions) in other.deleted.iter() {
+            if let Some(deleted) = deletions.trusted {
+                self.insert(Tombstone {
+                    entry: entry.clone(),
+                    deleted,
+                });
+            }
+            for (signer, deleted) in deletions.signed.iter() {
+                let tomb = Tombstone {
+                    entry: entry.clone(),
+                    deleted: *deleted,
+                };
+                self.insert_signed(tomb, signer.clone());
+            }
+        }
+    }
+
+    /// Check if an entry has been deleted by a trusted tombstone or one from
+    /// its own signer, returning the earliest such deletion.
+    pub fn deleted(&self, entry: &Entry) -> Option<Timestamp> {
+        let deletions = self.deleted.get(entry.hash())?;
+        let signed = deletions.signed.get(&entry.signer().cloned()).copied();
+        match (deletions.trusted, signed) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Check if a query result is for an entry that hasn't been deleted.
+    /// Intended for use as a filter on query results.
+    pub fn is_live(&self, result: &QueryResult) -> bool {
+        self.deleted(&result.entry).is_none()
+    }
+
+    /// The number of entries the set has tombstones for, whether or not
+    /// they're from the entries' signers.
+    pub fn len(&self) -> usize {
+        self.deleted.len()
+    }
+
+    /// Check if the set has no tombstones.
+    pub fn is_empty(&self) -> bool {
+        self.deleted.is_empty()
+    }
+}
@@ -3,13 +3,22 @@
 //! Transactions can be executed on the local database, both for local
 //! communication and as a way of talking to remote nodes. Transactions follow
 //! ACID properties, and can consist of adding documents, and either adding,
-//! modifying, or removing entries. Managing schemas and database root names is
-//! outside the scope of the transaction interface.
+//! modifying, or removing entries. Database root names can also be changed as
+//! part of a transaction, so that a new document tree and the name pointing to
+//! it are committed together. Managing schemas is outside the scope of the
+//! transaction interface.
 use std::{
     collections::{HashMap, HashSet},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
+use async_trait::async_trait;
+use bytes::Bytes;
+use fog_crypto::identity::IdentityKey;
 use fog_pack::{
     document::{Document, NewDocument},
     entry::{Entry, EntryRef, NewEntry},
@@ -17,35 +26,140 @@ use fog_pack::{
     schema::{NoSchema, Schema},
     types::*,
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{DbCommit, DbResult, cert::Policy, };
+use crate::{
+    Db, DbCommit, DbError, DbResult,
+    access::CacheTier,
+    backpressure::CommitPermit,
+    cert::{EntryPolicy, Policy},
+    changes::CommitSeq,
+    coordinator::PreparedCommit,
+    names::NameError,
+    tombstone::{tombstone_key, Tombstone},
+};
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CommitError {
     /// Tried to change or delete an entry but it wasn't in the DB
-    MissingEntry(EntryRef),
+    MissingEntry(#[serde(with = "crate::wire::entry_ref")] EntryRef),
     /// Tried to add an entry but its parent document wasn't in the DB
-    MissingParent(EntryRef),
+    MissingParent(#[serde(with = "crate::wire::entry_ref")] EntryRef),
     /// Tried to change a document's references but it wasn't in the DB
     MissingDoc(Hash),
     /// Tried to change a document's references but the ref wasn't in the document
     MissingDocRef { doc: Hash, target: Hash },
     /// Tried to add a document but the schema was missing
     MissingSchema { doc: Hash, schema: Hash },
+    /// Tried to change a name that no longer pointed where it was expected to
+    NameChanged { name: String, current: Option<Hash> },
+    /// Tried to set a name that the database's naming policy doesn't allow
+    InvalidName { name: String, err: NameError },
+    /// Tried to point a name at a document that wasn't in the DB or the
+    /// transaction
+    MissingNameTarget { name: String, target: Hash },
+    /// Tried to restore an entry that wasn't being retained after deletion,
+    /// either because it was never deleted or because its retention window
+    /// had passed
+    NotRetained(#[serde(with = "crate::wire::entry_ref")] EntryRef),
+    /// Tried to give an entry a policy template that wasn't in the DB
+    MissingPolicyTemplate {
+        #[serde(with = "crate::wire::entry_ref")]
+        entry: EntryRef,
+        template: Hash,
+    },
+    /// The database refused the commit for a reason not tied to any one
+    /// change, like being overloaded or shutting down. Retrying later may
+    /// succeed.
+    Rejected(String),
+}
+
+/// How durable a commit must be before it is reported as complete.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Durability {
+    /// The commit is flushed to stable storage before completing. A completed
+    /// commit survives both process crashes and power loss.
+    #[default]
+    Flushed,
+    /// The commit is handed to the operating system before completing, but
+    /// not necessarily flushed. A completed commit survives a process crash,
+    /// but may be lost on power loss or an operating system crash.
+    Buffered,
+    /// The commit may be held in memory before completing. A completed commit
+    /// may be lost on any crash. Commits are still atomic, and are always
+    /// lost in order: if a commit survives a crash, every commit before it
+    /// does too.
+    BestEffort,
+}
+
+/// The document, entry, and name changes making up a single transaction.
+pub type ChangeSet = (
+    HashMap<Hash, DocChange>,
+    HashMap<EntryRef, EntryChange>,
+    HashMap<String, NameChange>,
+);
+
+/// What a successful commit did.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitReceipt {
+    /// The sequence number assigned to the commit.
+    pub seq: CommitSeq,
+    /// Documents added by the commit that weren't already in the database,
+    /// and so were newly stored.
+    pub stored: Vec<Hash>,
+    /// Documents added by the commit that were already in the database. These
+    /// cost nothing to store.
+    pub existing: Vec<Hash>,
+}
+
+impl CommitReceipt {
+    /// A receipt for a commit that added no documents.
+    pub fn new(seq: CommitSeq) -> Self {
+        Self {
+            seq,
+            stored: Vec::new(),
+            existing: Vec::new(),
+        }
+    }
+
+    /// The fraction of added documents that were already in the database, or
+    /// `None` if no documents were added.
+    pub fn dedup_ratio(&self) -> Option<f64> {
+        let total = self.stored.len() + self.existing.len();
+        (total > 0).then(|| self.existing.len() as f64 / total as f64)
+    }
 }
 
 pub struct CommitErrors {
     pub docs: HashMap<Hash, DocChange>,
     pub entries: HashMap<EntryRef, EntryChange>,
+    pub names: HashMap<String, NameChange>,
     pub errors: Vec<CommitError>,
 }
 
+/// Source of the IDs tying savepoints to the transaction they came from.
+static NEXT_TXN_ID: AtomicU64 = AtomicU64::new(0);
+
 /// A pending transaction to execute on a database.
 pub struct Transaction {
+    id: u64,
     db: Box<dyn DbCommit>,
     docs: HashMap<Hash, DocChange>,
     entries: HashMap<EntryRef, EntryChange>,
+    names: HashMap<String, NameChange>,
+    undo: Option<Vec<Undo>>,
+    rollbacks: Vec<usize>,
+    retain: Option<Duration>,
+    permit: Option<Box<dyn CommitPermit>>,
+}
+
+/// A staged change as it was before being overwritten, kept once a
+/// [`Savepoint`] has been taken so the change can be undone.
+enum Undo {
+    Doc(Hash, Option<DocChange>),
+    Entry(EntryRef, Option<EntryChange>),
+    Name(String, Option<NameChange>),
 }
 
 /// Failure while trying to find and complete a schema
@@ -69,6 +183,39 @@ impl std::fmt::Display for MissingSchema {
 
 impl std::error::Error for MissingSchema {}
 
+/// Tried to roll a transaction back to a savepoint taken from a different
+/// transaction, or one undone by rolling back to an earlier savepoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForeignSavepoint;
+
+impl std::fmt::Display for ForeignSavepoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Savepoint was taken from a different transaction or has been rolled back past")
+    }
+}
+
+impl std::error::Error for ForeignSavepoint {}
+
+/// Tried to commit transactions from different databases together with
+/// [`Transaction::commit_many`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MixedBatch;
+
+impl std::fmt::Display for MixedBatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Transactions in a batch were started on different databases")
+    }
+}
+
+impl std::error::Error for MixedBatch {}
+
+/// A point in a transaction's undo log, from [`Transaction::savepoint`].
+pub struct Savepoint {
+    txn: u64,
+    mark: usize,
+    rollbacks: usize,
+}
+
 /// Failure while processing an entry
 #[derive(Clone, Debug, Error)]
 pub enum EntryError {
@@ -80,34 +227,221 @@ pub enum EntryError {
     DocValidationFail { doc: Hash, source: FogError },
     #[error("Missing document {0}")]
     MissingDoc(Hash),
+    #[error("Resolved document {doc} needed missing schema {schema}")]
+    MissingDocSchema { doc: Hash, schema: Hash },
+}
+
+/// A source of documents needed to validate an entry, for documents that
+/// aren't in the transaction or the database. See
+/// [`Transaction::add_new_entry_resolved`].
+#[async_trait]
+pub trait DocResolver: Send + Sync {
+    /// Find a document by hash. Returning a document that doesn't match the
+    /// hash is treated the same as returning `None`.
+    async fn resolve(&self, hash: &Hash) -> Option<Arc<Document>>;
+}
+
+/// A prefetched set of documents.
+#[async_trait]
+impl DocResolver for HashMap<Hash, Arc<Document>> {
+    async fn resolve(&self, hash: &Hash) -> Option<Arc<Document>> {
+        self.get(hash).cloned()
+    }
 }
 
 impl Transaction {
     pub fn new(db: Box<dyn DbCommit>) -> Self {
         Self {
+            id: NEXT_TXN_ID.fetch_add(1, Ordering::Relaxed),
             db,
             docs: HashMap::new(),
             entries: HashMap::new(),
+            names: HashMap::new(),
+            undo: None,
+            rollbacks: Vec::new(),
+            retain: None,
+            permit: None,
         }
     }
 
-    /// Replace the current transaction with whatever transaction errored out last time.
+    /// Attach a [`CommitPermit`] from [`Db::commit_permit`].
+    /// It is held until the transaction is committed or dropped.
+    pub fn with_permit(mut self, permit: Box<dyn CommitPermit>) -> Self {
+        self.permit = Some(permit);
+        self
+    }
+
+    /// Replace the connection this transaction commits through with a wrapper
+    /// around it, for intercepting or instrumenting commits.
+    pub fn wrap_commit(
+        mut self,
+        wrap: impl FnOnce(Box<dyn DbCommit>) -> Box<dyn DbCommit>,
+    ) -> Self {
+        self.db = wrap(self.db);
+        self
+    }
+
+    /// Get the permit attached to this transaction, if there is one.
+    pub fn permit(&self) -> Option<&dyn CommitPermit> {
+        self.permit.as_deref()
+    }
+
+    /// Set how long entries deleted by this transaction should be retained as
+    /// history. This applies to every call to [`del_entry`][Self::del_entry]
+    /// made after it is set. If `None`, deleted entries are removed
+    /// immediately.
+    pub fn set_history_retention(&mut self, retain: Option<Duration>) {
+        self.retain = retain;
+    }
+
+    /// Replace the current transaction with whatever transaction errored out
+    /// last time. Savepoints taken before this can no longer be rolled back to.
     pub fn load_from_errors(&mut self, errs: CommitErrors) {
+        self.id = NEXT_TXN_ID.fetch_add(1, Ordering::Relaxed);
+        self.undo = None;
+        self.rollbacks.clear();
         self.docs = errs.docs;
         self.entries = errs.entries;
+        self.names = errs.names;
+    }
+
+    /// Take a savepoint of the changes staged so far, which the transaction
+    /// can later be rolled back to with [`rollback_to`][Self::rollback_to].
+    /// Savepoints nest: rolling back to an earlier savepoint undoes everything
+    /// staged after it, including work covered by later savepoints, which
+    /// can't be rolled back to afterwards. A savepoint can otherwise be rolled
+    /// back to any number of times. The history retention setting and any
+    /// attached permit aren't part of a savepoint.
+    ///
+    /// Once a savepoint has been taken, the transaction keeps the previous
+    /// value of every staged change it overwrites, so that rolling back only
+    /// costs as much as the changes being undone.
+    pub fn savepoint(&mut self) -> Savepoint {
+        Savepoint {
+            txn: self.id,
+            mark: self.undo.get_or_insert_with(Vec::new).len(),
+            rollbacks: self.rollbacks.len(),
+        }
+    }
+
+    /// Undo every change staged since a savepoint was taken, without
+    /// discarding the rest of the transaction. Fails if the savepoint was
+    /// taken from a different transaction, or was undone by rolling back to
+    /// an earlier savepoint.
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) -> Result<(), ForeignSavepoint> {
+        if savepoint.txn != self.id
+            || self.rollbacks[savepoint.rollbacks..]
+                .iter()
+                .any(|&mark| mark < savepoint.mark)
+        {
+            return Err(ForeignSavepoint);
+        }
+        let undo = self.undo.get_or_insert_with(Vec::new);
+        for undo in undo.drain(savepoint.mark..).rev() {
+            match undo {
+                Undo::Doc(hash, Some(change)) => {
+                    self.docs.insert(hash, change);
+                }
+                Undo::Doc(hash, None) => {
+                    self.docs.remove(&hash);
+                }
+                Undo::Entry(entry, Some(change)) => {
+                    self.entries.insert(entry, change);
+                }
+                Undo::Entry(entry, None) => {
+                    self.entries.remove(&entry);
+                }
+                Undo::Name(name, Some(change)) => {
+                    self.names.insert(name, change);
+                }
+                Undo::Name(name, None) => {
+                    self.names.remove(&name);
+                }
+            }
+        }
+        self.rollbacks.push(savepoint.mark);
+        Ok(())
+    }
+
+    /// Keep a document's staged change for undoing, if a savepoint has been
+    /// taken.
+    fn save_doc(&mut self, doc: &Hash) {
+        if let Some(undo) = &mut self.undo {
+            undo.push(Undo::Doc(doc.clone(), self.docs.get(doc).cloned()));
+        }
+    }
+
+    /// Keep an entry's staged change for undoing, if a savepoint has been
+    /// taken.
+    fn save_entry(&mut self, entry: &EntryRef) {
+        if let Some(undo) = &mut self.undo {
+            undo.push(Undo::Entry(entry.clone(), self.entries.get(entry).cloned()));
+        }
+    }
+
+    /// Keep a name's staged change for undoing, if a savepoint has been
+    /// taken.
+    fn save_name(&mut self, name: &str) {
+        if let Some(undo) = &mut self.undo {
+            undo.push(Undo::Name(name.to_owned(), self.names.get(name).cloned()));
+        }
+    }
+
+    /// Encode the pending changes as a sequence of fog-pack documents, so the
+    /// transaction can be written to a log, handed to another process, or
+    /// replayed later with [`from_encoded`][Self::from_encoded]. Each frame is
+    /// kept well under the maximum document size, so transactions of any size
+    /// can be encoded, and there's always at least one frame. Documents and
+    /// entries are carried in their database encoding. The history retention
+    /// setting and any attached permit aren't included, though retention
+    /// already applied to deleted entries is.
+    pub fn encode(&self) -> Result<Vec<Bytes>, FogError> {
+        let changes = crate::remote::encode_changes(&self.docs, &self.entries, &self.names);
+        crate::remote::split_changes(changes)
+            .iter()
+            .map(crate::remote::encode)
+            .collect()
+    }
+
+    /// Rebuild a transaction on a database from the frames produced by
+    /// [`encode`][Self::encode], in order. Every document and entry is decoded
+    /// and validated again against the database's own schemas, so the
+    /// database doesn't need to be the one the transaction was built on. Fails
+    /// with [`CommitError`]s for schemas, parent documents, and linked
+    /// documents the database doesn't have, exactly as committing would.
+    ///
+    /// Changes to reserved names fail with [`CommitError::InvalidName`] unless
+    /// `allow_reserved` is set. Only set it for transactions from sources
+    /// trusted as much as the code that built them.
+    pub async fn from_encoded(
+        db: &dyn Db,
+        frames: &[Bytes],
+        allow_reserved: bool,
+    ) -> DbResult<Result<Transaction, Vec<CommitError>>> {
+        let mut changes = crate::remote::WireChangeSet::default();
+        for frame in frames {
+            let part = crate::remote::decode(frame).map_err(|err| {
+                Box::new(DbError::FogOther {
+                    context: "decoding encoded transaction".into(),
+                    err,
+                })
+            })?;
+            changes.append(part);
+        }
+        crate::remote::load_changes(db, changes, "encoded transaction", allow_reserved).await
     }
 
     /// Try to add a [`NewDocument`] to the DB. Can fail due to internal
     /// database failure. It can also fail if the document's schema isn't in the
     /// database, or if validation fails. On success, it returns a copy of the
     /// document that will be committed.
-    pub fn add_new_doc(
+    pub async fn add_new_doc(
         &mut self,
         doc: NewDocument,
     ) -> DbResult<Result<Arc<Document>, SchemaError>> {
         let (doc, (encoded, doc_hash)) = match doc.schema_hash() {
             Some(schema) => {
-                let Some(schema) = self.db.schema_get(schema)? else {
+                let Some(schema) = self.db.schema_get(schema).await? else {
                     return Ok(Err(SchemaError::MissingSchema(schema.to_owned())));
                 };
                 let doc = match schema.validate_new_doc(doc) {
@@ -133,28 +467,44 @@ impl Transaction {
             }
         };
         let encoded = Box::new(encoded);
+        let defaults = self.weak_defaults(&doc).await?;
+        self.save_doc(&doc_hash);
         match self.docs.entry(doc_hash) {
             std::collections::hash_map::Entry::Occupied(mut e) => {
-                e.get_mut().add(encoded, doc.clone());
+                e.get_mut().add(encoded, doc.clone(), defaults);
             }
             std::collections::hash_map::Entry::Vacant(v) => {
                 v.insert(DocChange::Add {
                     doc: doc.clone(),
                     encoded,
-                    weak_ref: HashSet::new(),
+                    weak_ref: defaults,
+                    tier: None,
                 });
             }
         }
         Ok(Ok(doc))
     }
 
+    /// Find the links in a document that its schema says to weaken by default.
+    async fn weak_defaults(&self, doc: &Document) -> DbResult<HashSet<Hash>> {
+        let Some(schema) = doc.schema_hash() else {
+            return Ok(HashSet::new());
+        };
+        Ok(self
+            .db
+            .schema_weak_refs(schema)
+            .await?
+            .map(|d| d.weak_links(doc))
+            .unwrap_or_default())
+    }
+
     /// Try to add a [`Document`] to the DB. Can fail due to internal
     /// database failure. It can also fail if the document's schema isn't in the
     /// database.
-    pub fn add_doc(&mut self, doc: Arc<Document>) -> DbResult<Result<(), MissingSchema>> {
+    pub async fn add_doc(&mut self, doc: Arc<Document>) -> DbResult<Result<(), MissingSchema>> {
         let (encoded, doc_hash) = match doc.schema_hash() {
             Some(schema) => {
-                let Some(schema) = self.db.schema_get(schema)? else {
+                let Some(schema) = self.db.schema_get(schema).await? else {
                     return Ok(Err(MissingSchema(schema.to_owned())));
                 };
                 EncodedDoc::from_doc(Some(schema.as_ref()), doc.as_ref().clone())
@@ -162,15 +512,18 @@ impl Transaction {
             None => EncodedDoc::from_doc(None, doc.as_ref().clone()),
         };
         let encoded = Box::new(encoded);
+        let defaults = self.weak_defaults(&doc).await?;
+        self.save_doc(&doc_hash);
         match self.docs.entry(doc_hash) {
             std::collections::hash_map::Entry::Occupied(mut e) => {
-                e.get_mut().add(encoded, doc);
+                e.get_mut().add(encoded, doc, defaults);
             }
             std::collections::hash_map::Entry::Vacant(v) => {
                 v.insert(DocChange::Add {
                     encoded,
                     doc,
-                    weak_ref: HashSet::new(),
+                    weak_ref: defaults,
+                    tier: None,
                 });
             }
         }
@@ -181,61 +534,252 @@ impl Transaction {
     /// failure, if the schema is missing from the database, or if any of the
     /// documents needed for validation are missing from both the transaction
     /// and the database.
-    pub fn add_new_entry(&mut self, entry: NewEntry) -> DbResult<Result<(), EntryError>> {
-        let Some(schema) = self.db.schema_get(entry.schema_hash())? else {
+    pub async fn add_new_entry(&mut self, entry: NewEntry) -> DbResult<Result<(), EntryError>> {
+        let Some(schema) = self.db.schema_get(entry.schema_hash()).await? else {
+            return Ok(Err(EntryError::MissingEntrySchema(entry.schema_hash().to_owned())));
+        };
+        let (entry, e_ref) = match self.validate_new_entry(&schema, entry, &mut HashMap::new())
+            .await? {
+            Ok(validated) => validated,
+            Err(e) => return Ok(Err(e)),
+        };
+        self.stage_entry(entry, e_ref);
+        Ok(Ok(()))
+    }
+
+    /// Try to add a [`NewEntry`] to the DB, using `resolver` to find any of the
+    /// documents needed for validation that are missing from both the
+    /// transaction and the database. Resolved documents are added to the
+    /// transaction, so their schemas must be in the database. Otherwise, this
+    /// fails for the same reasons as [`add_new_entry`][Self::add_new_entry],
+    /// in which case nothing is added.
+    pub async fn add_new_entry_resolved(
+        &mut self,
+        entry: NewEntry,
+        resolver: &dyn DocResolver,
+    ) -> DbResult<Result<(), EntryError>> {
+        let Some(schema) = self.db.schema_get(entry.schema_hash()).await? else {
             return Ok(Err(EntryError::MissingEntrySchema(entry.schema_hash().to_owned())));
         };
         let mut checklist = match schema.validate_new_entry(entry) {
             Ok(list) => list,
             Err(e) => return Ok(Err(EntryError::EntryValidationFail(e))),
         };
+        let mut resolved = Vec::new();
         for (link_hash, item) in checklist.iter() {
-            if let Some(DocChange::Add { doc, .. }) = self.docs.get(&link_hash) {
-                if let Err(e) = item.check(doc) {
-                    return Ok(Err(EntryError::DocValidationFail {
-                        doc: link_hash,
-                        source: e,
-                    }));
-                }
-                continue;
+            let doc = match self.docs.get(&link_hash) {
+                Some(DocChange::Add { doc, .. }) => doc.clone(),
+                _ => match self.db.doc_get(&link_hash).await? {
+                    Some(doc) => doc,
+                    None => match resolver.resolve(&link_hash).await {
+                        Some(doc) if doc.hash() == &link_hash => {
+                            resolved.push(doc.clone());
+                            doc
+                        }
+                        _ => return Ok(Err(EntryError::MissingDoc(link_hash))),
+                    },
+                },
+            };
+            if let Err(e) = item.check(&doc) {
+                return Ok(Err(EntryError::DocValidationFail {
+                    doc: link_hash,
+                    source: e,
+                }));
             }
-            if let Some(doc) = self.db.doc_get(&link_hash)? {
-                if let Err(e) = item.check(&doc) {
-                    return Ok(Err(EntryError::DocValidationFail {
-                        doc: link_hash,
-                        source: e,
+        }
+        for doc in resolved.iter() {
+            if let Some(schema) = doc.schema_hash() {
+                if self.db.schema_get(schema).await?.is_none() {
+                    return Ok(Err(EntryError::MissingDocSchema {
+                        doc: doc.hash().clone(),
+                        schema: schema.clone(),
                     }));
                 }
-                continue;
             }
-            return Ok(Err(EntryError::MissingDoc(link_hash)));
+        }
+        for doc in resolved {
+            if let Err(MissingSchema(schema)) = self.add_doc(doc.clone()).await? {
+                return Ok(Err(EntryError::MissingDocSchema {
+                    doc: doc.hash().clone(),
+                    schema,
+                }));
+            }
         }
         let entry = checklist.complete().unwrap();
         let (entry, e_ref) = EncodedEntry::from_entry(&schema, entry);
-        let entry = Box::new(entry);
-        match self.entries.entry(e_ref) {
-            std::collections::hash_map::Entry::Occupied(mut e) => {
-                e.get_mut().add(entry);
+        self.stage_entry(Box::new(entry), e_ref);
+        Ok(Ok(()))
+    }
+
+    /// Try to add many [`NewEntry`]s to the DB at once. Schemas and the
+    /// documents needed for validation are only looked up once for the whole
+    /// batch. Fails for the same reasons as
+    /// [`add_new_entry`][Self::add_new_entry], returning the index of the
+    /// first entry that failed; in that case, none of the entries are added.
+    pub async fn add_new_entries(
+        &mut self,
+        entries: Vec<NewEntry>,
+    ) -> DbResult<Result<(), (usize, EntryError)>> {
+        let mut schemas: HashMap<Hash, Arc<Schema>> = HashMap::new();
+        let mut fetched = HashMap::new();
+        let mut validated = Vec::with_capacity(entries.len());
+        for (i, entry) in entries.into_iter().enumerate() {
+            let schema = match schemas.get(entry.schema_hash()) {
+                Some(schema) => schema.clone(),
+                None => {
+                    let Some(schema) = self.db.schema_get(entry.schema_hash()).await? else {
+                        let err = EntryError::MissingEntrySchema(entry.schema_hash().to_owned());
+                        return Ok(Err((i, err)));
+                    };
+                    schemas.insert(entry.schema_hash().to_owned(), schema.clone());
+                    schema
+                }
+            };
+            match self.validate_new_entry(&schema, entry, &mut fetched).await? {
+                Ok(v) => validated.push(v),
+                Err(e) => return Ok(Err((i, e))),
             }
-            std::collections::hash_map::Entry::Vacant(v) => {
-                v.insert(EntryChange::Add {
-                    entry,
-                    ttl: None,
-                    policy: None,
-                });
+        }
+        self.entries.reserve(validated.len());
+        for (entry, e_ref) in validated {
+            self.stage_entry(entry, e_ref);
+        }
+        Ok(Ok(()))
+    }
+
+    /// Try to add many [`NewDocument`]s to the DB at once, validating them on
+    /// the database's [`Validator`][crate::validate::Validator] if it has
+    /// one. Fails for the same reasons as [`add_new_doc`][Self::add_new_doc],
+    /// returning the index of the first document that failed; in that case,
+    /// none of the documents are added. On success, returns copies of the
+    /// documents that will be committed, in the same order.
+    pub async fn add_new_docs_pooled(
+        &mut self,
+        docs: Vec<NewDocument>,
+    ) -> DbResult<Result<Vec<Arc<Document>>, (usize, SchemaError)>> {
+        let results = match self.db.validator() {
+            Some(validator) => validator.validate_docs(docs).await?,
+            None => {
+                let mut results = Vec::with_capacity(docs.len());
+                for doc in docs {
+                    results.push(match doc.schema_hash() {
+                        Some(schema) => match self.db.schema_get(schema).await? {
+                            Some(schema) => schema.validate_new_doc(doc).map_err(Into::into),
+                            None => Err(SchemaError::MissingSchema(schema.to_owned())),
+                        },
+                        None => NoSchema::validate_new_doc(doc).map_err(Into::into),
+                    });
+                }
+                results
+            }
+        };
+        let mut validated = Vec::with_capacity(results.len());
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(doc) => validated.push(Arc::new(doc)),
+                Err(e) => return Ok(Err((i, e))),
             }
         }
+        // Staging is all-or-nothing, so find every schema before adding any
+        // of the documents.
+        for (i, doc) in validated.iter().enumerate() {
+            if let Some(schema) = doc.schema_hash() {
+                if self.db.schema_get(schema).await?.is_none() {
+                    return Ok(Err((i, SchemaError::MissingSchema(schema.to_owned()))));
+                }
+            }
+        }
+        for (i, doc) in validated.iter().enumerate() {
+            if let Err(MissingSchema(schema)) = self.add_doc(doc.clone()).await? {
+                return Ok(Err((i, SchemaError::MissingSchema(schema))));
+            }
+        }
+        Ok(Ok(validated))
+    }
+
+    /// Try to add many [`NewEntry`]s to the DB at once, validating them on the
+    /// database's [`Validator`][crate::validate::Validator] if it has one.
+    /// Documents the entries link to are looked for in the transaction, then
+    /// in the database. Fails for the same reasons as
+    /// [`add_new_entries`][Self::add_new_entries], returning the index of the
+    /// first entry that failed; in that case, none of the entries are added.
+    pub async fn add_new_entries_pooled(
+        &mut self,
+        entries: Vec<NewEntry>,
+    ) -> DbResult<Result<(), (usize, EntryError)>> {
+        let Some(validator) = self.db.validator() else {
+            return self.add_new_entries(entries).await;
+        };
+        let staged: HashMap<Hash, Arc<Document>> = self
+            .docs
+            .iter()
+            .filter_map(|(hash, change)| match change {
+                DocChange::Add { doc, .. } => Some((hash.clone(), doc.clone())),
+                DocChange::Modify { .. } => None,
+            })
+            .collect();
+        let results = validator.validate_entries(entries, &staged).await?;
+        let mut validated = Vec::with_capacity(results.len());
+        for (i, result) in results.into_iter().enumerate() {
+            let entry = match result {
+                Ok(entry) => entry,
+                Err(e) => return Ok(Err((i, e))),
+            };
+            let Some(schema) = validator.schema(entry.schema_hash())? else {
+                let err = EntryError::MissingEntrySchema(entry.schema_hash().to_owned());
+                return Ok(Err((i, err)));
+            };
+            let (entry, e_ref) = EncodedEntry::from_entry(&schema, entry);
+            validated.push((Box::new(entry), e_ref));
+        }
+        self.entries.reserve(validated.len());
+        for (entry, e_ref) in validated {
+            self.stage_entry(entry, e_ref);
+        }
         Ok(Ok(()))
     }
 
-    /// Try to add a [`Entry`] to the DB. Can fail due to internal database
-    /// failure, or if the schema is missing from the database.
-    pub fn add_entry(&mut self, entry: Entry) -> DbResult<Result<(), EntryError>> {
-        let Some(schema) = self.db.schema_get(entry.schema_hash())? else {
-            return Ok(Err(EntryError::MissingEntrySchema(entry.schema_hash().to_owned())));
+    /// Validate a new entry, looking for the documents it links to in the
+    /// transaction, then in `fetched`, then in the database. Documents found
+    /// in the database are added to `fetched`.
+    async fn validate_new_entry(
+        &self,
+        schema: &Schema,
+        entry: NewEntry,
+        fetched: &mut HashMap<Hash, Arc<Document>>,
+    ) -> DbResult<Result<(Box<EncodedEntry>, EntryRef), EntryError>> {
+        let mut checklist = match schema.validate_new_entry(entry) {
+            Ok(list) => list,
+            Err(e) => return Ok(Err(EntryError::EntryValidationFail(e))),
         };
-        let (entry, e_ref) = EncodedEntry::from_entry(&schema, entry);
-        let entry = Box::new(entry);
+        for (link_hash, item) in checklist.iter() {
+            let doc = match self.docs.get(&link_hash) {
+                Some(DocChange::Add { doc, .. }) => doc.clone(),
+                _ => match fetched.get(&link_hash) {
+                    Some(doc) => doc.clone(),
+                    None => {
+                        let Some(doc) = self.db.doc_get(&link_hash).await? else {
+                            return Ok(Err(EntryError::MissingDoc(link_hash)));
+                        };
+                        fetched.insert(link_hash.clone(), doc.clone());
+                        doc
+                    }
+                },
+            };
+            if let Err(e) = item.check(&doc) {
+                return Ok(Err(EntryError::DocValidationFail {
+                    doc: link_hash,
+                    source: e,
+                }));
+            }
+        }
+        let entry = checklist.complete().unwrap();
+        let (entry, e_ref) = EncodedEntry::from_entry(schema, entry);
+        Ok(Ok((Box::new(entry), e_ref)))
+    }
+
+    fn stage_entry(&mut self, entry: Box<EncodedEntry>, e_ref: EntryRef) {
+        self.save_entry(&e_ref);
         match self.entries.entry(e_ref) {
             std::collections::hash_map::Entry::Occupied(mut e) => {
                 e.get_mut().add(entry);
@@ -248,11 +792,22 @@ impl Transaction {
                 });
             }
         }
+    }
+
+    /// Try to add a [`Entry`] to the DB. Can fail due to internal database
+    /// failure, or if the schema is missing from the database.
+    pub async fn add_entry(&mut self, entry: Entry) -> DbResult<Result<(), EntryError>> {
+        let Some(schema) = self.db.schema_get(entry.schema_hash()).await? else {
+            return Ok(Err(EntryError::MissingEntrySchema(entry.schema_hash().to_owned())));
+        };
+        let (entry, e_ref) = EncodedEntry::from_entry(&schema, entry);
+        self.stage_entry(Box::new(entry), e_ref);
         Ok(Ok(()))
     }
 
     /// Weaken/strengthen a reference for a Document.
     pub fn set_weak_ref(&mut self, doc: &Hash, ref_hash: &Hash, weak: bool) {
+        self.save_doc(doc);
         match self.docs.entry(doc.to_owned()) {
             std::collections::hash_map::Entry::Occupied(mut e) => match e.get_mut() {
                 DocChange::Add { weak_ref, .. } => {
@@ -262,14 +817,33 @@ impl Transaction {
                         weak_ref.remove(ref_hash);
                     }
                 },
-                DocChange::Modify { weak_ref } => {
+                DocChange::Modify { weak_ref, .. } => {
                     weak_ref.insert(ref_hash.to_owned(), weak);
                 }
             },
             std::collections::hash_map::Entry::Vacant(v) => {
                 let mut weak_ref = HashMap::new();
                 weak_ref.insert(ref_hash.to_owned(), weak);
-                v.insert(DocChange::Modify { weak_ref });
+                v.insert(DocChange::Modify { weak_ref, tier: None });
+            }
+        }
+    }
+
+    /// Hint at which storage tier a document should be placed in. The document
+    /// can either be added in this transaction or already be in the database.
+    pub fn set_cache_tier(&mut self, doc: &Hash, tier: CacheTier) {
+        self.save_doc(doc);
+        match self.docs.entry(doc.to_owned()) {
+            std::collections::hash_map::Entry::Occupied(mut e) => match e.get_mut() {
+                DocChange::Add { tier: t, .. } | DocChange::Modify { tier: t, .. } => {
+                    *t = Some(tier);
+                }
+            },
+            std::collections::hash_map::Entry::Vacant(v) => {
+                v.insert(DocChange::Modify {
+                    weak_ref: HashMap::new(),
+                    tier: Some(tier),
+                });
             }
         }
     }
@@ -277,15 +851,16 @@ impl Transaction {
     /// Set or clear the time-to-live for an Entry.
     pub fn set_ttl(&mut self, entry: &EntryRef, ttl: Option<Timestamp>) {
         let set = ttl;
+        self.save_entry(entry);
         match self.entries.entry(entry.to_owned()) {
             std::collections::hash_map::Entry::Occupied(mut e) => match e.get_mut() {
                 EntryChange::Add { ttl, .. } => {
                     *ttl = set;
                 },
-                EntryChange::Modify { ttl, .. } => {
+                EntryChange::Modify { ttl, .. } | EntryChange::Restore { ttl, .. } => {
                     *ttl = Some(set);
                 }
-                EntryChange::Delete => (),
+                EntryChange::Delete { .. } => (),
             },
             std::collections::hash_map::Entry::Vacant(v) => {
                 v.insert(EntryChange::Modify { ttl: Some(set), policy: None });
@@ -295,16 +870,28 @@ impl Transaction {
 
     /// Set or clear the policy for an Entry.
     pub fn set_policy(&mut self, entry: &EntryRef, policy: Option<Policy>) {
+        self.set_entry_policy(entry, policy.map(EntryPolicy::Inline));
+    }
+
+    /// Set or clear the policy for an Entry, by referring to a policy template
+    /// published in the [`PolicyRegistry`][crate::policies::PolicyRegistry].
+    pub fn set_policy_template(&mut self, entry: &EntryRef, template: Option<&Hash>) {
+        self.set_entry_policy(entry, template.cloned().map(EntryPolicy::Template));
+    }
+
+    /// Set or clear the policy for an Entry, either inline or as a template.
+    pub fn set_entry_policy(&mut self, entry: &EntryRef, policy: Option<EntryPolicy>) {
         let set = policy;
+        self.save_entry(entry);
         match self.entries.entry(entry.to_owned()) {
             std::collections::hash_map::Entry::Occupied(mut e) => match e.get_mut() {
                 EntryChange::Add { policy, .. } => {
                     *policy = set;
                 },
-                EntryChange::Modify { policy, .. } => {
+                EntryChange::Modify { policy, .. } | EntryChange::Restore { policy, .. } => {
                     *policy = Some(set);
                 }
-                EntryChange::Delete => (),
+                EntryChange::Delete { .. } => (),
             },
             std::collections::hash_map::Entry::Vacant(v) => {
                 v.insert(EntryChange::Modify { policy: Some(set), ttl: None });
@@ -312,23 +899,219 @@ impl Transaction {
         }
     }
 
-    /// Delete an entry from the database.
+    /// Delete an entry from the database. The entry is retained as history if
+    /// a retention window was set with
+    /// [`set_history_retention`][Self::set_history_retention].
     pub fn del_entry(&mut self, entry: &EntryRef) {
-        self.entries.insert(entry.to_owned(), EntryChange::Delete);
+        self.del_entry_retained(entry, self.retain);
+    }
+
+    /// Delete an entry from the database, retaining it as history for the
+    /// given window. Retained entries no longer show up in queries unless the
+    /// query asks for [history][crate::cursor::DbQuery::include_history], and
+    /// are removed once the window has passed. Until then, the deletion can be
+    /// undone with [`restore_entry`][Self::restore_entry].
+    pub fn del_entry_retained(&mut self, entry: &EntryRef, retain: Option<Duration>) {
+        self.save_entry(entry);
+        self.entries.insert(entry.to_owned(), EntryChange::Delete { retain });
+    }
+
+    /// Bring back an entry that was deleted but is still being retained as
+    /// history, undoing the deletion. The entry keeps the time-to-live and
+    /// policy it had when deleted, unless they are changed in this
+    /// transaction. The transaction fails with [`CommitError::NotRetained`]
+    /// if the entry isn't being retained when committed.
+    pub fn restore_entry(&mut self, entry: &EntryRef) {
+        self.save_entry(entry);
+        self.entries.insert(
+            entry.to_owned(),
+            EntryChange::Restore {
+                ttl: None,
+                policy: None,
+            },
+        );
+    }
+
+    /// Delete many entries from the database at once. They are retained as
+    /// history just as with [`del_entry`][Self::del_entry].
+    pub fn del_entries(&mut self, entries: &[EntryRef]) {
+        self.entries.reserve(entries.len());
+        for entry in entries {
+            self.del_entry_retained(entry, self.retain);
+        }
+    }
+
+    /// Delete an entry from the database and add a [`Tombstone`] recording the
+    /// deletion, so that other nodes querying the parent document can find out
+    /// about it. The tombstone is stored under the
+    /// [tombstone key][crate::tombstone::tombstone_key] for the entry's key,
+    /// and is signed with `signer` if one is provided. Other nodes only honor
+    /// the tombstone if it has the same signer as the deleted entry; see
+    /// [`TombstoneSet`][crate::tombstone::TombstoneSet]. Fails if the parent
+    /// document is missing, or if the tombstone fails validation. The deleted
+    /// entry is retained as history just as with [`del_entry`][Self::del_entry].
+    pub async fn del_entry_with_tombstone(
+        &mut self,
+        entry: &EntryRef,
+        deleted: Timestamp,
+        signer: Option<&IdentityKey>,
+    ) -> DbResult<Result<EntryRef, EntryError>> {
+        let parent = match self.docs.get(&entry.parent) {
+            Some(DocChange::Add { doc, .. }) => doc.clone(),
+            _ => match self.db.doc_get(&entry.parent).await? {
+                Some(doc) => doc,
+                None => return Ok(Err(EntryError::MissingDoc(entry.parent.clone()))),
+            },
+        };
+        let tomb = Tombstone {
+            entry: entry.hash.clone(),
+            deleted,
+        };
+        let tomb = match NewEntry::new(&tombstone_key(&entry.key), &parent, tomb) {
+            Ok(tomb) => tomb,
+            Err(e) => return Ok(Err(e.into())),
+        };
+        let tomb = match signer {
+            Some(signer) => match tomb.sign(signer) {
+                Ok(tomb) => tomb,
+                Err(e) => return Ok(Err(e.into())),
+            },
+            None => tomb,
+        };
+        let tomb_ref = tomb.reference().to_owned();
+        if let Err(e) = self.add_new_entry(tomb).await? {
+            return Ok(Err(e));
+        }
+        self.del_entry(entry);
+        Ok(Ok(tomb_ref))
+    }
+
+    /// Point a database root name at a document, or remove the name if
+    /// `target` is `None`. The document must either be in the database already
+    /// or be added by this transaction. The name is checked against the
+    /// database's [naming policy][crate::names::NamingPolicy] when committing;
+    /// reserved names can only be set with
+    /// [`swap_name_reserved`][Self::swap_name_reserved].
+    pub fn set_name(&mut self, name: &str, target: Option<&Hash>) {
+        self.save_name(name);
+        self.names.insert(
+            name.to_owned(),
+            NameChange {
+                target: target.cloned(),
+                expect: None,
+                reserved: false,
+            },
+        );
+    }
+
+    /// Like [`set_name`][Self::set_name], but the transaction fails with
+    /// [`CommitError::NameChanged`] unless the name still points at `expect`
+    /// when committed, or still doesn't exist if `expect` is `None`.
+    pub fn swap_name(&mut self, name: &str, target: Option<&Hash>, expect: Option<&Hash>) {
+        self.save_name(name);
+        self.names.insert(
+            name.to_owned(),
+            NameChange {
+                target: target.cloned(),
+                expect: Some(expect.cloned()),
+                reserved: false,
+            },
+        );
+    }
+
+    /// Like [`swap_name`][Self::swap_name], but for a name under one of the
+    /// [reserved prefixes][crate::names::NamingPolicy::reserved]. Only for use
+    /// by the components that own those prefixes, as with
+    /// [`Db::name_add_reserved`].
+    pub fn swap_name_reserved(
+        &mut self,
+        name: &str,
+        target: Option<&Hash>,
+        expect: Option<&Hash>,
+    ) {
+        self.save_name(name);
+        self.names.insert(
+            name.to_owned(),
+            NameChange {
+                target: target.cloned(),
+                expect: Some(expect.cloned()),
+                reserved: true,
+            },
+        );
     }
 
     /// Commit this transaction to the database. This can fail due to internal
     /// database errors, but it can also fail any of the various [`CommitError`]
-    /// reasons.
-    pub async fn commit(self) -> DbResult<Result<(), CommitErrors>> {
-        self.db.commit(self.docs, self.entries).await
+    /// reasons. On success, returns a receipt with the sequence number assigned
+    /// to the transaction, and which of its documents were already in the
+    /// database. The commit completes once it has reached the requested level
+    /// of [`Durability`].
+    pub async fn commit(
+        self,
+        durability: Durability,
+    ) -> DbResult<Result<CommitReceipt, CommitErrors>> {
+        self.db
+            .commit(self.docs, self.entries, self.names, durability)
+            .await
+    }
+
+    /// Prepare this transaction as the first half of a two-phase commit. This
+    /// fails for the same reasons [`commit`][Self::commit] does; on success,
+    /// the returned commit is guaranteed to apply unless the database fails
+    /// internally. Most users will want a
+    /// [`TxnCoordinator`][crate::coordinator::TxnCoordinator] instead.
+    pub async fn prepare(
+        self,
+        durability: Durability,
+    ) -> DbResult<Result<Box<dyn PreparedCommit>, CommitErrors>> {
+        self.db
+            .prepare(self.docs, self.entries, self.names, durability)
+            .await
+    }
+
+    /// Commit several independent transactions to the database at once,
+    /// sharing the cost of making them durable. Each transaction succeeds or
+    /// fails on its own, and the results are returned in the same order as the
+    /// transactions. The commit completes once every transaction has reached
+    /// the requested level of [`Durability`], and attached permits are held
+    /// until then.
+    ///
+    /// The transactions are committed together through the first one's
+    /// connection, including any wrapper installed with
+    /// [`wrap_commit`][Self::wrap_commit], so they must all have been started
+    /// on the same database. A batch mixing databases is rejected without
+    /// committing anything.
+    pub async fn commit_many(
+        txns: Vec<Transaction>,
+        durability: Durability,
+    ) -> DbResult<Result<Vec<Result<CommitReceipt, CommitErrors>>, MixedBatch>> {
+        let mut txns = txns.into_iter();
+        let Some(first) = txns.next() else {
+            return Ok(Ok(Vec::new()));
+        };
+        let db_id = first.db.db_id();
+        let db = first.db;
+        let mut permits = Vec::new();
+        permits.extend(first.permit);
+        let mut changes = vec![(first.docs, first.entries, first.names)];
+        for txn in txns {
+            if txn.db.db_id() != db_id {
+                return Ok(Err(MixedBatch));
+            }
+            permits.extend(txn.permit);
+            changes.push((txn.docs, txn.entries, txn.names));
+        }
+        let results = db.commit_many(changes, durability).await;
+        drop(permits);
+        results.map(Ok)
     }
 }
 
 /// A document, fully encoded and ready for the database.
+#[derive(Clone)]
 pub struct EncodedDoc {
     schema: Option<Hash>,
-    data: Vec<u8>,
+    data: Bytes,
     refs: Vec<Hash>,
 }
 
@@ -344,7 +1127,7 @@ impl EncodedDoc {
         (
             Self {
                 schema: schema_hash,
-                data,
+                data: Bytes::from(data),
                 refs,
             },
             hash,
@@ -356,8 +1139,8 @@ impl EncodedDoc {
         &self.schema
     }
 
-    /// The raw encoded document.
-    pub fn data(&self) -> &[u8] {
+    /// The raw encoded document. The buffer can be cheaply cloned and shared.
+    pub fn data(&self) -> &Bytes {
         &self.data
     }
 
@@ -368,8 +1151,9 @@ impl EncodedDoc {
 }
 
 /// An entry, fully encoded and ready for the database.
+#[derive(Clone)]
 pub struct EncodedEntry {
-    data: Vec<u8>,
+    data: Bytes,
     all_refs: Vec<Hash>,
     required_refs: Vec<Hash>,
 }
@@ -380,7 +1164,7 @@ impl EncodedEntry {
         let (e_ref, data, required_refs) = schema.encode_entry(entry).unwrap();
         (
             Self {
-                data,
+                data: Bytes::from(data),
                 all_refs,
                 required_refs,
             },
@@ -388,8 +1172,8 @@ impl EncodedEntry {
         )
     }
 
-    /// Get the encoded entry data
-    pub fn data(&self) -> &[u8] {
+    /// Get the encoded entry data. The buffer can be cheaply cloned and shared.
+    pub fn data(&self) -> &Bytes {
         &self.data
     }
 
@@ -408,6 +1192,7 @@ impl EncodedEntry {
 /// operation or a Modify operation. Documents cannot be deleted directly;
 /// instead, they are dropped once all references to them are gone or have been
 /// weakened.
+#[derive(Clone)]
 pub enum DocChange {
     /// Add a document to the DB
     Add {
@@ -417,25 +1202,32 @@ pub enum DocChange {
         doc: Arc<Document>,
         /// Set of references to weaken
         weak_ref: HashSet<Hash>,
+        /// Storage tier to place the document in, if set
+        tier: Option<CacheTier>,
     },
     /// Change the metadata of a document in the DB.
     Modify {
         /// Set to true to make a reference weak
         weak_ref: HashMap<Hash, bool>,
+        /// Storage tier to move the document to, if set
+        tier: Option<CacheTier>,
     },
 }
 
 impl DocChange {
-    fn add(&mut self, encoded: Box<EncodedDoc>, doc: Arc<Document>) {
-        if let DocChange::Modify { weak_ref } = self {
-            let weak_ref: HashSet<Hash> = weak_ref
+    fn add(&mut self, encoded: Box<EncodedDoc>, doc: Arc<Document>, mut defaults: HashSet<Hash>) {
+        if let DocChange::Modify { weak_ref, tier } = self {
+            defaults.retain(|h| !weak_ref.contains_key(h));
+            let mut weak_ref: HashSet<Hash> = weak_ref
                 .iter()
                 .filter_map(|(k, v)| if *v { Some(k.clone()) } else { None })
                 .collect();
+            weak_ref.extend(defaults);
             *self = DocChange::Add {
                 encoded,
                 doc,
                 weak_ref,
+                tier: *tier,
             };
         }
     }
@@ -443,30 +1235,39 @@ impl DocChange {
 
 /// A change to an entry in the database, consisting of either an Add, Modify,
 /// or Delete operation.
+#[derive(Clone)]
 pub enum EntryChange {
     Add {
         entry: Box<EncodedEntry>,
         ttl: Option<Timestamp>,
-        policy: Option<Policy>,
+        policy: Option<EntryPolicy>,
     },
     Modify {
         ttl: Option<Option<Timestamp>>,
-        policy: Option<Option<Policy>>,
+        policy: Option<Option<EntryPolicy>>,
+    },
+    Delete {
+        /// How long to retain the deleted entry as history.
+        retain: Option<Duration>,
+    },
+    /// Bring back a deleted entry that is still being retained as history.
+    Restore {
+        ttl: Option<Option<Timestamp>>,
+        policy: Option<Option<EntryPolicy>>,
     },
-    Delete,
 }
 
 impl EntryChange {
     fn add(&mut self, entry: Box<EncodedEntry>) {
         match self {
-            EntryChange::Modify { ttl, policy } => {
+            EntryChange::Modify { ttl, policy } | EntryChange::Restore { ttl, policy } => {
                 *self = EntryChange::Add {
                     entry,
                     ttl: ttl.unwrap_or_default(),
                     policy: policy.clone().unwrap_or_default(),
                 };
             }
-            EntryChange::Delete => {
+            EntryChange::Delete { .. } => {
                 *self = EntryChange::Add {
                     entry,
                     ttl: None,
@@ -477,3 +1278,15 @@ impl EntryChange {
         }
     }
 }
+
+/// A change to a database root name.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameChange {
+    /// The document to point the name at, or `None` to remove the name.
+    pub target: Option<Hash>,
+    /// If set, only make the change if the name currently points at this
+    /// document, or doesn't exist if this is `Some(None)`.
+    pub expect: Option<Option<Hash>>,
+    /// Whether the name is being set under a reserved prefix.
+    pub reserved: bool,
+}
@@ -0,0 +1,147 @@
+//! Network transport plugin interface.
+//!
+//! A FogDB backend doesn't implement networking itself. Instead, each network
+//! protocol (TCP/IP, WiFi Direct, Bluetooth, LoRa, Tor, ...) is provided as a
+//! [`Transport`], and transports are collected into a [`TransportRegistry`]
+//! that the [`Db`][crate::Db] and [`Group`][crate::group::Group]
+//! implementations consult when opening groups.
+//!
+//! The boundary is deliberately narrow: a transport finds candidate nodes for a
+//! group, dials or accepts connections, and hands each one off as a framed
+//! [`Connection`]. Everything carried over those frames - cursors, queries,
+//! gate access - is the backend's responsibility.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::{group::GroupSpec, NetInfo, NetType, NodeAddr, NodeInfo};
+
+/// Failure within a network transport.
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum TransportError {
+    /// The transport can't perform the requested operation, e.g. it can't
+    /// satisfy a group specification's network requirements.
+    #[error("Operation not supported by this transport")]
+    Unsupported,
+    /// The provided address isn't valid for this transport.
+    #[error("Address isn't valid for this transport")]
+    BadAddr,
+    /// The connection or transport has been closed.
+    #[error("Transport connection is closed")]
+    Closed,
+    /// Some other transport-specific failure occurred.
+    #[error("Transport failure: {0}")]
+    Other(String),
+}
+
+/// A node found by a transport, which may be dialed.
+#[derive(Clone, Debug)]
+pub struct PeerCandidate {
+    /// Name of the transport that can dial this candidate.
+    pub transport: String,
+    /// The network the candidate was found on.
+    pub net: NetType,
+    /// Transport-specific address, to be passed to [`Transport::dial`].
+    pub addr: Vec<u8>,
+    /// The candidate's node address, if it was announced during discovery.
+    pub node: Option<NodeAddr>,
+}
+
+/// A stream of candidate peers. Dropping it stops the underlying discovery.
+#[async_trait]
+pub trait PeerStream: Send + Sync {
+    /// Get the next candidate peer, or `None` if discovery has ended.
+    async fn next(&self) -> Option<PeerCandidate>;
+
+    /// Try to get the next candidate peer, returning `None` if no candidate is
+    /// yet available.
+    fn try_next(&self) -> Option<PeerCandidate>;
+}
+
+/// A framed, bidirectional connection to a remote node.
+///
+/// Frames are delivered whole and in order. The transport is responsible for
+/// any encryption and framing needed on the wire.
+#[async_trait]
+pub trait Connection: Send + Sync {
+    /// Information about the remote node.
+    fn remote(&self) -> NodeInfo;
+
+    /// Send a frame to the remote node.
+    async fn send(&self, frame: Bytes) -> Result<(), TransportError>;
+
+    /// Receive the next frame from the remote node.
+    async fn recv(&self) -> Result<Bytes, TransportError>;
+
+    /// Close the connection - should be equivalent to dropping it.
+    fn close(self: Box<Self>);
+}
+
+/// A network transport plugin.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// A unique name for this transport. For transports on
+    /// [`NetType::Other`] networks, this should match the network name.
+    fn name(&self) -> &str;
+
+    /// The network types this transport can operate on.
+    fn net_types(&self) -> Vec<NetType>;
+
+    /// Start looking for nodes that could be part of a group with the given
+    /// specification. Fails with [`TransportError::Unsupported`] if the
+    /// specification can't be met by this transport.
+    fn start_discovery(&self, spec: &GroupSpec) -> Result<Box<dyn PeerStream>, TransportError>;
+
+    /// Open a connection to a discovered peer.
+    async fn dial(&self, peer: &PeerCandidate) -> Result<Box<dyn Connection>, TransportError>;
+
+    /// Wait for the next incoming connection.
+    async fn accept(&self) -> Result<Box<dyn Connection>, TransportError>;
+}
+
+/// A collection of transports, keyed by name.
+#[derive(Clone, Default)]
+pub struct TransportRegistry {
+    transports: BTreeMap<String, Arc<dyn Transport>>,
+}
+
+impl TransportRegistry {
+    /// Create a new, empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a transport to the registry, returning the previous transport with
+    /// the same name, if there was one.
+    pub fn register(&mut self, transport: Arc<dyn Transport>) -> Option<Arc<dyn Transport>> {
+        self.transports
+            .insert(transport.name().to_owned(), transport)
+    }
+
+    /// Remove a transport from the registry.
+    pub fn unregister(&mut self, name: &str) -> Option<Arc<dyn Transport>> {
+        self.transports.remove(name)
+    }
+
+    /// Get a transport by name.
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Transport>> {
+        self.transports.get(name)
+    }
+
+    /// Iterate over all registered transports.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Transport>> {
+        self.transports.values()
+    }
+
+    /// Iterate over the transports that can operate on at least one of the
+    /// networks permitted by `net`.
+    pub fn for_net<'a>(&'a self, net: &'a NetInfo) -> impl Iterator<Item = &'a Arc<dyn Transport>> {
+        self.transports
+            .values()
+            .filter(|t| t.net_types().iter().any(|n| net.allows(n)))
+    }
+}
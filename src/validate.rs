@@ -0,0 +1,93 @@
+//! Shared schema validation.
+//!
+//! Validating documents and entries against their schemas is where a node
+//! that takes in a lot of data spends most of its time. Every transaction,
+//! and every gate checking what passes through it, would otherwise look up
+//! and compile the same schemas and validate on whatever task they happen to
+//! be running on. Instead, each database has a single [`Validator`], found
+//! with [`Db::validator`][crate::Db::validator], that keeps compiled schemas
+//! cached and spreads validation across a pool of workers. It's sized by the
+//! [`ValidatorConfig`] the database was opened with.
+//!
+//! Transactions use the validator for batches added with
+//! [`add_new_docs_pooled`][crate::transaction::Transaction::add_new_docs_pooled]
+//! and
+//! [`add_new_entries_pooled`][crate::transaction::Transaction::add_new_entries_pooled].
+//! Backends should use it for anything else they validate, like entries
+//! received through a group or responses from a gate's
+//! [query hooks][crate::gate::QueryHook].
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use fog_pack::{
+    document::{Document, NewDocument},
+    entry::{Entry, NewEntry},
+    schema::Schema,
+    types::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    transaction::{EntryError, SchemaError},
+    DbResult,
+};
+
+/// How a database's [`Validator`] is sized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidatorConfig {
+    /// How many workers validate at once. If `None`, the backend picks, usually
+    /// one per CPU core. `Some(0)` validates on the calling task instead.
+    pub workers: Option<u32>,
+    /// The most compiled schemas to keep cached. Schemas beyond this are
+    /// compiled again when next needed.
+    pub max_schemas: u32,
+}
+
+impl Default for ValidatorConfig {
+    fn default() -> Self {
+        Self {
+            workers: None,
+            max_schemas: 256,
+        }
+    }
+}
+
+/// A snapshot of a validator's state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ValidatorStats {
+    /// How many workers the validator has.
+    pub workers: u32,
+    /// How many compiled schemas are cached.
+    pub cached_schemas: u32,
+    /// How many documents and entries are waiting to be validated.
+    pub pending: u64,
+}
+
+/// A database's shared validation service.
+#[async_trait]
+pub trait Validator: Send + Sync {
+    /// Get a compiled schema from the database, using the cached copy if
+    /// there is one.
+    fn schema(&self, schema: &Hash) -> DbResult<Option<Arc<Schema>>>;
+
+    /// Validate a batch of documents against their schemas. The results are
+    /// in the same order as the documents.
+    async fn validate_docs(
+        &self,
+        docs: Vec<NewDocument>,
+    ) -> DbResult<Vec<Result<Document, SchemaError>>>;
+
+    /// Validate a batch of entries against their parent documents' schemas.
+    /// Documents the entries link to are looked for in `docs`, then in the
+    /// database. The results are in the same order as the entries.
+    async fn validate_entries(
+        &self,
+        entries: Vec<NewEntry>,
+        docs: &HashMap<Hash, Arc<Document>>,
+    ) -> DbResult<Vec<Result<Entry, EntryError>>>;
+
+    /// Get the validator's current state.
+    fn stats(&self) -> ValidatorStats;
+}
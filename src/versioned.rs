@@ -0,0 +1,177 @@
+//! Named roots that keep a history of their past versions.
+//!
+//! A [`VersionedRoot`] is a name that points at a small head document, which
+//! points at the current content and at the previous head. Each update adds a
+//! new head and swaps the name over to it in the same transaction, so the
+//! document tree is reachable from the name at every point and the garbage
+//! collector never sees a gap.
+//!
+//! Whether past versions are kept is decided by how heads link to their
+//! predecessors. With [`HistoryLinks::Strong`], every past version stays
+//! resident for as long as the name exists. With [`HistoryLinks::Weak`], past
+//! versions are only kept while something else holds on to them, and history
+//! ends at the first one that has been collected.
+
+use std::sync::Arc;
+
+use fog_pack::{
+    document::{Document, NewDocument},
+    types::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cursor::{Cursor, CursorError},
+    names::rotate_root,
+    transaction::{CommitErrors, Durability, Transaction},
+    Db, DbResult,
+};
+
+/// How head documents link to the previous head.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HistoryLinks {
+    /// Keep every past version resident.
+    #[default]
+    Strong,
+    /// Let past versions be garbage collected once nothing else holds them.
+    Weak,
+}
+
+/// A head document, recording one version of a root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Head {
+    /// The version number, starting from 0.
+    pub version: u64,
+    /// When this version was made.
+    pub time: Timestamp,
+    /// The root of this version's content.
+    pub content: Hash,
+    /// The previous head, if there is one.
+    pub prev: Option<Hash>,
+}
+
+/// A database root name that keeps a linked history of versions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionedRoot {
+    name: String,
+    links: HistoryLinks,
+}
+
+impl VersionedRoot {
+    /// Set up a versioned root under `name`, linking its versions as given.
+    pub fn new(name: impl Into<String>, links: HistoryLinks) -> Self {
+        Self {
+            name: name.into(),
+            links,
+        }
+    }
+
+    /// The name this root is kept under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the current head document and its hash, if the root has been
+    /// published.
+    pub async fn head<D: Db + ?Sized>(&self, db: &D) -> DbResult<Option<(Hash, Head)>> {
+        let Some(hash) = db.name_get(&self.name).await? else {
+            return Ok(None);
+        };
+        let Some(doc) = db.doc_get(&hash).await? else {
+            return Ok(None);
+        };
+        Ok(decode(&doc).map(|head| (hash, head)))
+    }
+
+    /// Publish `content` as the next version. The transaction should hold any
+    /// new documents in the content's tree. Returns the hash of the new head.
+    ///
+    /// Fails with [`CommitError::NameChanged`][crate::transaction::CommitError::NameChanged]
+    /// if the root was updated by someone else in the meantime, in which case
+    /// nothing is changed.
+    pub async fn update<D: Db + ?Sized>(
+        &self,
+        db: &D,
+        mut txn: Transaction,
+        content: &Hash,
+        durability: Durability,
+    ) -> DbResult<Result<Hash, CommitErrors>> {
+        let prev = self.head(db).await?;
+        let head = Head {
+            version: prev.as_ref().map_or(0, |(_, h)| h.version + 1),
+            time: Timestamp::now().unwrap_or_else(Timestamp::zero),
+            content: content.clone(),
+            prev: prev.map(|(hash, _)| hash),
+        };
+        let doc = NewDocument::new(None, &head).expect("Head should always be serializable");
+        let doc = txn
+            .add_new_doc(doc).await?
+            .expect("Head documents have no schema and are always valid");
+        if let (Some(prev), HistoryLinks::Weak) = (&head.prev, self.links) {
+            txn.set_weak_ref(doc.hash(), prev, true);
+        }
+        let hash = doc.hash().clone();
+        Ok(rotate_root(db, txn, &self.name, &hash, durability)
+            .await?
+            .map(|_| hash))
+    }
+
+    /// Walk back through up to `n` versions, starting from the head document
+    /// the cursor is on. History ends early at the first version no longer in
+    /// the local database.
+    pub fn history(&self, cursor: Box<dyn Cursor>, n: usize) -> History {
+        History {
+            cursor,
+            remaining: n,
+            started: false,
+        }
+    }
+}
+
+fn decode(doc: &Document) -> Option<Head> {
+    doc.deserialize().ok()
+}
+
+/// Past versions of a [`VersionedRoot`], newest first.
+pub struct History {
+    cursor: Box<dyn Cursor>,
+    remaining: usize,
+    started: bool,
+}
+
+impl History {
+    /// Get the cursor back, positioned on the last version returned.
+    pub fn into_cursor(self) -> Box<dyn Cursor> {
+        self.cursor
+    }
+}
+
+impl Iterator for History {
+    type Item = Result<(Arc<Document>, Head), CursorError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let doc = if self.started {
+            let current = self.cursor.current();
+            let prev = decode(&current)?.prev?;
+            match self.cursor.forward_local(&prev) {
+                Ok(doc) => doc?,
+                Err(e) => {
+                    self.remaining = 0;
+                    return Some(Err(e));
+                }
+            }
+        } else {
+            self.started = true;
+            self.cursor.current()
+        };
+        self.remaining -= 1;
+        let Some(head) = decode(&doc) else {
+            self.remaining = 0;
+            return Some(Err(CursorError::InvalidDoc(doc.hash().clone())));
+        };
+        Some(Ok((doc, head)))
+    }
+}
@@ -0,0 +1,191 @@
+//! Materialized views over the entries under a document.
+//!
+//! Aggregates over entries, like unread counts, leaderboards, or vote tallies,
+//! are expensive to compute from scratch, and every client that wants one
+//! would otherwise run the same query and do the same work. A [`View`]
+//! computes the aggregate once, with a [`Fold`] supplied by the application,
+//! and publishes the result as a document under a root name. Clients then just
+//! read that document.
+//!
+//! The fold is incremental: entries are folded in as they're added and folded
+//! out as they're deleted, so keeping the view current costs work in
+//! proportion to how much has changed. To build a view:
+//!
+//! 1. Open an [entry watch][crate::Db::entry_watch] on the document and key.
+//! 2. Run a query for the existing entries, and [add][View::add] each result.
+//! 3. [Run][View::run] the view on the watch, which folds in every change and
+//!    republishes the result after each batch of them.
+//!
+//! Opening the watch first means no change can fall between the query and the
+//! watch. An entry that shows up in both is only folded in once.
+
+use std::collections::HashSet;
+
+use fog_pack::{
+    document::NewDocument,
+    entry::{Entry, EntryRef},
+    error::Error as FogError,
+    types::*,
+};
+
+use crate::{
+    changes::{EntryEvent, EntryRecord, EntryWatch},
+    names::rotate_root,
+    transaction::{CommitErrors, Durability},
+    Db, DbError, DbResult,
+};
+
+/// An application-supplied aggregation over entries.
+pub trait Fold: Send + Sync {
+    /// The running result of the fold. This needs to hold enough to fold
+    /// entries back out: a leaderboard, for example, has to remember every
+    /// score and not just the top ones.
+    type State: Send;
+
+    /// The state before any entries have been folded in.
+    fn init(&self) -> Self::State;
+
+    /// Fold an entry in.
+    fn add(&self, state: &mut Self::State, entry: &Entry);
+
+    /// Fold a previously added entry back out.
+    fn remove(&self, state: &mut Self::State, entry: &EntryRef);
+
+    /// Make the document to publish for the current state.
+    fn render(&self, state: &Self::State) -> Result<NewDocument, FogError>;
+}
+
+/// A materialized view, maintained by a [`Fold`] and published under a root
+/// name.
+pub struct View<F: Fold> {
+    fold: F,
+    name: String,
+    state: F::State,
+    entries: HashSet<EntryRef>,
+    published: Option<Hash>,
+    dirty: bool,
+}
+
+impl<F: Fold> View<F> {
+    /// Start a view with no entries, to be published under `name`.
+    pub fn new(fold: F, name: impl Into<String>) -> Self {
+        Self {
+            state: fold.init(),
+            fold,
+            name: name.into(),
+            entries: HashSet::new(),
+            published: None,
+            dirty: true,
+        }
+    }
+
+    /// The name the view is published under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The view's current state, which may not be published yet.
+    pub fn state(&self) -> &F::State {
+        &self.state
+    }
+
+    /// The hash of the most recently published document, if this view has
+    /// published one.
+    pub fn published(&self) -> Option<&Hash> {
+        self.published.as_ref()
+    }
+
+    /// Check if the state has changed since it was last published.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Fold an entry in. Returns false if it was already folded in.
+    pub fn add(&mut self, entry: &Entry) -> bool {
+        if !self.entries.insert(entry.reference().to_owned()) {
+            return false;
+        }
+        self.fold.add(&mut self.state, entry);
+        self.dirty = true;
+        true
+    }
+
+    /// Fold an entry back out. Returns false if it wasn't folded in.
+    pub fn remove(&mut self, entry: &EntryRef) -> bool {
+        if !self.entries.remove(entry) {
+            return false;
+        }
+        self.fold.remove(&mut self.state, entry);
+        self.dirty = true;
+        true
+    }
+
+    /// Apply a change from an entry watch. Restored entries are folded back
+    /// in. Modifications don't change an entry's contents, so they leave the
+    /// view alone.
+    pub fn apply(&mut self, event: &EntryEvent) {
+        match &event.change {
+            EntryRecord::Added(entry) | EntryRecord::Restored(entry) => {
+                self.add(entry);
+            }
+            EntryRecord::Deleted(entry) => {
+                self.remove(entry);
+            }
+            EntryRecord::Modified(_) => (),
+        }
+    }
+
+    /// Publish the current state, pointing the view's name at the rendered
+    /// document. Does nothing if the state hasn't changed since it was last
+    /// published. Returns the hash of the published document.
+    ///
+    /// This is done with [`rotate_root`], so it fails with
+    /// [`CommitError::NameChanged`][crate::transaction::CommitError::NameChanged]
+    /// if something else changes the name while publishing.
+    pub async fn publish<D: Db + ?Sized>(
+        &mut self,
+        db: &D,
+        durability: Durability,
+    ) -> DbResult<Result<Hash, CommitErrors>> {
+        if let Some(published) = self.published.as_ref().filter(|_| !self.dirty) {
+            return Ok(Ok(published.clone()));
+        }
+        let doc = self.fold.render(&self.state).map_err(|err| {
+            Box::new(DbError::FogOther {
+                context: format!("rendering view {}", self.name),
+                err,
+            })
+        })?;
+        let mut txn = db.txn();
+        let doc = txn
+            .add_new_doc(doc).await?
+            .map_err(|e| Box::new(DbError::Internal(Box::new(e))))?;
+        let hash = doc.hash().clone();
+        if let Err(errs) = rotate_root(db, txn, &self.name, &hash, durability).await? {
+            return Ok(Err(errs));
+        }
+        self.published = Some(hash.clone());
+        self.dirty = false;
+        Ok(Ok(hash))
+    }
+
+    /// Keep the view up to date with changes from an entry watch, publishing
+    /// once any pending changes have been applied. Runs until publishing
+    /// fails, returning the commit errors.
+    pub async fn run<D: Db + ?Sized>(
+        &mut self,
+        db: &D,
+        watch: &dyn EntryWatch,
+        durability: Durability,
+    ) -> DbResult<CommitErrors> {
+        loop {
+            if let Err(errs) = self.publish(db, durability).await? {
+                return Ok(errs);
+            }
+            self.apply(&watch.next().await?);
+            while let Some(event) = watch.try_next()? {
+                self.apply(&event);
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Opening connections ahead of expected traffic.
+//!
+//! Connections to group members are opened when a cursor or request first
+//! needs them, so the first query to a node pays for the handshake (and for
+//! any mixnet circuit) on top of its own round trip. An application that knows
+//! a burst of activity is coming, like a chat view about to open, can warm the
+//! connections to the nodes it's about to talk to with
+//! [`Group::warm`][crate::group::Group::warm]. Connections that are already
+//! open are refreshed instead, confirming the node is still there.
+//!
+//! Warmed connections are handled like any other afterwards: they count
+//! against the node's [peer limit][crate::runtime::NodeLimits::max_peers], and
+//! are closed when they go idle for long enough.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::NodeAddr;
+
+/// Failure to warm the connection to a node.
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum WarmError {
+    /// The node couldn't be reached through the group.
+    #[error("Node couldn't be reached")]
+    Unreachable,
+    /// The node was already connected to the most peers it allows, and no
+    /// idle connection could be closed to make room.
+    #[error("Node-wide peer limit reached")]
+    PeerLimit,
+    /// The group wouldn't warm the connection, giving a reason.
+    #[error("Warming refused: {0}")]
+    Refused(String),
+}
+
+/// How warming the connection to one node went.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warmed {
+    /// The node.
+    pub node: NodeAddr,
+    /// The round trip time to the node once connected, or why the connection
+    /// couldn't be warmed.
+    pub result: Result<Duration, WarmError>,
+}
+
+/// Connections being warmed, waiting on the nodes to answer.
+#[async_trait]
+pub trait Warmup: Send + Sync {
+    /// Wait for every connection to be warmed or to fail. Results are in the
+    /// same order the nodes were given in.
+    async fn complete(self: Box<Self>) -> Vec<Warmed>;
+}
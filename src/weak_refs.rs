@@ -0,0 +1,56 @@
+//! Per-schema defaults for which document links are weak.
+//!
+//! Some link fields are only ever cache-like: a pointer to a thumbnail, or to
+//! a previous version that doesn't need to be kept. Rather than every caller
+//! weakening those links with
+//! [`Transaction::set_weak_ref`][crate::transaction::Transaction::set_weak_ref]
+//! after each add, a schema can be given [`WeakRefDefaults`] with
+//! [`Db::schema_set_weak_refs`][crate::Db::schema_set_weak_refs]. Documents of
+//! that schema then have the links in those fields weakened when they're added
+//! to a transaction. A link explicitly set strong or weak in the same
+//! transaction keeps its explicit setting.
+
+use std::collections::HashSet;
+
+use fog_pack::{document::Document, types::*};
+use serde::{Deserialize, Serialize};
+
+use crate::cursor::{lookup, Index};
+
+/// The fields of a schema's documents whose links default to weak.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WeakRefDefaults {
+    /// Locations of the weak fields within a document. If a field holds an
+    /// array or map, every hash anywhere inside it is weakened.
+    pub fields: Vec<Vec<Index>>,
+}
+
+impl WeakRefDefaults {
+    /// Find the links in a document that should be weakened.
+    pub fn weak_links(&self, doc: &Document) -> HashSet<Hash> {
+        let mut links = HashSet::new();
+        if self.fields.is_empty() {
+            return links;
+        }
+        let Ok(val) = doc.deserialize::<ValueRef>() else {
+            return links;
+        };
+        for field in self.fields.iter() {
+            if let Some(val) = lookup(&val, field) {
+                collect_hashes(val, &mut links);
+            }
+        }
+        links
+    }
+}
+
+fn collect_hashes(val: &ValueRef, links: &mut HashSet<Hash>) {
+    match val {
+        ValueRef::Hash(hash) => {
+            links.insert(hash.clone());
+        }
+        ValueRef::Array(vals) => vals.iter().for_each(|v| collect_hashes(v, links)),
+        ValueRef::Map(vals) => vals.values().for_each(|v| collect_hashes(v, links)),
+        _ => (),
+    }
+}
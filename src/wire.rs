@@ -0,0 +1,340 @@
+//! Serializable forms of the database error types.
+//!
+//! A [`Db`][crate::Db] implementation may be a proxy for a database living in
+//! another process, in which case errors need to cross the process boundary
+//! intact. [`DbError`], [`CommitError`][crate::transaction::CommitError], and
+//! [`CursorError`][crate::cursor::CursorError] all implement `Serialize` and
+//! `Deserialize` for this purpose. Their wire representation is the one serde
+//! derives for the types in this module, with enum variants identified by
+//! name, so it stays stable as long as variants aren't renamed.
+//!
+//! Some errors can't be reconstructed exactly on the far side:
+//!
+//! - [`DbError::Internal`] holds an arbitrary error, so it is sent as its
+//!   message and the messages of its chain of sources, and decoded into a
+//!   [`RemoteError`].
+//! - fog-pack and fog-crypto errors carrying static strings have them replaced
+//!   with [`REMOTE_STEP`] when decoded. Their messages are otherwise preserved.
+
+use std::{error::Error, fmt};
+
+use fog_crypto::CryptoError;
+use fog_pack::{entry::EntryRef, error::Error as FogError, types::*};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::DbError;
+
+/// Placeholder for static strings in errors that have been decoded from the
+/// wire.
+pub const REMOTE_STEP: &str = "remote";
+
+/// An error that occurred in another process, reconstructed from its messages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteError {
+    /// The error's message.
+    pub message: String,
+    /// The error that caused this one, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Box<RemoteError>>,
+}
+
+impl RemoteError {
+    /// Capture an error and its chain of sources.
+    pub fn capture(err: &(dyn Error + 'static)) -> Self {
+        Self {
+            message: err.to_string(),
+            source: err.source().map(|s| Box::new(Self::capture(s))),
+        }
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for RemoteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|s| s as &(dyn Error + 'static))
+    }
+}
+
+/// Wire form of an [`EntryRef`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WireEntryRef {
+    pub parent: Hash,
+    pub key: String,
+    pub hash: Hash,
+}
+
+impl From<&EntryRef> for WireEntryRef {
+    fn from(value: &EntryRef) -> Self {
+        Self {
+            parent: value.parent.clone(),
+            key: value.key.clone(),
+            hash: value.hash.clone(),
+        }
+    }
+}
+
+impl From<WireEntryRef> for EntryRef {
+    fn from(value: WireEntryRef) -> Self {
+        EntryRef {
+            parent: value.parent,
+            key: value.key,
+            hash: value.hash,
+        }
+    }
+}
+
+/// Serde helpers for [`EntryRef`] fields, for use with `#[serde(with)]`.
+pub(crate) mod entry_ref {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(e_ref: &EntryRef, s: S) -> Result<S::Ok, S::Error> {
+        WireEntryRef::from(e_ref).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<EntryRef, D::Error> {
+        Ok(WireEntryRef::deserialize(d)?.into())
+    }
+}
+
+/// Wire form of a fog-crypto error.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireCryptoError {
+    UnsupportedVersion(u8),
+    OldVersion(u8),
+    DecryptFailed,
+    BadLength {
+        step: String,
+        actual: u64,
+        expected: u64,
+    },
+    BadKey,
+    BadFormat(String),
+    ObjectMismatch(String),
+    SignatureFailed,
+    NotSupportedByVault,
+}
+
+impl From<&CryptoError> for WireCryptoError {
+    fn from(value: &CryptoError) -> Self {
+        match value {
+            CryptoError::UnsupportedVersion(v) => Self::UnsupportedVersion(*v),
+            CryptoError::OldVersion(v) => Self::OldVersion(*v),
+            CryptoError::DecryptFailed => Self::DecryptFailed,
+            CryptoError::BadLength {
+                step,
+                actual,
+                expected,
+            } => Self::BadLength {
+                step: step.to_string(),
+                actual: *actual as u64,
+                expected: *expected as u64,
+            },
+            CryptoError::BadKey => Self::BadKey,
+            CryptoError::BadFormat(s) => Self::BadFormat(s.to_string()),
+            CryptoError::ObjectMismatch(s) => Self::ObjectMismatch(s.to_string()),
+            CryptoError::SignatureFailed => Self::SignatureFailed,
+            CryptoError::NotSupportedByVault => Self::NotSupportedByVault,
+        }
+    }
+}
+
+impl From<WireCryptoError> for CryptoError {
+    fn from(value: WireCryptoError) -> Self {
+        match value {
+            WireCryptoError::UnsupportedVersion(v) => Self::UnsupportedVersion(v),
+            WireCryptoError::OldVersion(v) => Self::OldVersion(v),
+            WireCryptoError::DecryptFailed => Self::DecryptFailed,
+            WireCryptoError::BadLength {
+                actual, expected, ..
+            } => Self::BadLength {
+                step: REMOTE_STEP,
+                actual: actual as usize,
+                expected: expected as usize,
+            },
+            WireCryptoError::BadKey => Self::BadKey,
+            WireCryptoError::BadFormat(_) => Self::BadFormat(REMOTE_STEP),
+            WireCryptoError::ObjectMismatch(_) => Self::ObjectMismatch(REMOTE_STEP),
+            WireCryptoError::SignatureFailed => Self::SignatureFailed,
+            WireCryptoError::NotSupportedByVault => Self::NotSupportedByVault,
+        }
+    }
+}
+
+/// Wire form of a fog-pack error.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireFogError {
+    OldVersion(String),
+    SchemaMismatch {
+        actual: Option<Hash>,
+        expected: Option<Hash>,
+    },
+    SerdeFail(String),
+    BadHeader(String),
+    FailDecompress(String),
+    LengthTooLong {
+        max: u64,
+        actual: u64,
+    },
+    LengthTooShort {
+        step: String,
+        actual: u64,
+        expected: u64,
+    },
+    BadSignature,
+    BadEncode(String),
+    FailValidate(String),
+    CryptoError(WireCryptoError),
+    ParseLimit(String),
+}
+
+impl From<&FogError> for WireFogError {
+    fn from(value: &FogError) -> Self {
+        match value {
+            FogError::OldVersion(s) => Self::OldVersion(s.clone()),
+            FogError::SchemaMismatch { actual, expected } => Self::SchemaMismatch {
+                actual: actual.clone(),
+                expected: expected.clone(),
+            },
+            FogError::SerdeFail(s) => Self::SerdeFail(s.clone()),
+            FogError::BadHeader(s) => Self::BadHeader(s.clone()),
+            FogError::FailDecompress(s) => Self::FailDecompress(s.clone()),
+            FogError::LengthTooLong { max, actual } => Self::LengthTooLong {
+                max: *max as u64,
+                actual: *actual as u64,
+            },
+            FogError::LengthTooShort {
+                step,
+                actual,
+                expected,
+            } => Self::LengthTooShort {
+                step: step.to_string(),
+                actual: *actual as u64,
+                expected: *expected as u64,
+            },
+            FogError::BadSignature => Self::BadSignature,
+            FogError::BadEncode(s) => Self::BadEncode(s.clone()),
+            FogError::FailValidate(s) => Self::FailValidate(s.clone()),
+            FogError::CryptoError(e) => Self::CryptoError(e.into()),
+            FogError::ParseLimit(s) => Self::ParseLimit(s.clone()),
+        }
+    }
+}
+
+impl From<WireFogError> for FogError {
+    fn from(value: WireFogError) -> Self {
+        match value {
+            WireFogError::OldVersion(s) => Self::OldVersion(s),
+            WireFogError::SchemaMismatch { actual, expected } => {
+                Self::SchemaMismatch { actual, expected }
+            }
+            WireFogError::SerdeFail(s) => Self::SerdeFail(s),
+            WireFogError::BadHeader(s) => Self::BadHeader(s),
+            WireFogError::FailDecompress(s) => Self::FailDecompress(s),
+            WireFogError::LengthTooLong { max, actual } => Self::LengthTooLong {
+                max: max as usize,
+                actual: actual as usize,
+            },
+            WireFogError::LengthTooShort {
+                actual, expected, ..
+            } => Self::LengthTooShort {
+                step: REMOTE_STEP,
+                actual: actual as usize,
+                expected: expected as usize,
+            },
+            WireFogError::BadSignature => Self::BadSignature,
+            WireFogError::BadEncode(s) => Self::BadEncode(s),
+            WireFogError::FailValidate(s) => Self::FailValidate(s),
+            WireFogError::CryptoError(e) => Self::CryptoError(e.into()),
+            WireFogError::ParseLimit(s) => Self::ParseLimit(s),
+        }
+    }
+}
+
+/// Wire form of a [`DbError`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireDbError {
+    Internal(RemoteError),
+    FogDoc {
+        context: String,
+        doc: Hash,
+        err: WireFogError,
+    },
+    FogEntry {
+        context: String,
+        entry: WireEntryRef,
+        err: WireFogError,
+    },
+    FogOther {
+        context: String,
+        err: WireFogError,
+    },
+}
+
+impl From<&DbError> for WireDbError {
+    fn from(value: &DbError) -> Self {
+        match value {
+            DbError::Internal(e) => Self::Internal(RemoteError::capture(e.as_ref())),
+            DbError::FogDoc { context, doc, err } => Self::FogDoc {
+                context: context.clone(),
+                doc: doc.clone(),
+                err: err.into(),
+            },
+            DbError::FogEntry {
+                context,
+                entry,
+                err,
+            } => Self::FogEntry {
+                context: context.clone(),
+                entry: entry.into(),
+                err: err.into(),
+            },
+            DbError::FogOther { context, err } => Self::FogOther {
+                context: context.clone(),
+                err: err.into(),
+            },
+        }
+    }
+}
+
+impl From<WireDbError> for DbError {
+    fn from(value: WireDbError) -> Self {
+        match value {
+            WireDbError::Internal(e) => DbError::Internal(Box::new(e)),
+            WireDbError::FogDoc { context, doc, err } => DbError::FogDoc {
+                context,
+                doc,
+                err: err.into(),
+            },
+            WireDbError::FogEntry {
+                context,
+                entry,
+                err,
+            } => DbError::FogEntry {
+                context,
+                entry: entry.into(),
+                err: err.into(),
+            },
+            WireDbError::FogOther { context, err } => DbError::FogOther {
+                context,
+                err: err.into(),
+            },
+        }
+    }
+}
+
+impl Serialize for DbError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        WireDbError::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DbError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(WireDbError::deserialize(deserializer)?.into())
+    }
+}